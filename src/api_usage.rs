@@ -0,0 +1,104 @@
+//! Bookkeeping for how many API calls orange-zester makes.
+//!
+//! `orange-zest` doesn't tell us about individual requests, so calls are
+//! counted from the zesting events: one per page of likes or playlists, one
+//! per playlist fetched, one per track whose audio is requested.
+
+use crate::state::{load_state, save_state};
+use crate::Error;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const USAGE_FILE: &str = "api-usage.json";
+
+/// How long runs are remembered for
+const RETENTION_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct UsageLog {
+    pub runs: Vec<RunUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RunUsage {
+    pub started_at: DateTime<Utc>,
+    pub command: String,
+    pub calls: u64,
+}
+
+impl UsageLog {
+    /// Loads the usage log from the state directory, or an empty one if there
+    /// isn't one yet.
+    pub fn load() -> Result<Self, Error> {
+        load_state(USAGE_FILE)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        save_state(USAGE_FILE, self)
+    }
+
+    /// The number of calls made by runs started in the last 24 hours.
+    pub fn calls_last_24h(&self) -> u64 {
+        let cutoff = Utc::now() - Duration::hours(24);
+        self.runs.iter().filter(|r| r.started_at > cutoff).map(|r| r.calls).sum()
+    }
+}
+
+/// Counts the API calls made during a run, optionally capping them.
+///
+/// The count is added to the usage log in the state directory when the
+/// budget is dropped, so it's recorded however the run ends.
+pub struct ApiBudget {
+    started_at: DateTime<Utc>,
+    command: &'static str,
+    calls: AtomicU64,
+    max_calls: Option<u64>,
+}
+
+impl ApiBudget {
+    pub fn new(command: &'static str, max_calls: Option<u64>) -> Self {
+        Self {
+            started_at: Utc::now(),
+            command,
+            calls: AtomicU64::new(0),
+            max_calls
+        }
+    }
+
+    /// Notes that `calls` more API calls have been made.
+    pub fn record(&self, calls: u64) {
+        self.calls.fetch_add(calls, Ordering::SeqCst);
+    }
+
+    /// How many more calls this run may make, or `None` if it's unlimited.
+    pub fn remaining(&self) -> Option<u64> {
+        self.max_calls.map(|max| max.saturating_sub(self.calls.load(Ordering::SeqCst)))
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.remaining() == Some(0)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        let mut log = UsageLog::load()?;
+        let cutoff = Utc::now() - Duration::days(RETENTION_DAYS);
+
+        log.runs.retain(|r| r.started_at > cutoff);
+        log.runs.push(RunUsage {
+            started_at: self.started_at,
+            command: self.command.into(),
+            calls: self.calls.load(Ordering::SeqCst)
+        });
+        log.save()
+    }
+}
+
+impl Drop for ApiBudget {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            eprintln!("[warning] failed to record API usage: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Helpers for reading a JSON archive produced by the `json` subcommand.
+
+use crate::atomic::write_json;
+use crate::json_check::{load_checked, Strictness};
+use crate::soundcloud::PlayedTrack;
+use crate::stream::{self, LIKES_NDJSON};
+use crate::{sanitize, Error};
+use orange_zest::api::{Likes, Me, Playlist, Playlists, TrackInfo, User};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The playlists and albums the account has liked, as opposed to made
+pub const LIKED_PLAYLISTS_FILE: &str = "liked-playlists.json";
+/// The home feed, as captured by however many zests
+pub const STREAM_FILE: &str = "stream.json";
+/// Tracks the account has listened to
+pub const HISTORY_FILE: &str = "history.json";
+/// The users the account follows, and those following it
+pub const FOLLOWINGS_FILE: &str = "followings.json";
+pub const FOLLOWERS_FILE: &str = "followers.json";
+/// Where `json --split` puts a file per playlist, alongside `SPLIT_INDEX_FILE`
+pub const SPLIT_PLAYLISTS_DIR: &str = "playlists";
+const SPLIT_INDEX_FILE: &str = "index.json";
+
+/// Lists the playlists saved by `json --split`, in the order they'd be in
+/// `playlists.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct PlaylistIndex {
+    playlists: Vec<IndexedPlaylist>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct IndexedPlaylist {
+    id: Option<u64>,
+    title: Option<String>,
+    /// Relative to the index
+    file: String,
+    num_tracks: usize,
+}
+
+/// Loads `likes.json` from the given archive folder, or the `likes.ndjson`
+/// written instead by `json --stream`.
+pub fn load_likes(folder: &Path, strictness: Strictness) -> Result<Likes, Error> {
+    let streamed = folder.join(LIKES_NDJSON);
+    if !folder.join("likes.json").exists() && streamed.exists() {
+        return stream::load_likes(&streamed);
+    }
+
+    load_checked(&folder.join("likes.json"), strictness)
+}
+
+/// Loads `playlists.json` from the given archive folder, or the file per
+/// playlist written instead by `json --split`.
+pub fn load_playlists(folder: &Path, strictness: Strictness) -> Result<Playlists, Error> {
+    let split_index = folder.join(SPLIT_PLAYLISTS_DIR).join(SPLIT_INDEX_FILE);
+    if folder.join("playlists.json").exists() || !split_index.exists() {
+        return load_checked(&folder.join("playlists.json"), strictness);
+    }
+
+    let index: PlaylistIndex = load_checked(&split_index, strictness)?;
+    let playlists = index.playlists
+        .iter()
+        .map(|entry| load_checked(&folder.join(SPLIT_PLAYLISTS_DIR).join(&entry.file), strictness))
+        .collect::<Result<_, _>>()?;
+    Ok(Playlists { playlists })
+}
+
+/// Saves the given playlists into the archive folder, either all together in
+/// `playlists.json` or (with `split`) a file per playlist in `playlists/`,
+/// removing whichever layout was there before.
+pub fn save_playlists(folder: &Path, playlists: &Playlists, split: bool, pretty_print: bool) -> Result<(), Error> {
+    let split_folder = folder.join(SPLIT_PLAYLISTS_DIR);
+    let split_index = split_folder.join(SPLIT_INDEX_FILE);
+
+    // Only the files the last index listed; audio can live in playlists/ too
+    let mut stale: Vec<PathBuf> = match split_index.exists() {
+        true => orange_zest::load_json::<PlaylistIndex, _>(&split_index)?
+            .playlists
+            .into_iter()
+            .map(|entry| split_folder.join(entry.file))
+            .chain(Some(split_index.clone()))
+            .collect(),
+        false => Vec::new()
+    };
+
+    if split {
+        fs::create_dir_all(&split_folder)?;
+        let mut index = PlaylistIndex::default();
+        for playlist in &playlists.playlists {
+            let file = sanitize(format!(
+                "{} (id={}).json",
+                playlist.title.as_deref().unwrap_or("Untitled"),
+                playlist.id.map_or_else(|| "unknown".into(), |id| id.to_string())
+            ));
+            write_json(playlist, split_folder.join(&file), pretty_print)?;
+
+            stale.retain(|path| !path.ends_with(&file));
+            index.playlists.push(IndexedPlaylist {
+                id: playlist.id,
+                title: playlist.title.clone(),
+                file,
+                num_tracks: playlist_tracks(playlist).count()
+            });
+        }
+        write_json(&index, &split_index, true)?;
+        stale.retain(|path| *path != split_index);
+        stale.push(folder.join("playlists.json"));
+    } else {
+        write_json(playlists, folder.join("playlists.json"), pretty_print)?;
+    }
+
+    for path in stale {
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Loads `liked-playlists.json` from the given archive folder.
+pub fn load_liked_playlists(folder: &Path, strictness: Strictness) -> Result<Playlists, Error> {
+    load_checked(&folder.join(LIKED_PLAYLISTS_FILE), strictness)
+}
+
+/// Loads `me.json` from the given archive folder.
+pub fn load_me(folder: &Path, strictness: Strictness) -> Result<Me, Error> {
+    load_checked(&folder.join("me.json"), strictness)
+}
+
+/// Turns a missing JSON file into `None`, for commands that work with whatever
+/// parts of the archive happen to be present.
+pub fn optional<T>(loaded: Result<T, Error>) -> Result<Option<T>, Error> {
+    match loaded {
+        Ok(v) => Ok(Some(v)),
+        Err(Error::JsonFileNotFound(_)) => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+/// Iterates over every liked track alongside the time it was liked at.
+pub fn liked_tracks(likes: &Likes) -> impl Iterator<Item = (Option<&str>, &TrackInfo)> {
+    likes.collections
+        .iter()
+        .flat_map(|c| c.collection.iter())
+        .filter_map(|like| like.track.as_ref().map(|t| (like.created_at.as_deref(), t)))
+}
+
+/// Keeps only the liked tracks for which `keep` returns true (it's given the
+/// time the track was liked at as well), dropping everything else.
+pub fn retain_likes(likes: &mut Likes, mut keep: impl FnMut(Option<&str>, &TrackInfo) -> bool) {
+    for c in &mut likes.collections {
+        c.collection.retain(|like| match &like.track {
+            Some(track) => keep(like.created_at.as_deref(), track),
+            None => false
+        });
+    }
+}
+
+/// Adds the likes from an earlier zest that aren't among `recent` after them,
+/// so that zesting just the most recent likes updates a snapshot instead of
+/// replacing it. Anything unliked since the earlier zest stays in.
+pub fn merge_likes(recent: &mut Likes, mut earlier: Likes) {
+    let ids: HashSet<u64> = liked_tracks(recent).filter_map(|(_, track)| track.id).collect();
+    retain_likes(&mut earlier, |_, track| track.id.is_none_or(|id| !ids.contains(&id)));
+    recent.collections.extend(earlier.collections.into_iter().filter(|c| !c.collection.is_empty()));
+}
+
+/// Like `merge_likes`, for playlists: those zested again replace their
+/// earlier versions.
+pub fn merge_playlists(recent: &mut Playlists, earlier: Playlists) {
+    let ids: HashSet<u64> = recent.playlists.iter().filter_map(|p| p.id).collect();
+    recent.playlists.extend(earlier.playlists.into_iter().filter(|p| p.id.is_none_or(|id| !ids.contains(&id))));
+}
+
+/// Loads the feed items saved by earlier zests of the stream.
+pub fn load_stream(folder: &Path, strictness: Strictness) -> Result<Vec<Value>, Error> {
+    load_checked(&folder.join(STREAM_FILE), strictness)
+}
+
+/// Adds the feed items from earlier zests after those just zested, as the feed
+/// can't be gone back through once items have dropped off it.
+pub fn merge_stream(recent: &mut Vec<Value>, earlier: Vec<Value>) {
+    let uuids: HashSet<String> = recent.iter().filter_map(stream_uuid).collect();
+    recent.extend(earlier.into_iter().filter(|item| stream_uuid(item).is_none_or(|uuid| !uuids.contains(&uuid))));
+}
+
+fn stream_uuid(item: &Value) -> Option<String> {
+    item.get("uuid").and_then(Value::as_str).map(String::from)
+}
+
+/// Loads a list of users, as saved in `FOLLOWINGS_FILE` and `FOLLOWERS_FILE`.
+pub fn load_users(path: &Path, strictness: Strictness) -> Result<Vec<User>, Error> {
+    load_checked(path, strictness)
+}
+
+/// Loads the plays saved by earlier zests of the listening history.
+pub fn load_history(folder: &Path, strictness: Strictness) -> Result<Vec<PlayedTrack>, Error> {
+    load_checked(&folder.join(HISTORY_FILE), strictness)
+}
+
+/// Adds the plays from earlier zests that are older than those just zested;
+/// SoundCloud only keeps so much history.
+pub fn merge_history(recent: &mut Vec<PlayedTrack>, earlier: Vec<PlayedTrack>) {
+    let oldest = recent.iter().filter_map(|p| p.played_at).min();
+    let none_zested = recent.is_empty();
+    recent.extend(earlier.into_iter().filter(|p| match (p.played_at, oldest) {
+        (Some(at), Some(oldest)) => at < oldest,
+        _ => none_zested
+    }));
+}
+
+/// Iterates over the tracks in the given playlist.
+pub fn playlist_tracks(playlist: &Playlist) -> impl Iterator<Item = &TrackInfo> {
+    playlist.tracks.iter().flatten()
+}
+
+/// The username of the given track's uploader, if known.
+pub fn artist(track: &TrackInfo) -> Option<&str> {
+    track.user.as_ref().and_then(|u| u.username.as_deref())
+}
+
+/// Finds the audio files stored under the given folder, keyed by the track id
+/// embedded in their filenames (`... (id=<id>).m4a`).
+pub fn local_audio(folder: &Path) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
+    let mut found: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut pending = vec![folder.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Some(id) = track_id_from_path(&path) {
+                found.entry(id).or_default().push(path);
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+// Pulls the track id out of a filename like `Title (id=1234).m4a`
+fn track_id_from_path(path: &Path) -> Option<u64> {
+    if path.extension()? != "m4a" {
+        return None;
+    }
+
+    let stem = path.file_stem()?.to_str()?;
+    let start = stem.rfind("(id=")? + "(id=".len();
+    stem[start..].strip_suffix(')')?.parse().ok()
+}
@@ -0,0 +1,377 @@
+//! The archiving itself, for programs embedding it rather than running the
+//! command line.
+//!
+//! An [`Archiver`] holds the clients a run needs. The metadata it fetches is
+//! handed back to the caller to filter, merge and store, using an
+//! [`ArchiveSink`] for wherever the archive lives; audio is downloaded straight
+//! into the archive by a [`TrackSaver`]. Progress goes to an [`Observer`] as it
+//! happens.
+//!
+//! [`ArchiveSink`]: crate::sink::ArchiveSink
+
+use crate::archive;
+use crate::concurrency::{run_workers, split_round_robin, Credentials, Semaphore};
+use crate::download::{download_track, TrackSaver};
+use crate::events::Event;
+use crate::filter::TrackFilter;
+use crate::interrupt;
+use crate::logging;
+use crate::plan::DryRun;
+use crate::pool::PoolEntry;
+use crate::restriction::Restriction;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::space::SpaceCheck;
+use crate::user;
+use crate::Error;
+use orange_zest::api::{Likes, Me, Playlist, Playlists, TrackInfo};
+use orange_zest::Zester;
+
+/// Follows along with what an [`Archiver`] is doing. Everything is ignored
+/// unless implemented.
+pub trait Observer {
+    /// Progress, in the same events `--event-socket` sends.
+    fn event(&self, _event: Event<'_>) {}
+    /// An API request was made, for keeping to a budget.
+    fn api_call(&self) {}
+    /// Something worth knowing that isn't going wrong.
+    fn notice(&self, _message: &str) {}
+    /// Something went wrong that the archiver carried on past.
+    fn warning(&self, _message: &str) {}
+}
+
+/// An [`Observer`] that ignores everything.
+pub struct Quiet;
+
+impl Observer for Quiet {}
+
+/// How [`Archiver::download_likes`] and [`Archiver::download_playlists`]
+/// download audio.
+pub struct AudioOptions<'a> {
+    /// How many of the most recent likes (or playlists) to download
+    pub recent: u64,
+    pub filter: &'a TrackFilter,
+    /// How many tracks to download at once
+    pub download_concurrency: usize,
+    /// How many of those can be looking up their streams at the same time
+    pub api_concurrency: usize,
+    /// Makes sure the audio will fit before any of it's downloaded
+    pub space_check: Option<&'a SpaceCheck<'a>>,
+}
+
+/// Archives an account's likes and playlists, or another user's.
+pub struct Archiver {
+    zester: Zester,
+    api: ApiClient,
+    credentials: Credentials,
+}
+
+impl Archiver {
+    /// Sets up the clients for the given credentials, spreading requests
+    /// over those in the credential pool as well. The credentials aren't
+    /// checked until [`Archiver::check_credentials`] is called or the first
+    /// request is made.
+    pub fn new(credentials: Credentials, credential_pool: Vec<PoolEntry>) -> Result<Self, Error> {
+        let api = ApiClient::new(credentials.oauth_token.clone(), credentials.client_id.clone(), credential_pool);
+        let zester = Zester::new(credentials.oauth_token.clone(), credentials.client_id.clone())?;
+
+        Ok(Self { zester, api, credentials })
+    }
+
+    pub fn check_credentials(&self) -> Result<(), Error> {
+        self.api.check_credentials().map(|_| ())
+    }
+
+    pub fn zester(&self) -> &Zester {
+        &self.zester
+    }
+
+    pub fn api(&self) -> &ApiClient {
+        &self.api
+    }
+
+    pub fn credentials(&self) -> &Credentials {
+        &self.credentials
+    }
+
+    /// Gets the most recent `recent` likes of the account, or of the user
+    /// with the given id.
+    pub fn likes(&self, user_id: Option<u64>, recent: u64, observer: &dyn Observer) -> Result<Likes, Error> {
+        let whose = if user_id.is_some() { Whose::Public } else { Whose::Own };
+        let id = match user_id {
+            Some(id) => id,
+            None => match self.own_count(|me| me.likes_count, observer)? {
+                (_, Some(0)) => return Ok(Likes::default()),
+                (id, Some(count)) => {
+                    observer.event(Event::ItemsToFetch { phase: "likes", count: count.min(recent) });
+                    id
+                },
+                (id, None) => {
+                    observer.notice("SoundCloud didn't say how many likes there are; zesting them without a total");
+                    id
+                }
+            }
+        };
+
+        user::likes(&self.api, id, whose, recent, |count| {
+            observer.api_call();
+            observer.event(Event::ItemsFetched { phase: "likes", count: count as u64 });
+        })
+    }
+
+    /// Gets the most recent `recent` playlists of the account, or of the user
+    /// with the given id, along with all of their tracks.
+    ///
+    /// Playlists are listed first (the `playlists` phase), then their tracks
+    /// are fetched one playlist at a time (`playlist-tracks`). A playlist
+    /// whose tracks can't be fetched is left out with a warning.
+    pub fn playlists(&self, user_id: Option<u64>, recent: u64, observer: &dyn Observer) -> Result<Playlists, Error> {
+        let whose = if user_id.is_some() { Whose::Public } else { Whose::Own };
+        let id = match user_id {
+            Some(id) => id,
+            None => match self.own_count(|me| me.playlist_count, observer)? {
+                (_, Some(0)) => return Ok(Playlists { playlists: Vec::new() }),
+                (id, Some(count)) => {
+                    observer.event(Event::ItemsToFetch { phase: "playlists", count: count.min(recent) });
+                    id
+                },
+                (id, None) => {
+                    observer.notice("SoundCloud didn't say how many playlists there are; zesting them without a total");
+                    id
+                }
+            }
+        };
+
+        let mut playlists = self.api.user_playlists(id, whose, recent, || observer.api_call())?;
+        observer.event(Event::ItemsFetched { phase: "playlists", count: playlists.len() as u64 });
+        // Only what was actually listed gets its tracks fetched
+        observer.event(Event::ItemsToFetch { phase: "playlist-tracks", count: playlists.len() as u64 });
+
+        playlists.retain_mut(|playlist| {
+            observer.event(Event::PlaylistStarted { id: playlist.id, title: playlist.title.as_deref() });
+            let filled = user::fill_tracks(&self.api, playlist, whose, || observer.api_call());
+            let title = playlist.title.as_deref();
+            match &filled {
+                Ok(()) => observer.event(Event::PlaylistFinished { id: playlist.id, title }),
+                Err(e) => {
+                    observer.event(Event::PlaylistFailed { id: playlist.id, title, error: format!("{:?}", e) });
+                    observer.warning(&format!("failed to get info for {}: {:?}", title.unwrap_or("untitled playlist"), e));
+                }
+            }
+            observer.event(Event::ItemsFetched { phase: "playlist-tracks", count: 1 });
+            filled.is_ok()
+        });
+
+        Ok(Playlists { playlists })
+    }
+
+    /// Downloads the audio of the given likes, as `zester json` archived
+    /// them, saving it with `saver`. Given a plan, works out where the audio
+    /// would go instead of downloading anything.
+    ///
+    /// Tracks the filter leaves out, and those saved before an interrupted
+    /// run, are skipped; those already saved from a playlist are linked. Once
+    /// the run's been interrupted it stops between tracks, failing with
+    /// [`Error::Interrupted`] after saving the manifest.
+    pub fn download_likes(
+        &self,
+        saver: &TrackSaver<'_>,
+        mut likes: Likes,
+        opts: &AudioOptions<'_>,
+        plan: Option<&mut DryRun<'_>>,
+        observer: &(dyn Observer + Sync)
+    ) -> Result<(), Error> {
+        let pb = saver.pb;
+
+        // Each track costs an API call to look up its stream
+        let recent = saver.budget.remaining().map_or(opts.recent, |left| opts.recent.min(left));
+        let num_liked = archive::liked_tracks(&likes).count() as u64;
+        let mut num_tracks = 0;
+        let mut num_done = 0;
+        archive::retain_likes(&mut likes, |liked_at, track| {
+            let title = track.title.as_deref().unwrap_or("untitled");
+            if let Some(reason) = opts.filter.skip_reason(liked_at, track) {
+                logging::info(&format!("Skipping {}: {}", title, reason));
+                if Restriction::of(track).is_some() {
+                    saver.summary.restricted(1);
+                }
+                return false;
+            }
+
+            num_tracks += 1;
+            if num_tracks > recent {
+                logging::info(&format!("Skipping {}: past the number of tracks to download", title));
+                return false;
+            }
+            if saver.checkpoint.as_ref().is_some_and(|c| c.is_done(track, None)) {
+                logging::info(&format!("Skipping {}: saved before the run was interrupted", title));
+                num_done += 1;
+                return false;
+            }
+            true
+        });
+        saver.summary.skipped(num_liked - num_tracks.min(recent));
+
+        if let Some(plan) = plan {
+            let namer = saver.namer.lock().unwrap();
+            for (_, track) in archive::liked_tracks(&likes) {
+                plan.add(&namer, track, None)?;
+            }
+            return Ok(());
+        }
+        observer.event(Event::PhaseStarted { phase: "likes" });
+        pb.set_length(num_tracks.min(recent));
+        pb.inc(num_done);
+
+        // Tracks already saved from playlists get linked instead
+        let mut duplicates = Vec::new();
+        archive::retain_likes(&mut likes, |_, track| {
+            if saver.is_duplicate(track) {
+                logging::info(&format!(
+                    "Linking {} instead of downloading it again",
+                    track.title.as_deref().unwrap_or("untitled")
+                ));
+                duplicates.push(track.clone());
+                return false;
+            }
+
+            true
+        });
+        let tracks: Vec<&TrackInfo> = archive::liked_tracks(&likes).map(|(_, track)| track).collect();
+        if let Some(space_check) = opts.space_check {
+            space_check.ensure_room_for(tracks.iter().copied(), pb)?;
+        }
+        if let Some(transfer) = &saver.transfer {
+            transfer.start(tracks.iter().copied());
+        }
+
+        let api_permits = Semaphore::new(opts.api_concurrency);
+        let chunks = split_round_robin(&tracks, opts.download_concurrency);
+        let result = run_workers(&chunks, &self.zester, &self.credentials, |zester, chunk| {
+            chunk.iter().try_for_each(|track| download_track(saver, zester, track, None, &api_permits, observer))
+        });
+        saver.stop_if_interrupted()?;
+        for track in &duplicates {
+            saver.link_duplicate(track, None);
+            pb.inc(1);
+        }
+        saver.save_manifest()?;
+        result?;
+
+        observer.event(Event::PhaseFinished { phase: "likes" });
+        Ok(())
+    }
+
+    /// Downloads the audio of the given playlists like
+    /// [`Archiver::download_likes`], reporting it as `phase`. Only as many
+    /// playlists as fit in what's left of the API call budget are
+    /// downloaded, and each track only the first time it turns up.
+    ///
+    /// How many playlists there are to download is sent as
+    /// [`Event::ItemsToFetch`] before the first of them starts.
+    pub fn download_playlists(
+        &self,
+        saver: &TrackSaver<'_>,
+        mut playlists: Playlists,
+        phase: &str,
+        opts: &AudioOptions<'_>,
+        plan: Option<&mut DryRun<'_>>,
+        observer: &(dyn Observer + Sync)
+    ) -> Result<(), Error> {
+        let pb = saver.pb;
+
+        let num_archived: u64 = playlists.playlists.iter().map(|p| archive::playlist_tracks(p).count() as u64).sum();
+        saver.summary.restricted(opts.filter.retain_playlist_tracks(&mut playlists));
+
+        // Only take as many playlists as fit in what's left of the budget
+        let mut budget_left = saver.budget.remaining().unwrap_or(std::u64::MAX);
+        let selected: Vec<&Playlist> = playlists.playlists.iter().take(opts.recent as usize).take_while(|p| {
+            let num_tracks = archive::playlist_tracks(p).count() as u64;
+            if num_tracks > budget_left {
+                logging::info(&format!(
+                    "Skipping {} and the playlists after it: not enough API calls left in the budget",
+                    p.title.as_deref().unwrap_or("untitled")
+                ));
+                return false;
+            }
+
+            budget_left -= num_tracks;
+            true
+        }).collect();
+
+        if let Some(plan) = plan {
+            let namer = saver.namer.lock().unwrap();
+            for playlist in &selected {
+                for track in archive::playlist_tracks(playlist) {
+                    plan.add(&namer, track, Some(*playlist))?;
+                }
+            }
+            return Ok(());
+        }
+        observer.event(Event::PhaseStarted { phase });
+
+        // Each track is only downloaded the first time it turns up
+        let (mut deduped, duplicates) = saver.split_duplicates(&selected);
+        let mut num_done = 0;
+        if let Some(checkpoint) = &saver.checkpoint {
+            for (copy, playlist) in deduped.iter_mut().zip(&selected) {
+                if let Some(tracks) = &mut copy.tracks {
+                    let before = tracks.len();
+                    tracks.retain(|track| !checkpoint.is_done(track, Some(playlist)));
+                    num_done += (before - tracks.len()) as u64;
+                }
+            }
+        }
+        let to_download: Vec<&Playlist> = deduped.iter().collect();
+        if let Some(space_check) = opts.space_check {
+            space_check.ensure_room_for(to_download.iter().flat_map(|p| archive::playlist_tracks(p)), pb)?;
+        }
+        if let Some(transfer) = &saver.transfer {
+            transfer.start(to_download.iter().flat_map(|p| archive::playlist_tracks(p)));
+        }
+
+        let num_selected = selected.iter().map(|p| archive::playlist_tracks(p).count() as u64).sum();
+        pb.set_length(num_selected);
+        pb.inc(num_done);
+        saver.summary.skipped(num_archived - num_selected);
+        observer.event(Event::ItemsToFetch { phase, count: selected.len() as u64 });
+
+        let api_permits = Semaphore::new(opts.api_concurrency);
+        let chunks = split_round_robin(&to_download, opts.download_concurrency);
+        let result = run_workers(&chunks, &self.zester, &self.credentials, |zester, chunk| {
+            chunk.iter().try_for_each(|playlist| {
+                interrupt::check()?;
+                observer.event(Event::PlaylistStarted { id: playlist.id, title: playlist.title.as_deref() });
+                for track in archive::playlist_tracks(playlist) {
+                    download_track(saver, zester, track, Some(playlist), &api_permits, observer)?;
+                }
+                observer.event(Event::PlaylistFinished { id: playlist.id, title: playlist.title.as_deref() });
+                Ok(())
+            })
+        });
+        saver.stop_if_interrupted()?;
+        for (track, playlist) in duplicates {
+            if !saver.link_duplicate(track, Some(playlist)) {
+                observer.warning(&format!(
+                    "couldn't link {} (in {}): it failed to download elsewhere",
+                    track.title.as_deref().unwrap_or("untitled"),
+                    playlist.title.as_deref().unwrap_or("untitled")
+                ));
+            }
+            pb.inc(1);
+        }
+        saver.save_manifest()?;
+        result?;
+
+        observer.event(Event::PhaseFinished { phase });
+        Ok(())
+    }
+
+    // How many likes or playlists the account has, for a total to show
+    // progress against; new or unusual accounts don't always say
+    fn own_count(&self, count: fn(&Me) -> Option<u64>, observer: &dyn Observer) -> Result<(u64, Option<u64>), Error> {
+        let me = self.api.me()?;
+        observer.api_call();
+        let id = me.id.ok_or_else(|| Error::HttpError("the account has no id".into()))?;
+        Ok((id, count(&me)))
+    }
+}
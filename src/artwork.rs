@@ -0,0 +1,56 @@
+//! Downloading artwork at the best size there is.
+//!
+//! The API links to a 100x100 version of artwork. Bigger ones are at the same
+//! URL with the size swapped out, though not every size exists for every
+//! image, and tracks and playlists without artwork of their own are shown
+//! with their uploader's avatar instead.
+
+use crate::logging;
+use crate::net;
+use serde::Serialize;
+
+/// Where downloaded artwork came from, best first.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtworkSource {
+    /// The image as it was uploaded
+    Original,
+    /// Scaled to 3000x3000
+    T3000,
+    /// Scaled to 500x500
+    T500,
+    /// There was no artwork (that could be downloaded), so the uploader's
+    /// avatar stands in for it
+    UploaderAvatar,
+}
+
+pub struct Artwork {
+    pub url: String,
+    pub source: ArtworkSource,
+    pub response: ureq::Response,
+}
+
+/// Downloads the biggest version of `artwork_url` that's there, falling back
+/// on the uploader's avatar, if any of them can be downloaded.
+pub fn fetch(artwork_url: Option<&str>, avatar_url: Option<&str>) -> Option<Artwork> {
+    let mut candidates = Vec::new();
+    if let Some(url) = artwork_url {
+        if url.contains("-large.") {
+            candidates.push((url.replace("-large.", "-original."), ArtworkSource::Original));
+            candidates.push((url.replace("-large.", "-t3000x3000."), ArtworkSource::T3000));
+        }
+        candidates.push((url.replace("-large.", "-t500x500."), ArtworkSource::T500));
+    }
+    if let Some(url) = avatar_url {
+        candidates.push((url.replace("-large.", "-t500x500."), ArtworkSource::UploaderAvatar));
+    }
+
+    for (url, source) in candidates {
+        let response = net::get(&url).call();
+        if response.ok() {
+            return Some(Artwork { url, source, response });
+        }
+        logging::debug(&format!("No artwork at {}: returned {}", url, response.status()));
+    }
+    None
+}
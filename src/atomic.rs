@@ -0,0 +1,55 @@
+//! Writing files so that they only show up under their real name once they're
+//! complete. A crash partway through leaves a `.partial` file behind instead of
+//! a truncated one that later runs would take for the real thing.
+
+use crate::Error;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Where a file is written before being moved to `path`: next to it, so the
+/// move is a rename within the same filesystem.
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+/// Moves a completely written file from its partial path to `path`, making
+/// sure its contents have reached the disk first.
+pub fn finish(partial: &Path, path: &Path) -> io::Result<()> {
+    OpenOptions::new().write(true).open(partial)?.sync_all()?;
+    fs::rename(partial, path)
+}
+
+/// Like `orange_zest::write_json`, but only replaces what's at `path` once the
+/// new JSON has been written out in full.
+pub fn write_json<T: Serialize, P: AsRef<Path>>(data: &T, path: P, pretty: bool) -> Result<(), Error> {
+    let path = path.as_ref();
+    let partial = partial_path(path);
+    orange_zest::write_json(data, &partial, pretty)?;
+    Ok(finish(&partial, path)?)
+}
+
+/// Like [`write_json`], for files nobody but the user should be able to read.
+/// On Unix the file is created that way, before anything is written to it.
+pub fn write_private_json<T: Serialize>(data: &T, path: &Path) -> Result<(), Error> {
+    let partial = partial_path(path);
+    // The mode only applies when creating the file, not to one left behind
+    match fs::remove_file(&partial) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&partial)?.write_all(&serde_json::to_vec(data).unwrap())?;
+
+    Ok(finish(&partial, path)?)
+}
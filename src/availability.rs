@@ -0,0 +1,156 @@
+//! Checking whether archived tracks can still be found on SoundCloud.
+
+use crate::api_usage::ApiBudget;
+use crate::archive::{self, artist};
+use crate::atomic::write_json;
+use crate::json_check::Strictness;
+use crate::progress::Progress;
+use crate::restriction::Restriction;
+use crate::soundcloud::{ApiClient, TrackStatus};
+use crate::user;
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::api::TrackInfo;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// How many tracks to look up per request
+const BATCH_SIZE: usize = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Availability {
+    Available,
+    /// Gone entirely
+    Deleted,
+    /// Made private by the uploader
+    Private,
+    /// Blocked in the region the check was run from, or by a rights holder
+    GeoBlocked,
+    /// Only a preview can be streamed
+    PreviewOnly,
+    /// Still listed, but can't be streamed
+    NotStreamable,
+}
+
+impl Availability {
+    fn of(status: &TrackStatus) -> Self {
+        match Restriction::from_policy(status.policy.as_deref()) {
+            Some(Restriction::Blocked) => return Availability::GeoBlocked,
+            Some(Restriction::PreviewOnly) => return Availability::PreviewOnly,
+            None => {}
+        }
+
+        if status.sharing.as_deref() == Some("private") {
+            Availability::Private
+        } else if status.streamable == Some(false) {
+            Availability::NotStreamable
+        } else {
+            Availability::Available
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RemovedTrack {
+    pub id: u64,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub permalink_url: Option<String>,
+    pub status: Availability,
+    /// Where the track shows up in the archive ("likes" or a playlist title)
+    pub found_in: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RemovedTracksReport {
+    pub checked_at: DateTime<Utc>,
+    pub tracks_checked: usize,
+    pub tracks: Vec<RemovedTrack>,
+}
+
+/// Checks every track in the archive at `input_folder` against the live API,
+/// writing `removed-tracks.json` to `output_folder`.
+pub fn check(
+    input_folder: &Path,
+    output_folder: &Path,
+    client: &ApiClient,
+    budget: &ApiBudget,
+    pb: &Progress
+) -> Result<RemovedTracksReport, Error> {
+    let likes = archive::optional(archive::load_likes(input_folder, Strictness::Lenient))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, Strictness::Lenient))?;
+
+    let mut tracks: BTreeMap<u64, (&TrackInfo, Vec<String>)> = BTreeMap::new();
+    for (_, track) in likes.iter().flat_map(archive::liked_tracks) {
+        if let Some(id) = track.id {
+            tracks.entry(id).or_insert_with(|| (track, Vec::new())).1.push("likes".into());
+        }
+    }
+    for playlist in playlists.iter().flat_map(|p| p.playlists.iter()) {
+        let playlist_title = playlist.title.clone().unwrap_or_else(|| "untitled playlist".into());
+        for track in archive::playlist_tracks(playlist) {
+            if let Some(id) = track.id {
+                tracks.entry(id).or_insert_with(|| (track, Vec::new())).1.push(playlist_title.clone());
+            }
+        }
+    }
+
+    // Private tracks in the account's own archive are only visible to it
+    let whose = user::whose_archive(input_folder);
+    let ids: Vec<u64> = tracks.keys().copied().collect();
+    pb.set_length(ids.len() as u64);
+
+    let mut removed = Vec::new();
+    for batch in ids.chunks(BATCH_SIZE) {
+        let found: BTreeMap<u64, TrackStatus> = client.tracks::<TrackStatus>(batch, whose)?
+            .into_iter()
+            .map(|t| (t.id, t))
+            .collect();
+        budget.record(1);
+
+        for id in batch {
+            let status = match found.get(id) {
+                Some(status) => Availability::of(status),
+                // Missing from the batch; ask about it directly to find out why
+                None => {
+                    budget.record(1);
+                    match client.track(*id)? {
+                        Ok(status) => Availability::of(&status),
+                        Err(401) | Err(403) => Availability::Private,
+                        Err(_) => Availability::Deleted
+                    }
+                }
+            };
+
+            if status != Availability::Available {
+                let (track, found_in) = &tracks[id];
+                pb.println(format!(
+                    "  [{:?}] {}",
+                    status,
+                    track.title.as_deref().unwrap_or("untitled")
+                ));
+                removed.push(RemovedTrack {
+                    id: *id,
+                    title: track.title.clone(),
+                    artist: artist(track).map(String::from),
+                    permalink_url: track.permalink_url.clone(),
+                    status,
+                    found_in: found_in.clone()
+                });
+            }
+            pb.inc(1);
+        }
+    }
+
+    let report = RemovedTracksReport {
+        checked_at: Utc::now(),
+        tracks_checked: ids.len(),
+        tracks: removed
+    };
+    write_json(&report, output_folder.join("removed-tracks.json"), true)?;
+
+    Ok(report)
+}
@@ -0,0 +1,203 @@
+//! `bundle`: what zester can save about an account, in one go and in one
+//! file.
+//!
+//! Like the daemon, this runs the `json`, `audio` and `verify` commands as
+//! child processes of this executable, then packs what they saved into a
+//! single zip with an HTML index and SHA-256 checksums of everything in it.
+//! The checksums only catch damage; nothing is signed. Comments, messages and
+//! the audio of the account's own uploads aren't saved, and the index says so.
+
+use crate::atomic::write_json;
+use crate::checksum::sha256_file;
+use crate::history;
+use crate::keychain::ProfileOpts;
+use crate::lock::{Lock, LOCK_FILE};
+use crate::soundcloud::{ApiClient, Whose};
+use crate::trash::TRASH_DIR;
+use crate::{ensure_secrets_present, Error};
+use chrono::Utc;
+use dotenv::dotenv;
+use indicatif::HumanBytes;
+use orange_zest::api::Playlists;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use structopt::StructOpt;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// What zester can't get at (yet), listed in the index so that nobody
+/// mistakes the bundle for a complete export
+const NOT_INCLUDED: &[&str] = &["comments", "messages", "audio of your own uploads"];
+
+#[derive(StructOpt, Debug)]
+pub struct BundleOpts {
+    /// OAuth token
+    #[structopt(long)]
+    oauth_token: Option<String>,
+    /// Client ID
+    #[structopt(long)]
+    client_id: Option<String>,
+    #[structopt(flatten)]
+    pub profile: ProfileOpts,
+    /// Folder to save everything into; the bundle is written here too
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    output_folder: PathBuf,
+    /// Only save the JSON, leaving out the audio
+    #[structopt(long)]
+    no_audio: bool,
+}
+
+struct Step {
+    name: &'static str,
+    /// Why the step failed, if it did
+    problem: Option<String>,
+}
+
+pub fn run(opts: BundleOpts) -> Result<(), Error> {
+    dotenv().ok();
+    let (mut oauth_token, mut client_id) = (opts.oauth_token, opts.client_id);
+    ensure_secrets_present(opts.profile.name(), &mut oauth_token, &mut client_id)?;
+    let (oauth_token, client_id) = (oauth_token.unwrap(), client_id.unwrap());
+
+    let folder = opts.output_folder;
+    let (json_folder, audio_folder) = (folder.join("json"), folder.join("audio"));
+    fs::create_dir_all(&json_folder)?;
+    // The children lock the json and audio folders themselves; this keeps a
+    // second bundle from packing the folder while they're being filled
+    let _lock = Lock::acquire(folder.join(LOCK_FILE))?;
+
+    let exe = env::current_exe()?;
+    let zester = |args: &[&str]| -> Option<String> {
+        println!("Running: zester {}", args.join(" "));
+        let status = Command::new(&exe)
+            .args(args)
+            .env("OAUTH_TOKEN", &oauth_token)
+            .env("CLIENT_ID", &client_id)
+            .status();
+
+        match status {
+            Ok(status) if status.success() => None,
+            Ok(status) => Some(format!("exited with {}", status)),
+            Err(e) => Some(format!("failed to start: {}", e))
+        }
+    };
+    let json = json_folder.to_string_lossy();
+    let audio = audio_folder.to_string_lossy();
+
+    let mut steps = Vec::new();
+    steps.push(Step { name: "profile, likes, playlists, reposts, uploads, follows and listening history", problem: zester(&["json", "--all", "--pretty-print", "-o", &json]) });
+    steps.push(Step { name: "uploaded playlists", problem: save_uploaded_playlists(&json_folder, &oauth_token, &client_id).err().map(|e| format!("{:?}", e)) });
+    if !opts.no_audio {
+        let problem = zester(&["audio", "--all", "-i", &json, "-o", &audio]);
+        let downloaded = problem.is_none();
+        steps.push(Step { name: "audio", problem });
+
+        // Verifying what's only half there would just repeat the failure
+        if downloaded {
+            steps.push(Step { name: "verifying audio", problem: zester(&["verify", &audio]) });
+        }
+    }
+
+    let bundle = folder.join(format!("bundle-{}.zip", history::run_id()));
+    let (files, bytes, sha256) = write_bundle(&folder, &bundle, &steps)?;
+    fs::write(
+        bundle.with_extension("zip.sha256"),
+        format!("{}  {}\n", sha256, bundle.file_name().unwrap().to_string_lossy())
+    )?;
+
+    for step in steps.iter().filter(|step| step.problem.is_some()) {
+        println!("  [warning] {}: {}", step.name, step.problem.as_ref().unwrap());
+    }
+    println!("Bundled {} files ({}) into {}", files, HumanBytes(bytes), bundle.display());
+    println!("  sha256 {}", sha256);
+    Ok(())
+}
+
+// The playlists the account has made, as opposed to those it's liked
+fn save_uploaded_playlists(json_folder: &Path, oauth_token: &str, client_id: &str) -> Result<(), Error> {
+    let client = ApiClient::new(oauth_token.to_string(), client_id.to_string(), Vec::new());
+    let me = client.check_credentials()?;
+    let user_id = me.id.ok_or_else(|| Error::HttpError("the account has no id".into()))?;
+
+    let playlists = Playlists { playlists: client.user_playlists(user_id, Whose::Own, std::u64::MAX, || {})? };
+    write_json(&playlists, json_folder.join("uploaded-playlists.json"), true)
+}
+
+// Zips up everything in `folder` (other than earlier bundles, lock files and
+// the trash) along with an index and a list of checksums, returning the number of files,
+// their size and the bundle's checksum
+fn write_bundle(folder: &Path, bundle: &Path, steps: &[Step]) -> Result<(usize, u64, String), Error> {
+    let mut paths = Vec::new();
+    let mut pending = vec![folder.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let name = path.file_name().unwrap().to_string_lossy();
+
+            if path.is_dir() {
+                if name != TRASH_DIR {
+                    pending.push(path);
+                }
+            } else if name != LOCK_FILE && !(dir == folder && name.starts_with("bundle-")) {
+                paths.push(path);
+            }
+        }
+    }
+    paths.sort();
+
+    let mut zip = ZipWriter::new(File::create(bundle)?);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let (mut sums, mut bytes) = (String::new(), 0);
+    let mut listed = Vec::new();
+    for path in &paths {
+        let name = path.strip_prefix(folder).unwrap().to_string_lossy().replace('\\', "/");
+        // Audio is compressed already
+        let options = if name.ends_with(".m4a") { stored } else { deflated };
+
+        zip.start_file(name.as_str(), options)?;
+        let size = io::copy(&mut File::open(path)?, &mut zip)?;
+        sums.push_str(&format!("{}  {}\n", sha256_file(path)?, name));
+        bytes += size;
+        listed.push((name, size));
+    }
+
+    zip.start_file("index.html", deflated)?;
+    zip.write_all(index_html(&listed, steps).as_bytes())?;
+    zip.start_file("SHA256SUMS", deflated)?;
+    zip.write_all(sums.as_bytes())?;
+
+    zip.finish()?;
+    Ok((listed.len(), bytes, sha256_file(bundle)?))
+}
+
+fn index_html(files: &[(String, u64)], steps: &[Step]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>SoundCloud bundle</title></head>\n<body>\n"
+    );
+    html.push_str(&format!("<h1>SoundCloud bundle</h1>\n<p>Made {}.</p>\n", Utc::now().format("%Y-%m-%d %H:%M UTC")));
+
+    html.push_str("<h2>What was saved</h2>\n<ul>\n");
+    for step in steps {
+        match &step.problem {
+            None => html.push_str(&format!("<li>{}</li>\n", escape(step.name))),
+            Some(problem) => html.push_str(&format!("<li>{} (failed: {})</li>\n", escape(step.name), escape(problem)))
+        }
+    }
+    html.push_str(&format!("</ul>\n<p>Not included: {}.</p>\n", NOT_INCLUDED.join(", ")));
+
+    html.push_str("<h2>Files</h2>\n<p>Checksums are in <a href=\"SHA256SUMS\">SHA256SUMS</a>; they aren't signed.</p>\n<ul>\n");
+    for (name, size) in files {
+        html.push_str(&format!("<li><a href=\"{0}\">{0}</a> ({1})</li>\n", escape(name), HumanBytes(*size)));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
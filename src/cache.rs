@@ -0,0 +1,148 @@
+//! Keeping API responses on disk between runs, so that runs close together
+//! don't fetch unchanged metadata all over again.
+//!
+//! Responses are kept in the state folder, keyed by the URL requested and the
+//! token it was requested with. For a while after being fetched they're used
+//! as they are; after that they're revalidated with `If-None-Match` or `If-Modified-Since`
+//! when the API sent an `ETag` or `Last-Modified`, and fetched again otherwise.
+//!
+//! This covers every metadata request, the account's own likes and playlists
+//! included; audio is never cached.
+
+use crate::atomic::write_json;
+use crate::checksum::sha256_bytes;
+use crate::locale;
+use crate::logging;
+use crate::state::state_dir;
+use crate::Error;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use structopt::StructOpt;
+
+/// The folder (in the state folder) responses are kept in
+const CACHE_DIR: &str = "cache";
+
+/// How long a response is used without asking the API whether it's changed
+pub const FRESH_FOR_MINUTES: i64 = 15;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+#[derive(StructOpt, Debug)]
+pub enum CacheCommand {
+    /// Delete every cached API response
+    Clear,
+}
+
+/// A response kept from an earlier request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Cached {
+    pub url: String,
+    pub fetched_at: DateTime<Utc>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: Value,
+}
+
+impl Cached {
+    /// Whether the response is recent enough to be used without asking the
+    /// API about it.
+    pub fn is_fresh(&self) -> bool {
+        Utc::now() - self.fetched_at < Duration::minutes(FRESH_FOR_MINUTES)
+    }
+
+    /// Asks for the response only if it's changed since it was cached.
+    pub fn revalidate(&self, request: &mut ureq::Request) {
+        if let Some(etag) = &self.etag {
+            request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request.set("If-Modified-Since", last_modified);
+        }
+    }
+
+    /// Whether the API can be asked if the response has changed, rather than
+    /// sending all of it again.
+    pub fn can_revalidate(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
+
+/// Turns caching on or off for the rest of the run; it's off until this is
+/// called.
+pub fn init(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+/// The response cached for `url` when requested by `account` (an OAuth
+/// token), if caching's on and there is one.
+pub fn load(url: &str, account: Option<&str>) -> Option<Cached> {
+    if !enabled() {
+        return None;
+    }
+
+    // A damaged entry is as good as a missing one; it's fetched again
+    let contents = fs::read(path(url, account).ok()?).ok()?;
+    serde_json::from_slice::<Cached>(&contents).ok().filter(|cached| cached.url == url)
+}
+
+/// Keeps a response for later runs, if caching's on. Failing to is only
+/// logged; the run doesn't need it.
+pub fn store(cached: &Cached, account: Option<&str>) {
+    if !enabled() {
+        return;
+    }
+
+    let stored = path(&cached.url, account).map_err(Error::from).and_then(|path| write_json(cached, path, false));
+    if let Err(e) = stored {
+        logging::info(&format!("Couldn't cache the response from {}: {:?}", cached.url, e));
+    }
+}
+
+fn path(url: &str, account: Option<&str>) -> io::Result<PathBuf> {
+    let dir = state_dir()?.join(CACHE_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let key = sha256_bytes(format!("{}\n{}", account.unwrap_or(""), url).as_bytes());
+    Ok(dir.join(format!("{}.json", key)))
+}
+
+pub fn run(command: CacheCommand) -> Result<(), Error> {
+    match command {
+        CacheCommand::Clear => {
+            let dir = state_dir()?.join(CACHE_DIR);
+            let (mut count, mut bytes) = (0, 0);
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    println!("The cache is already empty");
+                    return Ok(());
+                },
+                Err(e) => return Err(e.into())
+            };
+
+            for entry in entries {
+                let entry = entry?;
+                bytes += entry.metadata()?.len();
+                fs::remove_file(entry.path())?;
+                count += 1;
+            }
+            println!(
+                "Deleted {} cached responses ({}) from {}",
+                locale::number(count),
+                locale::bytes(bytes),
+                dir.display()
+            );
+        }
+    }
+
+    Ok(())
+}
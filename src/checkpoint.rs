@@ -0,0 +1,102 @@
+//! Remembering which tracks an audio run has finished, so that running the
+//! same command again after a crash or interrupt picks up where it left off.
+
+use crate::atomic::write_json;
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::api::{Playlist, TrackInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const CHECKPOINT_FILE: &str = ".zester-state.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Stored {
+    /// The arguments of the run, which a later run has to match to resume it
+    command: Vec<String>,
+    updated_at: DateTime<Utc>,
+    /// `likes/<track id>` or `playlists/<playlist id>/<track id>`
+    completed: BTreeSet<String>,
+}
+
+/// The tracks finished so far by an unfinished run of the current command,
+/// kept up to date in the output folder as more are finished.
+pub struct Checkpoint {
+    path: PathBuf,
+    command: Vec<String>,
+    completed: Mutex<BTreeSet<String>>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint left in the output folder by an unfinished run of
+    /// the same command, or starts a new one.
+    pub fn load(output_folder: &Path) -> Result<Self, Error> {
+        let path = output_folder.join(CHECKPOINT_FILE);
+        let command: Vec<String> = env::args().skip(1).collect();
+
+        let completed = match fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice::<Stored>(&bytes) {
+                Ok(stored) if stored.command == command => {
+                    println!(
+                        "Resuming the run interrupted at {} ({} tracks already done)",
+                        stored.updated_at.format("%Y-%m-%d %H:%M"),
+                        stored.completed.len()
+                    );
+                    stored.completed
+                },
+                Ok(_) => {
+                    println!("Starting over: the unfinished run in {} was of a different command", output_folder.display());
+                    BTreeSet::new()
+                },
+                Err(e) => {
+                    println!("  [warning] ignoring damaged {}: {}", path.display(), e);
+                    BTreeSet::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e.into())
+        };
+
+        Ok(Self { path, command, completed: Mutex::new(completed) })
+    }
+
+    /// Whether the given track was finished by the run being resumed.
+    pub fn is_done(&self, track: &TrackInfo, playlist: Option<&Playlist>) -> bool {
+        key(track, playlist).is_some_and(|key| self.completed.lock().unwrap().contains(&key))
+    }
+
+    /// Records that the given track is finished.
+    pub fn complete(&self, track: &TrackInfo, playlist: Option<&Playlist>) -> Result<(), Error> {
+        let key = match key(track, playlist) {
+            Some(key) => key,
+            None => return Ok(())
+        };
+
+        let mut completed = self.completed.lock().unwrap();
+        completed.insert(key);
+
+        let stored = Stored { command: self.command.clone(), updated_at: Utc::now(), completed: completed.clone() };
+        write_json(&stored, &self.path, false)
+    }
+
+    /// Removes the checkpoint once the run has gone all the way through.
+    pub fn clear(&self) -> Result<(), Error> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(())
+        }
+    }
+}
+
+fn key(track: &TrackInfo, playlist: Option<&Playlist>) -> Option<String> {
+    let id = track.id?;
+    match playlist {
+        Some(playlist) => Some(format!("playlists/{}/{}", playlist.id?, id)),
+        None => Some(format!("likes/{}", id))
+    }
+}
@@ -0,0 +1,96 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Computes the hex-encoded SHA-256 digest of the file at the given path.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Computes the hex-encoded SHA-256 digest of the given bytes.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Wraps a writer, hashing and counting everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    bytes: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            bytes: 0
+        }
+    }
+
+    /// Returns the number of bytes written and their hex-encoded SHA-256 digest.
+    pub fn finish(self) -> (u64, String) {
+        (self.bytes, format!("{:x}", self.hasher.finalize()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.bytes += written as u64;
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Size of the blocks read by `sampled_sha256`
+const SAMPLE_BLOCK: u64 = 1024 * 1024;
+/// Number of blocks `sampled_sha256` reads from the middle of a file
+const SAMPLE_MIDDLE_BLOCKS: u64 = 4;
+
+/// Computes a hex-encoded SHA-256 digest over a sample of the file at the given
+/// path: its length, its first and last MiB, and a few MiB-sized blocks in
+/// between.
+///
+/// The blocks in between are picked pseudo-randomly from the file's length, so
+/// the same file always gets the same sample. Small files are hashed in full.
+pub fn sampled_sha256<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    sampled_sha256_of(file, len)
+}
+
+/// Like `sampled_sha256`, for `len` bytes read from anywhere that can seek.
+pub fn sampled_sha256_of<R: Read + Seek>(mut file: R, len: u64) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(len.to_le_bytes());
+
+    if len <= SAMPLE_BLOCK * (SAMPLE_MIDDLE_BLOCKS + 2) {
+        io::copy(&mut file, &mut hasher)?;
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+
+    let mut offsets = vec![0, len - SAMPLE_BLOCK];
+    let mut state = len;
+    for _ in 0..SAMPLE_MIDDLE_BLOCKS {
+        // A 64-bit LCG is plenty for spreading samples around
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        offsets.push((state >> 11) % (len - SAMPLE_BLOCK));
+    }
+
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        io::copy(&mut (&mut file).take(SAMPLE_BLOCK), &mut hasher)?;
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
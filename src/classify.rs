@@ -0,0 +1,55 @@
+//! Telling music apart from spoken-word content (podcasts, talk shows,
+//! audiobooks) using the metadata in the JSON archive.
+
+use crate::filter::parse_tag_list;
+use orange_zest::api::TrackInfo;
+
+/// Genres and tags that mark a track as spoken word
+const SPOKEN_TERMS: &[&str] = &[
+    "podcast", "podcasts", "talk", "talk show", "spoken word", "spoken", "audiobook",
+    "audiobooks", "audio book", "interview", "lecture", "sermon", "news", "politics",
+    "comedy", "storytelling", "education", "radio show", "speech",
+];
+
+/// Genres and tags that mark a long track as a mix rather than a show
+const MIX_TERMS: &[&str] = &["mix", "dj mix", "mixtape", "live set", "set", "dj set", "techno", "house"];
+
+/// Words in titles that suggest a show
+const SPOKEN_TITLE_WORDS: &[&str] = &["podcast", "episode", "ep.", "interview", "talk", "lecture", "sermon"];
+
+/// Tracks longer than this lean towards being shows
+const LONG_TRACK_MS: u64 = 45 * 60 * 1000;
+
+/// Guesses whether the given track is spoken word rather than music.
+pub fn is_spoken(track: &TrackInfo) -> bool {
+    spoken_score(track) >= 2
+}
+
+fn spoken_score(track: &TrackInfo) -> i32 {
+    let mut terms: Vec<String> = track.tag_list
+        .as_deref()
+        .map(parse_tag_list)
+        .unwrap_or_default();
+    terms.extend(track.genre.clone());
+    let terms: Vec<String> = terms.iter().map(|t| t.trim().to_lowercase()).collect();
+    let has_term = |list: &[&str]| terms.iter().any(|t| list.contains(&t.as_str()));
+
+    let mut score = 0;
+    if has_term(SPOKEN_TERMS) {
+        score += 2;
+    }
+
+    let title = track.title.as_deref().unwrap_or_default().to_lowercase();
+    if SPOKEN_TITLE_WORDS.iter().any(|w| title.split_whitespace().any(|t| t == *w)) {
+        score += 1;
+    }
+
+    if track.duration.is_some_and(|d| d >= LONG_TRACK_MS) {
+        score += 1;
+    }
+    if has_term(MIX_TERMS) {
+        score -= 2;
+    }
+
+    score
+}
@@ -0,0 +1,131 @@
+//! Watching the clipboard for SoundCloud links and downloading whatever they
+//! point to into an inbox folder.
+
+use crate::api_usage::ApiBudget;
+use crate::concurrency::Credentials;
+use crate::download::{download_loose_tracks, TrackSaver};
+use crate::events::EventFeed;
+use crate::naming::{FolderLayout, Namer};
+use crate::progress::Progress;
+use crate::soundcloud::ApiClient;
+use crate::Error;
+use orange_zest::Zester;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// Commands that print the clipboard's contents, tried in order
+#[cfg(target_os = "macos")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(windows)]
+const PASTE_COMMANDS: &[&[&str]] = &[&["powershell", "-NoProfile", "-Command", "Get-Clipboard"]];
+#[cfg(not(any(target_os = "macos", windows)))]
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+
+// Finds a way of reading the clipboard that works here
+fn paste_command() -> Option<&'static [&'static str]> {
+    PASTE_COMMANDS.iter().copied().find(|cmd| {
+        Command::new(cmd[0]).args(&cmd[1..]).output().is_ok_and(|o| o.status.success())
+    })
+}
+
+fn read_clipboard(cmd: &[&str]) -> Option<String> {
+    let output = Command::new(cmd[0]).args(&cmd[1..]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Pulls SoundCloud track and playlist links out of the given text.
+pub fn soundcloud_urls(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .map(|word| word.trim_matches(|c: char| c == '<' || c == '>' || c == '"' || c == '\''))
+        .filter(|word| {
+            let rest = word.trim_start_matches("https://").trim_start_matches("http://");
+            let rest = rest.trim_start_matches("www.").trim_start_matches("m.");
+            rest.starts_with("soundcloud.com/") || rest.starts_with("on.soundcloud.com/")
+        })
+        .map(|word| {
+            // Drop tracking parameters so the same link isn't queued twice
+            let word = word.split('?').next().unwrap();
+            if word.starts_with("http") {
+                word.to_string()
+            } else {
+                format!("https://{}", word)
+            }
+        })
+        .collect()
+}
+
+/// Checks the clipboard every `interval`, downloading the tracks behind any new
+/// SoundCloud links into `inbox`. Runs until interrupted.
+pub fn watch(
+    inbox: &Path,
+    interval: Duration,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    let paste = paste_command().ok_or_else(|| Error::IoError(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!(
+            "couldn't read the clipboard; install one of: {}",
+            PASTE_COMMANDS.iter().map(|c| c[0]).collect::<Vec<_>>().join(", ")
+        )
+    )))?;
+    fs::create_dir_all(inbox)?;
+
+    let budget = ApiBudget::new("clipboard", None);
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::Flat, filename: None };
+    let saver = TrackSaver::new(inbox, namer, api_client, &budget, &events, pb)?;
+
+    // Whatever's on the clipboard when we start was copied before we were
+    // asked to watch it
+    let mut seen: HashSet<String> = read_clipboard(paste)
+        .map(|text| soundcloud_urls(&text).into_iter().collect())
+        .unwrap_or_default();
+    pb.set_message(&format!("Watching the clipboard, saving to {}", inbox.display()));
+
+    loop {
+        thread::sleep(interval);
+        saver.stop_if_interrupted()?;
+        let text = match read_clipboard(paste) {
+            Some(text) => text,
+            None => continue
+        };
+
+        for url in soundcloud_urls(&text) {
+            if !seen.insert(url.clone()) {
+                continue;
+            }
+
+            pb.println(format!("  [queued] {}", url));
+            let tracks = match api_client.resolve_tracks(&url, || budget.record(1)) {
+                Ok(tracks) => tracks,
+                Err(e) => {
+                    pb.println(format!("  [warning] couldn't look up {}: {:?}", url, e));
+                    continue;
+                }
+            };
+
+            match download_loose_tracks(&saver, tracks, 1, zester, credentials) {
+                Err(Error::Interrupted) => return Err(Error::Interrupted),
+                Err(e) => pb.println(format!("  [warning] failed to download {}: {:?}", url, e)),
+                Ok(()) => {}
+            }
+            saver.save_manifest()?;
+            pb.set_message(&format!("Watching the clipboard, saving to {}", inbox.display()));
+        }
+    }
+}
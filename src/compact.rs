@@ -0,0 +1,140 @@
+//! Deduplicating an existing archive in place by hardlinking identical audio.
+
+use crate::checksum::sha256_file;
+use crate::lock::{Lock, LOCK_FILE};
+use crate::manifest::{FileEntry, Manifest, MANIFEST_FILE};
+use crate::trash::Trash;
+use crate::Error;
+use indicatif::HumanBytes;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct CompactOpts {
+    /// Archive folder containing a manifest.json
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+    /// Report what would be reclaimed without touching any files
+    #[structopt(long)]
+    dry_run: bool,
+    /// Where to move duplicates before linking over them (defaults to .zester-trash in the archive)
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    trash_dir: Option<PathBuf>,
+}
+
+pub fn run(opts: CompactOpts) -> Result<(), Error> {
+    let folder = opts.folder;
+    if !folder.join(MANIFEST_FILE).exists() {
+        return Err(Error::JsonFileNotFound(folder.join(MANIFEST_FILE).to_string_lossy().into()));
+    }
+    let _lock = Lock::acquire(folder.join(LOCK_FILE))?;
+    let mut manifest = Manifest::load(&folder)?;
+    let mut trash = Trash::new(&folder, opts.trash_dir.as_deref(), "compact");
+
+    // Offloaded files live elsewhere and can't be linked to
+    let mut by_checksum: BTreeMap<String, Vec<&mut FileEntry>> = BTreeMap::new();
+    for entry in manifest.tracks
+        .values_mut()
+        .flat_map(|t| t.files.iter_mut().chain(t.previous_versions.iter_mut()))
+        .filter(|f| f.offloaded_to.is_none())
+    {
+        by_checksum.entry(entry.sha256.clone()).or_default().push(entry);
+    }
+
+    let (mut linked, mut restored, mut reclaimed) = (0, 0, 0);
+    for (sha256, entries) in by_checksum.iter_mut().filter(|(_, e)| e.len() > 1) {
+        // Link everything to the first copy that's still intact
+        let canonical = match entries.iter().position(|e| is_intact(&folder.join(&e.path), sha256)) {
+            Some(i) => folder.join(&entries[i].path),
+            None => continue
+        };
+
+        for entry in entries.iter_mut() {
+            let path = folder.join(&entry.path);
+            if path == canonical || same_file(&path, &canonical) {
+                continue;
+            }
+
+            if !path.exists() {
+                // The manifest says this copy should be here; put it back
+                if !opts.dry_run {
+                    link_over(&canonical, &path)?;
+                }
+                println!("  [restored] {}", entry.path);
+                restored += 1;
+            } else if is_intact(&path, sha256) {
+                if !opts.dry_run {
+                    replace_with_link(&canonical, &path, &mut trash)?;
+                }
+                println!("  [linked] {}", entry.path);
+                linked += 1;
+                reclaimed += entry.bytes;
+            } else {
+                // Leave damaged files for `verify` to report rather than
+                // quietly papering over them
+                println!("  [skipped] {} doesn't match its checksum", entry.path);
+            }
+        }
+    }
+
+    if !opts.dry_run {
+        manifest.save(&folder)?;
+    }
+    trash.report();
+
+    println!(
+        "{} {} duplicate files, {} {} missing copies, reclaiming {}",
+        if opts.dry_run { "Would link" } else { "Linked" },
+        linked,
+        if opts.dry_run { "would restore" } else { "restored" },
+        restored,
+        HumanBytes(reclaimed)
+    );
+    Ok(())
+}
+
+fn is_intact(path: &Path, sha256: &str) -> bool {
+    sha256_file(path).is_ok_and(|actual| actual == sha256)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(_: &Path, _: &Path) -> bool {
+    false
+}
+
+// Moves `path` to the trash and puts a hardlink to `target` in its place. The
+// link is made first, so that there's nothing to undo if that fails
+fn replace_with_link(target: &Path, path: &Path, trash: &mut Trash) -> Result<(), Error> {
+    let tmp = path.with_extension("zester-link");
+    let _ = fs::remove_file(&tmp);
+    fs::hard_link(target, &tmp)?;
+
+    trash.discard(path)?;
+    Ok(fs::rename(&tmp, path)?)
+}
+
+// Replaces `path` with a hardlink to `target`, without leaving `path` missing
+// if linking fails part way
+fn link_over(target: &Path, path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let tmp = path.with_extension("zester-link");
+    let _ = fs::remove_file(&tmp);
+    fs::hard_link(target, &tmp)?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,131 @@
+//! Pieces for running downloads on several threads at once.
+
+use crate::Error;
+use orange_zest::Zester;
+use std::collections::HashSet;
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, ThreadId};
+
+/// A counting semaphore that hands out at most one permit per thread.
+///
+/// Permits are tracked per thread so that an event stream missing its closing
+/// event can't leak one; `release_for_thread` is always safe to call.
+pub struct Semaphore {
+    state: Mutex<Permits>,
+    available: Condvar,
+}
+
+struct Permits {
+    available: usize,
+    // The threads holding one of this semaphore's permits
+    holders: HashSet<ThreadId>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(Permits { available: permits.max(1), holders: HashSet::new() }),
+            available: Condvar::new()
+        }
+    }
+
+    /// Blocks until a permit is available and takes it, unless the current
+    /// thread already holds one.
+    pub fn acquire_for_thread(&self) {
+        let id = thread::current().id();
+        let mut state = self.state.lock().unwrap();
+        if state.holders.contains(&id) {
+            return;
+        }
+
+        while state.available == 0 {
+            state = self.available.wait(state).unwrap();
+        }
+        state.available -= 1;
+        state.holders.insert(id);
+    }
+
+    /// Gives back the current thread's permit, if it holds one.
+    pub fn release_for_thread(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.holders.remove(&thread::current().id()) {
+            return;
+        }
+
+        state.available += 1;
+        self.available.notify_one();
+    }
+}
+
+/// Credentials used to create a zester for each worker thread.
+#[derive(Clone)]
+pub struct Credentials {
+    pub oauth_token: String,
+    pub client_id: String,
+}
+
+/// Runs `work` over each of the given chunks, each on its own thread with its
+/// own zester.
+///
+/// A single chunk is worked on using the given zester on the current thread.
+pub fn run_workers<T, F>(chunks: &[T], zester: &Zester, credentials: &Credentials, work: F) -> Result<(), Error>
+where
+    T: Sync,
+    F: Fn(&Zester, &T) -> Result<(), Error> + Sync
+{
+    if chunks.len() <= 1 {
+        for chunk in chunks {
+            work(zester, chunk)?;
+        }
+        return Ok(());
+    }
+
+    thread::scope(|s| {
+        let workers: Vec<_> = chunks
+            .iter()
+            .map(|chunk| {
+                let work = &work;
+                s.spawn(move || -> Result<(), Error> {
+                    let zester = Zester::new(credentials.oauth_token.clone(), credentials.client_id.clone())?;
+                    work(&zester, chunk)?;
+                    Ok(())
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .try_for_each(|w| w.join().expect("download worker panicked"))
+    })
+}
+
+/// Deals the given items out round-robin into at most `parts` chunks.
+pub fn split_round_robin<T: Clone>(items: &[T], parts: usize) -> Vec<Vec<T>> {
+    let parts = parts.max(1).min(items.len().max(1));
+    let mut chunks = vec![Vec::new(); parts];
+    for (i, item) in items.iter().enumerate() {
+        chunks[i % parts].push(item.clone());
+    }
+
+    chunks.retain(|c| !c.is_empty());
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_tracked_per_semaphore() {
+        let (api, cdn) = (Semaphore::new(1), Semaphore::new(1));
+        api.acquire_for_thread();
+        cdn.acquire_for_thread();
+        assert_eq!(api.state.lock().unwrap().available, 0);
+        assert_eq!(cdn.state.lock().unwrap().available, 0);
+
+        api.release_for_thread();
+        api.release_for_thread();
+        assert_eq!(api.state.lock().unwrap().available, 1);
+        assert_eq!(cdn.state.lock().unwrap().available, 0);
+    }
+}
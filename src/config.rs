@@ -0,0 +1,374 @@
+//! `orange-zester.toml`, where options that would otherwise have to be passed
+//! on every run can live. Anything given on the command line wins.
+
+use crate::pool::PoolEntry;
+use crate::state::{state_dir, STATE_DIR_VAR};
+use crate::{filter, sanitize, space, throttle, Error, Opts};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::clap;
+
+pub const CONFIG_FILE: &str = "orange-zester.toml";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// A `.env`-style file to read `OAUTH_TOKEN` and `CLIENT_ID` from, so the
+    /// credentials themselves needn't be kept in the config
+    pub credentials: Option<PathBuf>,
+    /// More client ids (and optionally tokens) to move on to when SoundCloud
+    /// rate limits requests
+    pub credential_pool: Vec<PoolEntry>,
+    json: JsonConfig,
+    audio: AudioConfig,
+    panic: PanicConfig,
+    clipboard_watch: ClipboardWatchConfig,
+    /// Named sets of options, picked with `--profile`
+    profiles: BTreeMap<String, ProfileConfig>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// A profile's credentials and sections replace the top-level ones when it's
+/// picked; anything it leaves out is shared.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ProfileConfig {
+    credentials: Option<PathBuf>,
+    credential_pool: Option<Vec<PoolEntry>>,
+    json: Option<JsonConfig>,
+    audio: Option<AudioConfig>,
+    panic: Option<PanicConfig>,
+    clipboard_watch: Option<ClipboardWatchConfig>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct JsonConfig {
+    output_folder: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct AudioConfig {
+    output_folder: Option<PathBuf>,
+    input_folder: Option<PathBuf>,
+    naming_script: Option<PathBuf>,
+    filename_template: Option<String>,
+    organize_by: Option<String>,
+    api_concurrency: Option<usize>,
+    download_concurrency: Option<usize>,
+    limit_rate: Option<String>,
+    min_free_space: Option<String>,
+    filters: FilterConfig,
+}
+
+/// The same filters `zester audio` takes; dates and durations are written the
+/// same way as on the command line.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct FilterConfig {
+    artists: Vec<String>,
+    since: Option<String>,
+    until: Option<String>,
+    genres: Vec<String>,
+    tags: Vec<String>,
+    min_duration: Option<String>,
+    max_duration: Option<String>,
+    playlists: Vec<String>,
+    exclude_playlists: Vec<String>,
+    skip_spoken: bool,
+    only_spoken: bool,
+    include_restricted: bool,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct PanicConfig {
+    output_folder: Option<PathBuf>,
+    concurrency: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+struct ClipboardWatchConfig {
+    output_folder: Option<PathBuf>,
+}
+
+impl Config {
+    /// Loads `orange-zester.toml` from the current folder, or failing that from
+    /// the user's config folder (`$XDG_CONFIG_HOME` on Linux). Having neither
+    /// is the same as having an empty one.
+    pub fn load() -> Result<Self, Error> {
+        let path = match find() {
+            Some(path) => path,
+            None => return Ok(Self::default())
+        };
+
+        let mut config: Config = toml::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| Error::ConfigError(format!("{}: {}", path.display(), e)))?;
+        config.resolve_paths(path.parent().unwrap());
+        config.path = path;
+
+        Ok(config)
+    }
+
+    /// Switches to the named profile's options, keeping its state apart from
+    /// other profiles' unless a state folder was set explicitly.
+    pub fn select_profile(&mut self, name: &str) -> Result<(), Error> {
+        let profile = self.profiles.remove(name).ok_or_else(|| Error::ConfigError(format!(
+            "no profile named {} in {}",
+            name,
+            if self.path.as_os_str().is_empty() { CONFIG_FILE.into() } else { self.path.display().to_string() }
+        )))?;
+
+        self.credentials = profile.credentials.or_else(|| self.credentials.take());
+        self.credential_pool = profile.credential_pool.unwrap_or_else(|| std::mem::take(&mut self.credential_pool));
+        self.json = profile.json.unwrap_or_else(|| std::mem::take(&mut self.json));
+        self.audio = profile.audio.unwrap_or_else(|| std::mem::take(&mut self.audio));
+        self.panic = profile.panic.unwrap_or_else(|| std::mem::take(&mut self.panic));
+        self.clipboard_watch = profile.clipboard_watch.unwrap_or_else(|| std::mem::take(&mut self.clipboard_watch));
+
+        if env::var_os(STATE_DIR_VAR).is_none() {
+            env::set_var(STATE_DIR_VAR, state_dir()?.join("profiles").join(sanitize(name)));
+        }
+        Ok(())
+    }
+
+    // Relative paths in the config are relative to the file, not to wherever
+    // zester happens to be run from
+    fn resolve_paths(&mut self, dir: &Path) {
+        resolve(dir, &mut self.credentials);
+        self.json.resolve_paths(dir);
+        self.audio.resolve_paths(dir);
+        self.panic.resolve_paths(dir);
+        self.clipboard_watch.resolve_paths(dir);
+
+        for profile in self.profiles.values_mut() {
+            resolve(dir, &mut profile.credentials);
+            profile.json.iter_mut().for_each(|c| c.resolve_paths(dir));
+            profile.audio.iter_mut().for_each(|c| c.resolve_paths(dir));
+            profile.panic.iter_mut().for_each(|c| c.resolve_paths(dir));
+            profile.clipboard_watch.iter_mut().for_each(|c| c.resolve_paths(dir));
+        }
+    }
+
+    /// Fills in whatever the command line left out, exiting with a usage error
+    /// if a required option is missing from both.
+    pub fn apply(self, opt: &mut Opts) -> Result<(), Error> {
+        let Config { json, audio, panic, clipboard_watch, path, .. } = self;
+        let config_err = |msg: String| Error::ConfigError(format!("{}: {}", path.display(), msg));
+
+        match opt {
+            Opts::Json { output_folder, output, .. } => {
+                fill(output_folder, json.output_folder);
+                if output.is_none() {
+                    require(output_folder, "--output-folder");
+                }
+            },
+            Opts::Audio {
+                output_folder,
+                input_folder,
+                naming_script,
+                filename_template,
+                organize_by,
+                api_concurrency,
+                download_concurrency,
+                limit_rate,
+                min_free_space,
+                artists,
+                since,
+                until,
+                genres,
+                tags,
+                min_duration,
+                max_duration,
+                playlists,
+                excluded_playlists,
+                skip_spoken,
+                only_spoken,
+                include_restricted,
+                ..
+            } => {
+                fill(output_folder, audio.output_folder);
+                fill(input_folder, audio.input_folder);
+                require(output_folder, "--output-folder");
+                require(input_folder, "--input-folder");
+
+                // Naming options conflict with each other on the command line;
+                // don't mix one given there with another from the config
+                if naming_script.is_none() && filename_template.is_none() && organize_by.is_none() {
+                    *naming_script = audio.naming_script;
+                    *filename_template = audio.filename_template;
+                    *organize_by = audio.organize_by;
+                }
+                fill(api_concurrency, audio.api_concurrency);
+                fill(download_concurrency, audio.download_concurrency);
+                // Each download looks up its own stream, so there can't be
+                // more lookups going than downloads
+                let (api, download) = (api_concurrency.unwrap_or(1), download_concurrency.unwrap_or(1));
+                if api > download {
+                    clap::Error::with_description(
+                        &format!("--api-concurrency ({}) can't be more than --download-concurrency ({})", api, download),
+                        clap::ErrorKind::ArgumentConflict
+                    ).exit();
+                }
+                if limit_rate.is_none() {
+                    *limit_rate = audio.limit_rate.as_deref().map(throttle::parse_rate).transpose().map_err(&config_err)?;
+                }
+                if min_free_space.is_none() {
+                    *min_free_space = audio.min_free_space.as_deref().map(space::parse_size).transpose().map_err(&config_err)?;
+                }
+
+                let filters = audio.filters;
+                fill_list(artists, filters.artists);
+                fill_list(genres, filters.genres);
+                fill_list(tags, filters.tags);
+                fill_list(playlists, filters.playlists);
+                fill_list(excluded_playlists, filters.exclude_playlists);
+                if since.is_none() {
+                    *since = filters.since.as_deref().map(filter::parse_since).transpose().map_err(&config_err)?;
+                }
+                if until.is_none() {
+                    *until = filters.until.as_deref().map(filter::parse_until).transpose().map_err(&config_err)?;
+                }
+                if min_duration.is_none() {
+                    *min_duration = filters.min_duration.as_deref().map(filter::parse_duration).transpose().map_err(&config_err)?;
+                }
+                if max_duration.is_none() {
+                    *max_duration = filters.max_duration.as_deref().map(filter::parse_duration).transpose().map_err(&config_err)?;
+                }
+                if !*skip_spoken && !*only_spoken {
+                    if filters.skip_spoken && filters.only_spoken {
+                        return Err(config_err("skip-spoken and only-spoken can't both be set".into()));
+                    }
+                    *skip_spoken = filters.skip_spoken;
+                    *only_spoken = filters.only_spoken;
+                }
+                *include_restricted |= filters.include_restricted;
+            },
+            Opts::Panic { output_folder, concurrency, .. } => {
+                fill(output_folder, panic.output_folder);
+                fill(concurrency, panic.concurrency);
+                require(output_folder, "--output-folder");
+            },
+            Opts::ClipboardWatch { output_folder, .. } => {
+                fill(output_folder, clipboard_watch.output_folder);
+                require(output_folder, "--output-folder");
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl JsonConfig {
+    fn resolve_paths(&mut self, dir: &Path) {
+        resolve(dir, &mut self.output_folder);
+    }
+}
+
+impl AudioConfig {
+    fn resolve_paths(&mut self, dir: &Path) {
+        resolve(dir, &mut self.output_folder);
+        resolve(dir, &mut self.input_folder);
+        resolve(dir, &mut self.naming_script);
+    }
+}
+
+impl PanicConfig {
+    fn resolve_paths(&mut self, dir: &Path) {
+        resolve(dir, &mut self.output_folder);
+    }
+}
+
+impl ClipboardWatchConfig {
+    fn resolve_paths(&mut self, dir: &Path) {
+        resolve(dir, &mut self.output_folder);
+    }
+}
+
+fn resolve(dir: &Path, path: &mut Option<PathBuf>) {
+    if let Some(path) = path {
+        *path = dir.join(&path);
+    }
+}
+
+/// Where the config file is, if there is one: `orange-zester.toml` in the
+/// current folder, or failing that in the user's config folder.
+fn find() -> Option<PathBuf> {
+    let local = env::current_dir().ok()?.join(CONFIG_FILE);
+    if local.is_file() {
+        return Some(local);
+    }
+
+    dirs::config_dir().map(|dir| dir.join(CONFIG_FILE)).filter(|path| path.is_file())
+}
+
+/// Prints the profiles defined in the config file.
+pub fn list_profiles() -> Result<(), Error> {
+    let config = Config::load()?;
+    if config.profiles.is_empty() {
+        match find() {
+            Some(path) => println!("No profiles defined in {}", path.display()),
+            None => println!("No {} found in the current folder or the config folder", CONFIG_FILE)
+        }
+        return Ok(());
+    }
+
+    println!("Profiles in {}:", config.path.display());
+    for (name, profile) in &config.profiles {
+        let mut details = Vec::new();
+        if let Some(credentials) = &profile.credentials {
+            details.push(format!("credentials from {}", credentials.display()));
+        }
+        if let Some(pool) = profile.credential_pool.as_ref().filter(|p| !p.is_empty()) {
+            details.push(format!("{} pooled credentials", pool.len()));
+        }
+        let folders = [
+            ("json", profile.json.as_ref().and_then(|c| c.output_folder.as_ref())),
+            ("audio", profile.audio.as_ref().and_then(|c| c.output_folder.as_ref())),
+            ("panic", profile.panic.as_ref().and_then(|c| c.output_folder.as_ref())),
+            ("clipboard-watch", profile.clipboard_watch.as_ref().and_then(|c| c.output_folder.as_ref())),
+        ];
+        for (command, folder) in folders.iter() {
+            if let Some(folder) = folder {
+                details.push(format!("{} into {}", command, folder.display()));
+            }
+        }
+
+        if details.is_empty() {
+            println!("  {}", name);
+        } else {
+            println!("  {} ({})", name, details.join(", "));
+        }
+    }
+
+    Ok(())
+}
+
+fn fill<T>(arg: &mut Option<T>, configured: Option<T>) {
+    if arg.is_none() {
+        *arg = configured;
+    }
+}
+
+fn fill_list<T>(arg: &mut Vec<T>, configured: Vec<T>) {
+    if arg.is_empty() {
+        *arg = configured;
+    }
+}
+
+// Exits the same way clap does when a required argument is missing
+fn require<T>(arg: &Option<T>, flag: &str) {
+    if arg.is_none() {
+        clap::Error::with_description(
+            &format!("{} wasn't given and isn't set in {}", flag, CONFIG_FILE),
+            clap::ErrorKind::MissingRequiredArgument
+        ).exit();
+    }
+}
@@ -0,0 +1,424 @@
+//! Running zester commands on a schedule, with a JSON-RPC control socket for
+//! steering the daemon while it runs.
+//!
+//! The daemon runs one or more profiles, each with its own schedule, command
+//! lines, credentials and state folder. Each cycle of a profile runs its
+//! command lines as child processes of this executable, one after another;
+//! cycles follow an interval or a cron expression, as with `watch`.
+
+use crate::schedule::{parse_schedule, signal, Runner, Schedule};
+use crate::state::state_dir;
+use crate::{ensure_secrets_present, Error};
+use chrono::{DateTime, Local, Utc};
+use dotenv::dotenv;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct DaemonOpts {
+    /// When to run cycles: a time between them (e.g. 30m, 6h) or a cron
+    /// expression (e.g. "0 */6 * * *", in local time)
+    #[structopt(long, parse(try_from_str = parse_schedule), default_value = "6h", value_name = "schedule")]
+    every: Schedule,
+    /// Accept JSON-RPC control requests on a Unix socket at this path
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    control_socket: Option<PathBuf>,
+    /// A zester command line to run each cycle, such as "json -o archive --all"
+    /// (repeatable; run in order; arguments are split on whitespace)
+    #[structopt(
+        long = "run",
+        required_unless = "config",
+        conflicts_with = "config",
+        number_of_values = 1,
+        value_name = "command"
+    )]
+    runs: Vec<String>,
+    /// JSON file defining several profiles to run, instead of --run and --every
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    config: Option<PathBuf>,
+}
+
+/// The daemon's config file.
+#[derive(Deserialize, Debug)]
+struct DaemonConfig {
+    profiles: BTreeMap<String, ProfileConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ProfileConfig {
+    /// Time between cycles (e.g. `30m`, `6h`) or a cron expression
+    every: String,
+    /// Command lines to run each cycle; filters and destinations go here
+    runs: Vec<String>,
+    /// Passed as `--max-api-calls` to every `json` and `audio` run
+    #[serde(default)]
+    max_api_calls: Option<u64>,
+    /// Defaults to the daemon's own credentials
+    #[serde(default)]
+    oauth_token: Option<String>,
+    #[serde(default)]
+    client_id: Option<String>,
+    /// Where runs keep their state; defaults to `profiles/<name>` in the
+    /// daemon's state folder
+    #[serde(default)]
+    state_dir: Option<PathBuf>,
+}
+
+struct Profile {
+    every: Schedule,
+    runs: Vec<String>,
+    oauth_token: String,
+    client_id: String,
+    /// `None` to share the daemon's state folder
+    state_dir: Option<PathBuf>,
+    shared: Arc<Shared>,
+}
+
+/// What a profile is up to, shared with the control socket.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ProfileStatus {
+    pub paused: bool,
+    pub cycles_completed: u64,
+    pub last_cycle_finished_at: Option<DateTime<Utc>>,
+    /// `ok`, or what went wrong during the last cycle
+    pub last_cycle_result: Option<String>,
+    /// The command line currently being run
+    pub running: Option<String>,
+    /// Passed as `--max-api-calls` to every `json` and `audio` run
+    pub max_api_calls_per_run: Option<u64>,
+    #[serde(skip)]
+    running_pid: Option<u32>,
+    #[serde(skip)]
+    trigger_requested: bool,
+}
+
+/// State shared between a profile's scheduling loop and the control socket.
+#[derive(Default)]
+pub struct Shared {
+    status: Mutex<ProfileStatus>,
+    /// Signalled whenever `status` changes in a way the scheduler cares about
+    changed: Condvar,
+}
+
+impl Shared {
+    pub fn status(&self) -> ProfileStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Starts a cycle now rather than waiting for the next scheduled one.
+    pub fn trigger(&self) {
+        self.status.lock().unwrap().trigger_requested = true;
+        self.changed.notify_all();
+    }
+
+    /// Stops starting new cycles, suspending whatever is running.
+    pub fn pause(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.paused = true;
+        if let Some(pid) = status.running_pid {
+            signal(pid, "STOP");
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.paused = false;
+        if let Some(pid) = status.running_pid {
+            signal(pid, "CONT");
+        }
+        drop(status);
+        self.changed.notify_all();
+    }
+
+    /// Takes effect from the next run onwards.
+    pub fn set_max_api_calls(&self, max: Option<u64>) {
+        self.status.lock().unwrap().max_api_calls_per_run = max;
+    }
+}
+
+/// Every profile the daemon runs, by name.
+pub type Profiles = BTreeMap<String, Arc<Shared>>;
+
+pub fn run(opts: DaemonOpts) -> Result<(), Error> {
+    let profiles = match &opts.config {
+        Some(path) => load_profiles(path)?,
+        None => {
+            let (oauth_token, client_id) = daemon_credentials()?;
+            let mut profiles = BTreeMap::new();
+            profiles.insert("default".to_string(), Profile {
+                every: opts.every,
+                runs: opts.runs,
+                oauth_token,
+                client_id,
+                state_dir: None,
+                shared: Default::default()
+            });
+            profiles
+        }
+    };
+
+    let exe = env::current_exe()?;
+    let mut control_handles = Profiles::new();
+    let mut workers = Vec::new();
+    for (name, profile) in profiles {
+        control_handles.insert(name.clone(), profile.shared.clone());
+
+        let runner = Runner::new(exe.clone(), profile.oauth_token.clone(), profile.client_id.clone())
+            .state_dir(profile.state_dir.clone());
+        workers.push(thread::spawn(move || loop {
+            run_cycle(&name, &profile, &runner);
+            wait_for_next_cycle(&profile.shared, profile.every.next_after(Local::now()));
+        }));
+    }
+
+    if let Some(path) = opts.control_socket {
+        control::listen(path, Arc::new(control_handles))?;
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+// Children would otherwise each ask for these on the terminal
+pub(crate) fn daemon_credentials() -> Result<(String, String), Error> {
+    dotenv().ok();
+    let (mut oauth_token, mut client_id) = (None, None);
+    ensure_secrets_present(None, &mut oauth_token, &mut client_id)?;
+
+    Ok((oauth_token.unwrap(), client_id.unwrap()))
+}
+
+fn load_profiles(path: &Path) -> Result<BTreeMap<String, Profile>, Error> {
+    let config: DaemonConfig = orange_zest::load_json(path)
+        .map_err(|e| crate::specific_json_err(e, path.to_string_lossy().into()))?;
+    let config_err = |msg: String| Error::ConfigError(format!("{}: {}", path.display(), msg));
+
+    // Only ask for the daemon's own credentials if some profile needs them
+    let needs_default = config.profiles.values().any(|p| p.oauth_token.is_none() || p.client_id.is_none());
+    let defaults = if needs_default { Some(daemon_credentials()?) } else { None };
+
+    let mut profiles = BTreeMap::new();
+    for (name, profile) in config.profiles {
+        let every = parse_schedule(&profile.every).map_err(|e| config_err(format!("profile {}: {}", name, e)))?;
+        if profile.runs.is_empty() {
+            return Err(config_err(format!("profile {} has nothing to run", name)));
+        }
+
+        let status = ProfileStatus {
+            max_api_calls_per_run: profile.max_api_calls,
+            ..Default::default()
+        };
+        profiles.insert(name.clone(), Profile {
+            every,
+            runs: profile.runs,
+            oauth_token: profile.oauth_token.unwrap_or_else(|| defaults.as_ref().unwrap().0.clone()),
+            client_id: profile.client_id.unwrap_or_else(|| defaults.as_ref().unwrap().1.clone()),
+            state_dir: Some(match profile.state_dir {
+                Some(dir) => dir,
+                None => state_dir()?.join("profiles").join(crate::sanitize(&name))
+            }),
+            shared: Arc::new(Shared {
+                status: Mutex::new(status),
+                changed: Condvar::new()
+            })
+        });
+    }
+
+    Ok(profiles)
+}
+
+fn run_cycle(name: &str, profile: &Profile, runner: &Runner) {
+    let shared = &profile.shared;
+    let mut result = Ok(());
+    for run in &profile.runs {
+        let mut args: Vec<String> = run.split_whitespace().map(String::from).collect();
+        let max_api_calls = shared.status().max_api_calls_per_run;
+        if let (Some(max), Some("json" | "audio")) = (max_api_calls, args.first().map(String::as_str)) {
+            args.extend(vec!["--max-api-calls".into(), max.to_string()]);
+        }
+
+        println!(
+            "[{}] [{}] running: zester {}",
+            Utc::now().format("%Y-%m-%d %H:%M:%S"),
+            name,
+            args.join(" ")
+        );
+        result = runner.run(&args, |pid| {
+            let mut status = shared.status.lock().unwrap();
+            status.running = pid.map(|_| run.clone());
+            status.running_pid = pid;
+        });
+        if result.is_err() {
+            break;
+        }
+    }
+
+    if let Err(e) = &result {
+        eprintln!("  [warning] [{}] {}", name, e);
+    }
+    let mut status = shared.status.lock().unwrap();
+    status.cycles_completed += 1;
+    status.last_cycle_finished_at = Some(Utc::now());
+    status.last_cycle_result = Some(result.err().unwrap_or_else(|| "ok".into()));
+}
+
+// Sleeps until `next` or a triggered cycle, whichever comes first, and for as
+// long as the profile is paused
+fn wait_for_next_cycle(shared: &Shared, next: DateTime<Local>) {
+    let mut status = shared.status.lock().unwrap();
+    loop {
+        let left = (next - Local::now()).to_std().unwrap_or_default();
+        if !status.paused && (status.trigger_requested || left.is_zero()) {
+            status.trigger_requested = false;
+            return;
+        }
+
+        let timeout = if status.paused { Duration::from_secs(3600) } else { left };
+        status = shared.changed.wait_timeout(status, timeout).unwrap().0;
+    }
+}
+
+#[cfg(unix)]
+mod control {
+    //! The JSON-RPC 2.0 control socket: one request per line, one response per
+    //! line.
+    //!
+    //! Methods: `status`, `trigger`, `pause`, `resume` and `set_rate_limit`
+    //! (params: `{"max_api_calls": <n or null>}`). Each takes an optional
+    //! `profile` param; without one, it applies to every profile.
+
+    use super::{Profiles, Shared};
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Deserialize)]
+    struct Request {
+        #[serde(default)]
+        id: Value,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    }
+
+    pub fn listen(path: PathBuf, profiles: Arc<Profiles>) -> io::Result<()> {
+        // Clear out a socket left behind by a daemon that didn't exit cleanly
+        if fs::symlink_metadata(&path).is_ok() && UnixStream::connect(&path).is_err() {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let profiles = profiles.clone();
+                thread::spawn(move || serve(stream, &profiles));
+            }
+        });
+
+        Ok(())
+    }
+
+    fn serve(stream: UnixStream, profiles: &Profiles) {
+        let mut writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => line,
+                Err(_) => return
+            };
+
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => match handle(&request, profiles) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+                    Err((code, message)) => json!({
+                        "jsonrpc": "2.0",
+                        "id": request.id,
+                        "error": { "code": code, "message": message }
+                    })
+                },
+                Err(e) => json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": -32700, "message": e.to_string() }
+                })
+            };
+
+            if writeln!(writer, "{}", response).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn handle(request: &Request, profiles: &Profiles) -> Result<Value, (i64, String)> {
+        let selected: Vec<(&String, &Shared)> = match request.params.get("profile") {
+            None | Some(Value::Null) => profiles.iter().map(|(n, s)| (n, s.as_ref())).collect(),
+            Some(Value::String(name)) => match profiles.get_key_value(name) {
+                Some((n, s)) => vec![(n, s.as_ref())],
+                None => return Err((-32602, format!("no profile named `{}`", name)))
+            },
+            Some(_) => return Err((-32602, "profile must be a string".into()))
+        };
+
+        match request.method.as_str() {
+            "status" => Ok(selected
+                .iter()
+                .map(|(name, shared)| (name.to_string(), serde_json::to_value(shared.status()).unwrap()))
+                .collect::<serde_json::Map<_, _>>()
+                .into()),
+            "trigger" => {
+                selected.iter().for_each(|(_, s)| s.trigger());
+                Ok(json!(true))
+            },
+            "pause" => {
+                selected.iter().for_each(|(_, s)| s.pause());
+                Ok(json!(true))
+            },
+            "resume" => {
+                selected.iter().for_each(|(_, s)| s.resume());
+                Ok(json!(true))
+            },
+            "set_rate_limit" => {
+                let max = match request.params.get("max_api_calls").cloned().unwrap_or(Value::Null) {
+                    Value::Null => None,
+                    Value::Number(n) if n.is_u64() => n.as_u64(),
+                    _ => return Err((-32602, "max_api_calls must be a non-negative integer or null".into()))
+                };
+                selected.iter().for_each(|(_, s)| s.set_max_api_calls(max));
+                Ok(json!(true))
+            },
+            other => Err((-32601, format!("unknown method `{}`", other)))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod control {
+    use super::Profiles;
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    pub fn listen(path: PathBuf, _: Arc<Profiles>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("cannot listen on {}: control sockets need Unix domain sockets", path.display())
+        ))
+    }
+}
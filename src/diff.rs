@@ -0,0 +1,227 @@
+//! Comparing two JSON archives of the same account.
+
+use crate::archive::{self, artist};
+use crate::json_check::Strictness;
+use crate::{Error, OutputFormat};
+use orange_zest::api::{Likes, Playlist, Playlists, TrackInfo};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct DiffOpts {
+    /// Folder containing the older JSON archive
+    #[structopt(parse(from_os_str))]
+    old_folder: PathBuf,
+    /// Folder containing the newer JSON archive
+    #[structopt(parse(from_os_str))]
+    new_folder: PathBuf,
+    /// How to print the differences
+    #[structopt(
+        long,
+        possible_values = &OutputFormat::variants(),
+        case_insensitive = true,
+        default_value = "Text"
+    )]
+    format: OutputFormat,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct TrackSummary {
+    id: u64,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistSummary {
+    id: u64,
+    title: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistChange {
+    id: u64,
+    title: Option<String>,
+    tracks_added: Vec<TrackSummary>,
+    tracks_removed: Vec<TrackSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct LikesDiff {
+    added: Vec<TrackSummary>,
+    removed: Vec<TrackSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct PlaylistsDiff {
+    created: Vec<PlaylistSummary>,
+    deleted: Vec<PlaylistSummary>,
+    changed: Vec<PlaylistChange>,
+}
+
+/// Sections are `None` when either archive is missing the relevant file.
+#[derive(Serialize, Debug)]
+struct ArchiveDiff {
+    likes: Option<LikesDiff>,
+    playlists: Option<PlaylistsDiff>,
+}
+
+impl TrackSummary {
+    fn of(track: &TrackInfo) -> Option<Self> {
+        Some(Self {
+            id: track.id?,
+            title: track.title.clone(),
+            artist: artist(track).map(String::from)
+        })
+    }
+}
+
+impl std::fmt::Display for TrackSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} - {} (id={})",
+            self.artist.as_deref().unwrap_or("unknown artist"),
+            self.title.as_deref().unwrap_or("untitled"),
+            self.id
+        )
+    }
+}
+
+// Tracks present in `new` but not `old`
+fn added(old: &BTreeMap<u64, TrackSummary>, new: &BTreeMap<u64, TrackSummary>) -> Vec<TrackSummary> {
+    new.iter()
+        .filter(|(id, _)| !old.contains_key(id))
+        .map(|(_, t)| t.clone())
+        .collect()
+}
+
+fn track_map<'a>(tracks: impl Iterator<Item = &'a TrackInfo>) -> BTreeMap<u64, TrackSummary> {
+    tracks.filter_map(TrackSummary::of).map(|t| (t.id, t)).collect()
+}
+
+fn diff_likes(old: &Likes, new: &Likes) -> LikesDiff {
+    let old = track_map(archive::liked_tracks(old).map(|(_, t)| t));
+    let new = track_map(archive::liked_tracks(new).map(|(_, t)| t));
+
+    LikesDiff {
+        added: added(&old, &new),
+        removed: added(&new, &old)
+    }
+}
+
+fn playlists_by_id(playlists: &Playlists) -> BTreeMap<u64, &Playlist> {
+    playlists.playlists.iter().filter_map(|p| p.id.map(|id| (id, p))).collect()
+}
+
+fn diff_playlists(old: &Playlists, new: &Playlists) -> PlaylistsDiff {
+    let summary = |id: u64, p: &Playlist| PlaylistSummary { id, title: p.title.clone() };
+    let (old, new) = (playlists_by_id(old), playlists_by_id(new));
+
+    let mut diff = PlaylistsDiff {
+        created: Vec::new(),
+        deleted: Vec::new(),
+        changed: Vec::new()
+    };
+    for (id, playlist) in &new {
+        match old.get(id) {
+            None => diff.created.push(summary(*id, playlist)),
+            Some(old_playlist) => {
+                let old_tracks = track_map(archive::playlist_tracks(old_playlist));
+                let new_tracks = track_map(archive::playlist_tracks(playlist));
+                let change = PlaylistChange {
+                    id: *id,
+                    title: playlist.title.clone(),
+                    tracks_added: added(&old_tracks, &new_tracks),
+                    tracks_removed: added(&new_tracks, &old_tracks)
+                };
+
+                if !change.tracks_added.is_empty() || !change.tracks_removed.is_empty() {
+                    diff.changed.push(change);
+                }
+            }
+        }
+    }
+    for (id, playlist) in &old {
+        if !new.contains_key(id) {
+            diff.deleted.push(summary(*id, playlist));
+        }
+    }
+
+    diff
+}
+
+pub fn run(opts: DiffOpts) -> Result<(), Error> {
+    let old_likes = archive::optional(archive::load_likes(&opts.old_folder, Strictness::Lenient))?;
+    let new_likes = archive::optional(archive::load_likes(&opts.new_folder, Strictness::Lenient))?;
+    let old_playlists = archive::optional(archive::load_playlists(&opts.old_folder, Strictness::Lenient))?;
+    let new_playlists = archive::optional(archive::load_playlists(&opts.new_folder, Strictness::Lenient))?;
+
+    let diff = ArchiveDiff {
+        likes: match (&old_likes, &new_likes) {
+            (Some(old), Some(new)) => Some(diff_likes(old, new)),
+            _ => None
+        },
+        playlists: match (&old_playlists, &new_playlists) {
+            (Some(old), Some(new)) => Some(diff_playlists(old, new)),
+            _ => None
+        }
+    };
+
+    match opts.format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&diff).unwrap()),
+        OutputFormat::Text => print_text(&diff)
+    }
+
+    Ok(())
+}
+
+fn print_text(diff: &ArchiveDiff) {
+    match &diff.likes {
+        Some(likes) => {
+            println!("Likes: {} added, {} removed", likes.added.len(), likes.removed.len());
+            for track in &likes.added {
+                println!("  + {}", track);
+            }
+            for track in &likes.removed {
+                println!("  - {}", track);
+            }
+        },
+        None => println!("Likes: not present in both archives")
+    }
+
+    match &diff.playlists {
+        Some(playlists) => {
+            println!(
+                "Playlists: {} created, {} deleted, {} changed",
+                playlists.created.len(),
+                playlists.deleted.len(),
+                playlists.changed.len()
+            );
+            for playlist in &playlists.created {
+                println!("  + {} (id={})", playlist.title.as_deref().unwrap_or("untitled"), playlist.id);
+            }
+            for playlist in &playlists.deleted {
+                println!("  - {} (id={})", playlist.title.as_deref().unwrap_or("untitled"), playlist.id);
+            }
+            for change in &playlists.changed {
+                println!(
+                    "  ~ {} (id={}): {} added, {} removed",
+                    change.title.as_deref().unwrap_or("untitled"),
+                    change.id,
+                    change.tracks_added.len(),
+                    change.tracks_removed.len()
+                );
+                for track in &change.tracks_added {
+                    println!("      + {}", track);
+                }
+                for track in &change.tracks_removed {
+                    println!("      - {}", track);
+                }
+            }
+        },
+        None => println!("Playlists: not present in both archives")
+    }
+}
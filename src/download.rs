@@ -0,0 +1,636 @@
+//! Saving downloaded audio to disk (or uploading it to an `--output`), along
+//! with everything recorded about it.
+
+use crate::api_usage::ApiBudget;
+use crate::archiver::Observer;
+use crate::atomic;
+use crate::checkpoint::Checkpoint;
+use crate::checksum::{self, HashingWriter};
+use crate::concurrency::{run_workers, split_round_robin, Credentials, Semaphore};
+use crate::events::{Event, EventFeed};
+use crate::hook::Hook;
+use crate::interrupt::{self, Interruptible};
+use crate::logging;
+use crate::pace;
+use crate::manifest::{self, Manifest, Replacement, MANIFEST_FILE};
+use crate::net::Deadline;
+use crate::naming::{Namer, TrackContext};
+use crate::offload::make_symlink;
+use crate::progress::{Progress, Transfer};
+use crate::restriction::Restriction;
+use crate::sidecar::{self, SidecarOptions};
+use crate::sink::ArchiveSink;
+use crate::soundcloud::ApiClient;
+use crate::summary::{RunSummary, FAILURES_FILE};
+use crate::throttle::{RateLimiter, Throttled};
+use crate::Error;
+use indicatif::HumanBytes;
+use orange_zest::api::{Like, Likes, LikesCollection, Playlist, TrackInfo};
+use orange_zest::events::TracksAudioZestingEvent;
+use orange_zest::Zester;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use structopt::clap::arg_enum;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum DedupMode {
+        Hardlink,
+        Symlink,
+        Off
+    }
+}
+
+/// Everything needed to put a downloaded track where it belongs.
+///
+/// Shared between download threads.
+pub struct TrackSaver<'a> {
+    pub output_folder: &'a Path,
+    pub namer: Mutex<Namer>,
+    pub manifest: Mutex<Manifest>,
+    pub replacements: Mutex<Vec<Replacement>>,
+    pub sidecar_opts: SidecarOptions,
+    pub api_client: &'a ApiClient,
+    pub budget: &'a ApiBudget,
+    pub events: &'a EventFeed,
+    pub pb: &'a Progress,
+    /// How tracks that turn up in several places are stored in all but the
+    /// first
+    pub dedup: DedupMode,
+    /// Where each track saved during this run went, relative to the output
+    /// folder
+    pub saved: Mutex<HashMap<u64, PathBuf>>,
+    /// Bytes not downloaded thanks to deduplication
+    pub dedup_bytes: AtomicU64,
+    pub summary: RunSummary,
+    /// Records finished tracks so an interrupted run can be resumed
+    pub checkpoint: Option<Checkpoint>,
+    /// Caps how fast audio is downloaded, across all downloads
+    pub rate_limit: Option<RateLimiter>,
+    /// Counts the bytes downloaded, to show download speeds and how long the
+    /// rest should take
+    pub transfer: Option<Transfer>,
+    /// Where audio and the manifest are uploaded instead of being kept in the
+    /// output folder, which still gets everything else
+    pub sink: Option<&'a dyn ArchiveSink>,
+    /// Run for each track once it's saved
+    pub after_track: Option<Hook>,
+    /// Run once everything's done
+    pub after_run: Option<Hook>,
+}
+
+impl<'a> TrackSaver<'a> {
+    /// Sets up a saver with no sidecars or deduplication, loading the
+    /// manifest from the output folder.
+    pub fn new(
+        output_folder: &'a Path,
+        namer: Namer,
+        api_client: &'a ApiClient,
+        budget: &'a ApiBudget,
+        events: &'a EventFeed,
+        pb: &'a Progress
+    ) -> Result<Self, Error> {
+        interrupt::stop_cleanly();
+        Ok(Self {
+            output_folder,
+            namer: Mutex::new(namer),
+            manifest: Mutex::new(Manifest::open(output_folder)?),
+            replacements: Mutex::new(Vec::new()),
+            sidecar_opts: SidecarOptions::default(),
+            api_client,
+            budget,
+            events,
+            pb,
+            dedup: DedupMode::Off,
+            saved: Mutex::new(HashMap::new()),
+            dedup_bytes: AtomicU64::new(0),
+            summary: RunSummary::default(),
+            checkpoint: None,
+            rate_limit: None,
+            transfer: None,
+            sink: None,
+            after_track: None,
+            after_run: None
+        })
+    }
+
+    // Asks the namer where a track should go (relative to the output folder)
+    // and makes sure the folder it's going into exists.
+    fn track_output_path(&self, ctx: &TrackContext) -> Result<PathBuf, Error> {
+        let relative = self.namer.lock().unwrap().track_path(ctx)?;
+        if let Some(parent) = self.output_folder.join(&relative).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Ok(relative)
+    }
+
+    /// Writes the given track's audio to disk and records it in the manifest,
+    /// printing warnings for anything that goes wrong.
+    pub fn save(&self, track: &TrackInfo, playlist: Option<&Playlist>, data: impl Read + Send + 'static) {
+        {
+            let _writing = interrupt::writing();
+            // Throttling's waits happen outside of the deadline's reads
+            let data = Interruptible(Throttled::new(Deadline::new(data), self.rate_limit.as_ref()));
+            match &self.transfer {
+                Some(transfer) => self.write_track(track, playlist, transfer.track(track, self.pb, data)),
+                None => self.write_track(track, playlist, data)
+            }
+        }
+    }
+
+    fn write_track(&self, track: &TrackInfo, playlist: Option<&Playlist>, data: impl Read) {
+        let pb = self.pb;
+        let title = track.title.as_deref().unwrap_or("untitled");
+        let kind = if playlist.is_some() { "playlists" } else { "likes" };
+        let ctx = TrackContext::new(kind, track, playlist);
+
+        let relative = match self.track_output_path(&ctx) {
+            Ok(relative) => relative,
+            Err(e) => {
+                match playlist {
+                    Some(playlist) => pb.println(format!(
+                        "  [warning] failed to name {} (in {}): {:?}",
+                        title,
+                        playlist.title.as_deref().unwrap_or("untitled"),
+                        e
+                    )),
+                    None => pb.println(format!("  [warning] failed to name {}: {:?}", title, e))
+                }
+                self.fail(track, playlist, format!("failed to name track: {:?}", e));
+                return;
+            }
+        };
+        let output_file = self.output_folder.join(&relative);
+
+        let preserved = self.manifest.lock().unwrap().preserve_replaced(self.output_folder, track, &relative);
+        match preserved {
+            Ok(Some(replacement)) => {
+                pb.println(format!(
+                    "  [notice] audio for {} was replaced; kept the old version at {}",
+                    title,
+                    replacement.previous_path
+                ));
+                self.replacements.lock().unwrap().push(replacement);
+            },
+            Ok(None) => {},
+            Err(e) => pb.println(format!("  [warning] failed to preserve old audio for {}: {:?}", title, e))
+        }
+
+        // Don't write through a link left behind by `offload`
+        if fs::symlink_metadata(&output_file).is_ok_and(|m| m.file_type().is_symlink()) {
+            let _ = fs::remove_file(&output_file);
+        }
+
+        let written = match self.sink {
+            Some(sink) => upload_track(sink, &manifest::manifest_path(&relative), title, pb, data),
+            None => stream_track_to_file(&output_file, title, pb, data)
+                .map(|(bytes, sha256)| (bytes, sha256, checksum::sampled_sha256(&output_file).ok()))
+        };
+        if let Some((bytes, sha256, sampled)) = written {
+            self.summary.downloaded(bytes);
+            if let Err(e) = self.manifest.lock().unwrap().record(track, &relative, bytes, sha256, sampled) {
+                pb.println(format!("  [warning] failed to record {} in the manifest: {:?}", title, e));
+            }
+            if let Some(id) = track.id {
+                self.saved.lock().unwrap().entry(id).or_insert_with(|| relative.clone());
+            }
+            if let Some(checkpoint) = &self.checkpoint {
+                if let Err(e) = checkpoint.complete(track, playlist) {
+                    pb.println(format!("  [warning] failed to update the checkpoint for {}: {:?}", title, e));
+                }
+            }
+            self.events.emit(Event::TrackSaved {
+                id: track.id,
+                title: track.title.as_deref(),
+                path: &manifest::manifest_path(&relative),
+                bytes
+            });
+
+            if self.sidecar_opts.enabled() {
+                if let Err(e) = sidecar::write_sidecar(&output_file, track, &self.sidecar_opts, self.api_client, self.budget) {
+                    pb.println(format!("  [warning] failed to write sidecar for {}: {:?}", title, e));
+                }
+            }
+
+            if let Some(hook) = &self.after_track {
+                let path = match self.sink {
+                    Some(sink) => sink.location(&manifest::manifest_path(&relative)),
+                    None => output_file.display().to_string()
+                };
+                let ran = hook.run(&[
+                    ("path", path),
+                    ("title", ctx.title.clone()),
+                    ("artist", ctx.artist.clone().unwrap_or_default()),
+                    ("playlist", ctx.playlist_title.clone().unwrap_or_default()),
+                    ("id", ctx.id.to_string())
+                ]);
+                if let Err(e) = ran {
+                    pb.println(format!("  [warning] --exec-after-track failed for {}: {}", title, e));
+                }
+            }
+        } else if interrupt::requested() {
+            self.fail(track, playlist, "interrupted before it finished downloading".into());
+        } else {
+            self.fail(track, playlist, format!("failed to write audio to {}", output_file.display()));
+        }
+    }
+
+    /// If the run has been interrupted, waits for the tracks being written to
+    /// be finished or cleaned up, saves the manifest and failure report and
+    /// fails with [`Error::Interrupted`].
+    pub fn stop_if_interrupted(&self) -> Result<(), Error> {
+        if !interrupt::requested() {
+            return Ok(());
+        }
+
+        let _stopping = interrupt::stopping();
+        if let Err(e) = self.save_manifest() {
+            self.pb.println(format!("  [warning] failed to save the manifest: {:?}", e));
+        }
+        if let Err(e) = self.summary.write_report(self.output_folder) {
+            self.pb.println(format!("  [warning] failed to write {}: {:?}", FAILURES_FILE, e));
+        }
+        self.events.emit(Event::Interrupted);
+        self.pb.println(format!(
+            "Interrupted; saved the manifest and listed unfinished tracks in {}",
+            self.output_folder.join(FAILURES_FILE).display()
+        ));
+        Err(Error::Interrupted)
+    }
+
+    /// Reports that the given track couldn't be saved, for the end of the run.
+    pub fn fail(&self, track: &TrackInfo, playlist: Option<&Playlist>, mut error: String) {
+        if let Some(restriction) = Restriction::of(track) {
+            error.push_str(&format!(" (the track is restricted: {})", restriction.describe()));
+        }
+        self.events.emit(Event::TrackFailed {
+            id: track.id,
+            title: track.title.as_deref(),
+            error: error.clone()
+        });
+        self.summary.failed(track, playlist, error);
+        self.pb.failed();
+    }
+
+    /// Whether the given track has already been saved somewhere during this
+    /// run, and so would be linked rather than downloaded again.
+    pub fn is_duplicate(&self, track: &TrackInfo) -> bool {
+        self.dedup != DedupMode::Off && self.was_saved(track)
+    }
+
+    /// Whether the given track has been saved during this run.
+    pub fn was_saved(&self, track: &TrackInfo) -> bool {
+        track.id.is_some_and(|id| self.saved.lock().unwrap().contains_key(&id))
+    }
+
+    /// Splits the given playlists into copies holding only the tracks that
+    /// need downloading, and the tracks (with their playlists) that are
+    /// duplicates of ones saved earlier or elsewhere in the playlists.
+    pub fn split_duplicates<'p>(&self, playlists: &[&'p Playlist]) -> (Vec<Playlist>, Vec<(&'p TrackInfo, &'p Playlist)>) {
+        if self.dedup == DedupMode::Off {
+            return (playlists.iter().map(|p| (*p).clone()).collect(), Vec::new());
+        }
+
+        let mut seen: HashSet<u64> = self.saved.lock().unwrap().keys().copied().collect();
+        let mut duplicates = Vec::new();
+        let to_download = playlists
+            .iter()
+            .map(|playlist| {
+                let mut copy = (*playlist).clone();
+                if let Some(tracks) = &mut copy.tracks {
+                    tracks.retain(|track| match track.id {
+                        Some(id) if !seen.insert(id) => {
+                            // Point at the original rather than the copy so the
+                            // caller can hold onto it
+                            let original = archive_track(playlist, id);
+                            duplicates.push((original, *playlist));
+                            false
+                        },
+                        _ => true
+                    });
+                }
+                copy
+            })
+            .collect();
+
+        (to_download, duplicates)
+    }
+
+    /// Links a track that was already saved during this run into the place
+    /// it would otherwise have been downloaded to, recording it in the
+    /// manifest. Returns false if there's nothing to link to.
+    pub fn link_duplicate(&self, track: &TrackInfo, playlist: Option<&Playlist>) -> bool {
+        let pb = self.pb;
+        let title = track.title.as_deref().unwrap_or("untitled");
+        let source = match track.id.and_then(|id| self.saved.lock().unwrap().get(&id).cloned()) {
+            Some(source) => source,
+            None => return false
+        };
+
+        let kind = if playlist.is_some() { "playlists" } else { "likes" };
+        let relative = match self.track_output_path(&TrackContext::new(kind, track, playlist)) {
+            Ok(relative) => relative,
+            Err(e) => {
+                pb.println(format!("  [warning] failed to name {}: {:?}", title, e));
+                return true;
+            }
+        };
+        if relative == source {
+            return true;
+        }
+
+        let link = self.output_folder.join(&relative);
+        let _ = fs::remove_file(&link);
+        let linked = match self.dedup {
+            DedupMode::Symlink => make_symlink(&relative_target(&relative, &source), &link),
+            // Hardlinks can't cross filesystems, so fall back to a copy
+            _ => fs::hard_link(self.output_folder.join(&source), &link)
+                .or_else(|_| fs::copy(self.output_folder.join(&source), &link).map(|_| ()))
+        };
+        if let Err(e) = linked {
+            pb.println(format!("  [warning] failed to link {} to {}: {}", title, link.display(), e));
+            return true;
+        }
+
+        let mut manifest = self.manifest.lock().unwrap();
+        let source_path = manifest::manifest_path(&source);
+        let original = track.id
+            .and_then(|id| manifest.tracks.get(&id))
+            .and_then(|entry| entry.files.iter().find(|f| f.path == source_path))
+            .cloned();
+        if let Some(original) = original {
+            if let Err(e) = manifest.record(track, &relative, original.bytes, original.sha256, original.sampled_sha256) {
+                pb.println(format!("  [warning] failed to record {} in the manifest: {:?}", title, e));
+            }
+            self.dedup_bytes.fetch_add(original.bytes, Ordering::SeqCst);
+            self.summary.linked();
+            self.events.emit(Event::TrackSaved {
+                id: track.id,
+                title: track.title.as_deref(),
+                path: &manifest::manifest_path(&relative),
+                bytes: original.bytes
+            });
+        }
+
+        true
+    }
+
+    pub fn save_manifest(&self) -> Result<(), Error> {
+        if let Some(song_links) = &self.sidecar_opts.song_links {
+            song_links.save()?;
+        }
+        self.manifest.lock().unwrap().save(self.output_folder)?;
+        if let Some(sink) = self.sink {
+            sink.put(MANIFEST_FILE, &fs::read(self.output_folder.join(MANIFEST_FILE))?)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the manifest, reports any tracks whose audio was replaced and
+    /// sums up the run. The run is over, so there's nothing left to resume.
+    pub fn finish(self) -> Result<(), Error> {
+        self.save_manifest()?;
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.clear()?;
+        }
+
+        let dedup_bytes = self.dedup_bytes.load(Ordering::SeqCst);
+        if dedup_bytes > 0 {
+            self.pb.println(format!("Linked duplicate tracks instead of downloading them, saving {}", HumanBytes(dedup_bytes)));
+        }
+
+        let replacements = self.replacements.into_inner().unwrap();
+        if !replacements.is_empty() {
+            self.pb.println(format!(
+                "{} tracks had their audio replaced since they were last downloaded (see replacements.json)",
+                replacements.len()
+            ));
+        }
+        manifest::report_replacements(self.output_folder, replacements)?;
+
+        let counts = self.summary.counts();
+        self.summary.finish(self.output_folder, self.pb)?;
+
+        if let Some(hook) = &self.after_run {
+            let output = match self.sink {
+                Some(sink) => sink.location(""),
+                None => self.output_folder.display().to_string()
+            };
+            let ran = hook.run(&[
+                ("output", output),
+                ("downloaded", counts.downloaded.to_string()),
+                ("failed", counts.failed.to_string()),
+                ("bytes", counts.bytes.to_string())
+            ]);
+            if let Err(e) = ran {
+                self.pb.println(format!("  [warning] --exec-after-run failed: {}", e));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports what's being downloaded the way the saver reports what it saves.
+impl Observer for TrackSaver<'_> {
+    fn event(&self, event: Event<'_>) {
+        self.events.emit(event);
+    }
+
+    fn api_call(&self) {
+        self.budget.record(1);
+    }
+
+    fn notice(&self, message: &str) {
+        self.pb.println(format!("  [notice] {}", message));
+    }
+
+    fn warning(&self, message: &str) {
+        self.pb.println(format!("  [warning] {}", message));
+    }
+}
+
+/// Downloads the given tracks, which don't belong to a playlist, with up to
+/// `concurrency` downloads at once. The progress bar moves on by one per track.
+pub fn download_loose_tracks(
+    saver: &TrackSaver<'_>,
+    tracks: Vec<TrackInfo>,
+    concurrency: usize,
+    zester: &Zester,
+    credentials: &Credentials
+) -> Result<(), Error> {
+    let tracks: Vec<&TrackInfo> = tracks.iter().collect();
+    let api_permits = Semaphore::new(concurrency);
+    let chunks = split_round_robin(&tracks, concurrency);
+    let result = run_workers(&chunks, zester, credentials, |zester, chunk| {
+        chunk.iter().try_for_each(|track| download_track(saver, zester, track, None, &api_permits, saver))
+    });
+
+    saver.stop_if_interrupted()?;
+    result
+}
+
+/// Downloads a single track, saving it as part of `playlist` if it's given.
+/// Looking up its stream waits for one of `api_permits`. Fails with
+/// [`Error::Interrupted`] instead of starting once the run's been interrupted.
+///
+/// Each track is zested on its own so that an interrupted run stops between
+/// tracks, rather than going on to look up the rest.
+pub fn download_track(
+    saver: &TrackSaver<'_>,
+    zester: &Zester,
+    track: &TrackInfo,
+    playlist: Option<&Playlist>,
+    api_permits: &Semaphore,
+    observer: &(dyn Observer + Sync)
+) -> Result<(), Error> {
+    use TracksAudioZestingEvent::*;
+
+    interrupt::check()?;
+    let pb = saver.pb;
+    let on_event = |e: TracksAudioZestingEvent<'_>| match e {
+        NumTracksToDownload { .. } => {},
+
+        StartTrackDownload { track_info } => {
+            observer.api_call();
+            pace::wait();
+            api_permits.acquire_for_thread();
+            observer.event(Event::TrackStarted {
+                id: track_info.id,
+                title: track_info.title.as_deref()
+            });
+            pb.set_message(track_info.title.as_deref().unwrap_or("untitled"));
+        },
+
+        FinishTrackDownload { track_info, track_data } => {
+            api_permits.release_for_thread();
+            saver.save(track_info, playlist, track_data);
+            pb.inc(1);
+        },
+
+        TrackDownloadError { track_info, err } => {
+            api_permits.release_for_thread();
+            if let Some(transfer) = &saver.transfer {
+                transfer.skip(track_info);
+            }
+            saver.fail(track_info, playlist, format!("{:?}", err));
+            let title = track_info.title.as_deref().unwrap_or("untitled");
+            observer.warning(&match playlist {
+                Some(playlist) => format!(
+                    "failed to download {} (in {}): {:?}",
+                    title,
+                    playlist.title.as_deref().unwrap_or("untitled"),
+                    err
+                ),
+                None => format!("failed to download {}: {:?}", title, err)
+            });
+            pb.inc(1);
+        },
+
+        PausedAfterServerError { time_secs } => {
+            observer.api_call();
+            observer.event(Event::Retrying { after_secs: time_secs });
+            logging::info(&format!("Server error, retrying after {}s", time_secs));
+            pace::server_error(Some(Duration::from_secs(time_secs)));
+            pb.set_message(&format!("Server error, retrying after {}s", time_secs));
+        }
+    };
+
+    // A track downloads the same way whether it's liked or in a playlist,
+    // which it's only saved as part of
+    let single = Likes {
+        collections: vec![LikesCollection {
+            collection: vec![Like {
+                created_at: track.created_at.clone(),
+                kind: Some("track".into()),
+                track: Some(track.clone())
+            }],
+            next_href: None
+        }]
+    };
+    let result = zester.likes_audio(&single, std::u64::MAX, on_event);
+    api_permits.release_for_thread();
+    Ok(result?)
+}
+
+// Finds the track with the given id in the playlist
+fn archive_track(playlist: &Playlist, id: u64) -> &TrackInfo {
+    playlist.tracks.iter().flatten().find(|t| t.id == Some(id)).unwrap()
+}
+
+// The path a symlink at `link` (relative to the output folder) needs to hold to
+// point at `target` (also relative to the output folder)
+fn relative_target(link: &Path, target: &Path) -> PathBuf {
+    let depth = link.parent().map_or(0, |p| p.components().filter(|c| matches!(c, Component::Normal(_))).count());
+    let mut path = PathBuf::new();
+    for _ in 0..depth {
+        path.push("..");
+    }
+
+    path.join(target)
+}
+
+// Reads the given `Read` instance in full and uploads it to the sink as
+// `name`, returning the number of bytes uploaded, their SHA-256 digest and
+// their sampled one.
+//
+// Handles pretty-printing relevant errors.
+fn upload_track(
+    sink: &dyn ArchiveSink,
+    name: &str,
+    track_title: &str,
+    pb: &Progress,
+    mut data: impl Read
+) -> Option<(u64, String, Option<String>)> {
+    let mut audio = Vec::new();
+    if let Err(e) = data.read_to_end(&mut audio) {
+        if !interrupt::requested() {
+            pb.println(format!("  [warning] Failed to download \"{}\": {}", track_title, e));
+        }
+        return None;
+    }
+
+    if let Err(e) = sink.put(name, &audio) {
+        pb.println(format!("  [warning] Failed to upload \"{}\" to {}: {:?}", track_title, sink.location(name), e));
+        return None;
+    }
+    let sampled = checksum::sampled_sha256_of(Cursor::new(&audio), audio.len() as u64).ok();
+    Some((audio.len() as u64, checksum::sha256_bytes(&audio), sampled))
+}
+
+// Streams the given `Read` instance to the given file path, returning the number
+// of bytes written and their SHA-256 digest. The file only appears at the path
+// once it's been written in full.
+//
+// Handles pretty-printing relevant errors.
+fn stream_track_to_file<P: AsRef<Path>>(path: P, track_title: &str, pb: &Progress, mut data: impl Read) -> Option<(u64, String)> {
+    let path = path.as_ref();
+    let partial = atomic::partial_path(path);
+    match File::create(&partial) {
+        Ok(f) => {
+            let mut writer = HashingWriter::new(f);
+            let written = io::copy(&mut data, &mut writer)
+                .map(|_| writer.finish())
+                .and_then(|written| atomic::finish(&partial, path).map(|_| written));
+            match written {
+                Ok(written) => Some(written),
+                Err(e) => {
+                    if !interrupt::requested() {
+                        pb.println(format!("  [warning] Failed to write \"{}\" to file: {}", track_title, e));
+                    }
+                    let _ = fs::remove_file(&partial);
+                    None
+                }
+            }
+        },
+        Err(e) => {
+            pb.println(format!("  [warning] Failed to create {}: {}", partial.display(), e));
+            None
+        }
+    }
+}
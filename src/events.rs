@@ -0,0 +1,169 @@
+//! A machine-readable feed of what a run is doing, for other processes to
+//! follow along with.
+//!
+//! Each event is a single line of JSON (NDJSON).
+
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressMode {
+    Bar,
+    Json,
+    Plain,
+    /// Plain, but with periodic full sentences and nothing else in between,
+    /// for screen readers
+    PlainVerbose,
+}
+
+impl ProgressMode {
+    pub fn variants() -> [&'static str; 4] {
+        ["Bar", "Json", "Plain", "Plain-Verbose"]
+    }
+}
+
+impl FromStr for ProgressMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bar" => Ok(ProgressMode::Bar),
+            "json" => Ok(ProgressMode::Json),
+            "plain" => Ok(ProgressMode::Plain),
+            "plain-verbose" => Ok(ProgressMode::PlainVerbose),
+            _ => Err(format!("valid values: {}", Self::variants().join(", ")))
+        }
+    }
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted { command: &'a str },
+    PhaseStarted { phase: &'a str },
+    /// How many likes or playlists are going to be fetched
+    ItemsToFetch { phase: &'a str, count: u64 },
+    /// More likes or playlists were fetched
+    ItemsFetched { phase: &'a str, count: u64 },
+    PhaseFinished { phase: &'a str },
+    PlaylistStarted { id: Option<u64>, title: Option<&'a str> },
+    PlaylistFinished { id: Option<u64>, title: Option<&'a str> },
+    PlaylistFailed { id: Option<u64>, title: Option<&'a str>, error: String },
+    TrackStarted { id: Option<u64>, title: Option<&'a str> },
+    TrackSaved { id: Option<u64>, title: Option<&'a str>, path: &'a str, bytes: u64 },
+    TrackFailed { id: Option<u64>, title: Option<&'a str>, error: String },
+    /// The server errored; the request will be retried after a pause
+    Retrying { after_secs: u64 },
+    /// The run was stopped by Ctrl-C or SIGTERM
+    Interrupted,
+    RunFinished { command: &'a str },
+}
+
+/// Where events go; does nothing unless a destination has been set up.
+#[derive(Default)]
+pub struct EventFeed {
+    /// Print events to stdout (`--progress json`)
+    stdout: bool,
+    #[cfg(unix)]
+    socket: Option<socket::EventSocket>,
+}
+
+impl EventFeed {
+    /// Sets up a feed that, if given a path, listens on a Unix socket there and
+    /// sends every event to each process connected to it. In JSON progress
+    /// mode events are printed to stdout as well.
+    pub fn new(socket_path: Option<PathBuf>, progress: ProgressMode) -> io::Result<Self> {
+        let stdout = progress == ProgressMode::Json;
+        let path = match socket_path {
+            Some(path) => path,
+            None => return Ok(Self { stdout, ..Self::default() })
+        };
+
+        #[cfg(unix)]
+        {
+            Ok(Self { stdout, socket: Some(socket::EventSocket::bind(path)?) })
+        }
+
+        #[cfg(not(unix))]
+        {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("cannot listen on {}: event sockets need Unix domain sockets", path.display())
+            ))
+        }
+    }
+
+    pub fn emit(&self, event: Event<'_>) {
+        let mut line = serde_json::to_vec(&event).unwrap();
+        line.push(b'\n');
+
+        if self.stdout {
+            // Whoever's reading may have gone away; that's no reason to stop
+            let _ = io::stdout().lock().write_all(&line);
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some(socket) = &self.socket {
+                socket.broadcast(&line);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+mod socket {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// How long a client gets to take an event before it's disconnected
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub struct EventSocket {
+        path: PathBuf,
+        clients: Arc<Mutex<Vec<UnixStream>>>,
+    }
+
+    impl EventSocket {
+        pub fn bind(path: PathBuf) -> io::Result<Self> {
+            // Clear out a socket left behind by a run that didn't exit cleanly
+            if fs::symlink_metadata(&path).is_ok() && UnixStream::connect(&path).is_err() {
+                fs::remove_file(&path)?;
+            }
+
+            let listener = UnixListener::bind(&path)?;
+            let clients: Arc<Mutex<Vec<UnixStream>>> = Default::default();
+
+            let accepting = clients.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() {
+                        accepting.lock().unwrap().push(stream);
+                    }
+                }
+            });
+
+            Ok(Self { path, clients })
+        }
+
+        /// Sends the line to every connected client, dropping clients that
+        /// have gone away or stopped reading rather than holding up the run.
+        pub fn broadcast(&self, line: &[u8]) {
+            self.clients.lock().unwrap().retain_mut(|client| client.write_all(line).is_ok());
+        }
+    }
+
+    impl Drop for EventSocket {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! What zester exits with, so that scripts can tell what kind of trouble a run
+//! ran into without reading its output, and how it says what went wrong.
+
+use crate::{Error, OutputFormat};
+use serde_json::json;
+use std::io;
+
+/// Anything not covered below
+pub const FAILURE: i32 = 1;
+/// The run finished, but some tracks couldn't be saved (see `failures.json`)
+pub const PARTIAL: i32 = 2;
+/// SoundCloud rejected the OAuth token or client ID
+pub const AUTH: i32 = 3;
+/// A connection failed or timed out, or a server answered with an error
+pub const NETWORK: i32 = 4;
+/// A JSON archive that was needed isn't there
+pub const JSON_NOT_FOUND: i32 = 5;
+/// The disk filled up, or the audio to download wouldn't fit on it
+pub const DISK_FULL: i32 = 6;
+/// Another zester is already working in the output folder
+pub const ALREADY_RUNNING: i32 = 7;
+/// A URL given on the command line isn't the kind of link the command takes
+pub const USAGE: i32 = 8;
+
+/// The exit codes, as listed by `--help`
+pub const HELP: &str = "EXIT CODES:
+    0      Success
+    1      Any other error
+    2      Finished, but some tracks couldn't be saved (see failures.json)
+    3      The OAuth token or client ID was rejected; log in again
+    4      A network error; try again later
+    5      A JSON archive that was needed isn't there
+    6      The disk is full, or too full for the audio to download
+    7      Another zester is already working in the output folder
+    8      A URL given isn't the kind of link the command takes
+    130    Interrupted (Ctrl-C or SIGTERM)";
+
+impl Error {
+    /// What zester exits with after failing with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::InvalidCredentials(_) => AUTH,
+            Error::HttpError(_) => NETWORK,
+            Error::InvalidUrl(_) => USAGE,
+            Error::PartialFailure(_) => PARTIAL,
+            Error::JsonFileNotFound(_) => JSON_NOT_FOUND,
+            Error::AlreadyRunning(_) => ALREADY_RUNNING,
+            Error::NotEnoughSpace(_) => DISK_FULL,
+            Error::Interrupted => crate::interrupt::EXIT_CODE,
+            Error::IoError(e) | Error::OrangeZestError(orange_zest::Error::IoError(e)) => io_exit_code(e),
+            _ => FAILURE
+        }
+    }
+
+    /// What kind of error this is, as `--error-format json` reports it.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::IoError(e) | Error::OrangeZestError(orange_zest::Error::IoError(e)) if io_exit_code(e) == DISK_FULL => {
+                "disk_full"
+            },
+            Error::IoError(e) | Error::OrangeZestError(orange_zest::Error::IoError(e)) if io_exit_code(e) == NETWORK => {
+                "network"
+            },
+            Error::OrangeZestError(_) => "soundcloud",
+            Error::VarError(_) => "missing_credentials",
+            Error::IoError(_) => "io",
+            Error::JsonFileNotFound(_) => "json_file_not_found",
+            Error::NamingScriptError(_) => "naming_script",
+            Error::FilenameTemplateError(_) => "filename_template",
+            Error::ConfigError(_) => "config",
+            Error::Cancelled(_) => "cancelled",
+            Error::SqliteError(_) => "sqlite",
+            Error::KeyringError(_) => "keyring",
+            Error::CsvError(_) => "csv",
+            Error::IntegrityCheckFailed(_) => "integrity_check_failed",
+            Error::HttpError(_) => "network",
+            Error::InvalidCredentials(_) => "invalid_credentials",
+            Error::JsonFormatError(_) => "json_format",
+            Error::ZipError(_) => "zip",
+            Error::NoSuchPlaylist(_) => "no_such_playlist",
+            Error::NoSuchRun(_) => "no_such_run",
+            Error::ProbeError(_) => "probe",
+            Error::AlreadyRunning(_) => "already_running",
+            Error::NotEnoughSpace(_) => "not_enough_space",
+            Error::InvalidUrl(_) => "invalid_url",
+            Error::PartialFailure(_) => "partial_failure",
+            Error::Interrupted => "interrupted"
+        }
+    }
+
+    /// What went wrong, in a sentence.
+    pub fn message(&self) -> String {
+        match self {
+            Error::IoError(e) | Error::OrangeZestError(orange_zest::Error::IoError(e)) => e.to_string(),
+            Error::OrangeZestError(e) => format!("{:?}", e),
+            Error::VarError(e) => e.to_string(),
+            Error::JsonFileNotFound(path) => format!("no JSON file at {}", path),
+            Error::SqliteError(e) => e.to_string(),
+            Error::KeyringError(e) => e.to_string(),
+            Error::CsvError(e) => e.to_string(),
+            Error::ZipError(e) => e.to_string(),
+            Error::Interrupted => "interrupted".into(),
+            Error::NamingScriptError(message)
+            | Error::FilenameTemplateError(message)
+            | Error::ConfigError(message)
+            | Error::Cancelled(message)
+            | Error::IntegrityCheckFailed(message)
+            | Error::HttpError(message)
+            | Error::InvalidCredentials(message)
+            | Error::JsonFormatError(message)
+            | Error::NoSuchPlaylist(message)
+            | Error::NoSuchRun(message)
+            | Error::ProbeError(message)
+            | Error::AlreadyRunning(message)
+            | Error::NotEnoughSpace(message)
+            | Error::InvalidUrl(message)
+            | Error::PartialFailure(message) => message.clone()
+        }
+    }
+
+    /// The file or thing the error is about, when it's known apart from the
+    /// message.
+    pub fn failed_item(&self) -> Option<&str> {
+        match self {
+            Error::JsonFileNotFound(path) => Some(path),
+            _ => None
+        }
+    }
+
+    /// What to do about the error, if there's something to suggest.
+    pub fn suggested_action(&self) -> Option<&'static str> {
+        Some(match self.kind() {
+            "disk_full" => "free up space where the archive is kept, then run again",
+            "network" => "check the connection and try again later",
+            "missing_credentials" | "invalid_credentials" => {
+                "log in again with `zester login`, or pass a fresh --oauth-token and --client-id"
+            },
+            "json_file_not_found" => "run `zester json` into that folder first, or point --input-folder at the archive",
+            "naming_script" => "fix the naming script",
+            "filename_template" => "fix the filename or folder template",
+            "config" => "fix the config file",
+            "keyring" => "pass the credentials with --oauth-token and --client-id or in .env instead",
+            "integrity_check_failed" => "download the affected audio again with `zester audio`",
+            "probe" => "make sure ffmpeg (with ffprobe) is installed",
+            "not_enough_space" => "free up space, or download fewer tracks at once (e.g. with --recent)",
+            "already_running" => "wait for the other run to finish",
+            "invalid_url" => "check the link, or use the subcommand for that kind of link",
+            "partial_failure" => "run again to retry the tracks that failed",
+            _ => return None
+        })
+    }
+
+    /// Prints the error to stderr, in the given format.
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Text => eprintln!("Error: {:?}", self),
+            OutputFormat::Json => eprintln!(
+                "{}",
+                json!({
+                    "kind": self.kind(),
+                    "message": self.message(),
+                    "failed_item": self.failed_item(),
+                    "suggested_action": self.suggested_action(),
+                    "exit_code": self.exit_code()
+                })
+            )
+        }
+    }
+}
+
+fn io_exit_code(error: &io::Error) -> i32 {
+    use io::ErrorKind::*;
+
+    // ENOSPC on Unix, ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL on Windows
+    #[cfg(unix)]
+    const DISK_FULL_ERRORS: &[i32] = &[28];
+    #[cfg(windows)]
+    const DISK_FULL_ERRORS: &[i32] = &[39, 112];
+    #[cfg(not(any(unix, windows)))]
+    const DISK_FULL_ERRORS: &[i32] = &[];
+
+    match error.kind() {
+        _ if error.raw_os_error().is_some_and(|code| DISK_FULL_ERRORS.contains(&code)) => DISK_FULL,
+        ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected | AddrNotAvailable | TimedOut => NETWORK,
+        _ => FAILURE
+    }
+}
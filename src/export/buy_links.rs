@@ -0,0 +1,169 @@
+use crate::archive::{self, artist};
+use crate::json_check::Strictness;
+use crate::sidecar::extract_links;
+use crate::{percent_decode, Error};
+use orange_zest::api::TrackInfo;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Stores that sell music on the artist's behalf, most preferred first
+const STORES: &[(&str, &str)] = &[
+    ("Bandcamp", "bandcamp.com"),
+    ("Beatport", "beatport.com"),
+    ("Traxsource", "traxsource.com"),
+    ("Juno Download", "junodownload.com"),
+    ("7digital", "7digital.com"),
+];
+
+/// A link to buy a track, cleaned up.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BuyLink {
+    /// Position in `STORES`, or `STORES.len()` for a purchase link elsewhere
+    rank: usize,
+    pub url: String,
+}
+
+impl BuyLink {
+    pub fn store(&self) -> &'static str {
+        STORES.get(self.rank).map_or("Other", |(name, _)| name)
+    }
+}
+
+/// Normalizes a link: unwraps SoundCloud's `gate.sc` redirects, drops
+/// tracking parameters and fragments, and lowercases the host.
+fn normalize(url: &str) -> Option<String> {
+    let mut url = url.to_string();
+    if url.contains("gate.sc") {
+        if let Some(target) = query_param(&url, "url") {
+            url = target;
+        }
+    }
+
+    let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let path = path.split('#').next().unwrap();
+    let (path, query) = match path.find('?') {
+        Some(i) => (&path[..i], Some(&path[i + 1..])),
+        None => (path, None)
+    };
+
+    let query: Vec<&str> = query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .filter(|param| !param.is_empty() && !param.starts_with("utm_") && !param.starts_with("fbclid"))
+        .collect();
+    let mut normalized = format!("https://{}{}", host.to_lowercase().trim_start_matches("www."), path.trim_end_matches('/'));
+    if !query.is_empty() {
+        normalized.push('?');
+        normalized.push_str(&query.join("&"));
+    }
+
+    Some(normalized)
+}
+
+fn query_param(url: &str, name: &str) -> Option<String> {
+    url.split(['?', '&'])
+        .skip(1)
+        .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        .map(percent_decode)
+}
+
+fn store_rank(url: &str) -> Option<usize> {
+    let host = url.trim_start_matches("https://").split(['/', '?']).next()?;
+    STORES
+        .iter()
+        .position(|(_, domain)| host == *domain || host.ends_with(&format!(".{}", domain)))
+}
+
+/// Every buy link for the track: its purchase link, plus any store links in
+/// its description. Best first.
+pub fn buy_links(track: &TrackInfo) -> Vec<BuyLink> {
+    let mut links: Vec<BuyLink> = Vec::new();
+
+    // The purchase link is often a free download gate or the artist's site,
+    // but it's what they chose to put there
+    if let Some(url) = track.purchase_url.as_deref().and_then(normalize) {
+        links.push(BuyLink { rank: store_rank(&url).unwrap_or(STORES.len()), url });
+    }
+    for url in track.description.iter().flat_map(|d| extract_links(d)).filter_map(|l| normalize(&l)) {
+        if let Some(rank) = store_rank(&url) {
+            links.push(BuyLink { rank, url });
+        }
+    }
+
+    links.sort();
+    links.dedup_by(|a, b| a.url == b.url);
+    links
+}
+
+/// The best place to buy the track.
+pub fn preferred_buy_link(track: &TrackInfo) -> Option<BuyLink> {
+    buy_links(track).into_iter().next()
+}
+
+#[derive(Default)]
+struct ArtistLinks<'a> {
+    links: Vec<BuyLink>,
+    tracks: Vec<&'a str>,
+}
+
+/// Writes `buy-links.csv` (one row per artist and link) and `support-artists.md`
+/// (the same, as a checklist) into `output_folder`.
+pub fn export(input_folder: &Path, output_folder: &Path, strictness: Strictness) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(input_folder, strictness))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, strictness))?;
+    fs::create_dir_all(output_folder)?;
+
+    let tracks = likes
+        .iter()
+        .flat_map(archive::liked_tracks)
+        .map(|(_, track)| track)
+        .chain(playlists.iter().flat_map(|p| p.playlists.iter()).flat_map(archive::playlist_tracks));
+
+    let mut artists: BTreeMap<String, ArtistLinks> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for track in tracks {
+        if !track.id.is_some_and(|id| seen.insert(id)) {
+            continue;
+        }
+
+        let links = buy_links(track);
+        if links.is_empty() {
+            continue;
+        }
+
+        let entry = artists.entry(artist(track).unwrap_or("Unknown Artist").to_string()).or_default();
+        entry.links.extend(links);
+        entry.tracks.push(track.title.as_deref().unwrap_or("Untitled"));
+    }
+
+    for entry in artists.values_mut() {
+        entry.links.sort();
+        entry.links.dedup_by(|a, b| a.url == b.url);
+    }
+
+    let mut writer = csv::Writer::from_path(output_folder.join("buy-links.csv"))?;
+    writer.write_record(["artist", "store", "url", "tracks"])?;
+    for (name, entry) in &artists {
+        for link in &entry.links {
+            writer.write_record([name.as_str(), link.store(), link.url.as_str(), &entry.tracks.join("; ")])?;
+        }
+    }
+    writer.flush()?;
+
+    let mut md = BufWriter::new(File::create(output_folder.join("support-artists.md"))?);
+    writeln!(md, "# Go support these artists\n")?;
+    for (name, entry) in &artists {
+        let best = &entry.links[0];
+        writeln!(md, "- [ ] **{}**: [{}]({}) ({} tracks)", name, best.store(), best.url, entry.tracks.len())?;
+        for other in &entry.links[1..] {
+            writeln!(md, "  - [{}]({})", other.store(), other.url)?;
+        }
+    }
+    md.flush()?;
+
+    println!("Exported buy links for {} artists to {}", artists.len(), output_folder.display());
+    Ok(())
+}
@@ -0,0 +1,174 @@
+use super::buy_links::preferred_buy_link;
+use super::encoding::EncodingOpts;
+use super::versions;
+use crate::archive::{self, artist};
+use crate::json_check::Strictness;
+use crate::restriction::Restriction;
+use crate::songlink::SongLinks;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+struct TrackRow<'a> {
+    track: Option<&'a TrackInfo>,
+    liked_at: Option<&'a str>,
+    playlists: Vec<&'a str>,
+}
+
+struct SongRow {
+    name: String,
+    /// Track ids with what sets each version apart
+    versions: Vec<(u64, Option<String>)>,
+}
+
+/// Writes `tracks.csv` (one row per unique track), `playlist_tracks.csv`
+/// (one row per playlist entry), `songs.csv` (one row per song, with its
+/// versions) and, if the listening history was zested, `history.csv` (one row
+/// per play) into `output_folder`.
+///
+/// Tracks looked up with `audio --song-links` get links to other platforms.
+/// Everything's written in the given encoding.
+pub fn export(input_folder: &Path, output_folder: &Path, strictness: Strictness, encoding: &EncodingOpts) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(input_folder, strictness))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, strictness))?;
+    fs::create_dir_all(output_folder)?;
+    let song_links = SongLinks::load()?;
+
+    let mut rows: BTreeMap<u64, TrackRow> = BTreeMap::new();
+    for (liked_at, track) in likes.iter().flat_map(archive::liked_tracks) {
+        if let Some(id) = track.id {
+            let row = rows.entry(id).or_default();
+            row.track = Some(track);
+            row.liked_at = liked_at;
+        }
+    }
+
+    let mut playlist_writer = csv::Writer::from_writer(Vec::new());
+    playlist_writer.write_record(["playlist_id", "playlist", "position", "track_id", "artist", "title", "buy_url"])?;
+
+    for playlist in playlists.iter().flat_map(|p| p.playlists.iter()) {
+        let playlist_title = playlist.title.as_deref().unwrap_or("");
+
+        for (position, track) in archive::playlist_tracks(playlist).enumerate() {
+            let id = match track.id {
+                Some(id) => id,
+                None => continue
+            };
+
+            let row = rows.entry(id).or_default();
+            row.track.get_or_insert(track);
+            row.playlists.push(playlist_title);
+
+            playlist_writer.write_record([
+                playlist.id.map(|id| id.to_string()).unwrap_or_default(),
+                playlist_title.to_string(),
+                (position + 1).to_string(),
+                id.to_string(),
+                artist(track).unwrap_or("").to_string(),
+                track.title.clone().unwrap_or_default(),
+                preferred_buy_link(track).map(|l| l.url).unwrap_or_default()
+            ])?;
+        }
+    }
+    write_encoded(playlist_writer, &output_folder.join("playlist_tracks.csv"), encoding)?;
+
+    let mut track_writer = csv::Writer::from_writer(Vec::new());
+    track_writer.write_record([
+        "id", "artist", "title", "duration", "duration_ms", "permalink_url", "liked_at", "playlists",
+        "spotify_url", "apple_music_url", "bandcamp_url", "restriction", "song", "version"
+    ])?;
+
+    let mut songs: BTreeMap<String, SongRow> = BTreeMap::new();
+
+    for (id, row) in &rows {
+        let track = match row.track {
+            Some(track) => track,
+            None => continue
+        };
+        let links = song_links.cached(*id).unwrap_or_default();
+        let version = versions::of(track);
+        let song = songs
+            .entry(version.key.clone())
+            .or_insert_with(|| SongRow { name: version.song.clone(), versions: Vec::new() });
+        // Named after the original if there is one
+        if version.version.is_none() {
+            song.name = version.song.clone();
+        }
+        song.versions.push((*id, version.version.clone()));
+        let link = |platform: &str| links.get(platform).map(|l| l.url.clone()).unwrap_or_default();
+
+        track_writer.write_record([
+            id.to_string(),
+            artist(track).unwrap_or("").to_string(),
+            track.title.clone().unwrap_or_default(),
+            track.duration.map(format_duration).unwrap_or_default(),
+            track.duration.map(|d| d.to_string()).unwrap_or_default(),
+            track.permalink_url.clone().unwrap_or_default(),
+            row.liked_at.unwrap_or("").to_string(),
+            row.playlists.join("; "),
+            link("spotify"),
+            link("appleMusic"),
+            link("bandcamp"),
+            Restriction::of(track).map(|r| r.name().to_string()).unwrap_or_default(),
+            version.song,
+            version.version.unwrap_or_default()
+        ])?;
+    }
+    write_encoded(track_writer, &output_folder.join("tracks.csv"), encoding)?;
+
+    let mut song_writer = csv::Writer::from_writer(Vec::new());
+    song_writer.write_record(["song", "num_versions", "versions", "track_ids"])?;
+    let mut songs: Vec<_> = songs.into_values().collect();
+    songs.sort_by_key(|song| song.name.to_lowercase());
+    for song in &songs {
+        song_writer.write_record([
+            song.name.clone(),
+            song.versions.len().to_string(),
+            song.versions.iter().map(|(_, version)| version.as_deref().unwrap_or("original")).collect::<Vec<_>>().join("; "),
+            song.versions.iter().map(|(id, _)| id.to_string()).collect::<Vec<_>>().join("; ")
+        ])?;
+    }
+    write_encoded(song_writer, &output_folder.join("songs.csv"), encoding)?;
+
+    if let Some(plays) = archive::optional(archive::load_history(input_folder, strictness))? {
+        let mut history_writer = csv::Writer::from_writer(Vec::new());
+        history_writer.write_record(["played_at", "track_id", "artist", "title", "duration", "permalink_url"])?;
+        for play in &plays {
+            let track = play.track.as_ref();
+            history_writer.write_record([
+                play.played_at_rfc3339().unwrap_or_default(),
+                play.track_id.or_else(|| track.and_then(|t| t.id)).map(|id| id.to_string()).unwrap_or_default(),
+                track.and_then(artist).unwrap_or("").to_string(),
+                track.and_then(|t| t.title.clone()).unwrap_or_default(),
+                track.and_then(|t| t.duration).map(format_duration).unwrap_or_default(),
+                track.and_then(|t| t.permalink_url.clone()).unwrap_or_default()
+            ])?;
+        }
+        write_encoded(history_writer, &output_folder.join("history.csv"), encoding)?;
+        println!("Exported {} plays to {}", plays.len(), output_folder.display());
+    }
+
+    println!("Exported {} tracks ({} songs) to {}", rows.len(), songs.len(), output_folder.display());
+    Ok(())
+}
+
+fn write_encoded(writer: csv::Writer<Vec<u8>>, path: &Path, encoding: &EncodingOpts) -> Result<(), Error> {
+    let csv = writer.into_inner().map_err(|e| e.into_error())?;
+    fs::write(path, encoding.encode(&String::from_utf8_lossy(&csv)))?;
+    Ok(())
+}
+
+// Formats a duration in milliseconds as `h:mm:ss` (or `m:ss` when under an hour)
+fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (h, m, s) = (secs / 3600, (secs / 60) % 60, secs % 60);
+
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
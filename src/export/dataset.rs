@@ -0,0 +1,199 @@
+use crate::archive::{self, artist};
+use crate::atomic::write_json;
+use crate::json_check::Strictness;
+use crate::checksum::sha256_file;
+use crate::Error;
+use chrono::{Datelike, Utc};
+use orange_zest::api::TrackInfo;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub struct DatasetOptions {
+    pub input_folder: PathBuf,
+    pub audio_folder: PathBuf,
+    pub include_audio: bool,
+    pub title: String,
+    pub creators: Vec<String>,
+    pub output_folder: PathBuf,
+    pub strictness: Strictness,
+}
+
+/// A track as it appears in the dataset.
+///
+/// Deliberately leaves out anything about the account the archive was made
+/// from (like timestamps, playlist membership, etc.).
+#[derive(Serialize)]
+struct DatasetTrack<'a> {
+    id: u64,
+    title: Option<&'a str>,
+    artist: Option<&'a str>,
+    genre: Option<&'a str>,
+    tags: Option<&'a str>,
+    duration_ms: Option<u64>,
+    license: Option<&'a str>,
+    permalink_url: Option<&'a str>,
+    created_at: Option<&'a str>,
+    /// Path of the audio file within the dataset, if it was included
+    audio: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    path: String,
+    bytes: u64,
+    sha256: String,
+}
+
+#[derive(Serialize)]
+struct Manifest<'a> {
+    format_version: u32,
+    generated_at: String,
+    generator: &'static str,
+    track_count: usize,
+    licenses: BTreeMap<&'a str, usize>,
+    files: Vec<ManifestFile>,
+}
+
+pub fn export(opts: &DatasetOptions) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(&opts.input_folder, opts.strictness))?;
+    let playlists = archive::optional(archive::load_playlists(&opts.input_folder, opts.strictness))?;
+
+    // Every unique track in the archive, regardless of where it showed up
+    let mut tracks: BTreeMap<u64, &TrackInfo> = BTreeMap::new();
+    for (_, track) in likes.iter().flat_map(archive::liked_tracks) {
+        if let Some(id) = track.id {
+            tracks.insert(id, track);
+        }
+    }
+    for track in playlists.iter().flat_map(|p| p.playlists.iter()).flat_map(archive::playlist_tracks) {
+        if let Some(id) = track.id {
+            tracks.insert(id, track);
+        }
+    }
+
+    let metadata_folder = opts.output_folder.join("metadata");
+    fs::create_dir_all(&metadata_folder)?;
+
+    let local_audio = if opts.include_audio {
+        fs::create_dir_all(opts.output_folder.join("audio"))?;
+        archive::local_audio(&opts.audio_folder)?
+    } else {
+        HashMap::new()
+    };
+
+    let mut licenses = BTreeMap::new();
+    let mut dataset_tracks = Vec::with_capacity(tracks.len());
+    for (id, track) in &tracks {
+        let license = track.license.as_deref().unwrap_or("unknown");
+        *licenses.entry(license).or_insert(0) += 1;
+
+        let audio = match local_audio.get(id).and_then(|paths| paths.first()) {
+            Some(source) => {
+                let relative = format!("audio/{}.m4a", id);
+                fs::copy(source, opts.output_folder.join(&relative))?;
+                Some(relative)
+            },
+            None => None
+        };
+
+        dataset_tracks.push(DatasetTrack {
+            id: *id,
+            title: track.title.as_deref(),
+            artist: artist(track),
+            genre: track.genre.as_deref(),
+            tags: track.tag_list.as_deref(),
+            duration_ms: track.duration,
+            license: track.license.as_deref(),
+            permalink_url: track.permalink_url.as_deref(),
+            created_at: track.created_at.as_deref(),
+            audio
+        });
+    }
+    write_json(&dataset_tracks, metadata_folder.join("tracks.json"), true)?;
+
+    let now = Utc::now();
+    let formats = if opts.include_audio {
+        vec!["application/json", "audio/mp4"]
+    } else {
+        vec!["application/json"]
+    };
+    let datacite = json!({
+        "titles": [{ "title": opts.title }],
+        "creators": opts.creators.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>(),
+        "publisher": "orange-zester",
+        "publicationYear": now.year().to_string(),
+        "types": {
+            "resourceTypeGeneral": "Dataset",
+            "resourceType": "SoundCloud track metadata"
+        },
+        "rightsList": licenses.keys().map(|l| json!({ "rights": l })).collect::<Vec<_>>(),
+        "formats": formats,
+        "sizes": [format!("{} tracks", tracks.len())],
+        "descriptions": [{
+            "description": "Public SoundCloud track metadata collected with orange-zester. \
+                Information about the collecting account has been removed.",
+            "descriptionType": "Methods"
+        }]
+    });
+    write_json(&datacite, opts.output_folder.join("datacite.json"), true)?;
+
+    // Checksum everything we've written so far
+    let mut files = Vec::new();
+    for relative in dataset_files(&opts.output_folder)? {
+        let path = opts.output_folder.join(&relative);
+        files.push(ManifestFile {
+            path: relative,
+            bytes: fs::metadata(&path)?.len(),
+            sha256: sha256_file(&path)?
+        });
+    }
+
+    let mut checksums = fs::File::create(opts.output_folder.join("checksums.sha256"))?;
+    for file in &files {
+        writeln!(checksums, "{}  {}", file.sha256, file.path)?;
+    }
+
+    let manifest = Manifest {
+        format_version: 1,
+        generated_at: now.to_rfc3339(),
+        generator: concat!("orange-zester ", env!("CARGO_PKG_VERSION")),
+        track_count: tracks.len(),
+        licenses,
+        files
+    };
+    write_json(&manifest, opts.output_folder.join("manifest.json"), true)?;
+
+    println!(
+        "Exported {} tracks to {}",
+        manifest.track_count,
+        opts.output_folder.display()
+    );
+    Ok(())
+}
+
+// The paths (relative to `root`, `/`-separated) of the files making up the
+// dataset, excluding the manifest and checksum list themselves.
+fn dataset_files(root: &Path) -> Result<Vec<String>, Error> {
+    let mut files = Vec::new();
+    for folder in &["metadata", "audio"] {
+        let folder_path = root.join(folder);
+        if !folder_path.exists() {
+            continue;
+        }
+
+        for entry in fs::read_dir(&folder_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                files.push(format!("{}/{}", folder, entry.file_name().to_string_lossy()));
+            }
+        }
+    }
+    files.push("datacite.json".into());
+    files.sort();
+
+    Ok(files)
+}
@@ -0,0 +1,63 @@
+//! Writing exported playlists and CSVs in encodings older software copes
+//! with, for car stereos and DJ software that choke on emoji or anything
+//! outside Latin-1.
+
+use structopt::clap::arg_enum;
+use structopt::StructOpt;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum TextEncoding {
+        Utf8,
+        Latin1
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct EncodingOpts {
+    /// Write playlists and CSVs as UTF-8, or as Latin-1 with anything it can't hold replaced by `?`
+    #[structopt(long, possible_values = &TextEncoding::variants(), case_insensitive = true, default_value = "Utf8")]
+    playlist_encoding: TextEncoding,
+    /// Leave emoji out of titles and file names altogether
+    #[structopt(long)]
+    strip_emoji: bool,
+}
+
+impl EncodingOpts {
+    /// The `#EXTENC` header for M3U playlists in this encoding.
+    pub fn m3u_header(&self) -> &'static str {
+        match self.playlist_encoding {
+            TextEncoding::Utf8 => "#EXTENC:UTF-8\n",
+            TextEncoding::Latin1 => "#EXTENC:ISO-8859-1\n"
+        }
+    }
+
+    /// Makes text safe to write in this encoding.
+    pub fn clean(&self, text: &str) -> String {
+        text.chars()
+            .filter(|&c| !(self.strip_emoji && is_emoji(c)))
+            .map(|c| match self.playlist_encoding {
+                TextEncoding::Latin1 if c as u32 > 0xff => '?',
+                _ => c
+            })
+            .collect()
+    }
+
+    /// Cleans text and encodes it.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        let text = self.clean(text);
+        match self.playlist_encoding {
+            TextEncoding::Utf8 => text.into_bytes(),
+            // Everything left fits in a byte
+            TextEncoding::Latin1 => text.chars().map(|c| c as u8).collect()
+        }
+    }
+}
+
+// Pictographs, symbols and what's used to join and style them
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1f000..=0x1faff | 0x2600..=0x27bf | 0x2b00..=0x2bff | 0xfe00..=0xfe0f | 0x200d | 0x20e3 | 0xe0020..=0xe007f
+    )
+}
@@ -0,0 +1,159 @@
+use crate::archive::{self, FOLLOWERS_FILE, FOLLOWINGS_FILE};
+use crate::json_check::Strictness;
+use crate::user::USERS_DIR;
+use crate::Error;
+use orange_zest::api::User;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use structopt::clap::arg_enum;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy)]
+    pub enum GraphFormat {
+        Graphml,
+        Dot
+    }
+}
+
+struct Node {
+    username: String,
+    permalink: String,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: BTreeMap<u64, Node>,
+    /// Follower, followed
+    edges: BTreeSet<(u64, u64)>,
+}
+
+impl Graph {
+    fn add_node(&mut self, id: Option<u64>, username: Option<&str>, permalink: Option<&str>) -> Option<u64> {
+        let id = id?;
+        let node = self.nodes.entry(id).or_insert_with(|| Node { username: String::new(), permalink: String::new() });
+        if node.username.is_empty() {
+            node.username = username.unwrap_or_default().to_string();
+        }
+        if node.permalink.is_empty() {
+            node.permalink = permalink.unwrap_or_default().to_string();
+        }
+        Some(id)
+    }
+
+    fn add_user(&mut self, user: &User) -> Option<u64> {
+        self.add_node(user.id, user.username.as_deref(), user.permalink.as_deref())
+    }
+}
+
+/// Writes the follow graph recorded in the archive at `input_folder`, and in
+/// the archives of other users under its `users/` folder, to `output_file`.
+///
+/// Every archive with `followers.json` or `followings.json` adds edges to and
+/// from its account; accounts are nodes labelled with their username.
+pub fn export(input_folder: &Path, output_file: &Path, format: GraphFormat, strictness: Strictness) -> Result<(), Error> {
+    let mut folders = vec![input_folder.to_path_buf()];
+    if let Ok(entries) = fs::read_dir(input_folder.join(USERS_DIR)) {
+        let mut users: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir()).collect();
+        users.sort();
+        folders.extend(users);
+    }
+
+    let mut graph = Graph::default();
+    let mut archives = 0;
+    for folder in &folders {
+        let followers = archive::optional(archive::load_users(&folder.join(FOLLOWERS_FILE), strictness))?;
+        let followings = archive::optional(archive::load_users(&folder.join(FOLLOWINGS_FILE), strictness))?;
+        if followers.is_none() && followings.is_none() {
+            continue;
+        }
+
+        let me = archive::load_me(folder, strictness)?;
+        let me = match graph.add_node(me.id, me.username.as_deref(), me.permalink.as_deref()) {
+            Some(id) => id,
+            None => {
+                println!("  [warning] {} has no account id; leaving it out", folder.join("me.json").display());
+                continue;
+            }
+        };
+        archives += 1;
+
+        for follower in followers.iter().flatten() {
+            if let Some(id) = graph.add_user(follower) {
+                graph.edges.insert((id, me));
+            }
+        }
+        for followed in followings.iter().flatten() {
+            if let Some(id) = graph.add_user(followed) {
+                graph.edges.insert((me, id));
+            }
+        }
+    }
+
+    if archives == 0 {
+        return Err(Error::JsonFileNotFound(format!(
+            "no {} or {} in {} (zest them with `json followers followings`)",
+            FOLLOWERS_FILE,
+            FOLLOWINGS_FILE,
+            input_folder.display()
+        )));
+    }
+
+    let mut out = BufWriter::new(File::create(output_file)?);
+    match format {
+        GraphFormat::Graphml => write_graphml(&graph, &mut out)?,
+        GraphFormat::Dot => write_dot(&graph, &mut out)?
+    }
+    out.flush()?;
+
+    println!(
+        "Exported {} accounts and {} follows from {} archives to {}",
+        graph.nodes.len(),
+        graph.edges.len(),
+        archives,
+        output_file.display()
+    );
+    Ok(())
+}
+
+fn write_graphml(graph: &Graph, out: &mut impl Write) -> Result<(), Error> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#)?;
+    writeln!(out, r#"  <key id="username" for="node" attr.name="username" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <key id="permalink" for="node" attr.name="permalink" attr.type="string"/>"#)?;
+    writeln!(out, r#"  <graph id="follows" edgedefault="directed">"#)?;
+    for (id, node) in &graph.nodes {
+        writeln!(out, r#"    <node id="{}">"#, id)?;
+        writeln!(out, r#"      <data key="username">{}</data>"#, escape_xml(&node.username))?;
+        writeln!(out, r#"      <data key="permalink">{}</data>"#, escape_xml(&node.permalink))?;
+        writeln!(out, "    </node>")?;
+    }
+    for (from, to) in &graph.edges {
+        writeln!(out, r#"    <edge source="{}" target="{}"/>"#, from, to)?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")?;
+    Ok(())
+}
+
+fn write_dot(graph: &Graph, out: &mut impl Write) -> Result<(), Error> {
+    writeln!(out, "digraph follows {{")?;
+    for (id, node) in &graph.nodes {
+        let label = if node.username.is_empty() { id.to_string() } else { node.username.clone() };
+        writeln!(out, "  {} [label=\"{}\", permalink=\"{}\"];", id, escape_dot(&label), escape_dot(&node.permalink))?;
+    }
+    for (from, to) in &graph.edges {
+        writeln!(out, "  {} -> {};", from, to)?;
+    }
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
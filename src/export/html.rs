@@ -0,0 +1,168 @@
+use crate::archive::{self, artist};
+use crate::html::{self, Collection, Row};
+use crate::json_check::Strictness;
+use crate::manifest;
+use crate::sidecar::artwork_paths;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+const ARTWORK_DIR: &str = "artwork";
+const AUDIO_DIR: &str = "audio";
+
+pub fn export(
+    input_folder: &Path,
+    audio_folder: &Path,
+    output_folder: &Path,
+    copy_audio: bool,
+    strictness: Strictness
+) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(input_folder, strictness))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, strictness))?;
+    if likes.is_none() && playlists.is_none() {
+        return Err(Error::JsonFileNotFound(input_folder.join("likes.json").to_string_lossy().into()));
+    }
+
+    fs::create_dir_all(output_folder.join(ARTWORK_DIR))?;
+    if copy_audio {
+        fs::create_dir_all(output_folder.join(AUDIO_DIR))?;
+    }
+    let audio = manifest::audio_locations(audio_folder)?;
+
+    // Worked out once per track, as tracks are often in several collections
+    let mut links: HashMap<u64, (Option<String>, Option<String>)> = HashMap::new();
+    let mut row = |track: &TrackInfo| -> Result<Row, Error> {
+        let (artwork, audio) = match track.id {
+            Some(id) => match links.get(&id) {
+                Some(found) => found.clone(),
+                None => {
+                    let source = audio.get(&id).and_then(|paths| paths.iter().find(|path| path.is_file()));
+                    let found = (
+                        artwork_link(output_folder, id, source)?,
+                        source.map(|source| audio_link(output_folder, id, source, copy_audio)).transpose()?
+                    );
+                    links.insert(id, found.clone());
+                    found
+                }
+            },
+            None => (None, None)
+        };
+
+        Ok(Row {
+            title: track.title.clone().unwrap_or_else(|| "untitled".into()),
+            artist: artist(track).unwrap_or("unknown artist").into(),
+            artwork: artwork.or_else(|| track.artwork_url.clone()),
+            duration: track.duration,
+            audio
+        })
+    };
+
+    let mut collections = Vec::new();
+    if let Some(likes) = &likes {
+        let tracks = archive::liked_tracks(likes).map(|(_, track)| row(track)).collect::<Result<Vec<_>, _>>()?;
+        collections.push(Collection {
+            title: "Likes".into(),
+            href: "likes.html".into(),
+            artwork: tracks.iter().find_map(|t| t.artwork.clone()),
+            tracks
+        });
+    }
+    for (n, playlist) in playlists.iter().flat_map(|p| &p.playlists).enumerate() {
+        let tracks = archive::playlist_tracks(playlist).map(&mut row).collect::<Result<Vec<_>, _>>()?;
+        collections.push(Collection {
+            title: playlist.title.clone().unwrap_or_else(|| "untitled playlist".into()),
+            href: format!("playlist-{}.html", n + 1),
+            artwork: playlist.artwork_url.clone().or_else(|| tracks.iter().find_map(|t| t.artwork.clone())),
+            tracks
+        });
+    }
+
+    fs::write(output_folder.join("index.html"), html::index_page(&collections))?;
+    for collection in &collections {
+        fs::write(output_folder.join(&collection.href), html::collection_page(collection, "index.html"))?;
+    }
+
+    let with_audio = links.values().filter(|(_, audio)| audio.is_some()).count();
+    println!(
+        "Wrote pages for {} collections into {} ({} of {} tracks with audio)",
+        collections.len(),
+        output_folder.display(),
+        with_audio,
+        links.len()
+    );
+    Ok(())
+}
+
+// Copies the artwork saved next to the track's audio into the site, so it
+// shows up without a connection
+fn artwork_link(output_folder: &Path, id: u64, audio: Option<&PathBuf>) -> Result<Option<String>, Error> {
+    let artwork = match audio.and_then(|audio| artwork_paths(audio).into_iter().next()) {
+        Some(artwork) => artwork,
+        None => return Ok(None)
+    };
+    let extension = artwork.extension().and_then(|e| e.to_str()).unwrap_or("bin");
+    let name = format!("{}.{}", id, extension);
+
+    fs::copy(&artwork, output_folder.join(ARTWORK_DIR).join(&name))?;
+    Ok(Some(format!("{}/{}", ARTWORK_DIR, name)))
+}
+
+// Either copies the audio into the site, or links to where it is relative to
+// the site
+fn audio_link(output_folder: &Path, id: u64, audio: &Path, copy: bool) -> Result<String, Error> {
+    if copy {
+        let extension = audio.extension().and_then(|e| e.to_str()).unwrap_or("m4a");
+        let name = format!("{}.{}", id, extension);
+        fs::copy(audio, output_folder.join(AUDIO_DIR).join(&name))?;
+        return Ok(format!("{}/{}", AUDIO_DIR, name));
+    }
+
+    let from = output_folder.canonicalize()?;
+    let to = audio.canonicalize()?;
+    let common = from.components().zip(to.components()).take_while(|(a, b)| a == b).count();
+    // Nothing in common but the root, or on another drive on Windows
+    if common <= 1 {
+        return Ok(format!("file://{}", encode_path(&to)));
+    }
+
+    let mut relative = PathBuf::new();
+    for _ in from.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(to.components().skip(common));
+    Ok(encode_path(&relative))
+}
+
+// Turns a path into the path part of a URL
+fn encode_path(path: &Path) -> String {
+    let parts = path
+        .components()
+        .filter_map(|component| match component {
+            Component::ParentDir => Some("..".into()),
+            Component::Normal(part) => Some(percent_encode(&part.to_string_lossy())),
+            Component::Prefix(prefix) => match prefix.kind() {
+                Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => Some(format!("{}:", drive as char)),
+                _ => Some(percent_encode(&prefix.as_os_str().to_string_lossy()))
+            },
+            Component::RootDir | Component::CurDir => None
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+
+    if path.has_root() {
+        format!("/{}", parts)
+    } else {
+        parts
+    }
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
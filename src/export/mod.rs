@@ -0,0 +1,194 @@
+//! Conversions of an existing JSON archive into other formats.
+
+use crate::json_check::Strictness;
+use crate::Error;
+use encoding::EncodingOpts;
+use graph::GraphFormat;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+mod buy_links;
+mod csv;
+mod dataset;
+mod encoding;
+mod graph;
+mod html;
+mod pack;
+mod sqlite;
+mod versions;
+mod zip;
+
+#[derive(StructOpt, Debug)]
+pub enum ExportOpts {
+    /// Package anonymized metadata, license info and checksums as a research dataset
+    Dataset {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Folder holding archived audio (defaults to the input folder)
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        audio_folder: Option<PathBuf>,
+        /// Copy the archived audio into the dataset
+        #[structopt(long)]
+        include_audio: bool,
+        /// Title recorded in the dataset's DataCite metadata
+        #[structopt(long, default_value = "SoundCloud track corpus")]
+        title: String,
+        /// Creator recorded in the dataset's DataCite metadata (repeatable)
+        #[structopt(long = "creator", value_name = "name")]
+        creators: Vec<String>,
+        /// Output folder
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+    },
+    /// Write a normalized SQLite database of the archived metadata
+    Sqlite {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Database file to write (replaced if it already exists)
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_file: PathBuf,
+    },
+    /// List where to buy the archived tracks, grouped by artist
+    BuyLinks {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Output folder
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+    },
+    /// Write flat CSV files of the archived tracks and playlists, with versions of the same
+    /// song grouped together
+    Csv {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Output folder
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+        #[structopt(flatten)]
+        encoding: EncodingOpts,
+    },
+    /// Package one playlist's audio, artwork, M3U and metadata into a zip for sharing
+    Zip {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Folder holding archived audio (defaults to the input folder)
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        audio_folder: Option<PathBuf>,
+        /// Playlist to package, by id or title (* and ? work as wildcards)
+        #[structopt(long, value_name = "playlist")]
+        playlist: String,
+        /// Zip file to write
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_file: PathBuf,
+        #[structopt(flatten)]
+        encoding: EncodingOpts,
+    },
+    /// Write the follow graph between the archived accounts and who they follow / are
+    /// followed by, for Gephi and the like
+    Graph {
+        /// Input folder from which to obtain JSON; archives of other users in its users/
+        /// folder are included
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        #[structopt(
+            long,
+            possible_values = &GraphFormat::variants(),
+            case_insensitive = true,
+            default_value = "Graphml"
+        )]
+        format: GraphFormat,
+        /// File to write
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_file: PathBuf,
+    },
+    /// Build a static site of the archive's likes and playlists, with artwork and players
+    /// for the archived audio, to open from disk or put on any web host
+    Html {
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Folder holding archived audio (defaults to the input folder)
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        audio_folder: Option<PathBuf>,
+        /// Copy the audio into the site instead of linking to where it is, so the site
+        /// works on its own
+        #[structopt(long)]
+        copy_audio: bool,
+        /// Output folder
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+    },
+    /// Package archived audio and its manifest into a zip for offsite backups
+    Pack {
+        /// Audio folder holding the manifest.json
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        audio_folder: PathBuf,
+        /// Only package files downloaded after the given run, for a delta on top of earlier packs
+        #[structopt(long, value_name = "run-id")]
+        incremental_since: Option<String>,
+        /// Zip file to write
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_file: PathBuf,
+    }
+}
+
+pub fn run(opts: ExportOpts) -> Result<(), Error> {
+    match opts {
+        ExportOpts::Dataset { input_folder, strict_json, audio_folder, include_audio, title, creators, output_folder } => {
+            let audio_folder = audio_folder.unwrap_or_else(|| input_folder.clone());
+            dataset::export(&dataset::DatasetOptions {
+                input_folder,
+                audio_folder,
+                include_audio,
+                title,
+                creators,
+                output_folder,
+                strictness: Strictness::from_flag(strict_json)
+            })
+        },
+        ExportOpts::Sqlite { input_folder, strict_json, output_file } =>
+            sqlite::export(&input_folder, &output_file, Strictness::from_flag(strict_json)),
+        ExportOpts::BuyLinks { input_folder, strict_json, output_folder } =>
+            buy_links::export(&input_folder, &output_folder, Strictness::from_flag(strict_json)),
+        ExportOpts::Csv { input_folder, strict_json, output_folder, encoding } =>
+            csv::export(&input_folder, &output_folder, Strictness::from_flag(strict_json), &encoding),
+        ExportOpts::Zip { input_folder, strict_json, audio_folder, playlist, output_file, encoding } => {
+            let audio_folder = audio_folder.unwrap_or_else(|| input_folder.clone());
+            zip::export(&input_folder, &audio_folder, &playlist, &output_file, Strictness::from_flag(strict_json), &encoding)
+        },
+        ExportOpts::Graph { input_folder, strict_json, format, output_file } =>
+            graph::export(&input_folder, &output_file, format, Strictness::from_flag(strict_json)),
+        ExportOpts::Html { input_folder, strict_json, audio_folder, copy_audio, output_folder } => {
+            let audio_folder = audio_folder.unwrap_or_else(|| input_folder.clone());
+            html::export(&input_folder, &audio_folder, &output_folder, copy_audio, Strictness::from_flag(strict_json))
+        },
+        ExportOpts::Pack { audio_folder, incremental_since, output_file } =>
+            pack::export(&audio_folder, incremental_since.as_deref(), &output_file)
+    }
+}
@@ -0,0 +1,110 @@
+use crate::manifest::{FileEntry, Manifest, MANIFEST_FILE};
+use crate::sidecar;
+use crate::Error;
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Written into every pack, saying what it holds.
+#[derive(Serialize)]
+struct PackIndex<'a> {
+    created_at: DateTime<Utc>,
+    /// The run the pack builds on, if it's incremental
+    since_run: Option<&'a str>,
+    /// The latest run with files in the pack; pass it to
+    /// `--incremental-since` for the next pack
+    through_run: Option<&'a str>,
+    files: Vec<PackedFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct PackedFile<'a> {
+    track_id: u64,
+    #[serde(flatten)]
+    file: &'a FileEntry,
+}
+
+/// Packages the audio recorded in the manifest of `audio_folder` (and any
+/// sidecars next to it) into a zip at `output_file`, keeping the folder
+/// layout. With `since_run`, only files downloaded by later runs go in.
+///
+/// Every pack carries the whole current manifest, so extracting a full pack
+/// and then each incremental one in order gives back the archive.
+pub fn export(audio_folder: &Path, since_run: Option<&str>, output_file: &Path) -> Result<(), Error> {
+    let manifest = Manifest::load(audio_folder)?;
+    let runs = manifest.runs();
+    if let Some(since) = since_run {
+        if !runs.contains(since) {
+            return Err(Error::NoSuchRun(format!(
+                "no run {} downloaded anything into {} (runs: {})",
+                since,
+                audio_folder.display(),
+                if runs.is_empty() { "none recorded".into() } else { runs.iter().copied().collect::<Vec<_>>().join(", ") }
+            )));
+        }
+    }
+
+    let files: Vec<PackedFile> = manifest.tracks
+        .iter()
+        .flat_map(|(id, entry)| entry.files.iter().chain(&entry.previous_versions).map(move |file| (*id, file)))
+        .filter(|(_, file)| match since_run {
+            Some(since) => file.run.as_deref().is_some_and(|run| run > since),
+            None => true
+        })
+        .map(|(track_id, file)| PackedFile { track_id, file })
+        .collect();
+
+    let mut zip = ZipWriter::new(File::create(output_file)?);
+    // Audio is compressed already
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut packed = Vec::new();
+    let mut bytes = 0;
+    for entry in files {
+        let source = entry.file.location(audio_folder);
+        let mut audio = match File::open(&source) {
+            Ok(audio) => audio,
+            Err(e) => {
+                println!("  [warning] couldn't read {}, leaving it out: {}", source.display(), e);
+                continue;
+            }
+        };
+        zip.start_file(entry.file.path.as_str(), stored)?;
+        bytes += io::copy(&mut audio, &mut zip)?;
+
+        let sidecar = sidecar::sidecar_path(&source);
+        if let Ok(mut sidecar) = File::open(sidecar) {
+            zip.start_file(sidecar::sidecar_path(Path::new(&entry.file.path)).to_string_lossy(), deflated)?;
+            io::copy(&mut sidecar, &mut zip)?;
+        }
+        packed.push(entry);
+    }
+
+    zip.start_file(MANIFEST_FILE, deflated)?;
+    zip.write_all(&serde_json::to_vec_pretty(&manifest).unwrap())?;
+
+    let through_run = packed.iter().filter_map(|entry| entry.file.run.as_deref()).max();
+    let num_packed = packed.len();
+    let index = PackIndex { created_at: Utc::now(), since_run, through_run, files: packed };
+    zip.start_file("pack.json", deflated)?;
+    zip.write_all(&serde_json::to_vec_pretty(&index).unwrap())?;
+    zip.finish()?;
+
+    println!(
+        "Packed {} files ({}){} into {}",
+        num_packed,
+        HumanBytes(bytes),
+        since_run.map_or(String::new(), |since| format!(" downloaded since run {}", since)),
+        output_file.display()
+    );
+    if let Some(run) = runs.iter().next_back() {
+        println!("The latest run is {}; pass --incremental-since {} to pack only what's new next time", run, run);
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use super::versions;
+use crate::archive;
+use crate::json_check::Strictness;
+use crate::Error;
+use orange_zest::api::{TrackInfo, User};
+use rusqlite::{params, Connection, Transaction};
+use std::fs;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE users (
+        id INTEGER PRIMARY KEY,
+        username TEXT,
+        permalink TEXT,
+        permalink_url TEXT,
+        avatar_url TEXT
+    );
+    CREATE TABLE me (
+        user_id INTEGER NOT NULL REFERENCES users(id)
+    );
+    CREATE TABLE tracks (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER REFERENCES users(id),
+        title TEXT,
+        description TEXT,
+        genre TEXT,
+        tag_list TEXT,
+        duration_ms INTEGER,
+        license TEXT,
+        permalink_url TEXT,
+        artwork_url TEXT,
+        created_at TEXT,
+        -- The same for every version (edit, mix, remix) of a song, going by titles
+        song_key TEXT,
+        song TEXT,
+        version TEXT
+    );
+    CREATE TABLE playlists (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER REFERENCES users(id),
+        title TEXT,
+        description TEXT,
+        permalink_url TEXT,
+        artwork_url TEXT,
+        created_at TEXT
+    );
+    CREATE TABLE playlist_tracks (
+        playlist_id INTEGER NOT NULL REFERENCES playlists(id),
+        position INTEGER NOT NULL,
+        track_id INTEGER NOT NULL REFERENCES tracks(id),
+        PRIMARY KEY (playlist_id, position)
+    );
+    CREATE TABLE likes (
+        track_id INTEGER PRIMARY KEY REFERENCES tracks(id),
+        liked_at TEXT
+    );
+    CREATE INDEX tracks_user_id ON tracks(user_id);
+    CREATE INDEX tracks_song_key ON tracks(song_key);
+    CREATE INDEX playlist_tracks_track_id ON playlist_tracks(track_id);
+";
+
+/// Writes the JSON archive in `input_folder` to a fresh SQLite database at
+/// `output_file`, replacing anything already there.
+pub fn export(input_folder: &Path, output_file: &Path, strictness: Strictness) -> Result<(), Error> {
+    let me = archive::optional(archive::load_me(input_folder, strictness))?;
+    let likes = archive::optional(archive::load_likes(input_folder, strictness))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, strictness))?;
+
+    if output_file.exists() {
+        fs::remove_file(output_file)?;
+    }
+
+    let mut conn = Connection::open(output_file)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+
+    if let Some(me) = &me {
+        if let Some(id) = me.id {
+            tx.execute(
+                "INSERT OR REPLACE INTO users (id, username, permalink, permalink_url, avatar_url)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id as i64, me.username, me.permalink, me.permalink_url, me.avatar_url]
+            )?;
+            tx.execute("INSERT INTO me (user_id) VALUES (?1)", params![id as i64])?;
+        }
+    }
+
+    let mut num_likes = 0;
+    for (liked_at, track) in likes.iter().flat_map(archive::liked_tracks) {
+        if let Some(id) = insert_track(&tx, track)? {
+            tx.execute(
+                "INSERT OR REPLACE INTO likes (track_id, liked_at) VALUES (?1, ?2)",
+                params![id as i64, liked_at]
+            )?;
+            num_likes += 1;
+        }
+    }
+
+    let mut num_playlists = 0;
+    for playlist in playlists.iter().flat_map(|p| p.playlists.iter()) {
+        let playlist_id = match playlist.id {
+            Some(id) => id as i64,
+            None => continue
+        };
+        let user_id = match &playlist.user {
+            Some(user) => insert_user(&tx, user)?,
+            None => None
+        };
+
+        tx.execute(
+            "INSERT OR REPLACE INTO playlists (id, user_id, title, description, permalink_url, artwork_url, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                playlist_id,
+                user_id,
+                playlist.title,
+                playlist.description,
+                playlist.permalink_url,
+                playlist.artwork_url,
+                playlist.created_at
+            ]
+        )?;
+
+        for (position, track) in archive::playlist_tracks(playlist).enumerate() {
+            if let Some(track_id) = insert_track(&tx, track)? {
+                tx.execute(
+                    "INSERT OR REPLACE INTO playlist_tracks (playlist_id, position, track_id)
+                     VALUES (?1, ?2, ?3)",
+                    params![playlist_id, position as i64, track_id as i64]
+                )?;
+            }
+        }
+        num_playlists += 1;
+    }
+
+    tx.commit()?;
+    println!(
+        "Exported {} likes and {} playlists to {}",
+        num_likes,
+        num_playlists,
+        output_file.display()
+    );
+    Ok(())
+}
+
+// Returns the id of the inserted user, if it had one
+fn insert_user(tx: &Transaction, user: &User) -> Result<Option<i64>, Error> {
+    let id = match user.id {
+        Some(id) => id as i64,
+        None => return Ok(None)
+    };
+
+    tx.execute(
+        "INSERT OR REPLACE INTO users (id, username, permalink, permalink_url, avatar_url)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![id, user.username, user.permalink, user.permalink_url, user.avatar_url]
+    )?;
+    Ok(Some(id))
+}
+
+// Returns the id of the inserted track, if it had one
+fn insert_track(tx: &Transaction, track: &TrackInfo) -> Result<Option<u64>, Error> {
+    let id = match track.id {
+        Some(id) => id,
+        None => return Ok(None)
+    };
+    let user_id = match &track.user {
+        Some(user) => insert_user(tx, user)?,
+        None => None
+    };
+    let version = versions::of(track);
+
+    tx.execute(
+        "INSERT OR REPLACE INTO tracks
+            (id, user_id, title, description, genre, tag_list, duration_ms, license, permalink_url, artwork_url, created_at,
+             song_key, song, version)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            id as i64,
+            user_id,
+            track.title,
+            track.description,
+            track.genre,
+            track.tag_list,
+            track.duration.map(|d| d as i64),
+            track.license,
+            track.permalink_url,
+            track.artwork_url,
+            track.created_at,
+            version.key,
+            version.song,
+            version.version
+        ]
+    )?;
+    Ok(Some(id))
+}
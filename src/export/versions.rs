@@ -0,0 +1,95 @@
+//! Telling when tracks are versions of the same song (a radio edit, an
+//! extended mix, remixes) by their titles, so exports can group them.
+
+use crate::archive::artist;
+use orange_zest::api::TrackInfo;
+
+/// Words that mark a bracketed part of a title, or the part after a dash, as
+/// naming a version rather than being part of the song's name
+const VERSION_WORDS: &[&str] = &[
+    "mix", "remix", "edit", "version", "vip", "bootleg", "rework", "flip", "dub", "instrumental",
+    "acapella", "extended", "radio", "live", "remaster", "remastered", "cover", "demo", "sped up", "slowed"
+];
+
+pub struct SongVersion {
+    /// The same for every version of a song
+    pub key: String,
+    /// The song as it'd be listed, like `Artist - Title`
+    pub song: String,
+    /// What sets this version apart, like `Extended Mix`; unset for what
+    /// looks like the original
+    pub version: Option<String>,
+}
+
+/// Works out which song the given track is a version of.
+pub fn of(track: &TrackInfo) -> SongVersion {
+    let title = track.title.as_deref().unwrap_or("").trim();
+
+    // Uploads often go by "Artist - Title", naming the song's artist even
+    // when it's someone else's remix (but "Title - Extended Mix" is a version)
+    let (song_artist, title) = match title.split_once(" - ") {
+        Some((song_artist, rest)) if !is_version(rest.split(['(', '[']).next().unwrap()) => (song_artist.trim(), rest.trim()),
+        _ => (artist(track).unwrap_or(""), title)
+    };
+
+    let mut versions = Vec::new();
+    let mut base = String::new();
+    let mut rest = title;
+    while let Some(open) = rest.find(['(', '[']) {
+        let close = if rest.as_bytes()[open] == b'(' { ')' } else { ']' };
+        let end = match rest[open..].find(close) {
+            Some(end) => open + end,
+            None => break
+        };
+
+        let inside = rest[open + 1..end].trim();
+        base.push_str(&rest[..open]);
+        if is_version(inside) {
+            versions.push(inside.to_string());
+        } else if !is_featuring(inside) {
+            base.push_str(&rest[open..=end]);
+        }
+        rest = &rest[end + 1..];
+    }
+    base.push_str(rest);
+
+    // "Title - Extended Mix"
+    let mut base = base.trim().to_string();
+    if let Some((name, version)) = base.rsplit_once(" - ") {
+        if is_version(version) {
+            versions.insert(0, version.trim().to_string());
+            base = name.trim().to_string();
+        }
+    }
+    // "Title feat. Someone"
+    let lower = base.to_lowercase();
+    if let Some(at) = [" feat. ", " ft. ", " featuring "].iter().filter_map(|f| lower.find(f)).min() {
+        base.truncate(at);
+    }
+
+    SongVersion {
+        key: format!("{}\n{}", normalize(song_artist), normalize(&base)),
+        song: if song_artist.is_empty() { base.clone() } else { format!("{} - {}", song_artist, base) },
+        version: if versions.is_empty() { None } else { Some(versions.join(", ")) }
+    }
+}
+
+fn is_version(text: &str) -> bool {
+    let words = format!(" {} ", normalize(text));
+    VERSION_WORDS.iter().any(|word| words.contains(&format!(" {} ", word)))
+}
+
+fn is_featuring(text: &str) -> bool {
+    let words = normalize(text);
+    ["feat ", "ft ", "featuring "].iter().any(|f| words.starts_with(f))
+}
+
+// Lowercase, with anything that isn't a letter or digit turned into single
+// spaces
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,127 @@
+use super::encoding::EncodingOpts;
+use crate::archive::{self, artist};
+use crate::artwork::{self, ArtworkSource};
+use crate::filter::wildcard_match;
+use crate::json_check::Strictness;
+use crate::sidecar::extension;
+use crate::{sanitize, Error};
+use orange_zest::api::Playlist;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Packages the audio, artwork, an M3U and the metadata of the playlist picked
+/// by `selector` (an id or a title, which may use `*` wildcards) into a zip at
+/// `output_file`, with everything inside a folder named after the playlist.
+///
+/// The M3U, and the file names in it, are written in the given encoding.
+pub fn export(
+    input_folder: &Path,
+    audio_folder: &Path,
+    selector: &str,
+    output_file: &Path,
+    strictness: Strictness,
+    encoding: &EncodingOpts
+) -> Result<(), Error> {
+    let playlists = archive::load_playlists(input_folder, strictness)?;
+    let playlist = select(&playlists.playlists, selector)?;
+    let title = playlist.title.as_deref().unwrap_or("untitled");
+    let folder = encoding.clean(&sanitize(title));
+    let local_audio = archive::local_audio(audio_folder)?;
+
+    let mut zip = ZipWriter::new(File::create(output_file)?);
+    // Audio and artwork are compressed already
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let mut m3u = format!("#EXTM3U\n{}", encoding.m3u_header());
+    let mut missing = 0;
+    for (position, track) in archive::playlist_tracks(playlist).enumerate() {
+        let track_title = track.title.as_deref().unwrap_or("untitled");
+        let source = match track.id.and_then(|id| local_audio.get(&id)).and_then(|paths| paths.first()) {
+            Some(source) => source,
+            None => {
+                println!("  [warning] no audio archived for {}, leaving it out", track_title);
+                missing += 1;
+                continue;
+            }
+        };
+
+        let name = encoding.clean(&sanitize(format!(
+            "{:02} - {} - {}.m4a",
+            position + 1,
+            artist(track).unwrap_or("unknown"),
+            track_title
+        )));
+        zip.start_file(format!("{}/{}", folder, name), stored)?;
+        io::copy(&mut File::open(source)?, &mut zip)?;
+
+        m3u.push_str(&format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            track.duration.map_or(-1, |ms| (ms / 1000) as i64),
+            artist(track).unwrap_or("unknown"),
+            track_title,
+            name
+        ));
+    }
+
+    zip.start_file(format!("{}/{}.m3u", folder, folder), deflated)?;
+    zip.write_all(&encoding.encode(&m3u))?;
+
+    zip.start_file(format!("{}/playlist.json", folder), deflated)?;
+    zip.write_all(&serde_json::to_vec_pretty(playlist).unwrap())?;
+
+    let avatar_url = playlist.user.as_ref().and_then(|user| user.avatar_url.as_deref());
+    match artwork::fetch(playlist.artwork_url.as_deref(), avatar_url) {
+        Some(found) => {
+            if found.source != ArtworkSource::Original {
+                println!("  [notice] using {:?} artwork for the playlist, as nothing better is available", found.source);
+            }
+            let ext = extension(found.response.content_type(), &found.url);
+            zip.start_file(format!("{}/artwork.{}", folder, ext), stored)?;
+            io::copy(&mut found.response.into_reader(), &mut zip)?;
+        },
+        None if playlist.artwork_url.is_some() || avatar_url.is_some() => {
+            println!("  [warning] couldn't download the playlist's artwork");
+        },
+        None => {}
+    }
+
+    zip.finish()?;
+    println!(
+        "Packaged {} ({} tracks{}) into {}",
+        title,
+        archive::playlist_tracks(playlist).count() - missing,
+        if missing > 0 { format!(", {} missing", missing) } else { String::new() },
+        output_file.display()
+    );
+    Ok(())
+}
+
+// Finds the one playlist the selector picks out
+fn select<'a>(playlists: &'a [Playlist], selector: &str) -> Result<&'a Playlist, Error> {
+    let matches: Vec<&Playlist> = playlists
+        .iter()
+        .filter(|p| {
+            p.id.is_some_and(|id| selector.parse() == Ok(id))
+                || p.title.as_deref().is_some_and(|title| wildcard_match(selector, title))
+        })
+        .collect();
+
+    match matches.as_slice() {
+        [playlist] => Ok(playlist),
+        [] => Err(Error::NoSuchPlaylist(format!("no archived playlist matches \"{}\"", selector))),
+        _ => Err(Error::NoSuchPlaylist(format!(
+            "\"{}\" matches {} playlists ({}); pick one by id or a more specific title",
+            selector,
+            matches.len(),
+            matches
+                .iter()
+                .map(|p| format!("{} (id {})", p.title.as_deref().unwrap_or("untitled"), p.id.unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    }
+}
@@ -0,0 +1,332 @@
+//! Narrowing down which tracks an audio run downloads.
+
+use crate::classify;
+use crate::logging;
+use crate::restriction::Restriction;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use orange_zest::api::{Likes, Playlist, Playlists, TrackInfo};
+
+/// A span of time that items must have been liked / added in.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DateRange {
+    /// Inclusive
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl DateRange {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.until.is_none()
+    }
+
+    /// Whether the given SoundCloud timestamp falls inside the range.
+    ///
+    /// Missing or unreadable timestamps only fall inside an empty range.
+    pub fn contains(&self, timestamp: Option<&str>) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        match timestamp.and_then(parse_timestamp) {
+            Some(at) => self.since.is_none_or(|since| at >= since)
+                && self.until.is_none_or(|until| at < until),
+            None => false
+        }
+    }
+
+    /// Drops likes made outside the range.
+    pub fn retain_likes(&self, likes: &mut Likes) {
+        for c in &mut likes.collections {
+            c.collection.retain(|like| self.contains(like.created_at.as_deref()));
+        }
+    }
+
+    /// Drops playlists created outside the range.
+    pub fn retain_playlists(&self, playlists: &mut Playlists) {
+        playlists.playlists.retain(|p| self.contains(p.created_at.as_deref()));
+    }
+}
+
+/// Reads a timestamp from the API, which has handed them out in two formats
+/// over the years.
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_str(timestamp, "%Y/%m/%d %H:%M:%S %z"))
+        .ok()
+        .map(|at| at.with_timezone(&Utc))
+}
+
+// Accepts either a full RFC 3339 timestamp or a plain `YYYY-MM-DD` date, which
+// is taken to mean the start of that day (UTC)
+fn parse_date_arg(arg: &str) -> Result<(DateTime<Utc>, bool), String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(arg) {
+        return Ok((at.with_timezone(&Utc), false));
+    }
+
+    NaiveDate::parse_from_str(arg, "%Y-%m-%d")
+        .map(|date| (DateTime::from_naive_utc_and_offset(date.and_hms_opt(0, 0, 0).unwrap(), Utc), true))
+        .map_err(|_| format!("\"{}\" is not a date (YYYY-MM-DD) or RFC 3339 timestamp", arg))
+}
+
+/// Splits a SoundCloud tag list, where tags are separated by spaces and tags
+/// containing spaces are wrapped in double quotes.
+pub fn parse_tag_list(tag_list: &str) -> Vec<String> {
+    tag_list
+        .split('"')
+        .enumerate()
+        .flat_map(|(i, part)| -> Vec<String> {
+            // Every other part is inside quotes
+            if i % 2 == 1 {
+                vec![part.trim().to_string()]
+            } else {
+                part.split_whitespace().map(String::from).collect()
+            }
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters
+/// and `?` for any single character, ignoring case.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Where to resume from if the text so far can't be matched: just past the
+    // most recent `*`, with it swallowing one more character
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parses durations like `90`, `90s`, `20m` or `1h30m` into milliseconds.
+pub fn parse_duration(arg: &str) -> Result<u64, String> {
+    let err = || format!("\"{}\" is not a duration like 90s, 20m or 1h30m", arg);
+    if let Ok(secs) = arg.parse::<u64>() {
+        return Ok(secs * 1000);
+    }
+
+    let (mut total_secs, mut num) = (0, String::new());
+    for c in arg.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+
+        let n: u64 = num.parse().map_err(|_| err())?;
+        total_secs += n * match c {
+            'h' => 3600,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(err())
+        };
+        num.clear();
+    }
+    if !num.is_empty() {
+        return Err(err());
+    }
+
+    Ok(total_secs * 1000)
+}
+
+/// Parses a `--since` argument.
+pub fn parse_since(arg: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_arg(arg).map(|(at, _)| at)
+}
+
+/// Parses an `--until` argument; plain dates include the whole of that day.
+pub fn parse_until(arg: &str) -> Result<DateTime<Utc>, String> {
+    parse_date_arg(arg).map(|(at, date_only)| if date_only { at + Duration::days(1) } else { at })
+}
+
+/// The conditions a track must meet to be downloaded.
+///
+/// Empty conditions let everything through.
+#[derive(Debug, Default)]
+pub struct TrackFilter {
+    /// Uploader usernames, permalinks or ids; a track matches if its uploader
+    /// matches any of them
+    pub artists: Vec<String>,
+    /// When the track was liked, or for playlist tracks (which don't record
+    /// when they were added) uploaded
+    pub dates: DateRange,
+    /// Genres, compared case-insensitively; a track matches if it has any of them
+    pub genres: Vec<String>,
+    /// Tags, compared case-insensitively; a track matches if it has any of them
+    pub tags: Vec<String>,
+    /// Inclusive, in milliseconds
+    pub min_duration: Option<u64>,
+    /// Inclusive, in milliseconds
+    pub max_duration: Option<u64>,
+    /// `Some(true)` for only spoken-word tracks, `Some(false)` for only music
+    pub spoken: Option<bool>,
+    /// Playlist titles (which may contain `*` and `?` wildcards) or ids; only
+    /// playlists matching one of them are kept
+    pub playlists: Vec<String>,
+    /// Like `playlists`, but playlists matching any of these are dropped
+    pub excluded_playlists: Vec<String>,
+    /// Try tracks SoundCloud restricts (see `Restriction`) rather than
+    /// skipping them
+    pub include_restricted: bool,
+}
+
+impl TrackFilter {
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty()
+            && self.dates.is_empty()
+            && self.genres.is_empty()
+            && self.tags.is_empty()
+            && self.min_duration.is_none()
+            && self.max_duration.is_none()
+            && self.spoken.is_none()
+            && self.playlists.is_empty()
+            && self.excluded_playlists.is_empty()
+            && self.include_restricted
+    }
+
+    /// Why the given track, liked / added at `added_at`, shouldn't be
+    /// downloaded, if it shouldn't.
+    pub fn skip_reason(&self, added_at: Option<&str>, track: &TrackInfo) -> Option<String> {
+        if !self.include_restricted {
+            if let Some(restriction) = Restriction::of(track) {
+                return Some(format!("{} (--include-restricted tries anyway)", restriction.describe()));
+            }
+        }
+
+        if self.matches(added_at, track) {
+            None
+        } else {
+            Some("doesn't match the filters".into())
+        }
+    }
+
+    /// Whether the given track, liked / added at `added_at`, should be
+    /// downloaded.
+    pub fn matches(&self, added_at: Option<&str>, track: &TrackInfo) -> bool {
+        self.dates.contains(added_at)
+            && self.matches_artist(track)
+            && self.matches_genre(track)
+            && self.matches_tag(track)
+            && self.matches_duration(track)
+            && self.spoken.is_none_or(|spoken| classify::is_spoken(track) == spoken)
+    }
+
+    fn matches_genre(&self, track: &TrackInfo) -> bool {
+        self.genres.is_empty() || track.genre.as_deref().is_some_and(|genre| {
+            self.genres.iter().any(|wanted| genre.trim().eq_ignore_ascii_case(wanted))
+        })
+    }
+
+    fn matches_tag(&self, track: &TrackInfo) -> bool {
+        if self.tags.is_empty() {
+            return true;
+        }
+
+        let tags = track.tag_list.as_deref().map(parse_tag_list).unwrap_or_default();
+        tags.iter().any(|tag| self.tags.iter().any(|wanted| tag.eq_ignore_ascii_case(wanted)))
+    }
+
+    // Tracks without a known duration only get through when no bounds are set
+    fn matches_duration(&self, track: &TrackInfo) -> bool {
+        if self.min_duration.is_none() && self.max_duration.is_none() {
+            return true;
+        }
+
+        match track.duration {
+            Some(duration) => self.min_duration.is_none_or(|min| duration >= min)
+                && self.max_duration.is_none_or(|max| duration <= max),
+            None => false
+        }
+    }
+
+    fn matches_artist(&self, track: &TrackInfo) -> bool {
+        if self.artists.is_empty() {
+            return true;
+        }
+
+        let user = match &track.user {
+            Some(user) => user,
+            None => return false
+        };
+
+        self.artists.iter().any(|wanted| {
+            let eq = |s: &Option<String>| s.as_deref().is_some_and(|s| s.eq_ignore_ascii_case(wanted));
+            eq(&user.username) || eq(&user.permalink) || wanted.parse().ok() == user.id
+        })
+    }
+
+    /// Whether the given playlist was selected (and not excluded).
+    pub fn matches_playlist(&self, playlist: &Playlist) -> bool {
+        let matches = |pattern: &String| {
+            playlist.id.is_some_and(|id| pattern.parse() == Ok(id))
+                || playlist.title.as_deref().is_some_and(|title| wildcard_match(pattern, title))
+        };
+
+        (self.playlists.is_empty() || self.playlists.iter().any(matches))
+            && !self.excluded_playlists.iter().any(matches)
+    }
+
+    /// Drops unselected playlists and every playlist track that doesn't match,
+    /// along with playlists left without any tracks. Returns how many tracks
+    /// were dropped for being restricted.
+    pub fn retain_playlist_tracks(&self, playlists: &mut Playlists) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        let mut restricted = 0;
+
+        playlists.playlists.retain(|p| {
+            let matches = self.matches_playlist(p);
+            if !matches {
+                logging::info(&format!("Skipping playlist {}: not selected", p.title.as_deref().unwrap_or("untitled")));
+            }
+            matches
+        });
+
+        for playlist in &mut playlists.playlists {
+            let playlist_title = playlist.title.as_deref().unwrap_or("untitled");
+            if let Some(tracks) = &mut playlist.tracks {
+                tracks.retain(|t| match self.skip_reason(t.created_at.as_deref(), t) {
+                    Some(reason) => {
+                        logging::info(&format!(
+                            "Skipping {} in {}: {}",
+                            t.title.as_deref().unwrap_or("untitled"),
+                            playlist_title,
+                            reason
+                        ));
+                        if Restriction::of(t).is_some() {
+                            restricted += 1;
+                        }
+                        false
+                    },
+                    None => true
+                });
+            }
+        }
+        playlists.playlists.retain(|p| p.tracks.as_ref().is_some_and(|t| !t.is_empty()));
+
+        restricted
+    }
+}
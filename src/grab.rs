@@ -0,0 +1,119 @@
+//! Downloading a single track or playlist straight from its URL, with no JSON
+//! archive needed.
+
+use crate::api_usage::ApiBudget;
+use crate::concurrency::{Credentials, Semaphore};
+use crate::download::{download_loose_tracks, download_track, TrackSaver};
+use crate::atomic::write_json;
+use crate::events::EventFeed;
+use crate::filter::TrackFilter;
+use crate::naming::{FolderLayout, Namer};
+use crate::progress::Progress;
+use crate::sidecar::SidecarOptions;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::user::fill_tracks;
+use crate::{sanitize, Error};
+use orange_zest::api::{Playlist, Playlists, TrackInfo};
+use orange_zest::Zester;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/// Downloads the track at `url` into `output_folder`, with a sidecar.
+pub fn track(
+    url: &str,
+    output_folder: &Path,
+    sidecar_opts: SidecarOptions,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    fs::create_dir_all(output_folder)?;
+
+    let budget = ApiBudget::new("track", None);
+    let resolved: Value = api_client.resolve(url)?;
+    budget.record(1);
+    let track: TrackInfo = match resolved.get("kind").and_then(Value::as_str) {
+        Some("track") => serde_json::from_value(resolved)
+            .map_err(|e| Error::HttpError(format!("unexpected response for {}: {}", url, e)))?,
+        Some("playlist") => return Err(Error::InvalidUrl(format!("{} is a playlist; use `zester playlist` for it", url))),
+        other => return Err(Error::InvalidUrl(format!("{} is a {}, not a track", url, other.unwrap_or("something unknown"))))
+    };
+    let title = track.title.clone().unwrap_or_else(|| "untitled".into());
+
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::Flat, filename: None };
+    let mut saver = TrackSaver::new(output_folder, namer, api_client, &budget, &events, pb)?;
+    saver.sidecar_opts = sidecar_opts;
+
+    pb.set_length(1);
+    pb.set_prefix(&format!("Zesting {}", title));
+    let result = download_loose_tracks(&saver, vec![track.clone()], 1, zester, credentials);
+    saver.save_manifest()?;
+    result?;
+
+    let saved = saver.was_saved(&track);
+    saver.finish()?;
+    if !saved {
+        return Err(Error::PartialFailure(format!("{} couldn't be downloaded", title)));
+    }
+    Ok(())
+}
+
+/// Downloads the tracks of the playlist or album at `url` (that pass
+/// `filter`) into `output_folder`, saving its metadata alongside them.
+pub fn playlist(
+    url: &str,
+    output_folder: &Path,
+    namer: Namer,
+    filter: &TrackFilter,
+    zester: &Zester,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    fs::create_dir_all(output_folder)?;
+
+    let budget = ApiBudget::new("playlist", None);
+    let resolved: Value = api_client.resolve(url)?;
+    budget.record(1);
+    let mut playlist: Playlist = match resolved.get("kind").and_then(Value::as_str) {
+        Some("playlist") => serde_json::from_value(resolved)
+            .map_err(|e| Error::HttpError(format!("unexpected response for {}: {}", url, e)))?,
+        Some("track") => return Err(Error::InvalidUrl(format!("{} is a track; use `zester track` for it", url))),
+        other => return Err(Error::InvalidUrl(format!("{} is a {}, not a playlist", url, other.unwrap_or("something unknown"))))
+    };
+    fill_tracks(api_client, &mut playlist, Whose::Public, || budget.record(1))?;
+    let title = playlist.title.clone().unwrap_or_else(|| "untitled".into());
+
+    let metadata = match playlist.id {
+        Some(id) => sanitize(format!("{} (id={}).json", title, id)),
+        None => sanitize(format!("{}.json", title))
+    };
+    write_json(&playlist, output_folder.join(metadata), true)?;
+
+    let events = EventFeed::default();
+    let saver = TrackSaver::new(output_folder, namer, api_client, &budget, &events, pb)?;
+    let mut playlists = Playlists { playlists: vec![playlist] };
+    saver.summary.restricted(filter.retain_playlist_tracks(&mut playlists));
+    let playlist = match playlists.playlists.pop() {
+        Some(playlist) => playlist,
+        None => {
+            pb.println(format!("None of the tracks in {} match", title));
+            return saver.finish();
+        }
+    };
+
+    pb.set_length(playlist.tracks.iter().flatten().count() as u64);
+    pb.set_prefix(&format!("Zesting {}", title));
+    let api_permits = Semaphore::new(1);
+    let result = playlist
+        .tracks
+        .iter()
+        .flatten()
+        .try_for_each(|track| download_track(&saver, zester, track, Some(&playlist), &api_permits, &saver));
+    saver.stop_if_interrupted()?;
+    saver.save_manifest()?;
+    result?;
+    saver.finish()
+}
@@ -0,0 +1,143 @@
+//! Commands run after each downloaded track (`--exec-after-track`) and after
+//! the whole run (`--exec-after-run`), for handing things off to other tools.
+//!
+//! Commands go through the shell (`sh -c`, or `cmd /C` on Windows), with
+//! placeholders like `{path}` replaced by shell-quoted values, so they don't
+//! need quoting themselves. Braces around anything else aren't touched.
+
+use crate::logging;
+use std::io;
+use std::process::Command;
+
+/// Placeholders `--exec-after-track` can use.
+pub const TRACK_FIELDS: &[&str] = &["path", "title", "artist", "playlist", "id"];
+/// Placeholders `--exec-after-run` can use.
+pub const RUN_FIELDS: &[&str] = &["output", "downloaded", "failed", "bytes"];
+
+#[derive(Debug, Clone)]
+pub struct Hook {
+    command: String,
+    fields: &'static [&'static str],
+}
+
+impl Hook {
+    /// Parses an `--exec-after-track` command.
+    pub fn parse_track(command: &str) -> Result<Self, String> {
+        Self::parse(command, TRACK_FIELDS)
+    }
+
+    /// Parses an `--exec-after-run` command.
+    pub fn parse_run(command: &str) -> Result<Self, String> {
+        Self::parse(command, RUN_FIELDS)
+    }
+
+    fn parse(command: &str, fields: &'static [&'static str]) -> Result<Self, String> {
+        if command.trim().is_empty() {
+            return Err("the command is empty".into());
+        }
+
+        Ok(Self { command: command.into(), fields })
+    }
+
+    /// Runs the command with its placeholders filled in from `values`, those
+    /// missing left empty, and waits for it to finish.
+    pub fn run(&self, values: &[(&str, String)]) -> io::Result<()> {
+        let command = self.fill(values);
+
+        logging::debug(&format!("Running {}", command));
+        let output = shell(&command).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(match stderr.trim() {
+                "" => format!("`{}` exited with {}", command, output.status),
+                stderr => format!("`{}` exited with {}: {}", command, output.status, stderr)
+            }));
+        }
+        Ok(())
+    }
+
+    // Replaces the placeholders with their quoted values. Only the names in
+    // `fields` are placeholders; any other braces (like the shell's `${HOME}`
+    // or awk's `{print $1}`) are left as they are
+    fn fill(&self, values: &[(&str, String)]) -> String {
+        let mut command = String::new();
+        let mut rest = self.command.as_str();
+        while let Some(start) = rest.find('{') {
+            command.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            let field = self.fields.iter().find(|field| {
+                after.strip_prefix(**field).is_some_and(|after| after.starts_with('}'))
+            });
+            match field {
+                Some(field) => {
+                    let value = values.iter().find(|(name, _)| name == field).map_or("", |(_, value)| value.as_str());
+                    command.push_str(&quote(value));
+                    rest = &after[field.len() + 1..];
+                },
+                None => {
+                    command.push('{');
+                    rest = after;
+                }
+            }
+        }
+        command.push_str(rest);
+        command
+    }
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(not(windows))]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    fn fill(command: &str, values: &[(&str, String)]) -> String {
+        Hook::parse_track(command).unwrap().fill(values)
+    }
+
+    #[test]
+    fn fills_in_placeholders_quoted() {
+        let values = [("path", "it's here.m4a".to_string()), ("id", "42".to_string())];
+        assert_eq!(fill("cp {path} /backup/{id}", &values), "cp 'it'\\''s here.m4a' /backup/'42'");
+    }
+
+    #[test]
+    fn leaves_missing_values_empty() {
+        assert_eq!(fill("echo {playlist}", &[]), "echo ''");
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() {
+        let values = [("path", "a.m4a".to_string())];
+        assert_eq!(fill("echo ${HOME} {path}", &values), "echo ${HOME} 'a.m4a'");
+        assert_eq!(fill("awk '{print $1}' {path}", &values), "awk '{print $1}' 'a.m4a'");
+        assert_eq!(fill("echo {output} {path", &values), "echo {output} {path");
+    }
+
+    #[test]
+    fn rejects_empty_commands() {
+        assert!(Hook::parse_run("  ").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Pages listing an archive's likes and playlists, as `serve` serves them and
+//! `export html` writes them out.
+
+/// Likes or a playlist, as listed on the index.
+pub struct Collection {
+    pub title: String,
+    /// Link to its page
+    pub href: String,
+    pub artwork: Option<String>,
+    pub tracks: Vec<Row>,
+}
+
+/// A track as listed on its collection's page.
+pub struct Row {
+    pub title: String,
+    pub artist: String,
+    pub artwork: Option<String>,
+    /// Length in milliseconds
+    pub duration: Option<u64>,
+    /// Link to its audio, if it's been archived
+    pub audio: Option<String>,
+}
+
+const STYLE: &str = "body{font-family:sans-serif;max-width:60em;margin:2em auto;padding:0 1em;background:#fafafa;color:#222}\
+a{color:#f50;text-decoration:none}\
+.grid{display:grid;grid-template-columns:repeat(auto-fill,minmax(10em,1fr));gap:1.5em}\
+.grid img,.grid .blank{width:100%;aspect-ratio:1;object-fit:cover;background:#ddd;border-radius:4px}\
+.track{display:flex;align-items:center;gap:1em;padding:.5em 0;border-bottom:1px solid #eee}\
+.track img,.track .blank{width:4em;height:4em;object-fit:cover;background:#ddd;border-radius:4px;flex:none}\
+.track .info{flex:1}.track .artist,.missing{color:#888}audio{width:20em;max-width:100%}";
+
+/// The page listing every collection.
+pub fn index_page(collections: &[Collection]) -> String {
+    let mut body = String::from("<h1>Archive</h1><div class=\"grid\">");
+    for collection in collections {
+        body.push_str(&format!(
+            "<a href=\"{}\">{}<div>{}</div><div class=\"missing\">{} tracks</div></a>",
+            escape_html(&collection.href),
+            artwork_tag(collection.artwork.as_deref()),
+            escape_html(&collection.title),
+            collection.tracks.len()
+        ));
+    }
+    body.push_str("</div>");
+    page("Archive", &body)
+}
+
+/// The page listing a collection's tracks, with a player for each one that has
+/// audio; `home` links back to the index.
+pub fn collection_page(collection: &Collection, home: &str) -> String {
+    let mut body = format!(
+        "<p><a href=\"{}\">&larr; Archive</a></p><h1>{}</h1>",
+        escape_html(home),
+        escape_html(&collection.title)
+    );
+    for track in &collection.tracks {
+        let player = match &track.audio {
+            Some(audio) => format!("<audio controls preload=\"none\" src=\"{}\"></audio>", escape_html(audio)),
+            None => "<span class=\"missing\">not downloaded</span>".into()
+        };
+        body.push_str(&format!(
+            "<div class=\"track\">{}<div class=\"info\"><div>{}</div><div class=\"artist\">{}{}</div></div>{}</div>",
+            artwork_tag(track.artwork.as_deref()),
+            escape_html(&track.title),
+            escape_html(&track.artist),
+            track.duration.map(|ms| format!(" &middot; {}:{:02}", ms / 60_000, ms / 1000 % 60)).unwrap_or_default(),
+            player
+        ));
+    }
+    page(&collection.title, &body)
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width\">\
+         <title>{}</title><style>{}</style></head><body>{}</body></html>\n",
+        escape_html(title),
+        STYLE,
+        body
+    )
+}
+
+fn artwork_tag(artwork: Option<&str>) -> String {
+    match artwork {
+        Some(url) => format!("<img src=\"{}\" alt=\"\" loading=\"lazy\">", escape_html(url)),
+        None => "<div class=\"blank\"></div>".into()
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,98 @@
+//! Stopping cleanly on Ctrl-C or SIGTERM, so that an interrupted run doesn't
+//! leave truncated audio behind and still records how far it got.
+
+use crate::Error;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+/// What zester exits with when it's been interrupted (the usual 128 + SIGINT)
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Whether something is around to stop the run cleanly; until then there's
+/// nothing worth waiting for
+static STOPS_CLEANLY: AtomicBool = AtomicBool::new(false);
+/// How many tracks are being written to disk right now
+static WRITING: AtomicUsize = AtomicUsize::new(0);
+static STOPPING: Mutex<()> = Mutex::new(());
+
+/// Traps Ctrl-C and SIGTERM. The first one asks the run to stop once the
+/// downloads in progress have been cleaned up; a second one, or one before
+/// anything that stops cleanly has started, calls `quit`, which is expected to
+/// end the process.
+pub fn install(quit: impl Fn() + Send + 'static) -> Result<(), Error> {
+    ctrlc::set_handler(move || {
+        if !STOPS_CLEANLY.load(Ordering::SeqCst) || INTERRUPTED.swap(true, Ordering::SeqCst) {
+            quit();
+            return;
+        }
+
+        eprintln!("Stopping after cleaning up the downloads in progress (press Ctrl-C again to quit right away)");
+    })
+    .map_err(|e| Error::IoError(io::Error::other(e)))
+}
+
+/// Marks the run as one that stops cleanly when interrupted, by checking
+/// `requested` as it goes.
+pub fn stop_cleanly() {
+    STOPS_CLEANLY.store(true, Ordering::SeqCst);
+}
+
+/// Whether the run has been asked to stop.
+pub fn requested() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails with [`Error::Interrupted`] once the run has been asked to stop, for
+/// checking between tracks.
+pub fn check() -> Result<(), Error> {
+    if requested() {
+        return Err(Error::Interrupted);
+    }
+
+    Ok(())
+}
+
+/// Marks a track as being written to disk until the returned guard is dropped.
+pub fn writing() -> WritingGuard {
+    WRITING.fetch_add(1, Ordering::SeqCst);
+    WritingGuard
+}
+
+pub struct WritingGuard;
+
+impl Drop for WritingGuard {
+    fn drop(&mut self) {
+        WRITING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for every track being written to disk to be finished or cleaned up,
+/// returning a guard that keeps any other thread from stopping the run at the
+/// same time.
+pub fn stopping() -> MutexGuard<'static, ()> {
+    let guard = STOPPING.lock().unwrap_or_else(|e| e.into_inner());
+    while WRITING.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    guard
+}
+
+/// Wraps a download so that reading from it fails once the run has been
+/// interrupted, rather than finishing a track that could take minutes.
+pub struct Interruptible<R>(pub R);
+
+impl<R: Read> Read for Interruptible<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if requested() {
+            // Not `ErrorKind::Interrupted`, which `io::copy` would retry
+            return Err(io::Error::other("interrupted"));
+        }
+
+        self.0.read(buf)
+    }
+}
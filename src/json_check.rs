@@ -0,0 +1,114 @@
+//! Loading JSON while watching for format drift: fields the file has that the
+//! types reading it don't know about, and fields the types expect that the file
+//! doesn't have.
+
+use crate::{specific_json_err, Error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// How many drifted fields to name in a warning
+const MAX_LISTED: usize = 5;
+
+/// What to do about drift.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strictness {
+    /// Fill in missing fields with nulls and warn
+    Lenient,
+    /// Refuse to load the file (`--strict-json`)
+    Strict,
+}
+
+impl Strictness {
+    pub fn from_flag(strict_json: bool) -> Self {
+        if strict_json { Strictness::Strict } else { Strictness::Lenient }
+    }
+}
+
+#[derive(Default)]
+struct Drift {
+    /// Paths like `collections[].collection[].track.foo`
+    unknown: BTreeSet<String>,
+    missing: BTreeSet<String>,
+}
+
+/// Loads the JSON file at `path` as a `T`, checking it for drift.
+pub fn load_checked<T: DeserializeOwned + Serialize>(path: &Path, strictness: Strictness) -> Result<T, Error> {
+    let raw: Value = orange_zest::load_json(path)
+        .map_err(|e| specific_json_err(e, path.to_string_lossy().into()))?;
+    let loaded: T = serde_json::from_value(raw.clone())
+        .map_err(|e| Error::JsonFormatError(format!("{}: {}", path.display(), e)))?;
+
+    // Whatever `T` keeps from the file is what it knows about
+    let known = serde_json::to_value(&loaded).unwrap();
+    let mut drift = Drift::default();
+    compare(&raw, &known, &mut String::new(), &mut drift);
+    if drift.unknown.is_empty() && drift.missing.is_empty() {
+        return Ok(loaded);
+    }
+
+    let summary = format!(
+        "{}: {} unknown fields{}, {} missing fields{}",
+        path.display(),
+        drift.unknown.len(),
+        listed(&drift.unknown),
+        drift.missing.len(),
+        listed(&drift.missing)
+    );
+    match strictness {
+        Strictness::Strict => Err(Error::JsonFormatError(summary)),
+        Strictness::Lenient => {
+            eprintln!("[warning] {} (--strict-json refuses files like this)", summary);
+            Ok(loaded)
+        }
+    }
+}
+
+fn listed(fields: &BTreeSet<String>) -> String {
+    if fields.is_empty() {
+        return String::new();
+    }
+
+    let mut list: Vec<&str> = fields.iter().take(MAX_LISTED).map(String::as_str).collect();
+    if fields.len() > MAX_LISTED {
+        list.push("...");
+    }
+    format!(" ({})", list.join(", "))
+}
+
+fn compare(raw: &Value, known: &Value, path: &mut String, drift: &mut Drift) {
+    match (raw, known) {
+        (Value::Object(raw), Value::Object(known)) => {
+            for (key, raw_value) in raw {
+                let len = path.len();
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+
+                match known.get(key) {
+                    Some(known_value) => compare(raw_value, known_value, path, drift),
+                    None => {
+                        drift.unknown.insert(path.clone());
+                    }
+                }
+                path.truncate(len);
+            }
+
+            for key in known.keys().filter(|key| !raw.contains_key(*key)) {
+                drift.missing.insert(if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) });
+            }
+        },
+        (Value::Array(raw), Value::Array(known)) => {
+            let len = path.len();
+            path.push_str("[]");
+            for (raw_value, known_value) in raw.iter().zip(known) {
+                compare(raw_value, known_value, path, drift);
+            }
+            path.truncate(len);
+        },
+        _ => {}
+    }
+}
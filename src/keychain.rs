@@ -0,0 +1,66 @@
+//! Keeping credentials in the platform's keyring (Keychain, Credential Manager,
+//! Secret Service) between runs.
+
+use crate::concurrency::Credentials;
+use crate::Error;
+use keyring::Entry;
+use structopt::StructOpt;
+
+const SERVICE: &str = "orange-zester";
+
+// Which of the config file's profiles to run as
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct ProfileOpts {
+    /// Use the credentials and options of this profile from the config file
+    #[structopt(long, value_name = "name")]
+    profile: Option<String>,
+}
+
+impl ProfileOpts {
+    pub fn name(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+}
+
+// Whether to keep the credentials a run uses
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct KeyringOpts {
+    /// Remember the credentials in the system keyring for later runs
+    #[structopt(long)]
+    save_credentials: bool,
+}
+
+impl KeyringOpts {
+    pub fn should_save(&self) -> bool {
+        self.save_credentials
+    }
+}
+
+// Each profile keeps its own credentials
+fn entry(profile: Option<&str>, key: &str) -> Result<Entry, Error> {
+    let user = match profile {
+        Some(profile) => format!("{}:{}", profile, key),
+        None => key.to_string()
+    };
+
+    Ok(Entry::new(SERVICE, &user)?)
+}
+
+/// The credentials saved for the given profile (or the default one), if any.
+///
+/// A keyring that can't be reached counts as having nothing saved.
+pub fn load(profile: Option<&str>) -> Option<Credentials> {
+    let get = |key| entry(profile, key).ok()?.get_password().ok();
+
+    Some(Credentials {
+        oauth_token: get("oauth_token")?,
+        client_id: get("client_id")?
+    })
+}
+
+/// Saves the given credentials for the given profile (or the default one).
+pub fn save(profile: Option<&str>, credentials: &Credentials) -> Result<(), Error> {
+    entry(profile, "oauth_token")?.set_password(&credentials.oauth_token)?;
+    entry(profile, "client_id")?.set_password(&credentials.client_id)?;
+    Ok(())
+}
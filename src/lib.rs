@@ -0,0 +1,292 @@
+//! Archiving SoundCloud likes, playlists and audio, in a form other programs
+//! can embed; the `orange-zester` command line is built on top of it.
+//!
+//! [`Archiver`] is the place to start: it bundles the clients a run needs and
+//! fetches likes and playlists, reporting progress to an [`Observer`] and
+//! writing what it archives to an [`ArchiveSink`]. The modules below it are
+//! the building blocks the command line uses for everything else.
+
+use enum_iterator::IntoEnumIterator;
+use login::StoredLogin;
+use rpassword::read_password_from_tty;
+use std::env;
+use std::io;
+use structopt::clap::arg_enum;
+
+pub mod api_usage;
+pub mod archive;
+pub mod archiver;
+pub mod artwork;
+pub mod atomic;
+pub mod availability;
+pub mod bundle;
+pub mod cache;
+pub mod checkpoint;
+pub mod checksum;
+pub mod classify;
+pub mod clipboard;
+pub mod compact;
+pub mod concurrency;
+pub mod daemon;
+pub mod diff;
+pub mod download;
+pub mod events;
+pub mod exit;
+pub mod export;
+pub mod filter;
+pub mod grab;
+pub mod history;
+pub mod hook;
+pub mod html;
+pub mod interrupt;
+pub mod json_check;
+pub mod keychain;
+pub mod locale;
+pub mod lock;
+pub mod logging;
+pub mod login;
+pub mod manifest;
+pub mod mirror;
+pub mod naming;
+pub mod net;
+pub mod notify;
+pub mod offload;
+pub mod pace;
+pub mod panic;
+pub mod plan;
+pub mod pool;
+pub mod probe;
+pub mod progress;
+pub mod queue;
+pub mod regions;
+pub mod restriction;
+pub mod retry;
+pub mod schedule;
+pub mod schema;
+pub mod search;
+pub mod serve;
+pub mod sidecar;
+pub mod simulate;
+pub mod sink;
+pub mod songlink;
+pub mod space;
+pub mod soundcloud;
+pub mod state;
+pub mod stats;
+pub mod stream;
+pub mod subscribe;
+pub mod summary;
+pub mod throttle;
+pub mod trash;
+pub mod user;
+pub mod verify;
+pub mod watch;
+pub mod waveform;
+
+pub use archiver::{Archiver, Observer};
+pub use sink::{ArchiveSink, LocalFolder};
+
+arg_enum! {
+    #[derive(Debug, IntoEnumIterator)]
+    pub enum JsonType {
+        Followers,
+        Followings,
+        Likes,
+        LikedPlaylists,
+        History,
+        Me,
+        Playlists,
+        Reposts,
+        Stream,
+        Uploads,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, IntoEnumIterator)]
+    pub enum AudioType {
+        Likes,
+        LikedPlaylists,
+        Playlists
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum OutputFormat {
+        Text,
+        Json
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    OrangeZestError(orange_zest::Error),
+    VarError(std::env::VarError),
+    IoError(std::io::Error),
+    /// No JSON file present at path
+    JsonFileNotFound(String),
+    /// The naming script could not be loaded or failed to name a track
+    NamingScriptError(String),
+    /// The filename template is malformed
+    FilenameTemplateError(String),
+    /// A config file has invalid contents
+    ConfigError(String),
+    /// The user backed out of something that needed confirming
+    Cancelled(String),
+    SqliteError(rusqlite::Error),
+    KeyringError(keyring::Error),
+    CsvError(csv::Error),
+    /// Archived audio didn't match its manifest
+    IntegrityCheckFailed(String),
+    /// A request made outside of `orange-zest` failed
+    HttpError(String),
+    /// The OAuth token or client ID was rejected
+    InvalidCredentials(String),
+    /// A JSON file didn't have the expected format
+    JsonFormatError(String),
+    ZipError(zip::result::ZipError),
+    /// A playlist was asked for that isn't in the archive, or the request
+    /// matched several
+    NoSuchPlaylist(String),
+    /// A run was asked for that didn't download anything into the archive
+    NoSuchRun(String),
+    /// An audio file couldn't be probed for its format
+    ProbeError(String),
+    /// Another zester is already working in the same place
+    AlreadyRunning(String),
+    /// The audio to download won't fit on the disk
+    NotEnoughSpace(String),
+    /// A URL was given that isn't the kind of link the command takes
+    InvalidUrl(String),
+    /// Some of the tracks asked for couldn't be downloaded
+    PartialFailure(String),
+    /// The run was stopped by Ctrl-C or SIGTERM
+    Interrupted
+}
+
+impl From<orange_zest::Error> for Error {
+    fn from(err: orange_zest::Error) -> Self {
+        Error::OrangeZestError(err)
+    }
+}
+
+impl From<std::env::VarError> for Error {
+    fn from(err: std::env::VarError) -> Self {
+        Error::VarError(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::IoError(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::SqliteError(err)
+    }
+}
+
+impl From<keyring::Error> for Error {
+    fn from(err: keyring::Error) -> Self {
+        Error::KeyringError(err)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(err: csv::Error) -> Self {
+        Error::CsvError(err)
+    }
+}
+
+impl From<zip::result::ZipError> for Error {
+    fn from(err: zip::result::ZipError) -> Self {
+        Error::ZipError(err)
+    }
+}
+
+// Attempt to fill the given secrets from the environment, the keyring, a stored
+// login or the terminal if they are not already present
+pub fn ensure_secrets_present(
+    profile: Option<&str>,
+    oauth_token: &mut Option<String>,
+    client_id: &mut Option<String>
+) -> Result<(), Error> {
+    if oauth_token.is_none() && client_id.is_none() && env::var("OAUTH_TOKEN").is_err() {
+        if let Some(saved) = keychain::load(profile) {
+            *oauth_token = Some(saved.oauth_token);
+            *client_id = Some(saved.client_id);
+        }
+    }
+    let stored = StoredLogin::load();
+
+    if oauth_token.is_none() {
+        if let Ok(token) = env::var("OAUTH_TOKEN") {
+            *oauth_token = Some(token);
+        } else if let Some(login) = &stored {
+            *oauth_token = Some(login.oauth_token.clone());
+        } else {
+            *oauth_token = Some(read_password_from_tty(Some("OAuth token: "))?);
+        }
+    }
+
+    if client_id.is_none() {
+        if let Ok(id) = env::var("CLIENT_ID") {
+            *client_id = Some(id);
+        } else if let Some(login) = &stored {
+            *client_id = Some(login.client_id.clone());
+        } else {
+            *client_id = Some(read_password_from_tty(Some("Client ID: "))?);
+        }
+    }
+
+    Ok(())
+}
+
+// Sanitize the given filename for storage across different OS's
+pub fn sanitize<S: AsRef<str>>(name: S) -> String {
+    sanitize_filename::sanitize_with_options(
+        name,
+        sanitize_filename::Options {
+            windows: true,
+            .. Default::default()
+        }
+    )
+}
+
+// Decode the `%XX` escapes in a URL component
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// If the given generic error is an `io::ErrorKind::NotFound`, turn it into a
+// `JsonFileNotFound`.
+pub fn specific_json_err(generic_err: orange_zest::Error, filepath: String) -> Error {
+    match generic_err {
+        orange_zest::Error::IoError(e) => match e.kind() {
+            io::ErrorKind::NotFound => Error::JsonFileNotFound(filepath),
+            _ => e.into()
+        },
+        _ => generic_err.into()
+    }
+}
@@ -0,0 +1,156 @@
+//! Formatting numbers, sizes, durations and dates in reports and summaries
+//! for a locale other than the default English.
+
+use chrono::{DateTime, Utc};
+use indicatif::{HumanBytes, HumanDuration};
+use std::sync::OnceLock;
+use std::time::Duration;
+use structopt::clap::arg_enum;
+use structopt::StructOpt;
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Units {
+        Binary,
+        Si
+    }
+}
+
+// How reports format what they show
+#[derive(StructOpt, Debug)]
+pub struct ReportFormat {
+    /// Format numbers, sizes, durations and dates for this locale (e.g. de-DE, fr-FR, ja-JP)
+    #[structopt(long, parse(try_from_str = Locale::parse), value_name = "locale")]
+    locale: Option<Locale>,
+    /// Show sizes in binary (KiB, MiB) or SI (kB, MB) units
+    #[structopt(long, possible_values = &Units::variants(), case_insensitive = true, default_value = "Binary")]
+    units: Units,
+}
+
+impl ReportFormat {
+    /// Makes this the format for the rest of the run.
+    pub fn apply(self) {
+        let _ = SETTINGS.set(Settings { locale: self.locale, units: self.units });
+    }
+}
+
+struct Settings {
+    /// Unset for the default English formatting
+    locale: Option<Locale>,
+    units: Units,
+}
+
+#[derive(Debug, Clone)]
+pub struct Locale {
+    decimal: char,
+    /// Between groups of thousands
+    grouping: char,
+    /// For `chrono`'s `format`
+    date_time: &'static str,
+}
+
+impl Locale {
+    /// Parses a locale tag like `de-DE`, `fr` or `pt_BR`.
+    pub fn parse(tag: &str) -> Result<Self, String> {
+        let tag = tag.replace('_', "-").to_ascii_lowercase();
+        let (language, region) = match tag.split_once('-') {
+            Some((language, region)) => (language, Some(region)),
+            None => (tag.as_str(), None)
+        };
+
+        let locale = |decimal, grouping, date_time| Ok(Self { decimal, grouping, date_time });
+        match (language, region) {
+            ("en", Some("us") | None) => locale('.', ',', "%m/%d/%Y %H:%M"),
+            ("en", _) => locale('.', ',', "%d/%m/%Y %H:%M"),
+            ("de", Some("ch")) => locale('.', '\'', "%d.%m.%Y %H:%M"),
+            ("de", _) => locale(',', '.', "%d.%m.%Y %H:%M"),
+            ("fr", _) => locale(',', '\u{202f}', "%d/%m/%Y %H:%M"),
+            ("es" | "it" | "pt", _) => locale(',', '.', "%d/%m/%Y %H:%M"),
+            ("nl", _) => locale(',', '.', "%d-%m-%Y %H:%M"),
+            ("sv" | "nb" | "fi" | "pl" | "ru" | "uk" | "cs", _) => locale(',', '\u{a0}', "%d.%m.%Y %H:%M"),
+            ("ja" | "zh" | "ko", _) => locale('.', ',', "%Y/%m/%d %H:%M"),
+            _ => Err(format!(
+                "\"{}\" isn't a supported locale (try en, de, fr, es, it, pt, nl, sv, nb, fi, pl, ru, uk, cs, ja, zh or ko)",
+                tag
+            ))
+        }
+    }
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings { locale: None, units: Units::Binary })
+}
+
+/// A count, with its thousands grouped.
+pub fn number(n: u64) -> String {
+    match &settings().locale {
+        Some(locale) => group(&n.to_string(), locale.grouping),
+        None => n.to_string()
+    }
+}
+
+/// A size in bytes, like `1.50 MiB`.
+pub fn bytes(n: u64) -> String {
+    let settings = settings();
+    if settings.locale.is_none() && settings.units == Units::Binary {
+        return HumanBytes(n).to_string();
+    }
+
+    let (base, prefixes) = match settings.units {
+        Units::Binary => (1024.0, ["B", "KiB", "MiB", "GiB", "TiB"]),
+        Units::Si => (1000.0, ["B", "kB", "MB", "GB", "TB"])
+    };
+    let mut value = n as f64;
+    let mut prefix = 0;
+    while value >= base && prefix < prefixes.len() - 1 {
+        value /= base;
+        prefix += 1;
+    }
+
+    if prefix == 0 {
+        return format!("{} B", number(n));
+    }
+    let formatted = format!("{:.2}", value);
+    match &settings.locale {
+        Some(locale) => {
+            let (whole, fraction) = formatted.split_once('.').unwrap();
+            format!("{}{}{} {}", group(whole, locale.grouping), locale.decimal, fraction, prefixes[prefix])
+        },
+        None => format!("{} {}", formatted, prefixes[prefix])
+    }
+}
+
+/// How long something took, like `2 h 05 min` (or `2 hours` by default).
+pub fn duration(d: Duration) -> String {
+    if settings().locale.is_none() {
+        return HumanDuration(d).to_string();
+    }
+
+    let secs = d.as_secs();
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{} s", s),
+        (0, m, s) => format!("{} min {:02} s", m, s),
+        (h, m, _) => format!("{} h {:02} min", number(h), m)
+    }
+}
+
+/// A point in time, like `2024-01-31 23:59:59 UTC`.
+pub fn date_time(at: DateTime<Utc>) -> String {
+    match &settings().locale {
+        Some(locale) => format!("{} UTC", at.format(locale.date_time)),
+        None => at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+    }
+}
+
+fn group(digits: &str, separator: char) -> String {
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
@@ -0,0 +1,69 @@
+//! Lock files that two zesters can't both hold, so that they don't work in the
+//! same place at once.
+//!
+//! The lock is the operating system's (`flock` on Unix, `LockFileEx` on
+//! Windows), taken on a file that's left in place; it goes away with the
+//! process holding it, however that ends, so there's nothing stale to clean
+//! up. The holder's process id is written into the file only to say who has
+//! it.
+
+use crate::Error;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Taken in the output folder by every run that writes into it
+pub const LOCK_FILE: &str = ".zester.lock";
+
+/// Held until dropped.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+    // Closing it is what lets go of the lock
+    _file: File,
+}
+
+impl Lock {
+    /// Takes the lock at `path`, failing if another process holds it.
+    pub fn acquire<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        // Not truncated until it's ours, as the holder's id is in there
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => {
+                let holder = holder(&path).map(|pid| format!(" (process {})", pid)).unwrap_or_default();
+                return Err(Error::AlreadyRunning(format!("zester{} is already working in {}", holder, folder(&path))));
+            },
+            Err(TryLockError::Error(e)) => return Err(e.into())
+        }
+
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+        Ok(Self { path, _file: file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The folder the lock was taken in.
+    pub fn folder(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new("."))
+    }
+}
+
+// The process holding the lock, if the lock file says (Windows doesn't let it
+// be read while it's locked)
+fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+fn folder(path: &Path) -> String {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.display().to_string(),
+        _ => ".".into()
+    }
+}
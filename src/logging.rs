@@ -0,0 +1,91 @@
+//! Detail about what a run is doing beyond what the progress bar shows: shown
+//! with `-v`/`-vv`, and always written in full to `--log-file` if one is given.
+
+use chrono::Local;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use structopt::StructOpt;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    /// Warnings and notices, which are shown regardless
+    Notice,
+    /// Retries and decisions about what to skip (`-v`)
+    Info,
+    /// Every API request (`-vv`)
+    Debug,
+}
+
+struct Logger {
+    verbosity: u8,
+    file: Option<Mutex<File>>,
+}
+
+// How much a run logs, and where to
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct LogOpts {
+    /// Show retries and skipped tracks (-v), and every API request as well (-vv)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Append a timestamped record of requests, retries, skips and warnings to this file
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    log_file: Option<PathBuf>,
+}
+
+impl LogOpts {
+    /// Sets up logging for the rest of the run as asked.
+    pub fn init(&self) -> io::Result<()> {
+        init(self.verbose, self.log_file.as_deref())
+    }
+
+    pub fn file(&self) -> Option<&Path> {
+        self.log_file.as_deref()
+    }
+}
+
+/// Sets up logging for the rest of the run, appending to the given log file.
+pub fn init(verbosity: u8, log_file: Option<&Path>) -> io::Result<()> {
+    let file = match log_file {
+        Some(path) => Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => None
+    };
+
+    let _ = LOGGER.set(Logger { verbosity, file });
+    Ok(())
+}
+
+pub fn info(msg: &str) {
+    log(Level::Info, msg);
+}
+
+pub fn debug(msg: &str) {
+    log(Level::Debug, msg);
+}
+
+/// Writes a line that's already been shown (on the progress bar, say) to the
+/// log file.
+pub fn record(level: Level, msg: &str) {
+    if let Some(file) = LOGGER.get().and_then(|logger| logger.file.as_ref()) {
+        let line = format!(
+            "{} {:<5} {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            format!("{:?}", level).to_uppercase(),
+            msg.trim_start()
+        );
+        // One write per line, so that lines from different threads don't mix
+        let _ = file.lock().unwrap().write_all(line.as_bytes());
+    }
+}
+
+fn log(level: Level, msg: &str) {
+    let verbosity = LOGGER.get().map(|logger| logger.verbosity).unwrap_or(0);
+    if level as u8 <= verbosity {
+        eprintln!("  [{}] {}", format!("{:?}", level).to_lowercase(), msg);
+    }
+
+    record(level, msg);
+}
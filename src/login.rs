@@ -0,0 +1,189 @@
+//! Getting an OAuth token without digging through the browser's devtools, and
+//! keeping it around for later runs.
+
+use crate::net;
+use crate::soundcloud::ApiClient;
+use crate::state::{load_state, save_private_state, state_path};
+use crate::{percent_decode, Error};
+use chrono::{DateTime, Utc};
+use rpassword::read_password_from_tty;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use structopt::StructOpt;
+
+const LOGIN_FILE: &str = "login.json";
+
+#[derive(StructOpt, Debug)]
+pub struct LoginOpts {
+    /// Client ID to use instead of finding the web player's
+    #[structopt(long)]
+    client_id: Option<String>,
+    /// Paste the token instead of capturing it from the browser
+    #[structopt(long)]
+    paste: bool,
+    /// Local port to capture the token on
+    #[structopt(long, default_value = "8765", value_name = "port")]
+    port: u16,
+    /// Store the login for this profile from the config file
+    #[structopt(long, value_name = "name")]
+    pub profile: Option<String>,
+    /// Forget the stored login instead
+    #[structopt(long, conflicts_with_all = &["client_id", "paste"])]
+    logout: bool,
+}
+
+/// Credentials saved by `zester login`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StoredLogin {
+    pub oauth_token: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub logged_in_at: DateTime<Utc>,
+}
+
+impl StoredLogin {
+    /// The stored login, if there is one.
+    pub fn load() -> Option<Self> {
+        load_state(LOGIN_FILE).ok().flatten()
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        // It's as good as a password
+        save_private_state(LOGIN_FILE, self)
+    }
+}
+
+pub fn run(opts: LoginOpts) -> Result<(), Error> {
+    if opts.logout {
+        let path = state_path(LOGIN_FILE)?;
+        if path.exists() {
+            fs::remove_file(&path)?;
+            println!("Forgot the stored login");
+        } else {
+            println!("Not logged in");
+        }
+        return Ok(());
+    }
+
+    let client_id = match opts.client_id.or_else(|| env::var("CLIENT_ID").ok()) {
+        Some(id) => id,
+        None => {
+            println!("Finding the web player's client ID...");
+            match web_client_id() {
+                Ok(id) => id,
+                Err(e) => {
+                    println!("Couldn't find it ({:?})", e);
+                    read_password_from_tty(Some("Client ID: "))?
+                }
+            }
+        }
+    };
+
+    let oauth_token = if opts.paste {
+        println!("Log in at https://soundcloud.com, then copy the value of its `oauth_token` cookie.");
+        read_password_from_tty(Some("OAuth token: "))?
+    } else {
+        capture_token(opts.port)?
+    };
+
+    // Make sure it actually works before keeping it
+    let me = ApiClient::new(oauth_token.clone(), client_id.clone(), Vec::new()).check_credentials()?;
+    let login = StoredLogin {
+        oauth_token,
+        client_id,
+        username: me.username,
+        logged_in_at: Utc::now()
+    };
+    login.save()?;
+
+    println!(
+        "Logged in as {}; later runs will use this login unless given other credentials",
+        login.username.as_deref().unwrap_or("an unnamed user")
+    );
+    Ok(())
+}
+
+// Waits for a bookmarklet run on soundcloud.com to send over the session's
+// token
+fn capture_token(port: u16) -> Result<String, Error> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+
+    println!("1. Log in at https://soundcloud.com in your browser.");
+    println!("2. Make a bookmark with this as its URL, then open it while on soundcloud.com:");
+    println!();
+    println!(
+        "javascript:location='http://127.0.0.1:{}/?oauth_token='+encodeURIComponent(document.cookie.match(/oauth_token=([^;]+)/)[1])",
+        port
+    );
+    println!();
+    println!("Waiting for the token (Ctrl-C to give up; `zester login --paste` to paste it instead)...");
+
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let mut request_line = String::new();
+        BufReader::new(&stream).read_line(&mut request_line)?;
+
+        // GET /?oauth_token=... HTTP/1.1
+        let token = request_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|target| target.split("oauth_token=").nth(1))
+            .map(|token| percent_decode(token.split('&').next().unwrap()))
+            .filter(|token| !token.is_empty());
+
+        let body = if token.is_some() {
+            "Got it! You can close this tab and go back to orange-zester."
+        } else {
+            "No token in that request; make sure you're logged in on soundcloud.com and try again."
+        };
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+
+        if let Some(token) = token {
+            return Ok(token);
+        }
+    }
+
+    unreachable!("incoming() never ends")
+}
+
+// The web player embeds its client ID in one of the scripts soundcloud.com
+// loads, usually one of the last
+fn web_client_id() -> Result<String, Error> {
+    let fetch = |url: &str| -> Result<String, Error> {
+        let resp = net::get(url).call();
+        if !resp.ok() {
+            return Err(Error::HttpError(format!("GET {} returned {}", url, resp.status())));
+        }
+        Ok(resp.into_string()?)
+    };
+
+    let page = fetch("https://soundcloud.com")?;
+    let scripts: Vec<&str> = page
+        .split("<script crossorigin src=\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .collect();
+
+    for script in scripts.iter().rev() {
+        let source = fetch(script)?;
+        let id = source
+            .split("client_id:\"")
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .filter(|id| id.len() == 32 && id.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        if let Some(id) = id {
+            return Ok(id.into());
+        }
+    }
+
+    Err(Error::HttpError("no client ID in any of soundcloud.com's scripts".into()))
+}
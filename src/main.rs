@@ -1,22 +1,95 @@
 use structopt::StructOpt;
-use structopt::clap::arg_enum;
-use rpassword::read_password_from_tty;
 use enum_iterator::IntoEnumIterator;
-use indicatif::{ProgressBar, ProgressStyle};
-use orange_zest::{write_json, Zester};
-use orange_zest::api::{Likes, Playlists};
-use orange_zest::events::*;
+use indicatif::ProgressStyle;
 use dotenv::dotenv;
-use std::thread;
-use std::cell::RefCell;
 use std::time::Duration;
-use std::env;
 use std::path::{Path, PathBuf};
-use std::fs;
-use std::fs::File;
 use std::io;
-use std::io::Read;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+mod config;
+
+use config::Config;
+use orange_zester::archiver::{AudioOptions, Observer};
+use orange_zester::{
+    archive, availability, bundle, cache, clipboard, compact, daemon, diff, exit, export, filter, grab,
+    history, interrupt, keychain, locale, lock, login, mirror, offload,
+    panic, pool, progress, queue, regions, retry, schema, search, serve, simulate, sink,
+    space, state, stats, stream, subscribe, throttle, trash, user, verify, watch
+};
+use orange_zester::{ensure_secrets_present, sanitize, Archiver, AudioType, Error, JsonType, OutputFormat};
+use orange_zester::api_usage::ApiBudget;
+use orange_zester::bundle::BundleOpts;
+use orange_zester::cache::CacheCommand;
+use orange_zester::checkpoint::Checkpoint;
+use orange_zester::compact::CompactOpts;
+use orange_zester::concurrency::Credentials;
+use orange_zester::daemon::DaemonOpts;
+use orange_zester::download::{DedupMode, TrackSaver};
+use orange_zester::history::{HistoryCommand, Outcome};
+use orange_zester::hook::Hook;
+use orange_zester::diff::DiffOpts;
+use orange_zester::events::{Event, EventFeed, ProgressMode};
+use orange_zester::export::ExportOpts;
+use orange_zester::filter::{DateRange, TrackFilter};
+use orange_zester::json_check::Strictness;
+use orange_zester::keychain::{KeyringOpts, ProfileOpts};
+use orange_zester::locale::ReportFormat;
+use orange_zester::logging::LogOpts;
+use orange_zester::lock::Lock;
+use orange_zester::login::LoginOpts;
+use orange_zester::manifest::Manifest;
+use orange_zester::mirror::Prune;
+use orange_zester::naming::{FolderLayout, Namer, Template};
+use orange_zester::net::{ApiOpts, TimeoutOpts};
+use orange_zester::notify::{self, NotifyOpts};
+use orange_zester::offload::{OffloadOpts, RecallOpts};
+use orange_zester::plan::DryRun;
+use orange_zester::progress::{Phases, Progress, Transfer};
+use orange_zester::queue::QueueCommand;
+use orange_zester::schema::SchemaOpts;
+use orange_zester::search::SearchOpts;
+use orange_zester::sink::{ArchiveSink, SinkUrl};
+use orange_zester::serve::ServeOpts;
+use orange_zester::sidecar::SidecarOptions;
+use orange_zester::simulate::SimulateOpts;
+use orange_zester::songlink::SongLinks;
+use orange_zester::soundcloud::Whose;
+use orange_zester::space::SpaceCheck;
+use orange_zester::stats::StatsOpts;
+use orange_zester::subscribe::SubscribeCommand;
+use orange_zester::summary::RunSummary;
+use orange_zester::throttle::RateLimiter;
+use orange_zester::trash::{Trash, TrashCommand};
+use orange_zester::user::OtherUser;
+use orange_zester::verify::VerifyOpts;
+use orange_zester::watch::WatchOpts;
+use orange_zester::waveform::WaveformFormat;
+
+#[derive(StructOpt, Debug)]
+#[structopt(after_help = exit::HELP)]
+struct Cli {
+    /// Print an error that stops zester as text, or as a JSON object (kind, message,
+    /// failed_item, suggested_action and exit_code) for other programs to read
+    #[structopt(
+        long,
+        global = true,
+        possible_values = &OutputFormat::variants(),
+        case_insensitive = true,
+        default_value = "Text"
+    )]
+    error_format: OutputFormat,
+    #[structopt(subcommand)]
+    opts: Opts,
+}
+
+// Only ever one of these around, parsed once at startup
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt, Debug)]
 enum Opts {
     /// Obtain JSON archives of meaningful data
@@ -27,7 +100,20 @@ enum Opts {
         /// Client ID
         #[structopt(long)]
         client_id: Option<String>,
-        /// Only get n most recent items
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Only get the n most recent likes and playlists, adding them to those already
+        /// in the output folder
         #[structopt(short, long, value_name = "n")]
         recent: Option<u64>,
         /// Download all available data (archive everything)
@@ -36,9 +122,52 @@ enum Opts {
         /// Pretty print the JSON output
         #[structopt(short, long)]
         pretty_print: bool,
+        /// Write a file per playlist into playlists/, with an index, instead of one playlists.json
+        #[structopt(long)]
+        split: bool,
+        /// Write likes into likes.ndjson a page at a time as they come in, instead of all at once
+        /// into likes.json; an interrupted run carries on where it stopped
+        #[structopt(long)]
+        stream: bool,
+        /// Only keep likes made and playlists created at or after this date (YYYY-MM-DD or RFC 3339)
+        #[structopt(long, parse(try_from_str = filter::parse_since), value_name = "date")]
+        since: Option<DateTime<Utc>>,
+        /// Only keep likes made and playlists created before the end of this date
+        #[structopt(long, parse(try_from_str = filter::parse_until), value_name = "date")]
+        until: Option<DateTime<Utc>>,
+        /// Make at most n API calls during this run
+        #[structopt(long, value_name = "n")]
+        max_api_calls: Option<u64>,
+        /// Stream NDJSON progress events to processes connected to a Unix socket at this path
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        event_socket: Option<PathBuf>,
+        /// Show progress as a bar, as NDJSON events on stdout for other programs to read,
+        /// as plain timestamped log lines (the default when stdout isn't a terminal), or
+        /// as periodic full sentences for screen readers (plain-verbose)
+        #[structopt(
+            long,
+            possible_values = &ProgressMode::variants(),
+            case_insensitive = true,
+            default_value = "Bar"
+        )]
+        progress: ProgressMode,
+        /// Log progress as plain timestamped lines; the same as --progress plain
+        #[structopt(long, conflicts_with = "progress")]
+        no_progress: bool,
+        /// How often plain progress says how far along the run is (e.g. 2m; 30s by default)
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        progress_interval: Option<u64>,
         /// Output folder
-        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
-        output_folder: PathBuf,
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        output_folder: Option<PathBuf>,
+        /// Store the JSON here instead of in the output folder: s3://bucket/prefix, davs://host/path
+        /// (WebDAV over HTTPS), dav://host/path or a path
+        #[structopt(long, parse(try_from_str = sink::parse_url), value_name = "url", conflicts_with_all = &["split", "stream"])]
+        output: Option<SinkUrl>,
+        /// Get the public data of this user (a permalink, profile URL or id) instead of your
+        /// own, into users/<permalink> in the output folder
+        #[structopt(long, value_name = "user")]
+        user: Option<String>,
         /// Data kinds to get
         #[structopt(
             possible_values = &JsonType::variants(),
@@ -56,18 +185,173 @@ enum Opts {
         /// Client ID
         #[structopt(long)]
         client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
         /// Only get n most recent items
         #[structopt(short, long, value_name = "n")]
         recent: Option<u64>,
         /// Download all available audio (playlists, likes, etc.)
         #[structopt(short, long)]
         all: bool,
+        /// Make at most n API calls during this run
+        #[structopt(long, value_name = "n")]
+        max_api_calls: Option<u64>,
+        /// Stream NDJSON progress events to processes connected to a Unix socket at this path
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        event_socket: Option<PathBuf>,
+        /// Show progress as a bar, as NDJSON events on stdout for other programs to read,
+        /// as plain timestamped log lines (the default when stdout isn't a terminal), or
+        /// as periodic full sentences for screen readers (plain-verbose)
+        #[structopt(
+            long,
+            possible_values = &ProgressMode::variants(),
+            case_insensitive = true,
+            default_value = "Bar"
+        )]
+        progress: ProgressMode,
+        /// Log progress as plain timestamped lines; the same as --progress plain
+        #[structopt(long, conflicts_with = "progress")]
+        no_progress: bool,
+        /// How often plain progress says how far along the run is (e.g. 2m; 30s by default)
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        progress_interval: Option<u64>,
+        /// Look up the streams of at most n tracks from the API at once (default 1); each download
+        /// looks up its own stream, so this can't be more than --download-concurrency
+        #[structopt(long, value_name = "n")]
+        api_concurrency: Option<usize>,
+        /// Download the audio of at most n tracks from the CDN at once (default 1)
+        #[structopt(long, value_name = "n")]
+        download_concurrency: Option<usize>,
+        /// Download audio at most this fast, across all downloads (e.g. 2MiB/s, 500KB/s)
+        #[structopt(long, parse(try_from_str = throttle::parse_rate), value_name = "rate")]
+        limit_rate: Option<u64>,
+        /// Warn before downloading if the audio would leave less than this free on the disk
+        /// (default 1GiB); runs that wouldn't fit at all stop before downloading anything
+        #[structopt(long, parse(try_from_str = space::parse_size), value_name = "size")]
+        min_free_space: Option<u64>,
         /// Output folder
-        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
-        output_folder: PathBuf,
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        output_folder: Option<PathBuf>,
+        /// Upload the audio and manifest here as well: s3://bucket/prefix, davs://host/path
+        /// (WebDAV over HTTPS) or dav://host/path; sidecars and the run's bookkeeping stay in
+        /// the output folder
+        #[structopt(long, parse(try_from_str = sink::parse_url), value_name = "url", conflicts_with = "mirror")]
+        output: Option<SinkUrl>,
         /// Input folder from which to obtain JSON
-        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
-        input_folder: PathBuf,
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        input_folder: Option<PathBuf>,
+        /// Get the audio for the JSON of this user zested with `json --user`, from and into
+        /// users/<permalink> in the input and output folders
+        #[structopt(long, value_name = "user")]
+        user: Option<String>,
+        /// Refuse archives with fields that are unknown or missing instead of warning
+        #[structopt(long)]
+        strict_json: bool,
+        /// Lua script whose `track_path(track)` function decides where each track is saved
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        naming_script: Option<PathBuf>,
+        /// Name files after this template, using {artist}, {title}, {id}, {upload_date}, {year},
+        /// {month}, {playlist} and {index}
+        #[structopt(long, conflicts_with = "naming_script", value_name = "template")]
+        filename_template: Option<String>,
+        /// Sort tracks into folders by source, artist, playlist or year, or not at all (flat);
+        /// also takes a folder template like "{artist}/{year}"
+        #[structopt(long, conflicts_with = "naming_script", value_name = "layout")]
+        organize_by: Option<String>,
+        /// Save the uploader's own comments (often buy / download links) in a sidecar
+        #[structopt(long)]
+        uploader_comments: bool,
+        /// Record where each track can be found on other platforms (Spotify, Apple Music, Bandcamp, ...)
+        /// in its sidecar, via song.link; limited to about 10 tracks a minute
+        #[structopt(long)]
+        song_links: bool,
+        /// Download the visuals (artwork shown while a track plays) into a folder next to its sidecar
+        #[structopt(long)]
+        visuals: bool,
+        /// Download each track's artwork next to its sidecar, at the biggest size available
+        /// (falling back on the uploader's avatar), noting in the sidecar which it is
+        #[structopt(long)]
+        artwork: bool,
+        /// Save each track's waveform next to its sidecar, as the JSON samples, drawn as a PNG,
+        /// or both
+        #[structopt(long, possible_values = &WaveformFormat::variants(), case_insensitive = true, value_name = "format")]
+        waveform: Option<WaveformFormat>,
+        /// How to store tracks that turn up in several places after downloading them once
+        #[structopt(
+            long,
+            possible_values = &DedupMode::variants(),
+            case_insensitive = true,
+            default_value = "Hardlink"
+        )]
+        dedup_mode: DedupMode,
+        /// Print which tracks would be downloaded where, without downloading any audio
+        #[structopt(long)]
+        dry_run: bool,
+        /// Only get tracks uploaded by the given artist (username, permalink or id; repeatable)
+        #[structopt(long = "artist", value_name = "name_or_id", number_of_values = 1)]
+        artists: Vec<String>,
+        /// Only get tracks liked (or for playlist tracks, uploaded) at or after this date
+        #[structopt(long, parse(try_from_str = filter::parse_since), value_name = "date")]
+        since: Option<DateTime<Utc>>,
+        /// Only get tracks liked (or for playlist tracks, uploaded) before the end of this date
+        #[structopt(long, parse(try_from_str = filter::parse_until), value_name = "date")]
+        until: Option<DateTime<Utc>>,
+        /// Only get tracks in the given genre (repeatable)
+        #[structopt(long = "genre", value_name = "genre", number_of_values = 1)]
+        genres: Vec<String>,
+        /// Only get tracks with the given tag (repeatable)
+        #[structopt(long = "tag", value_name = "tag", number_of_values = 1)]
+        tags: Vec<String>,
+        /// Only get tracks at least this long (e.g. 90s, 20m, 1h30m)
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        min_duration: Option<u64>,
+        /// Only get tracks at most this long
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        max_duration: Option<u64>,
+        /// Only get playlists with this title or id; `*` and `?` wildcards work in titles (repeatable)
+        #[structopt(long = "playlist", value_name = "name_or_id", number_of_values = 1)]
+        playlists: Vec<String>,
+        /// Skip playlists with this title or id; `*` and `?` wildcards work in titles (repeatable)
+        #[structopt(long = "exclude-playlist", value_name = "name_or_id", number_of_values = 1)]
+        excluded_playlists: Vec<String>,
+        /// Leave out tracks that look like podcasts, talk shows and other spoken word
+        #[structopt(long, conflicts_with = "only_spoken")]
+        skip_spoken: bool,
+        /// Only get tracks that look like podcasts, talk shows and other spoken word
+        #[structopt(long)]
+        only_spoken: bool,
+        /// Try tracks that are blocked or only available as a preview instead of skipping them
+        #[structopt(long)]
+        include_restricted: bool,
+        #[structopt(flatten)]
+        format: ReportFormat,
+        /// Afterwards, move audio for tracks no longer liked or in any playlist to _removed/
+        #[structopt(long)]
+        mirror: bool,
+        /// With --mirror, delete that audio instead (by way of the trash)
+        #[structopt(long, requires = "mirror")]
+        delete: bool,
+        /// Trash folder for --delete (defaults to .zester-trash in the output folder)
+        #[structopt(long, parse(from_os_str), value_name = "path", requires = "delete")]
+        trash_dir: Option<PathBuf>,
+        /// Run this shell command after each track is saved, with {path}, {title}, {artist},
+        /// {playlist} and {id} filled in (already quoted); other braces are left as they are
+        #[structopt(long, parse(try_from_str = Hook::parse_track), value_name = "command")]
+        exec_after_track: Option<Hook>,
+        /// Run this shell command once the run is done, with {output}, {downloaded}, {failed}
+        /// and {bytes} filled in (already quoted); other braces are left as they are
+        #[structopt(long, parse(try_from_str = Hook::parse_run), value_name = "command")]
+        exec_after_run: Option<Hook>,
         /// Audio kinds to get
         #[structopt(
             possible_values = &AudioType::variants(),
@@ -76,9 +360,329 @@ enum Opts {
             min_values = 1
         )]
         audio_types: Vec<AudioType>
+    },
+    /// Grab everything an account has uploaded as fast as possible, before it's deleted
+    Panic {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Permalink (soundcloud.com/<permalink>) or profile URL of the account
+        #[structopt(long, value_name = "permalink")]
+        user: String,
+        /// Output folder
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        output_folder: Option<PathBuf>,
+        /// How many tracks to look up and download at once (default 8)
+        #[structopt(long, value_name = "n")]
+        concurrency: Option<usize>,
+        /// Don't ask for confirmation first
+        #[structopt(long)]
+        yes: bool,
+    },
+    /// Watch the clipboard for SoundCloud links and download them into an inbox folder
+    ClipboardWatch {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Folder to download linked tracks into
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        output_folder: Option<PathBuf>,
+        /// How often to check the clipboard, in milliseconds
+        #[structopt(long, default_value = "1000", value_name = "ms")]
+        interval: u64,
+    },
+    /// Download a single track from its soundcloud.com URL, with a sidecar, without
+    /// needing a JSON archive
+    Track {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// soundcloud.com track URL
+        url: String,
+        /// Folder to download the track into
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+        /// Capture the comments the uploader left on the track in its sidecar
+        #[structopt(long)]
+        uploader_comments: bool,
+        /// Download the visuals (artwork shown while the track plays) into a folder next to its sidecar
+        #[structopt(long)]
+        visuals: bool,
+        /// Download the track's artwork next to its sidecar, at the biggest size available
+        #[structopt(long)]
+        artwork: bool,
+        /// Save the track's waveform next to its sidecar, as the JSON samples, drawn as a PNG,
+        /// or both
+        #[structopt(long, possible_values = &WaveformFormat::variants(), case_insensitive = true, value_name = "format")]
+        waveform: Option<WaveformFormat>,
+    },
+    /// Download a single playlist or album from its soundcloud.com URL (yours or anyone's
+    /// public one), without needing a JSON archive
+    Playlist {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// soundcloud.com playlist or album URL
+        url: String,
+        /// Folder to download the playlist into
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+        /// Name files after this template, using {artist}, {title}, {id}, {upload_date}, {year},
+        /// {month}, {playlist} and {index}
+        #[structopt(long, value_name = "template")]
+        filename_template: Option<String>,
+        /// Sort tracks into folders by source, artist, playlist or year, or not at all (flat);
+        /// also takes a folder template like "{artist}/{year}"
+        #[structopt(long, value_name = "layout")]
+        organize_by: Option<String>,
+        /// Only get tracks uploaded by the given artist (username, permalink or id; repeatable)
+        #[structopt(long = "artist", value_name = "name_or_id", number_of_values = 1)]
+        artists: Vec<String>,
+        /// Only get tracks uploaded at or after this date
+        #[structopt(long, parse(try_from_str = filter::parse_since), value_name = "date")]
+        since: Option<DateTime<Utc>>,
+        /// Only get tracks uploaded before the end of this date
+        #[structopt(long, parse(try_from_str = filter::parse_until), value_name = "date")]
+        until: Option<DateTime<Utc>>,
+        /// Only get tracks in the given genre (repeatable)
+        #[structopt(long = "genre", value_name = "genre", number_of_values = 1)]
+        genres: Vec<String>,
+        /// Only get tracks with the given tag (repeatable)
+        #[structopt(long = "tag", value_name = "tag", number_of_values = 1)]
+        tags: Vec<String>,
+        /// Only get tracks at least this long (e.g. 90s, 20m, 1h30m)
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        min_duration: Option<u64>,
+        /// Only get tracks at most this long
+        #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+        max_duration: Option<u64>,
+        /// Leave out tracks that look like podcasts, talk shows and other spoken word
+        #[structopt(long, conflicts_with = "only_spoken")]
+        skip_spoken: bool,
+        /// Only get tracks that look like podcasts, talk shows and other spoken word
+        #[structopt(long)]
+        only_spoken: bool,
+        /// Try tracks that are blocked or only available as a preview instead of skipping them
+        #[structopt(long)]
+        include_restricted: bool,
+    },
+    /// Retry just the tracks listed in failures.json by an earlier audio run
+    RetryFailed {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Make at most n API calls during this run
+        #[structopt(long, value_name = "n")]
+        max_api_calls: Option<u64>,
+        /// Audio output folder holding the failures.json
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+    },
+    /// Check which archived tracks have been deleted, privated or blocked since
+    CheckAvailability {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Input folder from which to obtain JSON
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        input_folder: PathBuf,
+        /// Folder to write removed-tracks.json to (defaults to the input folder)
+        #[structopt(short, long, parse(from_os_str), value_name = "path")]
+        output_folder: Option<PathBuf>,
+    },
+    /// Check which regions a track can be streamed from, by probing it through proxies
+    CheckRegions {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// soundcloud.com URL of the track
+        #[structopt(long, value_name = "url")]
+        track: String,
+        /// File listing a proxy URL per line, each optionally followed by a label such as its country
+        #[structopt(long, parse(from_os_str), value_name = "path")]
+        via: PathBuf,
+    },
+    /// Hardlink identical audio files in an existing archive together
+    Compact(CompactOpts),
+    /// Run zester commands on a schedule, optionally controlled over a socket
+    Daemon(DaemonOpts),
+    /// Stay running and keep an archive's likes, playlists and their audio up to date on a
+    /// schedule, for running as a service
+    Watch(WatchOpts),
+    /// Compare two JSON archives, reporting what was added and removed
+    Diff(DiffOpts),
+    /// Convert pre-obtained JSON archives into other formats
+    Export(ExportOpts),
+    /// Check archived audio against its manifest, reporting missing, corrupted and extra files
+    #[structopt(alias = "scrub")]
+    Verify(VerifyOpts),
+    /// Move old audio out of the archive to secondary storage
+    Offload(OffloadOpts),
+    /// Bring offloaded audio back into the archive
+    Recall(RecallOpts),
+    /// Show statistics about previous runs and existing archives
+    Stats(StatsOpts),
+    /// Print JSON Schema documents describing the files zester writes
+    Schema(SchemaOpts),
+    /// Queue up links to download later, then download them
+    Queue {
+        #[structopt(subcommand)]
+        command: QueueCommand
+    },
+    /// Save the account's JSON and audio, check the audio and pack it all into one zip
+    /// with an index and checksums
+    Bundle(BundleOpts),
+    /// Make up an archive of silent tracks, without an account or network access, to try
+    /// layouts, templates, exports and the like on
+    Simulate(SimulateOpts),
+    /// Search the titles, artists, genres, tags and descriptions of archived tracks, listing
+    /// where each match's audio is
+    Search(SearchOpts),
+    /// Serve a web page for browsing the archive's likes and playlists and playing their
+    /// audio
+    Serve(ServeOpts),
+    /// Keep a list of artists whose uploads get archived, then archive them
+    Subscribe {
+        #[structopt(subcommand)]
+        command: SubscribeCommand
+    },
+    /// Log in to SoundCloud through the browser and keep the token for later runs
+    Login(LoginOpts),
+    /// Work with the profiles defined in the config file
+    Profiles {
+        #[structopt(subcommand)]
+        command: ProfilesCommand
+    },
+    /// List and inspect past runs
+    History {
+        #[structopt(flatten)]
+        format: ReportFormat,
+        #[structopt(subcommand)]
+        command: HistoryCommand
+    },
+    /// Restore or delete for good what compact has moved to the trash
+    Trash {
+        #[structopt(subcommand)]
+        command: TrashCommand
+    },
+    /// Work with the API responses kept between runs
+    Cache {
+        #[structopt(subcommand)]
+        command: CacheCommand
     }
 }
 
+#[derive(StructOpt, Debug)]
+enum ProfilesCommand {
+    /// List the profiles defined in the config file
+    List
+}
+
 impl Opts {
     /// Takes the tokens out of this `Opts` instance and hands them to you.
     fn tokens(&mut self) -> (Option<String>, Option<String>) {
@@ -86,123 +690,418 @@ impl Opts {
             Opts::Json { oauth_token, client_id, .. } => 
                 (oauth_token.take(), client_id.take()),
             Opts::Audio { oauth_token, client_id, .. } => 
-                (oauth_token.take(), client_id.take())
+                (oauth_token.take(), client_id.take()),
+            Opts::CheckAvailability { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::CheckRegions { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Panic { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::ClipboardWatch { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Track { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Playlist { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::RetryFailed { oauth_token, client_id, .. } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Queue { command: QueueCommand::Run { oauth_token, client_id, .. } } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Subscribe { command: SubscribeCommand::Run { oauth_token, client_id, .. } } =>
+                (oauth_token.take(), client_id.take()),
+            Opts::Compact(_)
+            | Opts::Daemon(_)
+            | Opts::Diff(_)
+            | Opts::Export(_)
+            | Opts::Offload(_)
+            | Opts::Recall(_)
+            | Opts::Stats(_)
+            | Opts::Schema(_)
+            | Opts::Verify(_)
+            | Opts::Queue { .. }
+            | Opts::Subscribe { .. }
+            | Opts::Bundle(_)
+            | Opts::Simulate(_)
+            | Opts::Search(_)
+            | Opts::Serve(_)
+            | Opts::Watch(_)
+            | Opts::Login(_)
+            | Opts::Profiles { .. }
+            | Opts::History { .. }
+            | Opts::Trash { .. }
+            | Opts::Cache { .. } => (None, None)
         }
     }
-}
 
-arg_enum! {
-    #[derive(Debug, IntoEnumIterator)]
-    enum JsonType {
-        Likes,
-        Me,
-        Playlists,
+    /// The config file profile this `Opts` instance asks for.
+    fn profile(&self) -> Option<&str> {
+        match self {
+            Opts::Json { profile, .. }
+            | Opts::Audio { profile, .. }
+            | Opts::CheckAvailability { profile, .. }
+            | Opts::CheckRegions { profile, .. }
+            | Opts::Panic { profile, .. }
+            | Opts::ClipboardWatch { profile, .. }
+            | Opts::Track { profile, .. }
+            | Opts::Playlist { profile, .. }
+            | Opts::RetryFailed { profile, .. }
+            | Opts::Queue { command: QueueCommand::Run { profile, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { profile, .. } } => profile.name(),
+            Opts::Bundle(bundle_opts) => bundle_opts.profile.name(),
+            Opts::Login(login_opts) => login_opts.profile.as_deref(),
+            _ => None
+        }
     }
-}
 
-arg_enum! {
-    #[derive(Debug, IntoEnumIterator)]
-    enum AudioType {
-        Likes,
-        Playlists
+    /// How progress should be reported.
+    fn progress(&self) -> ProgressMode {
+        let mode = match self {
+            Opts::Json { no_progress: true, .. } | Opts::Audio { no_progress: true, .. } => ProgressMode::Plain,
+            Opts::Json { progress, .. } | Opts::Audio { progress, .. } => *progress,
+            _ => ProgressMode::Bar
+        };
+
+        // A bar only garbles things when output is going to a file (say, under cron)
+        if mode == ProgressMode::Bar && !atty::is(atty::Stream::Stdout) {
+            ProgressMode::Plain
+        } else {
+            mode
+        }
     }
-}
 
-#[derive(Debug)]
-enum Error {
-    OrangeZestError(orange_zest::Error),
-    VarError(std::env::VarError),
-    IoError(std::io::Error),
-    /// No JSON file present at path
-    JsonFileNotFound(String)
-}
+    /// How often plain progress summarizes itself.
+    fn progress_interval(&self) -> Duration {
+        match self {
+            Opts::Json { progress_interval: Some(ms), .. } | Opts::Audio { progress_interval: Some(ms), .. } => {
+                Duration::from_millis(*ms)
+            },
+            _ => progress::SUMMARY_INTERVAL
+        }
+    }
 
-impl From<orange_zest::Error> for Error {
-    fn from(err: orange_zest::Error) -> Self {
-        Error::OrangeZestError(err)
+    /// How much detail to show, and the file to log it all to.
+    fn logging(&self) -> Option<&LogOpts> {
+        match self {
+            Opts::Json { log, .. }
+            | Opts::Audio { log, .. }
+            | Opts::CheckAvailability { log, .. }
+            | Opts::CheckRegions { log, .. }
+            | Opts::Panic { log, .. }
+            | Opts::ClipboardWatch { log, .. }
+            | Opts::Track { log, .. }
+            | Opts::Playlist { log, .. }
+            | Opts::RetryFailed { log, .. }
+            | Opts::Queue { command: QueueCommand::Run { log, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { log, .. } } => Some(log),
+            _ => None
+        }
     }
-}
 
-impl From<std::env::VarError> for Error {
-    fn from(err: std::env::VarError) -> Self {
-        Error::VarError(err)
+    /// The archive this `Opts` instance writes into, which other zesters are
+    /// kept out of while it runs.
+    fn output_folder(&self) -> Option<&Path> {
+        match self {
+            // The JSON goes there instead of into the output folder, and only
+            // a local one can be locked
+            Opts::Json { output: Some(SinkUrl::Local(folder)), .. } => Some(folder.as_path()),
+            Opts::Json { output: Some(_), .. } => None,
+            Opts::Json { output_folder, .. }
+            | Opts::Audio { output_folder, .. }
+            | Opts::Panic { output_folder, .. }
+            | Opts::ClipboardWatch { output_folder, .. } => output_folder.as_deref(),
+            Opts::Track { output_folder, .. }
+            | Opts::Playlist { output_folder, .. }
+            | Opts::RetryFailed { output_folder, .. }
+            | Opts::Queue { command: QueueCommand::Run { output_folder, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { output_folder, .. } } => Some(output_folder),
+            _ => None
+        }
     }
-}
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::IoError(err)
+    /// Which notifications to send once the run is over.
+    fn notify(&self) -> Option<&NotifyOpts> {
+        match self {
+            Opts::Json { notify, .. }
+            | Opts::Audio { notify, .. }
+            | Opts::CheckAvailability { notify, .. }
+            | Opts::CheckRegions { notify, .. }
+            | Opts::Panic { notify, .. }
+            | Opts::ClipboardWatch { notify, .. }
+            | Opts::Track { notify, .. }
+            | Opts::Playlist { notify, .. }
+            | Opts::RetryFailed { notify, .. }
+            | Opts::Queue { command: QueueCommand::Run { notify, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { notify, .. } } => Some(notify),
+            _ => None
+        }
     }
-}
 
-// Attempt to fill the given secrets from the terminal or the environment if they
-// are not already present
-fn ensure_secrets_present(oauth_token: &mut Option<String>, client_id: &mut Option<String>) -> Result<(), Error> {
-    if oauth_token.is_none() {
-        if let Ok(token) = env::var("OAUTH_TOKEN") {
-            *oauth_token = Some(token);
-        } else {
-            *oauth_token = Some(read_password_from_tty(Some("OAuth token: "))?);
+    /// How long to wait on connections and responses.
+    fn timeouts(&self) -> Option<&TimeoutOpts> {
+        match self {
+            Opts::Json { timeouts, .. }
+            | Opts::Audio { timeouts, .. }
+            | Opts::CheckAvailability { timeouts, .. }
+            | Opts::CheckRegions { timeouts, .. }
+            | Opts::Panic { timeouts, .. }
+            | Opts::ClipboardWatch { timeouts, .. }
+            | Opts::Track { timeouts, .. }
+            | Opts::Playlist { timeouts, .. }
+            | Opts::RetryFailed { timeouts, .. }
+            | Opts::Queue { command: QueueCommand::Run { timeouts, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { timeouts, .. } } => Some(timeouts),
+            _ => None
         }
     }
 
-    if client_id.is_none() {
-        if let Ok(id) = env::var("CLIENT_ID") {
-            *client_id = Some(id);
-        } else {
-            *client_id = Some(read_password_from_tty(Some("Client ID: "))?);
+    /// How requests to the API are made.
+    fn api(&self) -> Option<&ApiOpts> {
+        match self {
+            Opts::Json { api, .. }
+            | Opts::Audio { api, .. }
+            | Opts::CheckAvailability { api, .. }
+            | Opts::CheckRegions { api, .. }
+            | Opts::Panic { api, .. }
+            | Opts::ClipboardWatch { api, .. }
+            | Opts::Track { api, .. }
+            | Opts::Playlist { api, .. }
+            | Opts::RetryFailed { api, .. }
+            | Opts::Queue { command: QueueCommand::Run { api, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { api, .. } } => Some(api),
+            _ => None
         }
     }
 
-    Ok(())
+    /// Whether the credentials used should be saved to the keyring.
+    fn save_credentials(&self) -> bool {
+        match self {
+            Opts::Json { keyring, .. }
+            | Opts::Audio { keyring, .. }
+            | Opts::CheckAvailability { keyring, .. }
+            | Opts::CheckRegions { keyring, .. }
+            | Opts::Panic { keyring, .. }
+            | Opts::ClipboardWatch { keyring, .. }
+            | Opts::Track { keyring, .. }
+            | Opts::Playlist { keyring, .. }
+            | Opts::RetryFailed { keyring, .. }
+            | Opts::Queue { command: QueueCommand::Run { keyring, .. } }
+            | Opts::Subscribe { command: SubscribeCommand::Run { keyring, .. } } => keyring.should_save(),
+            _ => false
+        }
+    }
+}
+
+/// Shows what the archiver is doing on the progress bar, passing it on to the
+/// event feed and counting requests against the budget.
+struct ShowProgress<'a> {
+    pb: &'a Progress,
+    /// Set for playlists, which are listed before their tracks are fetched
+    phases: Option<&'a Phases<'a>>,
+    events: &'a EventFeed,
+    budget: &'a ApiBudget,
+    label: &'a str,
+    /// Switched to once there's a total to show
+    bar_style: Option<ProgressStyle>,
+    recent: u64,
+}
+
+impl Observer for ShowProgress<'_> {
+    fn event(&self, event: Event<'_>) {
+        match (&event, self.phases) {
+            (Event::ItemsToFetch { phase: "playlist-tracks", count }, Some(phases)) => {
+                let listed = phases.done(0);
+                phases.set_total(0, listed);
+                phases.set_total(1, *count);
+                phases.start(1);
+            },
+            (Event::ItemsToFetch { count, .. }, Some(phases)) => {
+                phases.set_total(0, *count);
+                // Until the listing's done, assume every playlist will be there
+                phases.set_total(1, (*count).min(self.recent));
+            },
+            (Event::ItemsFetched { count, .. }, Some(phases)) => phases.inc(*count),
+            (Event::PlaylistStarted { title, .. }, Some(phases)) => phases.working_on(title.unwrap_or("untitled playlist")),
+            (Event::Retrying { after_secs }, Some(phases)) => {
+                phases.working_on(&format!("Server error, retrying after {}s", after_secs))
+            },
+            (Event::ItemsToFetch { count, .. }, None) => {
+                if let Some(style) = &self.bar_style {
+                    self.pb.set_style(style.clone());
+                }
+                self.pb.set_length(*count);
+            },
+            (Event::ItemsFetched { count, .. }, None) => {
+                self.pb.set_message(self.label);
+                self.pb.inc(*count);
+            },
+            (Event::Retrying { after_secs }, None) => {
+                self.pb.set_message(&format!("Server error, retrying after {}s", after_secs))
+            },
+            _ => {}
+        }
+
+        self.events.emit(event);
+    }
+
+    fn api_call(&self) {
+        self.budget.record(1);
+    }
+
+    fn notice(&self, message: &str) {
+        self.pb.println(format!("  [notice] {}", message));
+    }
+
+    fn warning(&self, message: &str) {
+        self.pb.println(format!("  [warning] {}", message));
+    }
+}
+
+/// Shows the audio being downloaded on the progress bar, passing what the
+/// archiver's doing on to the event feed and counting requests against the
+/// budget.
+struct ShowDownloads<'a> {
+    pb: &'a Progress,
+    events: &'a EventFeed,
+    budget: &'a ApiBudget,
+    label: &'a str,
+    /// How many playlists there are to download
+    playlists: AtomicU64,
+    /// How many of them have been started on
+    started: AtomicU64,
 }
 
-// Sanitize the given filename for storage across different OS's
-fn sanitize<S: AsRef<str>>(name: S) -> String {
-    sanitize_filename::sanitize_with_options(
-        name,
-        sanitize_filename::Options {
-            windows: true,
-            .. Default::default()
+impl Observer for ShowDownloads<'_> {
+    fn event(&self, event: Event<'_>) {
+        match &event {
+            Event::ItemsToFetch { count, .. } => self.playlists.store(*count, Ordering::SeqCst),
+            Event::PlaylistStarted { title, .. } => {
+                let started = self.started.fetch_add(1, Ordering::SeqCst) + 1;
+                self.pb.set_prefix(&format!(
+                    "{} ({}/{}) - {}",
+                    self.label,
+                    started,
+                    self.playlists.load(Ordering::SeqCst),
+                    title.unwrap_or("untitled")
+                ));
+            },
+            _ => {}
         }
-    )
+
+        self.events.emit(event);
+    }
+
+    fn api_call(&self) {
+        self.budget.record(1);
+    }
+
+    fn notice(&self, message: &str) {
+        self.pb.println(format!("  [notice] {}", message));
+    }
+
+    fn warning(&self, message: &str) {
+        self.pb.println(format!("  [warning] {}", message));
+    }
 }
 
-// If the given generic error is an `io::ErrorKind::NotFound`, turn it into a
-// `JsonFileNotFound`.
-fn specific_json_err(generic_err: orange_zest::Error, filepath: String) -> Error {
-    match generic_err {
-        orange_zest::Error::IoError(e) => match e.kind() {
-            io::ErrorKind::NotFound => Error::JsonFileNotFound(filepath),
-            _ => e.into()
+fn main() {
+    let Cli { error_format, opts } = Cli::from_args();
+    let result = run(opts);
+    notify::run_ended(result.as_ref().err());
+
+    let partial = history::ended().and_then(|record| record.counts).is_some_and(|counts| counts.failed > 0);
+    match result {
+        Err(e) => {
+            e.print(error_format);
+            std::process::exit(e.exit_code());
         },
-        _ => generic_err.into()
+        Ok(()) if partial => std::process::exit(exit::PARTIAL),
+        Ok(()) => {}
     }
 }
 
-// Streams the given `Read` instance to the given file path.
-//
-// Handles pretty-printing relevant errors.
-fn stream_track_to_file<P: AsRef<Path>>(path: P, track_title: &str, pb: &ProgressBar, mut data: impl Read) {
-    match File::create(path.as_ref()) {
-        Ok(mut f) => match io::copy(&mut data, &mut f) {
-            Ok(_) => {},
-            Err(e) => {
-                pb.println(&format!("  [warning] Failed to write \"{}\" to file: {}", track_title, e));
-            }
+// Records a run that went through without a hitch as finished; one that
+// failed is recorded as it's dropped
+fn finished(result: Result<(), Error>) -> Result<(), Error> {
+    if result.is_ok() {
+        history::end(Outcome::Finished);
+    }
+    result
+}
+
+fn run(opt: Opts) -> Result<(), Error> {
+    // Before anything that can fail, so that failing is recorded and notified.
+    // Logging in doesn't touch an archive, and looking through the history
+    // shouldn't add to it
+    let log = opt.logging().cloned().unwrap_or_default();
+    let _run = match opt {
+        Opts::Login(_) | Opts::History { .. } => None,
+        _ => Some(history::begin(log.file()))
+    };
+    if let Some(notify) = opt.notify() {
+        notify.clone().apply();
+    }
+
+    // These work entirely from disk; no need for a zester
+    let mut opt = match opt {
+        Opts::Compact(compact_opts) => return finished(compact::run(compact_opts)),
+        Opts::Daemon(daemon_opts) => return finished(daemon::run(daemon_opts)),
+        Opts::Watch(watch_opts) => return finished(watch::run(watch_opts)),
+        Opts::Diff(diff_opts) => return finished(diff::run(diff_opts)),
+        Opts::Export(export_opts) => return finished(export::run(export_opts)),
+        Opts::Stats(stats_opts) => return finished(stats::run(stats_opts)),
+        Opts::Schema(schema_opts) => return finished(schema::run(schema_opts)),
+        Opts::Verify(verify_opts) => return finished(verify::run(verify_opts)),
+        Opts::Offload(offload_opts) => return finished(offload::run(offload_opts)),
+        Opts::Recall(recall_opts) => return finished(offload::recall(recall_opts)),
+        Opts::Queue { command } if command.is_local() => return finished(queue::edit(command)),
+        Opts::Subscribe { command } if command.is_local() => return finished(subscribe::edit(command)),
+        Opts::Profiles { command: ProfilesCommand::List } => return finished(config::list_profiles()),
+        Opts::History { command, format } => {
+            format.apply();
+            return history::run(command);
         },
-        Err(e) => {
-            pb.println(&format!("  [warning] Failed to create {}: {}", path.as_ref().display(), e));
-        }
+        Opts::Trash { command } => return finished(trash::run(command)),
+        Opts::Cache { command } => return finished(cache::run(command)),
+        Opts::Simulate(simulate_opts) => return finished(simulate::run(simulate_opts)),
+        Opts::Search(search_opts) => return finished(search::run(search_opts)),
+        Opts::Serve(serve_opts) => return finished(serve::run(serve_opts)),
+        opt => opt
     };
-}
 
-fn main() -> Result<(), Error> {
-    let mut opt = Opts::from_args();
+    let mut config = Config::load()?;
+    if let Some(name) = opt.profile() {
+        config.select_profile(name)?;
+    }
+    if let Opts::Login(login_opts) = opt {
+        return login::run(login_opts);
+    }
+    if let Some(path) = &config.credentials {
+        dotenv::from_path(path)
+            .map_err(|e| Error::ConfigError(format!("couldn't read credentials from {}: {}", path.display(), e)))?;
+    }
+    // Needs the profile's credentials, but runs everything else as children
+    if let Opts::Bundle(bundle_opts) = opt {
+        return finished(bundle::run(bundle_opts));
+    }
+    let credential_pool = std::mem::take(&mut config.credential_pool);
+    config.apply(&mut opt)?;
+    if let Opts::Panic { user, yes: false, .. } = &opt {
+        panic::confirm(user)?;
+    }
     dotenv().ok();
 
-    let pb = ProgressBar::new_spinner();
-    pb.enable_steady_tick(120);
+    log.init()?;
+    opt.timeouts().cloned().unwrap_or_default().apply();
+    if let Some(api) = opt.api() {
+        api.apply();
+    }
+    interrupt::install(|| {
+        eprintln!("Interrupted");
+        history::end(Outcome::Interrupted);
+        notify::run_ended(None);
+        std::process::exit(interrupt::EXIT_CODE);
+    })?;
+    let pb = Progress::new(opt.progress(), opt.progress_interval());
 
     let tick_strings = &[
         "▹▹▹▹▹",
@@ -224,57 +1123,163 @@ fn main() -> Result<(), Error> {
         .tick_strings(tick_strings)
         .progress_chars("#>-")
         .template("{spinner:.blue} {prefix:.bold}\n{msg:<40!} [{bar:30.cyan/blue}] ({pos}/{len}) ({eta})");
+    // Downloads say how fast they're going and how long is left themselves
+    let download_style = ProgressStyle::default_bar()
+        .tick_strings(tick_strings)
+        .progress_chars("#>-")
+        .template("{spinner:.blue} {prefix:.bold}\n[{bar:30.cyan/blue}] ({pos}/{len}) {wide_msg}");
 
     pb.set_style(
         spinner_style.clone()
     );
 
-    let zester;
+    let archiver;
     {
         let (mut oauth_token, mut client_id) = opt.tokens();
-        ensure_secrets_present(&mut oauth_token, &mut client_id)?;
+        ensure_secrets_present(opt.profile(), &mut oauth_token, &mut client_id)?;
+        let credentials = Credentials {
+            oauth_token: oauth_token.unwrap(),
+            client_id: client_id.unwrap()
+        };
 
         pb.set_message("Creating zester");
-        zester = Zester::new(oauth_token.unwrap(), client_id.unwrap())?;
+        archiver = Archiver::new(credentials, credential_pool)?;
         pb.println("Zester created");
+
+        // Stale tokens otherwise only show up as an opaque failure partway in
+        pb.set_message("Checking credentials");
+        archiver.check_credentials()?;
+
+        if opt.save_credentials() {
+            keychain::save(opt.profile(), archiver.credentials())?;
+            pb.println("Saved credentials to the system keyring");
+        }
     }
+    // Only once the credentials are known to work, so that bad ones don't
+    // leave an empty output folder behind
+    let lock = match opt.output_folder() {
+        Some(folder) => {
+            std::fs::create_dir_all(folder)?;
+            Some(Lock::acquire(folder.join(lock::LOCK_FILE))?)
+        },
+        None => None
+    };
+    let (zester, api_client, credentials) = (archiver.zester(), archiver.api(), archiver.credentials());
 
     match opt {
-        Opts::Json { recent, all, pretty_print, output_folder, mut json_types, .. } => {
+        Opts::Json {
+            recent,
+            all,
+            pretty_print,
+            split,
+            stream,
+            since,
+            until,
+            max_api_calls,
+            event_socket,
+            progress,
+            output_folder,
+            output,
+            user,
+            mut json_types,
+            ..
+        } => {
+            // Filled in from the config file if need be, unless going to --output
+            let output = output.unwrap_or_else(|| SinkUrl::Local(output_folder.unwrap()));
+            let other_user = user.map(|user| OtherUser::resolve(api_client, &user)).transpose()?;
+            let output = match &other_user {
+                Some(user) => user.within(&output),
+                None => output
+            };
+            let sink = output.open()?;
+            let sink = sink.as_ref();
+            // Only written to without --output, by --split and --stream
+            let output_folder = match &output {
+                SinkUrl::Local(folder) => {
+                    std::fs::create_dir_all(folder)?;
+                    folder.clone()
+                },
+                _ => PathBuf::new()
+            };
+            if let Some(user) = &other_user {
+                pb.println(format!("Zesting the public data of {} into {}", user.permalink, sink.location("")));
+            }
+            // Whose uploads and reposts to get, which the library can't
+            let whose = if other_user.is_some() { Whose::Public } else { Whose::Own };
+            let user_id = || match &other_user {
+                Some(user) => Ok(user.id),
+                None => api_client
+                    .check_credentials()?
+                    .id
+                    .ok_or_else(|| Error::HttpError("the account has no id".into()))
+            };
+
             // Manually stick all the possible types in the vector if the all flag
             // was set
             if all {
                 json_types = JsonType::into_enum_iter().collect();
             }
 
+            // A partial zest is merged into what's there rather than replacing it
+            let merge = recent.is_some();
             let recent = recent.unwrap_or(std::u64::MAX);
+            let dates = DateRange { since, until };
+            let budget = ApiBudget::new("json", max_api_calls);
+            let events = EventFeed::new(event_socket, progress)?;
+            events.emit(Event::RunStarted { command: "json" });
 
             // Grab all the data we were asked to
             for json_type in json_types {
-                match json_type {
-                    JsonType::Likes => {
-                        use LikesZestingEvent::*;
+                if budget.exhausted() {
+                    pb.println(format!("  [warning] API call budget used up, skipping {}", json_type));
+                    continue;
+                }
+
+                if matches!(json_type, JsonType::Stream | JsonType::History) && other_user.is_some() {
+                    pb.println(format!("  [skipped] {}: only your own can be zested", json_type.to_string().to_lowercase()));
+                    continue;
+                }
+
+                let phase = json_type.to_string().to_lowercase();
+                events.emit(Event::PhaseStarted { phase: &phase });
 
-                        pb.set_style(bar_style.clone());
+                match json_type {
+                    JsonType::Likes if stream => {
                         pb.set_message("Zesting likes");
 
-                        let path = output_folder.join("likes.json");
-                        let likes = zester.likes(recent, |e| match e {
-                            NumLikesInfoToDownload { num } => {
-                                pb.set_length(num);
-                            },
+                        let fetched = stream::zest_likes(api_client, other_user.as_ref().map(|u| u.id), &output_folder, recent, &dates, |count| {
+                            budget.record(1);
+                            events.emit(Event::ItemsFetched { phase: "likes", count: count as u64 });
+                            pb.inc(count as u64);
+                        })?;
+                        // Otherwise it'd be read instead of what was just streamed
+                        match std::fs::remove_file(output_folder.join("likes.json")) {
+                            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                            _ => {}
+                        }
 
-                            MoreLikesInfoDownloaded { count } => {
-                                pb.inc(count as u64);
-                            },
+                        pb.println(format!("Zested {} likes into {}", fetched, stream::LIKES_NDJSON));
+                    },
+                    JsonType::Likes => {
+                        pb.set_message("Zesting likes");
 
-                            PausedAfterServerError { time_secs } => {
-                                pb.set_message(&format!("Server error, retrying after {}s", time_secs));
-                                thread::sleep(Duration::from_secs(time_secs));
-                                pb.set_message("Zesting likes");
+                        let progress = ShowProgress {
+                            pb: &pb,
+                            phases: None,
+                            events: &events,
+                            budget: &budget,
+                            label: "Zesting likes",
+                            bar_style: Some(bar_style.clone()),
+                            recent
+                        };
+                        let mut likes = archiver.likes(other_user.as_ref().map(|u| u.id), recent, &progress)?;
+                        dates.retain_likes(&mut likes);
+                        if merge {
+                            if let Some(earlier) = earlier(sink, "likes.json", |folder| archive::load_likes(folder, Strictness::Lenient))? {
+                                archive::merge_likes(&mut likes, earlier);
                             }
-                        })?;
-                        write_json(&likes, &path, pretty_print)?;
+                        }
+                        sink::save_json(sink, "likes.json", &likes, pretty_print)?;
 
                         pb.reset();
                         pb.set_style(spinner_style.clone());
@@ -284,236 +1289,538 @@ fn main() -> Result<(), Error> {
                     JsonType::Me => {
                         pb.set_message("Zesting profile information");
 
-                        let path = output_folder.join("me.json");
-                        let me = zester.me()?;
-                        write_json(&me, &path, pretty_print)?;
+                        match &other_user {
+                            Some(user) => sink::save_json(sink, "me.json", &user.profile, pretty_print)?,
+                            None => {
+                                let me = zester.me()?;
+                                budget.record(1);
+                                sink::save_json(sink, "me.json", &me, pretty_print)?;
+                            }
+                        }
 
                         pb.println("Zested profile information");
                     },
                     JsonType::Playlists => {
-                        use PlaylistsZestingEvent::*;
-
                         pb.set_style(bar_style_prefix.clone());
-                        pb.set_prefix("Zesting playlists");
-                        pb.set_message("Getting list of playlists");
-
-                        let path = output_folder.join("playlists.json");
-                        let playlists = zester.playlists(recent, |e: PlaylistsZestingEvent<'_>| match e {
-                            NumPlaylistInfoToDownload { num } => {
-                                pb.set_length(num);
-                            },
-
-                            MorePlaylistMetaInfoDownloaded { count } => {
-                                pb.inc(count as u64);
-                            },
-                            FinishPlaylistMetaInfoDownloading => {
-                                pb.set_message("");
-                                pb.reset();
-                            },
-                            StartPlaylistInfoDownload { playlist_meta } => {
-                                pb.set_message(playlist_meta.title.as_ref().unwrap());
-                            },
-                            FinishPlaylistInfoDownload { .. } => {
-                                pb.inc(1);
-                            },
-                            PlaylistInfoDownloadError { playlist_meta, err } => {
-                                pb.println(format!(
-                                    "  [warning] failed to get info for {}: {:?}",
-                                    playlist_meta.title.as_ref().unwrap(),
-                                    err
-                                ));
-                                pb.inc(1);
-                            },
-                            PlaylistInfoCompletionError { playlist_meta, err } => {
-                                pb.println(format!(
-                                    "  [warning] failed to complete info for {}: {:?}",
-                                    playlist_meta.title.as_ref().unwrap(),
-                                    err
-                                ));
-                                pb.inc(1);
-                            }
-                            PausedAfterServerError { time_secs } => {
-                                pb.set_message(&format!("Server error, retrying after {}s", time_secs));
+                        let phases = Phases::new(&pb, "Zesting playlists", &["listing playlists", "getting their tracks"]);
+                        phases.start(0);
+
+                        let progress = ShowProgress {
+                            pb: &pb,
+                            phases: Some(&phases),
+                            events: &events,
+                            budget: &budget,
+                            label: "Zesting playlists",
+                            bar_style: None,
+                            recent
+                        };
+                        let mut playlists = archiver.playlists(other_user.as_ref().map(|u| u.id), recent, &progress)?;
+                        dates.retain_playlists(&mut playlists);
+                        if merge {
+                            if let Some(earlier) = earlier(sink, "playlists.json", |folder| archive::load_playlists(folder, Strictness::Lenient))? {
+                                archive::merge_playlists(&mut playlists, earlier);
                             }
-                        })?;
+                        }
 
-                        write_json(&playlists, &path, pretty_print)?;
+                        match sink.local_folder() {
+                            Some(folder) => archive::save_playlists(folder, &playlists, split, pretty_print)?,
+                            None => sink::save_json(sink, "playlists.json", &playlists, pretty_print)?
+                        }
 
                         pb.reset();
                         pb.set_style(spinner_style.clone());
                         pb.set_length(!0);
                         pb.println("Zested playlists");
+                    },
+                    JsonType::LikedPlaylists => {
+                        pb.set_message("Zesting liked playlists");
+
+                        let mut playlists = user::liked_playlists(api_client, user_id()?, whose, recent, || budget.record(1))?;
+                        dates.retain_playlists(&mut playlists);
+                        if merge {
+                            if let Some(earlier) = earlier(sink, archive::LIKED_PLAYLISTS_FILE, |folder| {
+                                archive::load_liked_playlists(folder, Strictness::Lenient)
+                            })? {
+                                archive::merge_playlists(&mut playlists, earlier);
+                            }
+                        }
+                        sink::save_json(sink, archive::LIKED_PLAYLISTS_FILE, &playlists, pretty_print)?;
+
+                        pb.println(format!("Zested {} liked playlists", playlists.playlists.len()));
+                    },
+                    JsonType::Followers => {
+                        pb.set_message("Zesting followers");
+
+                        let followers = api_client.user_followers(user_id()?, whose, || budget.record(1))?;
+                        sink::save_json(sink, archive::FOLLOWERS_FILE, &followers, pretty_print)?;
+
+                        pb.println(format!("Zested {} followers", followers.len()));
+                    },
+                    JsonType::Followings => {
+                        pb.set_message("Zesting followings");
+
+                        let followings = api_client.user_followings(user_id()?, whose, || budget.record(1))?;
+                        sink::save_json(sink, archive::FOLLOWINGS_FILE, &followings, pretty_print)?;
+
+                        pb.println(format!("Zested {} followings", followings.len()));
+                    },
+                    JsonType::Reposts => {
+                        pb.set_message("Zesting reposts");
+
+                        let reposts = api_client.user_reposts(user_id()?, whose, || budget.record(1))?;
+                        sink::save_json(sink, "reposts.json", &reposts, pretty_print)?;
+
+                        pb.println(format!("Zested {} reposts", reposts.len()));
+                    },
+                    JsonType::History => {
+                        pb.set_message("Zesting listening history");
+
+                        let mut plays = api_client.play_history(recent, |count| {
+                            budget.record(1);
+                            events.emit(Event::ItemsFetched { phase: "history", count: count as u64 });
+                            pb.inc(count as u64);
+                        })?;
+                        plays.retain(|p| dates.contains(p.played_at_rfc3339().as_deref()));
+                        let zested = plays.len();
+                        if let Some(earlier) = earlier(sink, archive::HISTORY_FILE, |folder| archive::load_history(folder, Strictness::Lenient))? {
+                            archive::merge_history(&mut plays, earlier);
+                        }
+                        sink::save_json(sink, archive::HISTORY_FILE, &plays, pretty_print)?;
+
+                        pb.println(format!("Zested {} plays ({} kept in all)", zested, plays.len()));
+                    },
+                    JsonType::Stream => {
+                        pb.set_message("Zesting stream");
+
+                        let max_items = if merge { Some(recent) } else { None };
+                        let mut items = api_client.stream(max_items, since, || budget.record(1))?;
+                        items.retain(|item| dates.contains(item.get("created_at").and_then(Value::as_str)));
+                        let zested = items.len();
+                        if let Some(earlier) = earlier(sink, archive::STREAM_FILE, |folder| archive::load_stream(folder, Strictness::Lenient))? {
+                            archive::merge_stream(&mut items, earlier);
+                        }
+                        sink::save_json(sink, archive::STREAM_FILE, &items, pretty_print)?;
+
+                        pb.println(format!("Zested {} stream items ({} kept in all)", zested, items.len()));
+                    },
+                    JsonType::Uploads => {
+                        pb.set_message("Zesting uploads");
+
+                        let uploads = api_client.user_tracks(user_id()?, whose, || budget.record(1))?;
+                        sink::save_json(sink, "uploads.json", &uploads, pretty_print)?;
+
+                        pb.println(format!("Zested {} uploads", uploads.len()));
                     }
                 }
+
+                events.emit(Event::PhaseFinished { phase: &phase });
             }
+
+            events.emit(Event::RunFinished { command: "json" });
         },
 
-        Opts::Audio { recent, all, output_folder, input_folder, mut audio_types, .. } => {
+        Opts::Audio {
+            recent,
+            all,
+            max_api_calls,
+            event_socket,
+            progress,
+            api_concurrency,
+            download_concurrency,
+            limit_rate,
+            min_free_space,
+            output_folder,
+            output,
+            input_folder,
+            strict_json,
+            naming_script,
+            filename_template,
+            organize_by,
+            uploader_comments,
+            song_links,
+            visuals,
+            artwork,
+            waveform,
+            dedup_mode,
+            dry_run,
+            artists,
+            since,
+            until,
+            genres,
+            tags,
+            min_duration,
+            max_duration,
+            playlists,
+            excluded_playlists,
+            skip_spoken,
+            only_spoken,
+            include_restricted,
+            mirror,
+            delete,
+            trash_dir,
+            exec_after_track,
+            exec_after_run,
+            format,
+            user,
+            mut audio_types,
+            ..
+        } => {
+            // Filled in from the config file if need be
+            let (output_folder, input_folder) = (output_folder.unwrap(), input_folder.unwrap());
+            let other_user = user.map(|user| OtherUser::resolve(api_client, &user)).transpose()?;
+            let (output_folder, input_folder) = match &other_user {
+                Some(user) => (user.folder(&output_folder), user.folder(&input_folder)),
+                None => (output_folder, input_folder)
+            };
+            let sink = match (output, &other_user) {
+                (Some(output), Some(user)) => Some(user.within(&output).open()?),
+                (Some(output), None) => Some(output.open()?),
+                (None, _) => None
+            };
+            // Links can't be made between uploaded files
+            let dedup_mode = match &sink {
+                Some(sink) if dedup_mode != DedupMode::Off => {
+                    pb.println(format!("  [notice] not deduplicating, as audio is going to {}", sink.location("")));
+                    DedupMode::Off
+                },
+                _ => dedup_mode
+            };
+            format.apply();
+            let (api_concurrency, download_concurrency) = (api_concurrency.unwrap_or(1), download_concurrency.unwrap_or(1));
+
             // Manually stick all the possible types in the vector if the all flag
             // was set
             if all {
                 audio_types = AudioType::into_enum_iter().collect();
             }
+
+            let namer = match naming_script {
+                Some(path) => Namer::from_script(path)?,
+                None => Namer::Standard {
+                    folders: match organize_by {
+                        Some(layout) => FolderLayout::parse(&layout)?,
+                        None => FolderLayout::BySource
+                    },
+                    filename: filename_template.map(|t| Template::parse(&t, false)).transpose()?
+                }
+            };
             pb.set_message("");
-            pb.set_style(bar_style_prefix.clone());
+            pb.set_style(download_style.clone());
 
             let recent = recent.unwrap_or(std::u64::MAX);
+            let filter = TrackFilter {
+                artists,
+                dates: DateRange { since, until },
+                genres,
+                tags,
+                min_duration,
+                max_duration,
+                spoken: if skip_spoken { Some(false) } else if only_spoken { Some(true) } else { None },
+                playlists,
+                excluded_playlists,
+                include_restricted
+            };
+            let budget = ApiBudget::new("audio", max_api_calls);
+            let events = EventFeed::new(event_socket, progress)?;
+            events.emit(Event::RunStarted { command: "audio" });
+            interrupt::stop_cleanly();
+            let saver = TrackSaver {
+                output_folder: &output_folder,
+                namer: Mutex::new(namer),
+                manifest: Mutex::new(Manifest::open(&output_folder)?),
+                replacements: Mutex::new(Vec::new()),
+                sidecar_opts: SidecarOptions {
+                    uploader_comments,
+                    song_links: if song_links { Some(SongLinks::load()?) } else { None },
+                    visuals,
+                    artwork,
+                    waveform,
+                    always: false
+                },
+                api_client,
+                budget: &budget,
+                events: &events,
+                pb: &pb,
+                dedup: dedup_mode,
+                saved: Mutex::new(HashMap::new()),
+                dedup_bytes: AtomicU64::new(0),
+                summary: RunSummary::default(),
+                checkpoint: if dry_run { None } else { Some(Checkpoint::load(&output_folder)?) },
+                rate_limit: limit_rate.map(RateLimiter::new),
+                transfer: Some(Transfer::default()),
+                sink: sink.as_deref(),
+                after_track: exec_after_track,
+                after_run: exec_after_run
+            };
+            let mut plan = if dry_run { Some(DryRun::new(&output_folder)) } else { None };
+            // Uploaded audio doesn't take up room here
+            let space_check = match &sink {
+                Some(_) => None,
+                None => Some(SpaceCheck::new(&output_folder, min_free_space.unwrap_or(space::DEFAULT_MIN_FREE)))
+            };
+
+            let audio_opts = AudioOptions {
+                recent,
+                filter: &filter,
+                download_concurrency,
+                api_concurrency,
+                space_check: space_check.as_ref()
+            };
+            let strictness = Strictness::from_flag(strict_json);
 
             // Grab all the data we were asked to
             for audio_type in audio_types {
+                if budget.exhausted() {
+                    pb.println(format!("  [warning] API call budget used up, skipping {}", audio_type));
+                    continue;
+                }
+
+                let (label, phase) = match audio_type {
+                    AudioType::Likes => ("Zesting likes audio", "likes"),
+                    AudioType::Playlists => ("Zesting playlists audio", "playlists"),
+                    // Liked playlists are archived just like the account's own
+                    AudioType::LikedPlaylists => ("Zesting liked playlists audio", "liked-playlists")
+                };
+                pb.set_prefix(label);
+                pb.set_style(download_style.clone());
+                let observer = ShowDownloads {
+                    pb: &pb,
+                    events: &events,
+                    budget: &budget,
+                    label,
+                    playlists: AtomicU64::new(0),
+                    started: AtomicU64::new(0)
+                };
+
                 match audio_type {
                     AudioType::Likes => {
-                        use TracksAudioZestingEvent::*;
-                        
-                        let input_file = input_folder.join("likes.json");
-                        let likes: Likes = orange_zest::load_json(&input_file)
-                            .map_err(|e| specific_json_err(e, input_file.to_str().unwrap().into()))?;
-
-                        let likes_folder = output_folder.join("likes/");
-                        if !likes_folder.exists() {
-                            fs::create_dir(&likes_folder)?;
-                        }
-                        pb.set_prefix("Zesting likes audio");
-
-                        zester.likes_audio(&likes, recent, |e| match e {
-                            NumTracksToDownload { num } => {
-                                pb.set_length(num);
-                            },
-
-                            StartTrackDownload { track_info } => {
-                                pb.set_message(track_info.title.as_ref().unwrap());
-                            },
-
-                            FinishTrackDownload { track_info, mut track_data } => {
-                                let title = track_info.title.as_ref().unwrap();
-                                let output_file = likes_folder.join(sanitize(format!(
-                                    "{} (id={}).m4a",
-                                    title,
-                                    track_info.id.unwrap()
-                                )));
-
-                                stream_track_to_file(&output_file, &title, &pb, &mut track_data);
-                                pb.inc(1);
-                            },
-
-                            TrackDownloadError { track_info, err } => {
-                                pb.println(format!(
-                                    "  [warning] failed to download {} {:?}",
-                                    track_info.title.as_ref().unwrap(),
-                                    err
-                                ));
-                                pb.inc(1);
-                            },
-
-                            PausedAfterServerError { time_secs } => {
-                                pb.set_message(&format!("Server error, retrying after {}s", time_secs));
-                            }
-                        })?;
-
-                        pb.reset();
-                        pb.set_style(spinner_style.clone());
-                        pb.set_length(!0);
-                        pb.println("Zested audio tracks from likes");
+                        let likes = archive::load_likes(&input_folder, strictness)?;
+                        archiver.download_likes(&saver, likes, &audio_opts, plan.as_mut(), &observer)?;
                     },
-
                     AudioType::Playlists => {
-                        use PlaylistsAudioZestingEvent::*;
-                        use TracksAudioZestingEvent::*;
-                        
-                        let input_file = input_folder.join("playlists.json");
-                        let playlists: Playlists = orange_zest::load_json(&input_file)
-                            .map_err(|e| specific_json_err(e, input_file.to_str().unwrap().into()))?;
-                        // We need these refcells to track additional state for the progressbar
-                        // that we can mutate from inside the Fn below
-                        let playlist_curr = RefCell::new(1);
-                        let playlist_total = RefCell::new(!0);
-
-                        let playlists_folder = output_folder.join("playlists/");
-                        if !playlists_folder.exists() {
-                            fs::create_dir(&playlists_folder)?;
-                        }
-                        pb.set_prefix("Zesting playlists audio");
-
-                        zester.playlists_audio(playlists.playlists.iter().take(recent as usize), |e| match e {
-                            NumItemsToDownload { playlists_num, tracks_num } => {
-                                *playlist_total.borrow_mut() = playlists_num;
-                                pb.set_length(tracks_num);
-                            },
-
-                            StartPlaylistDownload { playlist_info } => {
-                                pb.set_prefix(&format!(
-                                    "Zesting playlists audio ({}/{}) - {}",
-                                    playlist_curr.borrow(),
-                                    playlist_total.borrow(),
-                                    playlist_info.title.as_ref().unwrap()
-                                ));
-                            }
-
-                            TrackEvent(NumTracksToDownload { .. }, _) => {},
-
-                            TrackEvent(StartTrackDownload { track_info }, _) => {
-                                pb.set_message(track_info.title.as_ref().unwrap());
-                            },
-
-                            TrackEvent(FinishTrackDownload { track_info, mut track_data }, playlist_info) => {
-                                let track_title = track_info.title.as_ref().unwrap();
-                                let playlist_title = playlist_info.title.as_ref().unwrap();
-
-                                let playlist_folder = playlists_folder.join(sanitize(format!(
-                                    "{} (id={})",
-                                    playlist_title,
-                                    playlist_info.id.unwrap(),
-                                )));
-                                if !playlist_folder.exists() {
-                                    // TODO: don't unwrap
-                                    fs::create_dir(&playlist_folder).unwrap();
-                                }
-
-                                let output_file = playlist_folder.join(sanitize(format!(
-                                    "{} (id={}).m4a",
-                                    track_title,
-                                    track_info.id.unwrap()
-                                )));
-
-                                stream_track_to_file(&output_file, &track_title, &pb, &mut track_data);
-                                pb.inc(1);
-                            },
-
-                            TrackEvent(TrackDownloadError { track_info, err }, playlist_info) => {
-                                pb.println(format!(
-                                    "  [warning] failed to download {} (in {}): {:?}",
-                                    track_info.title.as_ref().unwrap(),
-                                    playlist_info.title.as_ref().unwrap(),
-                                    err
-                                ));
-                                pb.inc(1);
-                            },
-
-                            TrackEvent(PausedAfterServerError { time_secs }, _) => {
-                                pb.set_message(&format!("Server error, retrying after {}s", time_secs));
-                            },
-
-                            FinishPlaylistDownload { playlist_info } => {
-                                *playlist_curr.borrow_mut() += 1;
-                                pb.set_prefix(&format!(
-                                    "Zesting playlists audio ({}/{}) - {}",
-                                    playlist_curr.borrow(),
-                                    playlist_total.borrow(),
-                                    playlist_info.title.as_ref().unwrap()
-                                ));
-                            }
-                        })?;
-
-                        pb.reset();
-                        pb.set_style(spinner_style.clone());
-                        pb.set_length(!0);
-                        pb.println("Zested audio tracks from playlists");
+                        let playlists = archive::load_playlists(&input_folder, strictness)?;
+                        archiver.download_playlists(&saver, playlists, phase, &audio_opts, plan.as_mut(), &observer)?;
+                    },
+                    AudioType::LikedPlaylists => {
+                        let playlists = archive::load_liked_playlists(&input_folder, strictness)?;
+                        archiver.download_playlists(&saver, playlists, phase, &audio_opts, plan.as_mut(), &observer)?;
                     }
                 }
+                if plan.is_some() {
+                    continue;
+                }
+
+                pb.reset();
+                pb.set_style(spinner_style.clone());
+                pb.set_length(!0);
+                pb.println(match audio_type {
+                    AudioType::Likes => "Zested audio tracks from likes",
+                    AudioType::Playlists | AudioType::LikedPlaylists => "Zested audio tracks from playlists"
+                });
             }
+
+            if mirror {
+                let mut trash = Trash::new(&output_folder, trash_dir.as_deref(), "audio");
+                mirror::prune(
+                    lock.as_ref().unwrap(),
+                    &input_folder,
+                    strictness,
+                    &mut saver.manifest.lock().unwrap(),
+                    if delete { Prune::Delete(&mut trash) } else { Prune::Move },
+                    dry_run,
+                    &pb
+                )?;
+                trash.report();
+            }
+
+            if let Some(plan) = plan {
+                pb.reset();
+                pb.set_style(spinner_style.clone());
+                plan.print(&pb);
+                history::end(Outcome::Finished);
+                return Ok(());
+            }
+
+            saver.finish()?;
+            events.emit(Event::RunFinished { command: "audio" });
+        },
+
+        Opts::Panic { user, output_folder, concurrency, .. } => {
+            pb.set_style(bar_style.clone());
+            panic::run(&user, &output_folder.unwrap(), concurrency.unwrap_or(8), zester, credentials, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+            pb.println(format!("Zested everything {} uploaded", user));
+        },
+
+        Opts::ClipboardWatch { output_folder, interval, .. } => {
+            clipboard::watch(&output_folder.unwrap(), Duration::from_millis(interval), zester, credentials, api_client, &pb)?;
+        },
+
+        Opts::Track { url, output_folder, uploader_comments, visuals, artwork, waveform, .. } => {
+            pb.set_style(bar_style_prefix.clone());
+            let sidecar_opts = SidecarOptions { uploader_comments, song_links: None, visuals, artwork, waveform, always: true };
+            grab::track(&url, &output_folder, sidecar_opts, zester, credentials, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::Playlist {
+            url,
+            output_folder,
+            filename_template,
+            organize_by,
+            artists,
+            since,
+            until,
+            genres,
+            tags,
+            min_duration,
+            max_duration,
+            skip_spoken,
+            only_spoken,
+            include_restricted,
+            ..
+        } => {
+            let namer = Namer::Standard {
+                folders: match organize_by {
+                    Some(layout) => FolderLayout::parse(&layout)?,
+                    None => FolderLayout::BySource
+                },
+                filename: filename_template.map(|t| Template::parse(&t, false)).transpose()?
+            };
+            let filter = TrackFilter {
+                artists,
+                dates: DateRange { since, until },
+                genres,
+                tags,
+                min_duration,
+                max_duration,
+                spoken: if skip_spoken { Some(false) } else if only_spoken { Some(true) } else { None },
+                playlists: Vec::new(),
+                excluded_playlists: Vec::new(),
+                include_restricted
+            };
+
+            pb.set_style(bar_style_prefix.clone());
+            grab::playlist(&url, &output_folder, namer, &filter, zester, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::Queue { command: QueueCommand::Run { output_folder, max_api_calls, .. } } => {
+            pb.set_style(bar_style_prefix.clone());
+            queue::run(&output_folder, max_api_calls, zester, credentials, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::Subscribe { command: SubscribeCommand::Run { output_folder, max_api_calls, .. } } => {
+            pb.set_style(bar_style_prefix.clone());
+            subscribe::run(&output_folder, max_api_calls, zester, credentials, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::RetryFailed { output_folder, max_api_calls, .. } => {
+            pb.set_style(bar_style.clone());
+            pb.set_message("Retrying failed tracks");
+            retry::run(&output_folder, max_api_calls, zester, credentials, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::CheckAvailability { input_folder, output_folder, .. } => {
+            let output_folder = output_folder.unwrap_or_else(|| input_folder.clone());
+            let budget = ApiBudget::new("check", None);
+
+            pb.set_style(bar_style.clone());
+            pb.set_message("Checking track availability");
+            let report = availability::check(&input_folder, &output_folder, api_client, &budget, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+            pb.println(format!(
+                "Checked {} tracks, {} no longer available (see removed-tracks.json)",
+                report.tracks_checked,
+                report.tracks.len()
+            ));
+        },
+
+        Opts::CheckRegions { track, via, .. } => {
+            pb.set_style(bar_style.clone());
+            regions::check(&track, &via, api_client, &pb)?;
+
+            pb.reset();
+            pb.set_style(spinner_style.clone());
+            pb.set_length(!0);
+        },
+
+        Opts::Compact(_)
+            | Opts::Daemon(_)
+            | Opts::Diff(_)
+            | Opts::Export(_)
+            | Opts::Offload(_)
+            | Opts::Recall(_)
+            | Opts::Stats(_)
+            | Opts::Schema(_)
+            | Opts::Verify(_)
+            | Opts::Queue { .. }
+            | Opts::Subscribe { .. }
+            | Opts::Login(_)
+            | Opts::Profiles { .. }
+            | Opts::History { .. }
+            | Opts::Trash { .. }
+            | Opts::Cache { .. }
+            | Opts::Bundle(_)
+            | Opts::Simulate(_)
+            | Opts::Search(_)
+            | Opts::Serve(_)
+            | Opts::Watch(_) => unreachable!("handled before creating a zester")
+    }
+
+    // Stopped somewhere nothing was being downloaded, having wrapped up normally
+    interrupt::check()?;
+    history::end(Outcome::Finished);
+
+    if api_client.pool_size() > 1 {
+        pb.println("Requests per pooled credential:");
+        for usage in api_client.pool_usage() {
+            pb.println(format!(
+                "  {}: {} requests, {} rate limited",
+                usage.label,
+                locale::number(usage.requests),
+                locale::number(usage.rate_limited)
+            ));
         }
     }
 
     pb.finish_with_message("Zesting complete");
     Ok(())
 }
+
+// What's already stored as `name` in the sink, for merging into; `load` reads
+// it when the sink is a folder, as it might be split over several files there
+fn earlier<T: DeserializeOwned>(
+    sink: &dyn ArchiveSink,
+    name: &str,
+    load: impl FnOnce(&Path) -> Result<T, Error>
+) -> Result<Option<T>, Error> {
+    match sink.local_folder() {
+        Some(folder) => archive::optional(load(folder)),
+        None => sink::load_json(sink, name)
+    }
+}
@@ -0,0 +1,517 @@
+//! The `manifest.json` kept in the output folder of audio runs, recording
+//! what has been downloaded where.
+
+use crate::archive;
+use crate::atomic::write_json;
+use crate::checksum::{sampled_sha256, sha256_bytes, sha256_file};
+use crate::history;
+use crate::json_check::{load_checked, Strictness};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::api::TrackInfo;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Changes made since the manifest was last saved, one JSON object per line, so
+/// that nothing's lost if a run dies before it gets to save
+pub const JOURNAL_FILE: &str = "manifest.journal";
+
+/// How many changes the journal takes before they're folded into the manifest
+const COMPACT_EVERY: usize = 100;
+
+/// Bumped whenever the manifest's format changes in a way older versions of
+/// orange-zester can't read
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Of `tracks`, as compact JSON; missing from manifests written before
+    /// this was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Downloaded tracks, keyed by track id
+    pub tracks: BTreeMap<u64, ManifestEntry>,
+    /// Where changes go as they're made, if the manifest was opened for a run
+    /// that downloads
+    #[serde(skip)]
+    journal: Option<Journal>,
+}
+
+#[derive(Debug)]
+struct Journal {
+    output_folder: PathBuf,
+    file: File,
+    /// Changes written since the manifest was last saved
+    unsaved: usize,
+    /// Identifies the run in the files it records
+    run: String,
+}
+
+/// A single change to the manifest, as written to the journal.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "change", rename_all = "snake_case")]
+enum Change {
+    /// Audio for a track was written to `file.path`
+    Recorded {
+        track_id: u64,
+        title: Option<String>,
+        source_url: Option<String>,
+        file: FileEntry,
+    },
+    /// The audio at `path` was replaced by the uploader and moved aside to
+    /// `previous.path`
+    Replaced {
+        track_id: u64,
+        path: String,
+        previous: FileEntry,
+    },
+    /// The track is gone from the account and its audio was moved out of
+    /// the archive
+    Removed {
+        track_id: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ManifestEntry {
+    pub title: Option<String>,
+    /// The SoundCloud page the audio came from
+    pub source_url: Option<String>,
+    /// Every copy of the track's audio in the archive
+    pub files: Vec<FileEntry>,
+    /// Copies of audio that has since been replaced by the uploader
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_versions: Vec<FileEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct FileEntry {
+    /// Path relative to the output folder, `/`-separated
+    pub path: String,
+    pub bytes: u64,
+    pub sha256: String,
+    /// See `checksum::sampled_sha256`
+    #[serde(default)]
+    pub sampled_sha256: Option<String>,
+    /// What the track's audio looked like according to the API at download time
+    #[serde(default)]
+    pub signature: Option<AudioSignature>,
+    pub downloaded_at: DateTime<Utc>,
+    /// Where the file was moved to by `offload`, if it's been moved out of the
+    /// archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offloaded_to: Option<String>,
+    /// The run that downloaded the file (see `Manifest::runs`); missing for
+    /// files downloaded before runs were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<String>,
+}
+
+impl FileEntry {
+    /// Where the file's contents actually live, given the archive folder.
+    pub fn location(&self, folder: &Path) -> PathBuf {
+        match &self.offloaded_to {
+            Some(path) => PathBuf::from(path),
+            None => folder.join(&self.path)
+        }
+    }
+}
+
+/// Where the audio of each track in the archive at `folder` is, going by the
+/// manifest, or by the track ids in filenames if there isn't one.
+pub fn audio_locations(folder: &Path) -> Result<HashMap<u64, Vec<PathBuf>>, Error> {
+    if !folder.join(MANIFEST_FILE).exists() {
+        return Ok(archive::local_audio(folder)?);
+    }
+
+    Ok(Manifest::load(folder)?
+        .tracks
+        .into_iter()
+        .map(|(id, entry)| (id, entry.files.iter().map(|file| file.location(folder)).collect()))
+        .collect())
+}
+
+/// Properties of a track that change when its audio is replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct AudioSignature {
+    pub duration: Option<u64>,
+    pub transcoding_urls: Vec<String>,
+}
+
+impl AudioSignature {
+    pub fn of(track: &TrackInfo) -> Self {
+        let mut transcoding_urls: Vec<String> = track.media
+            .iter()
+            .flat_map(|m| m.transcodings.iter())
+            .filter_map(|t| t.url.clone())
+            .collect();
+        transcoding_urls.sort();
+
+        Self {
+            duration: track.duration,
+            transcoding_urls
+        }
+    }
+}
+
+/// A track whose audio changed between downloads.
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Replacement {
+    pub track_id: u64,
+    pub title: Option<String>,
+    pub detected_at: DateTime<Utc>,
+    /// Where the earlier version was moved to
+    pub previous_path: String,
+    pub previous: Option<AudioSignature>,
+    pub current: AudioSignature,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            sha256: None,
+            tracks: BTreeMap::new(),
+            journal: None
+        }
+    }
+}
+
+impl Manifest {
+    /// Loads the manifest from the given output folder, or starts a new one if
+    /// there isn't one there yet.
+    pub fn load(output_folder: &Path) -> Result<Self, Error> {
+        Self::load_checked(output_folder, Strictness::Lenient)
+    }
+
+    /// Loads the manifest for a run that's going to record downloads in it,
+    /// journaling every change as it's made.
+    pub fn open(output_folder: &Path) -> Result<Self, Error> {
+        let mut manifest = Self::load(output_folder)?;
+        manifest.journal = Some(Journal {
+            output_folder: output_folder.to_path_buf(),
+            file: OpenOptions::new().create(true).append(true).open(output_folder.join(JOURNAL_FILE))?,
+            unsaved: 0,
+            run: history::run_id().to_string()
+        });
+
+        Ok(manifest)
+    }
+
+    /// Like `load`, but with a choice of what to do about fields that are
+    /// unknown or missing.
+    ///
+    /// A manifest that's damaged is moved aside (to `manifest.json.corrupt`)
+    /// and rebuilt from the audio in the folder. Changes journaled by a run
+    /// that didn't get to save are applied.
+    pub fn load_checked(output_folder: &Path, strictness: Strictness) -> Result<Self, Error> {
+        let mut manifest = Self::load_saved(output_folder, strictness)?;
+
+        let journal = output_folder.join(JOURNAL_FILE);
+        if journal.exists() {
+            for line in BufReader::new(File::open(&journal)?).lines() {
+                match serde_json::from_str(&line?) {
+                    Ok(change) => manifest.apply(change),
+                    // The run died partway through writing this one
+                    Err(_) => break
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    fn load_saved(output_folder: &Path, strictness: Strictness) -> Result<Self, Error> {
+        let path = output_folder.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let problem = match serde_json::from_slice::<Self>(&fs::read(&path)?) {
+            Ok(manifest) if manifest.format_version > FORMAT_VERSION => return Err(Error::JsonFormatError(format!(
+                "{} was written by a newer orange-zester (format version {}, this one understands {})",
+                path.display(),
+                manifest.format_version,
+                FORMAT_VERSION
+            ))),
+            Ok(manifest) => match &manifest.sha256 {
+                Some(sha256) if sha256 != &tracks_sha256(&manifest.tracks) => "its checksum doesn't match".to_string(),
+                _ => return load_checked(&path, strictness)
+            },
+            Err(e) => e.to_string()
+        };
+
+        let aside = path.with_extension("json.corrupt");
+        fs::rename(&path, &aside)?;
+        eprintln!(
+            "[warning] {} is damaged ({}); moved it to {} and rebuilding it from the audio in {}",
+            path.display(),
+            problem,
+            aside.display(),
+            output_folder.display()
+        );
+
+        let mut manifest = Self::rebuild(output_folder)?;
+        manifest.save(output_folder)?;
+        Ok(manifest)
+    }
+
+    /// Builds a manifest from the audio files found under the given output
+    /// folder, going by the track ids in their names.
+    ///
+    /// What the filenames don't say (where the audio came from, what the API
+    /// said about it) is left out, and files are taken to have been downloaded
+    /// when they were last modified.
+    pub fn rebuild(output_folder: &Path) -> Result<Self, Error> {
+        let mut manifest = Self::default();
+
+        for (id, paths) in archive::local_audio(output_folder)? {
+            let mut files = Vec::new();
+            for path in &paths {
+                let metadata = fs::metadata(path)?;
+                files.push(FileEntry {
+                    path: manifest_path(path.strip_prefix(output_folder).unwrap()),
+                    bytes: metadata.len(),
+                    sha256: sha256_file(path)?,
+                    sampled_sha256: Some(sampled_sha256(path)?),
+                    signature: None,
+                    downloaded_at: metadata.modified()?.into(),
+                    offloaded_to: None,
+                    run: None
+                });
+            }
+
+            // `Title (id=1234).m4a`
+            let title = paths[0]
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.rfind(" (id=").map(|end| stem[..end].to_string()));
+            manifest.tracks.insert(id, ManifestEntry {
+                title,
+                source_url: None,
+                files,
+                previous_versions: Vec::new()
+            });
+        }
+
+        Ok(manifest)
+    }
+
+    /// Saves the manifest, replacing the old one only once the new one is
+    /// completely written, and empties the journal.
+    pub fn save(&mut self, output_folder: &Path) -> Result<(), Error> {
+        let mut manifest = serde_json::to_value(&*self).unwrap();
+        manifest["sha256"] = tracks_sha256(&self.tracks).into();
+
+        write_json(&manifest, output_folder.join(MANIFEST_FILE), true)?;
+
+        match &mut self.journal {
+            Some(journal) => {
+                journal.file.set_len(0)?;
+                journal.unsaved = 0;
+            },
+            None => match fs::remove_file(output_folder.join(JOURNAL_FILE)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The ids of the runs that downloaded the files in the archive, oldest
+    /// first (see `history::run_id`).
+    pub fn runs(&self) -> BTreeSet<&str> {
+        self.tracks
+            .values()
+            .flat_map(|entry| entry.files.iter().chain(&entry.previous_versions))
+            .filter_map(|file| file.run.as_deref())
+            .collect()
+    }
+
+    /// Records that the given track's audio was just written to `relative_path`
+    /// (relative to the output folder).
+    pub fn record(
+        &mut self,
+        track: &TrackInfo,
+        relative_path: &Path,
+        bytes: u64,
+        sha256: String,
+        sampled_sha256: Option<String>
+    ) -> Result<(), Error> {
+        self.commit(Change::Recorded {
+            track_id: track.id.unwrap(),
+            title: track.title.clone(),
+            source_url: track.permalink_url.clone(),
+            file: FileEntry {
+                path: manifest_path(relative_path),
+                bytes,
+                sha256,
+                sampled_sha256,
+                signature: Some(AudioSignature::of(track)),
+                downloaded_at: Utc::now(),
+                offloaded_to: None,
+                run: self.journal.as_ref().map(|journal| journal.run.clone())
+            }
+        })
+    }
+
+    /// Forgets about the given track, whose audio has been moved out of the
+    /// archive.
+    pub fn remove(&mut self, track_id: u64) -> Result<(), Error> {
+        self.commit(Change::Removed { track_id })
+    }
+
+    // Makes the change, journaling it first if there's a journal
+    fn commit(&mut self, change: Change) -> Result<(), Error> {
+        let compact = match &mut self.journal {
+            Some(journal) => {
+                // One write per line, so that lines from concurrent writers
+                // don't interleave
+                let mut line = serde_json::to_vec(&change).unwrap();
+                line.push(b'\n');
+                journal.file.write_all(&line)?;
+                journal.file.sync_data()?;
+
+                journal.unsaved += 1;
+                (journal.unsaved >= COMPACT_EVERY).then(|| journal.output_folder.clone())
+            },
+            None => None
+        };
+        self.apply(change);
+
+        if let Some(output_folder) = compact {
+            self.save(&output_folder)?;
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, change: Change) {
+        match change {
+            Change::Recorded { track_id, title, source_url, file } => {
+                let entry = self.tracks.entry(track_id).or_insert_with(|| ManifestEntry {
+                    title: None,
+                    source_url: None,
+                    files: Vec::new(),
+                    previous_versions: Vec::new()
+                });
+                entry.title = title;
+                entry.source_url = source_url;
+                entry.files.retain(|f| f.path != file.path);
+                entry.files.push(file);
+            },
+            Change::Replaced { track_id, path, previous } => {
+                if let Some(entry) = self.tracks.get_mut(&track_id) {
+                    entry.files.retain(|f| f.path != path);
+                    // Replaying a journal the manifest was already saved with
+                    // mustn't record the same version twice
+                    if !entry.previous_versions.iter().any(|v| v.path == previous.path) {
+                        entry.previous_versions.push(previous);
+                    }
+                }
+            },
+            Change::Removed { track_id } => {
+                self.tracks.remove(&track_id);
+            }
+        }
+    }
+
+    /// Checks whether the audio previously downloaded to `relative_path` for the
+    /// given track has been replaced by the uploader since.
+    ///
+    /// If it has, the old file is moved aside to `<name>.v<n>.<ext>` so the new
+    /// download doesn't overwrite it, and the replacement is returned.
+    pub fn preserve_replaced(
+        &mut self,
+        output_folder: &Path,
+        track: &TrackInfo,
+        relative_path: &Path
+    ) -> Result<Option<Replacement>, Error> {
+        let entry = match track.id.and_then(|id| self.tracks.get_mut(&id)) {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+
+        let path = manifest_path(relative_path);
+        let current = AudioSignature::of(track);
+        let index = match entry.files.iter().position(|f| f.path == path) {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+        // Files recorded before signatures were kept can't be compared
+        match &entry.files[index].signature {
+            Some(previous) if previous != &current => {},
+            _ => return Ok(None)
+        }
+
+        let old_file = output_folder.join(relative_path);
+        if !old_file.exists() {
+            return Ok(None);
+        }
+
+        let version = entry.previous_versions.len() + 1;
+        let versioned = relative_path.with_extension(format!(
+            "v{}.{}",
+            version,
+            relative_path.extension().map(|e| e.to_string_lossy()).unwrap_or_default()
+        ));
+
+        let mut previous = entry.files[index].clone();
+        previous.path = manifest_path(&versioned);
+        let replacement = Replacement {
+            track_id: track.id.unwrap(),
+            title: track.title.clone(),
+            detected_at: Utc::now(),
+            previous_path: previous.path.clone(),
+            previous: previous.signature.clone(),
+            current
+        };
+        // Journaled first, so that a run dying in between can't leave behind
+        // a version the manifest doesn't know about
+        self.commit(Change::Replaced { track_id: track.id.unwrap(), path, previous })?;
+        fs::rename(&old_file, output_folder.join(&versioned))?;
+
+        Ok(Some(replacement))
+    }
+}
+
+fn tracks_sha256(tracks: &BTreeMap<u64, ManifestEntry>) -> String {
+    sha256_bytes(serde_json::to_value(tracks).unwrap().to_string().as_bytes())
+}
+
+/// Appends the given replacements to `replacements.json` in the output folder.
+pub fn report_replacements(output_folder: &Path, replacements: Vec<Replacement>) -> Result<(), Error> {
+    if replacements.is_empty() {
+        return Ok(());
+    }
+
+    let path = output_folder.join("replacements.json");
+    let mut all: Vec<Replacement> = if path.exists() {
+        orange_zest::load_json(&path)?
+    } else {
+        Vec::new()
+    };
+    all.extend(replacements);
+
+    write_json(&all, path, true)
+}
+
+/// Turns a relative path into the platform-independent form stored in the
+/// manifest.
+pub fn manifest_path(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,129 @@
+//! Keeping an audio archive an exact copy of the account, by moving out audio
+//! for tracks that are no longer liked or in any playlist.
+
+use crate::archive;
+use crate::json_check::Strictness;
+use crate::lock::Lock;
+use crate::manifest::{FileEntry, Manifest};
+use crate::offload::move_file;
+use crate::progress::Progress;
+use crate::sidecar::{artwork_paths, sidecar_path, visuals_folder, waveform_paths};
+use crate::trash::Trash;
+use crate::Error;
+use indicatif::HumanBytes;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where audio for tracks gone from the account is moved to, inside the
+/// archive
+pub const REMOVED_DIR: &str = "_removed";
+
+/// What to do with the audio of tracks that are gone from the account.
+pub enum Prune<'a> {
+    /// Move it to `_removed/`, keeping the layout of the archive
+    Move,
+    /// Delete it, by way of the trash
+    Delete(&'a mut Trash),
+}
+
+/// Takes the audio for tracks in the manifest that aren't in the account's
+/// likes or playlists any more out of the output folder, and forgets about
+/// them. The output folder is the one `lock` was taken in, as it has to be
+/// held for this.
+///
+/// Both `likes.json` and `playlists.json` are needed, so that a track isn't
+/// taken for removed just because the part of the account it's in wasn't
+/// zested.
+pub fn prune(
+    lock: &Lock,
+    input_folder: &Path,
+    strictness: Strictness,
+    manifest: &mut Manifest,
+    mut prune: Prune,
+    dry_run: bool,
+    pb: &Progress
+) -> Result<(), Error> {
+    let output_folder = lock.folder();
+    let likes = archive::load_likes(input_folder, strictness)?;
+    let playlists = archive::load_playlists(input_folder, strictness)?;
+    // Optional, as they're only zested when asked for
+    let liked_playlists = archive::optional(archive::load_liked_playlists(input_folder, strictness))?;
+
+    let on_account: HashSet<u64> = archive::liked_tracks(&likes)
+        .map(|(_, track)| track)
+        .chain(playlists.playlists.iter().flat_map(archive::playlist_tracks))
+        .chain(liked_playlists.iter().flat_map(|p| p.playlists.iter()).flat_map(archive::playlist_tracks))
+        .filter_map(|track| track.id)
+        .collect();
+    // An empty account is far more likely to be a bad zest than the truth
+    if on_account.is_empty() && !manifest.tracks.is_empty() {
+        pb.println("  [warning] no tracks in likes.json or playlists.json, so not removing anything from the archive");
+        return Ok(());
+    }
+
+    let gone: Vec<u64> = manifest.tracks.keys().copied().filter(|id| !on_account.contains(id)).collect();
+    let (mut removed, mut bytes) = (0, 0);
+    for id in gone {
+        let entry = &manifest.tracks[&id];
+        let title = entry.title.as_deref().unwrap_or("untitled").to_string();
+
+        // Offloaded audio isn't in the archive to move
+        if entry.files.iter().chain(&entry.previous_versions).any(|file| file.offloaded_to.is_some()) {
+            pb.println(format!("  [skipped] {} is gone from the account but offloaded; recall it to remove it", title));
+            continue;
+        }
+
+        let files: Vec<FileEntry> = entry.files.iter().chain(&entry.previous_versions).cloned().collect();
+        if !dry_run {
+            for file in &files {
+                for path in with_extras(&output_folder.join(&file.path)) {
+                    match &mut prune {
+                        Prune::Move => {
+                            let dest = output_folder.join(REMOVED_DIR).join(path.strip_prefix(output_folder).unwrap());
+                            if let Some(parent) = dest.parent() {
+                                fs::create_dir_all(parent)?;
+                            }
+                            move_file(&path, &dest)?;
+                        },
+                        Prune::Delete(trash) => trash.discard(&path)?
+                    }
+                }
+                let _ = fs::remove_dir(visuals_folder(&output_folder.join(&file.path)));
+            }
+            manifest.remove(id)?;
+        }
+
+        pb.println(format!("  [removed] {}", title));
+        removed += 1;
+        bytes += files.iter().map(|file| file.bytes).sum::<u64>();
+    }
+
+    if removed > 0 {
+        pb.println(format!(
+            "{} {} tracks no longer on the account ({}){}",
+            if dry_run { "Would remove" } else { "Removed" },
+            removed,
+            HumanBytes(bytes),
+            match prune {
+                Prune::Move => format!(" to {}", output_folder.join(REMOVED_DIR).display()),
+                Prune::Delete(_) => String::new()
+            }
+        ));
+    }
+    Ok(())
+}
+
+// The audio file at `path` along with its sidecar, artwork, waveform and
+// visuals, those of them that exist
+fn with_extras(path: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![path.to_path_buf(), sidecar_path(path)];
+    paths.extend(artwork_paths(path));
+    paths.extend(waveform_paths(path));
+    if let Ok(visuals) = fs::read_dir(visuals_folder(path)) {
+        paths.extend(visuals.filter_map(|entry| entry.ok()).map(|entry| entry.path()));
+    }
+
+    paths.retain(|path| path.is_file());
+    paths
+}
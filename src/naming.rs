@@ -0,0 +1,394 @@
+use crate::{sanitize, Error};
+use orange_zest::api::{Playlist, TrackInfo};
+use std::path::{Component, Path, PathBuf};
+
+/// The metadata about a track that naming schemes get to work with.
+#[derive(Debug, Clone)]
+pub struct TrackContext {
+    /// Where the track came from ("likes" or "playlists")
+    pub kind: &'static str,
+    pub id: u64,
+    pub title: String,
+    pub artist: Option<String>,
+    /// `YYYY-MM-DD`
+    pub upload_date: Option<String>,
+    pub playlist_id: Option<u64>,
+    pub playlist_title: Option<String>,
+    /// 1-based position of the track in its playlist
+    pub index: Option<usize>,
+}
+
+impl TrackContext {
+    pub fn new(kind: &'static str, track: &TrackInfo, playlist: Option<&Playlist>) -> Self {
+        Self {
+            kind,
+            id: track.id.unwrap(),
+            title: track.title.clone().unwrap_or_default(),
+            artist: track.user.as_ref().and_then(|u| u.username.clone()),
+            // Timestamps start with the date in every format the API has used
+            upload_date: track.created_at
+                .as_deref()
+                .filter(|c| c.len() >= 10)
+                .map(|c| c[..10].replace('/', "-")),
+            playlist_id: playlist.and_then(|p| p.id),
+            playlist_title: playlist.and_then(|p| p.title.clone()),
+            index: playlist.and_then(|p| {
+                p.tracks.as_ref()?.iter().position(|t| t.id == track.id).map(|i| i + 1)
+            }),
+        }
+    }
+}
+
+/// Decides where downloaded audio is placed, relative to the output folder.
+pub enum Namer {
+    /// One of the built-in folder layouts, with filenames either the default
+    /// `<title> (id=<id>).m4a` or built from a template
+    Standard {
+        folders: FolderLayout,
+        filename: Option<Template>,
+    },
+    #[cfg(feature = "lua")]
+    Lua(lua::LuaNamer),
+}
+
+/// Which folders tracks are sorted into.
+pub enum FolderLayout {
+    /// `likes/` and `playlists/<playlist> (id=<id>)/`
+    BySource,
+    /// `<artist>/`
+    ByArtist,
+    /// `<playlist> (id=<id>)/`, or `likes/`
+    ByPlaylist,
+    /// `<upload year>/<upload month>/`
+    ByYear,
+    /// Everything straight into the output folder
+    Flat,
+    Template(Template),
+}
+
+impl FolderLayout {
+    /// Parses an `--organize-by` argument: either the name of a built-in layout
+    /// or a folder template like `{artist}/{year}`.
+    pub fn parse(arg: &str) -> Result<Self, Error> {
+        Ok(match arg.to_lowercase().as_str() {
+            "source" => FolderLayout::BySource,
+            "artist" => FolderLayout::ByArtist,
+            "playlist" => FolderLayout::ByPlaylist,
+            "year" => FolderLayout::ByYear,
+            "flat" => FolderLayout::Flat,
+            _ if arg.contains('{') => FolderLayout::Template(Template::parse(arg, true)?),
+            _ => return Err(Error::FilenameTemplateError(format!(
+                "\"{}\" is not a layout (source, artist, playlist, year or flat) or a folder template",
+                arg
+            )))
+        })
+    }
+
+    fn folder(&self, ctx: &TrackContext) -> PathBuf {
+        match self {
+            FolderLayout::BySource => match playlist_folder(ctx) {
+                Some(folder) => PathBuf::from("playlists").join(folder),
+                None => PathBuf::from(ctx.kind)
+            },
+            FolderLayout::ByArtist => PathBuf::from(sanitize(field(ctx, "artist"))),
+            FolderLayout::ByPlaylist => playlist_folder(ctx).map_or_else(|| PathBuf::from(ctx.kind), PathBuf::from),
+            FolderLayout::ByYear => PathBuf::from(sanitize(field(ctx, "year"))).join(sanitize(field(ctx, "month"))),
+            FolderLayout::Flat => PathBuf::new(),
+            FolderLayout::Template(template) => template.fill(ctx)
+        }
+    }
+}
+
+fn playlist_folder(ctx: &TrackContext) -> Option<String> {
+    Some(sanitize(format!("{} (id={})", ctx.playlist_title.as_ref()?, ctx.playlist_id?)))
+}
+
+impl Namer {
+    /// Loads the naming script at the given path.
+    ///
+    /// The script must define a global `track_path(track)` function that
+    /// returns the path (relative to the output folder) to save the track to.
+    pub fn from_script<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        #[cfg(feature = "lua")]
+        {
+            Ok(Namer::Lua(lua::LuaNamer::load(path.as_ref())?))
+        }
+
+        #[cfg(not(feature = "lua"))]
+        {
+            Err(Error::NamingScriptError(format!(
+                "cannot load {}: orange-zester was built without the `lua` feature",
+                path.as_ref().display()
+            )))
+        }
+    }
+
+    /// Returns the path, relative to the output folder, to store the given
+    /// track at.
+    pub fn track_path(&self, ctx: &TrackContext) -> Result<PathBuf, Error> {
+        match self {
+            Namer::Standard { folders, filename } => {
+                let mut filename = match filename {
+                    Some(template) => template.fill(ctx).to_string_lossy().into_owned(),
+                    None => format!("{} (id={})", ctx.title, ctx.id)
+                };
+                if !filename.to_lowercase().ends_with(".m4a") {
+                    filename.push_str(".m4a");
+                }
+
+                Ok(folders.folder(ctx).join(sanitize(filename)))
+            },
+            #[cfg(feature = "lua")]
+            Namer::Lua(namer) => validate_relative(&namer.track_path(ctx)?),
+        }
+    }
+}
+
+/// The placeholders templates can use
+const TEMPLATE_FIELDS: &[&str] = &["artist", "title", "id", "upload_date", "year", "month", "playlist", "index"];
+
+// The value of a template field for the given track, with something sensible
+// standing in for anything the track doesn't have
+fn field(ctx: &TrackContext, field: &str) -> String {
+    let date_part = |range: std::ops::Range<usize>| {
+        ctx.upload_date.as_ref().and_then(|d| d.get(range)).map(String::from)
+    };
+
+    match field {
+        "artist" => ctx.artist.clone().unwrap_or_else(|| "Unknown Artist".into()),
+        "title" if ctx.title.is_empty() => "Untitled".into(),
+        "title" => ctx.title.clone(),
+        "id" => ctx.id.to_string(),
+        "upload_date" => ctx.upload_date.clone().unwrap_or_else(|| "unknown date".into()),
+        "year" => date_part(0..4).unwrap_or_else(|| "unknown year".into()),
+        "month" => date_part(5..7).unwrap_or_else(|| "unknown month".into()),
+        "playlist" => ctx.playlist_title.clone().unwrap_or_else(|| ctx.kind.into()),
+        "index" => ctx.index.map_or_else(|| "00".into(), |i| format!("{:02}", i)),
+        _ => unreachable!("fields are checked when parsing templates")
+    }
+}
+
+enum TemplatePart {
+    Literal(String),
+    Field(&'static str),
+    Separator,
+}
+
+/// A filename like `{artist} - {title} (id={id})` or folder path like
+/// `{artist}/{year}`, checked when it's parsed.
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+impl Template {
+    /// Parses a template; only folder templates (`allow_folders`) may contain
+    /// `/`.
+    pub fn parse(template: &str, allow_folders: bool) -> Result<Self, Error> {
+        let err = |msg: String| Error::FilenameTemplateError(format!("\"{}\": {}", template, msg));
+        if template.contains('\\') || (!allow_folders && template.contains('/')) {
+            return Err(err("filename templates can't contain path separators".into()));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '/']) {
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].into()));
+            }
+            if rest[start..].starts_with('/') {
+                parts.push(TemplatePart::Separator);
+                rest = &rest[start + 1..];
+                continue;
+            }
+
+            let end = rest[start..].find('}').ok_or_else(|| err("unclosed `{`".into()))? + start;
+            let name = &rest[start + 1..end];
+            let field = TEMPLATE_FIELDS
+                .iter()
+                .find(|f| **f == name)
+                .ok_or_else(|| err(format!(
+                    "unknown placeholder {{{}}} (expected one of {})",
+                    name,
+                    TEMPLATE_FIELDS.iter().map(|f| format!("{{{}}}", f)).collect::<Vec<_>>().join(", ")
+                )))?;
+            parts.push(TemplatePart::Field(field));
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(err("unmatched `}`".into()));
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.into()));
+        }
+
+        if !allow_folders && !parts.iter().any(|p| matches!(p, TemplatePart::Field(_))) {
+            return Err(err("every track would get the same filename; use at least one placeholder".into()));
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Fills in the template for the given track, sanitizing each path
+    /// component.
+    pub fn fill(&self, ctx: &TrackContext) -> PathBuf {
+        let mut path = PathBuf::new();
+        let mut component = String::new();
+        let push = |path: &mut PathBuf, component: &mut String| {
+            if !component.trim().is_empty() {
+                path.push(sanitize(component.as_str()));
+            }
+            component.clear();
+        };
+
+        for part in &self.parts {
+            match part {
+                TemplatePart::Literal(s) => component.push_str(s),
+                TemplatePart::Field(name) => component.push_str(&field(ctx, name)),
+                TemplatePart::Separator => push(&mut path, &mut component)
+            }
+        }
+        push(&mut path, &mut component);
+
+        path
+    }
+}
+
+// Makes sure a path handed back by user code stays inside the output folder,
+// sanitizing each of its components along the way.
+#[cfg_attr(not(feature = "lua"), allow(dead_code))]
+fn validate_relative(path: &str) -> Result<PathBuf, Error> {
+    let mut validated = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => validated.push(sanitize(part.to_string_lossy())),
+            Component::CurDir => {},
+            _ => return Err(Error::NamingScriptError(format!(
+                "\"{}\" is not a relative path inside the output folder",
+                path
+            )))
+        }
+    }
+
+    if validated.as_os_str().is_empty() {
+        return Err(Error::NamingScriptError("naming script returned an empty path".into()));
+    }
+
+    Ok(validated)
+}
+
+#[cfg(feature = "lua")]
+mod lua {
+    use super::TrackContext;
+    use crate::Error;
+    use mlua::{Function, Lua};
+    use std::fs;
+    use std::path::Path;
+
+    pub struct LuaNamer {
+        lua: Lua,
+    }
+
+    impl LuaNamer {
+        pub fn load(path: &Path) -> Result<Self, Error> {
+            let source = fs::read_to_string(path)?;
+            let lua = Lua::new();
+
+            lua.load(&source)
+                .set_name(path.to_string_lossy())
+                .exec()
+                .map_err(lua_err)?;
+
+            // Fail now rather than on the first downloaded track
+            lua.globals()
+                .get::<_, Function>("track_path")
+                .map_err(|_| Error::NamingScriptError(format!(
+                    "{} does not define a `track_path` function",
+                    path.display()
+                )))?;
+
+            Ok(Self { lua })
+        }
+
+        pub fn track_path(&self, ctx: &TrackContext) -> Result<String, Error> {
+            let track = self.lua.create_table().map_err(lua_err)?;
+            track.set("kind", ctx.kind).map_err(lua_err)?;
+            track.set("id", ctx.id).map_err(lua_err)?;
+            track.set("title", ctx.title.as_str()).map_err(lua_err)?;
+            track.set("artist", ctx.artist.as_deref()).map_err(lua_err)?;
+            track.set("upload_date", ctx.upload_date.as_deref()).map_err(lua_err)?;
+            track.set("index", ctx.index).map_err(lua_err)?;
+            track.set("playlist_id", ctx.playlist_id).map_err(lua_err)?;
+            track.set("playlist_title", ctx.playlist_title.as_deref()).map_err(lua_err)?;
+
+            let track_path: Function = self.lua.globals().get("track_path").map_err(lua_err)?;
+            track_path.call(track).map_err(lua_err)
+        }
+    }
+
+    fn lua_err(err: mlua::Error) -> Error {
+        Error::NamingScriptError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(title: &str, artist: &str) -> TrackContext {
+        TrackContext {
+            kind: "likes",
+            id: 42,
+            title: title.into(),
+            artist: Some(artist.into()),
+            upload_date: Some("2019-06-01".into()),
+            playlist_id: None,
+            playlist_title: None,
+            index: None
+        }
+    }
+
+    fn parse_err(template: &str, allow_folders: bool) -> String {
+        match Template::parse(template, allow_folders) {
+            Err(Error::FilenameTemplateError(msg)) => msg,
+            Err(e) => panic!("unexpected error {:?}", e),
+            Ok(_) => panic!("{} parsed", template)
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(parse_err("{artist} - {name}", false).contains("unknown placeholder {name}"));
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(parse_err("{artist} - {title", false).contains("unclosed"));
+        assert!(parse_err("{artist} - title}", false).contains("unmatched"));
+    }
+
+    #[test]
+    fn rejects_separators_in_filenames() {
+        assert!(parse_err("{artist}/{title}", false).contains("path separators"));
+        assert!(parse_err("{artist}\\{title}", true).contains("path separators"));
+    }
+
+    #[test]
+    fn fills_in_fields() {
+        let template = Template::parse("{year}-{month} {artist} - {title} (id={id})", false).unwrap();
+        assert_eq!(template.fill(&ctx("Song", "Band")), PathBuf::from("2019-06 Band - Song (id=42)"));
+    }
+
+    #[test]
+    fn sanitizes_separators_in_values() {
+        let template = Template::parse("{artist}/{title}", true).unwrap();
+        let path = template.fill(&ctx("AC/DC: Live?", "a/b\\c"));
+
+        assert_eq!(path.components().count(), 2);
+        assert_eq!(path, Path::new(&sanitize("a/b\\c")).join(sanitize("AC/DC: Live?")));
+        for component in path.iter() {
+            let component = component.to_string_lossy();
+            assert!(!component.contains('/') && !component.contains('\\'), "{}", component);
+        }
+    }
+}
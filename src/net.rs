@@ -0,0 +1,199 @@
+//! Settings shared by the requests zester makes itself, so that a stalled
+//! connection fails the request instead of hanging the run.
+//!
+//! The audio streams are still requested inside `orange-zest`, which doesn't
+//! take timeouts; they're read through a [`Deadline`] instead, which fails a
+//! track once its data stops coming in for longer than the read timeout.
+
+use crate::cache;
+use crate::filter;
+use crate::pace;
+use crate::soundcloud;
+use std::io::{self, Cursor, Read};
+use std::num::NonZeroU32;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long to wait for a connection to be made
+    pub connect: Duration,
+    /// How long to wait for more of a response before giving up on it
+    pub read: Duration,
+}
+
+// How long to wait on the network
+#[derive(StructOpt, Debug, Clone, Default)]
+pub struct TimeoutOpts {
+    /// Give up on connecting after this long (e.g. 30s; the default)
+    #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+    connect_timeout: Option<u64>,
+    /// Give up on a response after this long without data (e.g. 60s; the default)
+    #[structopt(long, parse(try_from_str = filter::parse_duration), value_name = "duration")]
+    read_timeout: Option<u64>,
+}
+
+impl TimeoutOpts {
+    /// Makes these the timeouts for the rest of the run.
+    pub fn apply(&self) {
+        init(self.connect_timeout, self.read_timeout);
+    }
+}
+
+// How requests to the API are made
+#[derive(StructOpt, Debug, Clone)]
+pub struct ApiOpts {
+    /// Fetch everything from the API again, rather than using responses cached by
+    /// recent runs
+    #[structopt(long)]
+    no_cache: bool,
+    /// Make at most n requests to the API a minute; zester slows down further on its own
+    /// whenever SoundCloud says it's getting too many
+    #[structopt(long, value_name = "n")]
+    max_requests_per_minute: Option<NonZeroU32>,
+    /// Send API requests here instead of https://api-v2.soundcloud.com, e.g. to a mirror,
+    /// a test server or a debugging proxy (orange-zest's own requests still go to SoundCloud)
+    #[structopt(long, parse(try_from_str = parse_base_url), value_name = "url")]
+    api_base_url: Option<String>,
+    /// Send this as the User-Agent of requests zester makes itself
+    #[structopt(long, value_name = "agent")]
+    user_agent: Option<String>,
+}
+
+impl ApiOpts {
+    /// Makes these the settings for the rest of the run.
+    pub fn apply(&self) {
+        cache::init(!self.no_cache);
+        pace::init(self.max_requests_per_minute);
+        if let Some(url) = &self.api_base_url {
+            soundcloud::set_api_base(url);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            let _ = USER_AGENT.set(user_agent.clone());
+        }
+    }
+}
+
+fn parse_base_url(arg: &str) -> Result<String, String> {
+    if !arg.starts_with("http://") && !arg.starts_with("https://") {
+        return Err(format!("\"{}\" isn't an http:// or https:// URL", arg));
+    }
+    Ok(arg.trim_end_matches('/').to_string())
+}
+
+/// Sets the timeouts (in milliseconds) for the rest of the run, falling back
+/// on the defaults for those not given.
+pub fn init(connect: Option<u64>, read: Option<u64>) {
+    let _ = TIMEOUTS.set(Timeouts {
+        connect: connect.map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_millis),
+        read: read.map_or(DEFAULT_READ_TIMEOUT, Duration::from_millis)
+    });
+}
+
+/// The User-Agent given with `--user-agent`, if any.
+pub fn user_agent() -> Option<&'static str> {
+    USER_AGENT.get().map(String::as_str)
+}
+
+pub fn timeouts() -> Timeouts {
+    *TIMEOUTS.get_or_init(|| Timeouts { connect: DEFAULT_CONNECT_TIMEOUT, read: DEFAULT_READ_TIMEOUT })
+}
+
+/// Starts a GET request with the timeouts applied.
+pub fn get(url: &str) -> ureq::Request {
+    request("GET", url)
+}
+
+/// Starts a request with the given method and the timeouts applied.
+pub fn request(method: &str, url: &str) -> ureq::Request {
+    let timeouts = timeouts();
+    let mut request = ureq::request(method, url);
+    request
+        .timeout_connect(timeouts.connect.as_millis() as u64)
+        .timeout_read(timeouts.read.as_millis() as u64);
+    if let Some(user_agent) = user_agent() {
+        request.set("User-Agent", user_agent);
+    }
+    request
+}
+
+/// Wraps a download made without the timeouts, failing it with
+/// `ErrorKind::TimedOut` once no data has come in for longer than the read
+/// timeout.
+///
+/// The download is read on a thread of its own, so that a read that never
+/// returns can be given up on; the thread is left to finish whenever the OS
+/// gives up on the connection.
+pub struct Deadline {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    // What's left of the last chunk that came in
+    pending: Cursor<Vec<u8>>,
+    finished: bool,
+    timeout: Duration,
+}
+
+impl Deadline {
+    pub fn new<R: Read + Send + 'static>(mut inner: R) -> Self {
+        // Only a chunk is read ahead of what's been taken
+        let (sender, chunks) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let chunk = match inner.read(&mut buf) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    read => read.map(|n| buf[..n].to_vec())
+                };
+                // An empty chunk is the end of the download
+                let last = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
+                if sender.send(chunk).is_err() || last {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            chunks,
+            pending: Cursor::new(Vec::new()),
+            finished: false,
+            timeout: timeouts().read
+        }
+    }
+}
+
+impl Read for Deadline {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.position() as usize == self.pending.get_ref().len() {
+            let chunk = match self.chunks.recv_timeout(self.timeout) {
+                Ok(chunk) => chunk?,
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no data for over {}s", self.timeout.as_secs())
+                    ));
+                },
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::new(io::ErrorKind::Other, "the download stopped unexpectedly"));
+                }
+            };
+            if chunk.is_empty() {
+                self.finished = true;
+                return Ok(0);
+            }
+            self.pending = Cursor::new(chunk);
+        }
+
+        self.pending.read(buf)
+    }
+}
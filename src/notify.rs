@@ -0,0 +1,164 @@
+//! Saying how a run went once it's over, with `--notify` (a webhook) and
+//! `--desktop-notify`, so that runs under cron don't fail unnoticed.
+
+use crate::history::{self, Outcome, RunRecord};
+use crate::locale;
+use crate::logging;
+use crate::net;
+use crate::Error;
+use serde_json::json;
+use std::process::Command;
+use std::sync::OnceLock;
+use structopt::clap::arg_enum;
+use structopt::StructOpt;
+
+static SETTINGS: OnceLock<NotifyOpts> = OnceLock::new();
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum NotifyFormat {
+        Json,
+        Discord,
+        Slack
+    }
+}
+
+// Where to say how a run went
+#[derive(StructOpt, Debug, Clone)]
+pub struct NotifyOpts {
+    /// POST a summary to this URL when the run finishes, fails or is interrupted
+    #[structopt(long, value_name = "url")]
+    notify: Option<String>,
+    /// What --notify sends: a JSON summary, or a message for a Discord or Slack webhook
+    #[structopt(
+        long,
+        possible_values = &NotifyFormat::variants(),
+        case_insensitive = true,
+        default_value = "Json"
+    )]
+    notify_format: NotifyFormat,
+    /// Show a desktop notification when the run finishes, fails or is interrupted
+    #[structopt(long)]
+    desktop_notify: bool,
+}
+
+impl NotifyOpts {
+    /// Makes these the notifications sent for the rest of the run.
+    pub fn apply(self) {
+        let _ = SETTINGS.set(self);
+    }
+}
+
+/// Sends whatever notifications were asked for about the run that just
+/// ended, with the error that stopped it if there was one. Problems sending
+/// them are only warned about.
+pub fn run_ended(error: Option<&Error>) {
+    let settings = match SETTINGS.get() {
+        Some(settings) => settings,
+        None => return
+    };
+    let record = match history::ended() {
+        Some(record) => record,
+        None => return
+    };
+    let (title, message) = describe(&record, error);
+
+    if let Some(url) = &settings.notify {
+        let payload = match settings.notify_format {
+            NotifyFormat::Json => json!({
+                "id": record.id,
+                "command": record.command,
+                "outcome": record.outcome,
+                "started_at": record.started_at,
+                "finished_at": record.finished_at,
+                "counts": record.counts,
+                "output_folder": record.output_folder,
+                "report": record.report,
+                "error": error.map(|e| format!("{:?}", e)),
+                "message": format!("{}: {}", title, message)
+            }),
+            NotifyFormat::Discord => json!({ "content": format!("**{}**\n{}", title, message) }),
+            NotifyFormat::Slack => json!({ "text": format!("*{}*\n{}", title, message) })
+        };
+
+        logging::debug(&format!("POST {}", url));
+        let resp = net::request("POST", url).send_json(payload);
+        if !resp.ok() {
+            eprintln!("  [warning] --notify: POST {} returned {}", url, resp.status());
+        }
+    }
+
+    if settings.desktop_notify {
+        if let Err(e) = desktop(&title, &message) {
+            eprintln!("  [warning] couldn't show a desktop notification: {}", e);
+        }
+    }
+}
+
+// A title saying how the run ended, and a line of detail
+fn describe(record: &RunRecord, error: Option<&Error>) -> (String, String) {
+    let outcome = match record.outcome {
+        Some(Outcome::Finished) => "finished",
+        Some(Outcome::Interrupted) => "was interrupted",
+        Some(Outcome::Failed) | None => "failed"
+    };
+    let title = format!("zester {} {}", record.command, outcome);
+
+    let mut details = Vec::new();
+    if let Some(counts) = &record.counts {
+        details.push(format!(
+            "downloaded {} tracks ({}), linked {}, skipped {}, failed {}",
+            locale::number(counts.downloaded),
+            locale::bytes(counts.bytes),
+            locale::number(counts.linked),
+            locale::number(counts.skipped),
+            locale::number(counts.failed)
+        ));
+    }
+    if let Some(error) = error {
+        details.push(format!("{:?}", error));
+    }
+    if let Some(report) = &record.report {
+        details.push(format!("see {}", report.display()));
+    }
+    if let Some(finished_at) = record.finished_at {
+        let took = (finished_at - record.started_at).to_std().unwrap_or_default();
+        details.push(format!("took {}", locale::duration(took)));
+    }
+
+    (title, details.join("; "))
+}
+
+#[cfg(target_os = "macos")]
+fn desktop(title: &str, message: &str) -> Result<(), String> {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let script = format!("display notification {} with title {}", quote(message), quote(title));
+    run(Command::new("osascript").arg("-e").arg(script))
+}
+
+#[cfg(windows)]
+fn desktop(title: &str, message: &str) -> Result<(), String> {
+    // A balloon tip from the tray, which needs nothing installed
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; $n = New-Object System.Windows.Forms.NotifyIcon; \
+         $n.Icon = [System.Drawing.SystemIcons]::Information; $n.Visible = $true; \
+         $n.ShowBalloonTip(10000, {}, {}, 'None'); Start-Sleep -Seconds 5; $n.Dispose()",
+        quote(title),
+        quote(message)
+    );
+    run(Command::new("powershell").args(["-NoProfile", "-Command", &script]))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn desktop(title: &str, message: &str) -> Result<(), String> {
+    run(Command::new("notify-send").arg("--app-name=zester").arg(title).arg(message))
+}
+
+fn run(command: &mut Command) -> Result<(), String> {
+    match command.output() {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => Err(format!("{} ({})", output.status, String::from_utf8_lossy(&output.stderr).trim())),
+        Err(e) => Err(format!("couldn't run it: {}", e))
+    }
+}
@@ -0,0 +1,177 @@
+//! Moving archived audio out to secondary storage and back again.
+
+use crate::lock::{Lock, LOCK_FILE};
+use crate::manifest::{FileEntry, Manifest, MANIFEST_FILE};
+use crate::Error;
+use chrono::{Duration, Utc};
+use indicatif::HumanBytes;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct OffloadOpts {
+    /// Archive folder containing a manifest.json
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+    /// Only move audio downloaded longer ago than this (e.g. 30d, 12w, 6m, 2y)
+    #[structopt(long, parse(try_from_str = parse_age), value_name = "age")]
+    older_than: Duration,
+    /// Folder on secondary storage to move audio to
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    to: PathBuf,
+    /// Don't leave symlinks behind; offloaded files are then only listed in the manifest
+    #[structopt(long)]
+    no_symlinks: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RecallOpts {
+    /// Archive folder containing a manifest.json
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+    /// Ids of the tracks to bring back (defaults to every offloaded track)
+    track_ids: Vec<u64>,
+}
+
+/// Parses ages like `30d`, `12w`, `6m` or `2y`.
+pub fn parse_age(arg: &str) -> Result<Duration, String> {
+    let err = || format!("\"{}\" is not an age like 30d, 12w, 6m or 2y", arg);
+    if arg.len() < 2 {
+        return Err(err());
+    }
+
+    let (num, unit) = arg.split_at(arg.len() - 1);
+    let num: i64 = num.parse().map_err(|_| err())?;
+    match unit {
+        "d" => Ok(Duration::days(num)),
+        "w" => Ok(Duration::weeks(num)),
+        "m" => Ok(Duration::days(num * 30)),
+        "y" => Ok(Duration::days(num * 365)),
+        _ => Err(err())
+    }
+}
+
+// Locks the archive in `folder`, then loads its manifest
+fn load_manifest(folder: &Path) -> Result<(Lock, Manifest), Error> {
+    if !folder.join(MANIFEST_FILE).exists() {
+        return Err(Error::JsonFileNotFound(folder.join(MANIFEST_FILE).to_string_lossy().into()));
+    }
+
+    let lock = Lock::acquire(folder.join(LOCK_FILE))?;
+    Ok((lock, Manifest::load(folder)?))
+}
+
+pub fn run(opts: OffloadOpts) -> Result<(), Error> {
+    let (_lock, mut manifest) = load_manifest(&opts.folder)?;
+    let cutoff = Utc::now() - opts.older_than;
+    let to = if opts.to.is_absolute() { opts.to.clone() } else { std::env::current_dir()?.join(&opts.to) };
+
+    let (mut moved, mut bytes) = (0, 0);
+    let mut result = Ok(());
+    let entries = manifest.tracks
+        .values_mut()
+        .flat_map(|t| t.files.iter_mut().chain(t.previous_versions.iter_mut()))
+        .filter(|f| f.offloaded_to.is_none() && f.downloaded_at < cutoff);
+    for entry in entries {
+        if let Err(e) = offload_file(&opts.folder, &to, entry, !opts.no_symlinks) {
+            result = Err(e);
+            break;
+        }
+
+        println!("  [offloaded] {}", entry.path);
+        moved += 1;
+        bytes += entry.bytes;
+    }
+
+    // Record whatever was moved even if something went wrong part way
+    manifest.save(&opts.folder)?;
+    result?;
+
+    println!("Offloaded {} files ({}) to {}", moved, HumanBytes(bytes), to.display());
+    Ok(())
+}
+
+fn offload_file(folder: &Path, to: &Path, entry: &mut FileEntry, symlink: bool) -> Result<(), Error> {
+    let from = folder.join(&entry.path);
+    let dest = to.join(&entry.path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    move_file(&from, &dest)?;
+    entry.offloaded_to = Some(dest.to_string_lossy().into());
+
+    if symlink {
+        make_symlink(&dest, &from)?;
+    }
+
+    Ok(())
+}
+
+pub fn recall(opts: RecallOpts) -> Result<(), Error> {
+    let (_lock, mut manifest) = load_manifest(&opts.folder)?;
+
+    let (mut recalled, mut bytes) = (0, 0);
+    let mut result = Ok(());
+    let entries = manifest.tracks
+        .iter_mut()
+        .filter(|(id, _)| opts.track_ids.is_empty() || opts.track_ids.contains(id))
+        .flat_map(|(_, t)| t.files.iter_mut().chain(t.previous_versions.iter_mut()))
+        .filter(|f| f.offloaded_to.is_some());
+    for entry in entries {
+        if let Err(e) = recall_file(&opts.folder, entry) {
+            result = Err(e);
+            break;
+        }
+
+        println!("  [recalled] {}", entry.path);
+        recalled += 1;
+        bytes += entry.bytes;
+    }
+
+    manifest.save(&opts.folder)?;
+    result?;
+
+    println!("Recalled {} files ({})", recalled, HumanBytes(bytes));
+    Ok(())
+}
+
+fn recall_file(folder: &Path, entry: &mut FileEntry) -> Result<(), Error> {
+    let from = PathBuf::from(entry.offloaded_to.as_ref().unwrap());
+    let dest = folder.join(&entry.path);
+
+    if fs::symlink_metadata(&dest).is_ok_and(|m| m.file_type().is_symlink()) {
+        fs::remove_file(&dest)?;
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    move_file(&from, &dest)?;
+    entry.offloaded_to = None;
+
+    Ok(())
+}
+
+// Renames if possible, falling back to copying for moves across filesystems
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Creates a symlink at `link` pointing to `target`.
+#[cfg(unix)]
+pub fn make_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub fn make_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
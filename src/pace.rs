@@ -0,0 +1,107 @@
+//! Spacing out requests to the SoundCloud API: to at most
+//! `--max-requests-per-minute`, and further on its own whenever the API
+//! answers with 429 (too many requests) or a server error.
+//!
+//! After each 429 or server error in a row the gap between requests doubles
+//! (or becomes what `Retry-After` asks for, if that's longer), and it shrinks
+//! back as requests go through. `orange-zest` doesn't make its requests
+//! through here, so they're paced from the events it sends between them.
+
+use crate::locale;
+use crate::logging;
+use std::num::NonZeroU32;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How many times a request that was rate limited or hit a server error is
+/// tried again
+pub const MAX_RETRIES: u32 = 5;
+
+/// The gap between requests after the first 429 or server error
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest the gap gets, however many errors there are in a row
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+/// Below this, the gap is dropped altogether
+const FULL_SPEED: Duration = Duration::from_millis(100);
+
+static MAX_PER_MINUTE: OnceLock<Option<NonZeroU32>> = OnceLock::new();
+// Only for the length of the run; each run starts out at full speed
+static STATE: Mutex<State> = Mutex::new(State { next_at: None, backoff: Duration::ZERO });
+
+struct State {
+    /// When the next request may be made
+    next_at: Option<Instant>,
+    /// The gap the API's errors have asked for
+    backoff: Duration,
+}
+
+/// Caps requests at the given number a minute for the rest of the run.
+pub fn init(max_per_minute: Option<NonZeroU32>) {
+    let _ = MAX_PER_MINUTE.set(max_per_minute);
+}
+
+fn min_interval() -> Duration {
+    match MAX_PER_MINUTE.get().copied().flatten() {
+        Some(max) => Duration::from_secs(60) / max.get(),
+        None => Duration::ZERO
+    }
+}
+
+/// Waits until the next request may be made, and claims that turn.
+pub fn wait() {
+    let wait = {
+        let mut state = STATE.lock().unwrap();
+        let now = Instant::now();
+        let at = state.next_at.map_or(now, |at| at.max(now));
+        state.next_at = Some(at + min_interval().max(state.backoff));
+        at - now
+    };
+
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
+
+/// Whether the API answering with `status` means it wants fewer requests.
+pub fn is_overloaded(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// Notes how the API answered a request, slowing down after 429s and server
+/// errors (for at least `retry_after`, if it said) and speeding back up after
+/// anything else.
+pub fn record(status: u16, retry_after: Option<Duration>) {
+    if is_overloaded(status) {
+        slow_down(&status.to_string(), retry_after);
+        return;
+    }
+
+    let mut state = STATE.lock().unwrap();
+    if !state.backoff.is_zero() {
+        state.backoff = state.backoff.mul_f64(0.8);
+        if state.backoff < FULL_SPEED {
+            state.backoff = Duration::ZERO;
+            logging::info("Requests are going through again; back to full speed");
+        }
+    }
+}
+
+/// Notes that `orange-zest` ran into a server error, slowing down for at
+/// least as long as it says it's pausing for.
+pub fn server_error(pause: Option<Duration>) {
+    slow_down("server error", pause);
+}
+
+fn slow_down(cause: &str, at_least: Option<Duration>) {
+    let mut state = STATE.lock().unwrap();
+    let backoff = (state.backoff * 2).clamp(MIN_BACKOFF, MAX_BACKOFF).max(at_least.unwrap_or_default());
+    logging::info(&format!(
+        "Slowing down to a request every {} after a {}",
+        locale::duration(backoff),
+        cause
+    ));
+    state.backoff = backoff;
+    // Whatever was planned, nothing goes out until the gap has passed
+    state.next_at = Some(Instant::now() + backoff);
+}
@@ -0,0 +1,82 @@
+//! Grabbing everything a single account has uploaded as fast as possible,
+//! for when it's about to disappear.
+
+use crate::api_usage::ApiBudget;
+use crate::atomic::write_json;
+use crate::concurrency::Credentials;
+use crate::download::{download_loose_tracks, TrackSaver};
+use crate::events::EventFeed;
+use crate::naming::{FolderLayout, Namer};
+use crate::progress::Progress;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::Error;
+use orange_zest::api::Playlists;
+use orange_zest::Zester;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Makes the user type the account name back before going ahead, since panic
+/// mode ignores the usual limits on how hard it hits the API.
+pub fn confirm(user: &str) -> Result<(), Error> {
+    println!(
+        "Panic mode downloads everything {} has uploaded with as many parallel requests as it's told to,\n\
+         ignoring the usual politeness limits. This can get your account or IP rate limited.",
+        user
+    );
+    print!("Type the account name ({}) to go ahead: ", user);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    if answer.trim() != user {
+        return Err(Error::Cancelled("panic mode wasn't confirmed".into()));
+    }
+
+    Ok(())
+}
+
+/// Saves the account's profile, tracks and playlists as JSON to
+/// `output_folder`, then downloads the audio of every uploaded track into
+/// `output_folder/audio`.
+pub fn run(
+    user: &str,
+    output_folder: &Path,
+    concurrency: usize,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    let budget = ApiBudget::new("panic", None);
+
+    pb.set_message(&format!("Looking up {}", user));
+    let account = api_client.resolve_user(user)?;
+    budget.record(1);
+    let user_id = account.id.ok_or_else(|| Error::HttpError(format!("{} resolved to a user without an id", user)))?;
+    fs::create_dir_all(output_folder)?;
+    write_json(&account, output_folder.join("user.json"), true)?;
+
+    // Metadata first: it's quick and it's what's needed to tell what was lost
+    pb.set_message(&format!("Getting {}'s tracks", user));
+    let tracks = api_client.user_tracks(user_id, Whose::Public, || budget.record(1))?;
+    write_json(&tracks, output_folder.join("tracks.json"), true)?;
+
+    pb.set_message(&format!("Getting {}'s playlists", user));
+    let playlists = Playlists { playlists: api_client.user_playlists(user_id, Whose::Public, std::u64::MAX, || budget.record(1))? };
+    write_json(&playlists, output_folder.join("playlists.json"), true)?;
+    pb.println(format!("Saved {}'s profile, {} tracks and {} playlists", user, tracks.len(), playlists.playlists.len()));
+
+    let audio_folder = output_folder.join("audio");
+    fs::create_dir_all(&audio_folder)?;
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::Flat, filename: None };
+    let saver = TrackSaver::new(&audio_folder, namer, api_client, &budget, &events, pb)?;
+
+    pb.set_length(tracks.len() as u64);
+    let result = download_loose_tracks(&saver, tracks, concurrency, zester, credentials);
+    saver.save_manifest()?;
+    result?;
+
+    saver.finish()
+}
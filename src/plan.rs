@@ -0,0 +1,83 @@
+//! Working out what an audio run would do without doing it (`--dry-run`).
+
+use crate::naming::{Namer, TrackContext};
+use crate::progress::Progress;
+use crate::Error;
+use indicatif::HumanBytes;
+use orange_zest::api::{Playlist, TrackInfo};
+use std::path::{Path, PathBuf};
+
+/// Bitrate of the AAC streams audio is downloaded from, used to estimate file
+/// sizes from track durations
+const ESTIMATED_BITS_PER_SEC: u64 = 160_000;
+
+/// Roughly how big the track's audio will be, if its duration is known.
+pub fn estimated_bytes(track: &TrackInfo) -> Option<u64> {
+    track.duration.map(|ms| ms * ESTIMATED_BITS_PER_SEC / 8 / 1000)
+}
+
+pub struct PlannedTrack {
+    /// Where the track would be saved, relative to the output folder
+    pub path: PathBuf,
+    pub estimated_bytes: Option<u64>,
+    /// Whether something's already there, and would be replaced
+    pub already_present: bool,
+}
+
+/// The tracks an audio run would download and where it would put them.
+pub struct DryRun<'a> {
+    output_folder: &'a Path,
+    tracks: Vec<PlannedTrack>,
+}
+
+impl<'a> DryRun<'a> {
+    pub fn new(output_folder: &'a Path) -> Self {
+        Self {
+            output_folder,
+            tracks: Vec::new()
+        }
+    }
+
+    /// Notes down that the given track would be downloaded.
+    pub fn add(&mut self, namer: &Namer, track: &TrackInfo, playlist: Option<&Playlist>) -> Result<(), Error> {
+        let kind = if playlist.is_some() { "playlists" } else { "likes" };
+        let path = namer.track_path(&TrackContext::new(kind, track, playlist))?;
+
+        self.tracks.push(PlannedTrack {
+            already_present: self.output_folder.join(&path).exists(),
+            estimated_bytes: estimated_bytes(track),
+            path
+        });
+
+        Ok(())
+    }
+
+    pub fn tracks(&self) -> &[PlannedTrack] {
+        &self.tracks
+    }
+
+    /// Lists every planned track, followed by a summary.
+    pub fn print(&self, pb: &Progress) {
+        for track in &self.tracks {
+            pb.println(format!(
+                "  {}{} ({})",
+                track.path.display(),
+                if track.already_present { " [already present, would be re-downloaded]" } else { "" },
+                track.estimated_bytes.map_or("unknown size".into(), |b| format!("~{}", HumanBytes(b)))
+            ));
+        }
+
+        let estimated: u64 = self.tracks.iter().filter_map(|t| t.estimated_bytes).sum();
+        let unknown = self.tracks.iter().filter(|t| t.estimated_bytes.is_none()).count();
+        let present = self.tracks.iter().filter(|t| t.already_present).count();
+
+        pb.println(format!(
+            "Dry run: would download {} tracks ({} already present) to {}, roughly {}{}",
+            self.tracks.len(),
+            present,
+            self.output_folder.display(),
+            HumanBytes(estimated),
+            if unknown > 0 { format!(" plus {} tracks of unknown size", unknown) } else { String::new() }
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+//! Extra client ids (and optionally tokens) to spread API requests over, for
+//! archiving jobs big enough to run into SoundCloud's rate limits.
+//!
+//! The first credential is always the user's own, and requests for the user's
+//! own data (`/me`, their likes, playlists and so on) only ever go out with it.
+//! The others are moved on to in turn whenever a request is rate limited; they
+//! can leave out the token if they're only used for public data. Requests made inside `orange-zest` always use the
+//! user's own credentials.
+
+use serde::Deserialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// A credential listed under `credential-pool` in the config file.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PoolEntry {
+    pub client_id: String,
+    pub oauth_token: Option<String>,
+}
+
+struct Member {
+    client_id: String,
+    oauth_token: Option<String>,
+    requests: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+/// A credential to make a request with.
+pub struct Credential<'a> {
+    index: usize,
+    pub client_id: &'a str,
+    pub oauth_token: Option<&'a str>,
+}
+
+/// How much a credential was used during the run.
+#[derive(Debug, Clone)]
+pub struct CredentialUsage {
+    /// The end of the client id, enough to tell credentials apart
+    pub label: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+}
+
+pub struct CredentialPool {
+    members: Vec<Member>,
+    current: AtomicUsize,
+}
+
+impl CredentialPool {
+    pub fn new(oauth_token: String, client_id: String, extra: Vec<PoolEntry>) -> Self {
+        let own = PoolEntry { client_id, oauth_token: Some(oauth_token) };
+        let members = std::iter::once(own)
+            .chain(extra)
+            .map(|entry| Member {
+                client_id: entry.client_id,
+                oauth_token: entry.oauth_token,
+                requests: AtomicU64::new(0),
+                rate_limited: AtomicU64::new(0)
+            })
+            .collect();
+
+        Self { members, current: AtomicUsize::new(0) }
+    }
+
+    /// How many credentials there are, the user's own included.
+    pub fn size(&self) -> usize {
+        self.members.len()
+    }
+
+    /// The user's own credential.
+    pub fn primary(&self) -> Credential<'_> {
+        self.credential(0)
+    }
+
+    /// The credential requests are going out with at the moment.
+    pub fn current(&self) -> Credential<'_> {
+        self.credential(self.current.load(Ordering::SeqCst))
+    }
+
+    /// Notes that a request made with the given credential came back with
+    /// `status`.
+    pub fn record(&self, credential: &Credential, status: u16) {
+        let member = &self.members[credential.index];
+        member.requests.fetch_add(1, Ordering::SeqCst);
+        if status == 429 {
+            member.rate_limited.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Moves on from a rate limited credential to the next one, returning the
+    /// one to use now. Requests that were rate limited at the same time only
+    /// move things on once.
+    pub fn rotate(&self, from: &Credential) -> Credential<'_> {
+        let next = (from.index + 1) % self.members.len();
+        let _ = self.current.compare_exchange(from.index, next, Ordering::SeqCst, Ordering::SeqCst);
+        self.current()
+    }
+
+    pub fn usage(&self) -> Vec<CredentialUsage> {
+        self.members
+            .iter()
+            .map(|m| CredentialUsage {
+                label: label(&m.client_id),
+                requests: m.requests.load(Ordering::SeqCst),
+                rate_limited: m.rate_limited.load(Ordering::SeqCst)
+            })
+            .collect()
+    }
+
+    fn credential(&self, index: usize) -> Credential<'_> {
+        let member = &self.members[index];
+        Credential {
+            index,
+            client_id: &member.client_id,
+            oauth_token: member.oauth_token.as_deref()
+        }
+    }
+}
+
+impl Credential<'_> {
+    /// The end of the client id, so logs don't give it away.
+    pub fn label(&self) -> String {
+        label(self.client_id)
+    }
+}
+
+fn label(client_id: &str) -> String {
+    let tail: String = client_id.chars().rev().take(4).collect::<Vec<_>>().into_iter().rev().collect();
+    format!("…{}", tail)
+}
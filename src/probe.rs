@@ -0,0 +1,52 @@
+//! Reading what's inside audio files with `ffprobe`.
+
+use crate::Error;
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+
+/// The format of the first audio stream in a file.
+pub struct AudioInfo {
+    pub codec: String,
+    /// In bits per second
+    pub bit_rate: Option<u64>,
+    /// In Hz
+    pub sample_rate: Option<u64>,
+}
+
+/// Whether `ffprobe` can be run at all.
+pub fn ffprobe_installed() -> bool {
+    Command::new("ffprobe").arg("-version").output().is_ok_and(|o| o.status.success())
+}
+
+/// Probes the audio file at `path`.
+pub fn probe(path: &Path) -> Result<AudioInfo, Error> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "a:0", "-of", "json"])
+        .args(["-show_entries", "stream=codec_name,bit_rate,sample_rate:format=bit_rate"])
+        .arg(path)
+        .output()
+        .map_err(|e| Error::ProbeError(format!("couldn't run ffprobe (is ffmpeg installed?): {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::ProbeError(format!(
+            "{}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let probed: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| Error::ProbeError(format!("{}: unexpected ffprobe output: {}", path.display(), e)))?;
+    let stream = probed["streams"]
+        .get(0)
+        .ok_or_else(|| Error::ProbeError(format!("{} has no audio", path.display())))?;
+    // ffprobe gives numbers as strings
+    let number = |value: &Value| value.as_str().and_then(|n| n.parse().ok());
+
+    Ok(AudioInfo {
+        codec: stream["codec_name"].as_str().unwrap_or("unknown").to_string(),
+        // Streams in some containers only have the overall bitrate
+        bit_rate: number(&stream["bit_rate"]).or_else(|| number(&probed["format"]["bit_rate"])),
+        sample_rate: number(&stream["sample_rate"])
+    })
+}
@@ -0,0 +1,455 @@
+//! Reporting progress, either on a bar or (when nobody's watching, say under
+//! cron) as plain log lines, which can also be full sentences for screen
+//! readers.
+
+use crate::events::ProgressMode;
+use crate::locale;
+use crate::logging::{self, Level};
+use crate::plan;
+use chrono::Local;
+use indicatif::{ProgressBar, ProgressStyle};
+use orange_zest::api::TrackInfo;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often plain output summarizes how far along a bar would be, unless
+/// told otherwise
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often download speeds are shown next to the track being downloaded
+const TRANSFER_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A progress bar that can also report itself as timestamped lines of text.
+///
+/// Takes the same calls as `indicatif::ProgressBar`.
+pub struct Progress {
+    bar: ProgressBar,
+    /// Set when progress is logged instead of drawn
+    plain: Option<Mutex<PlainState>>,
+}
+
+struct PlainState {
+    prefix: String,
+    message: String,
+    position: u64,
+    length: Option<u64>,
+    /// When the bar was last reset, to estimate the time remaining from
+    started: Instant,
+    last_summary: Instant,
+    interval: Duration,
+    /// Summaries are sentences, and count failures
+    verbose: bool,
+    failures: u64,
+}
+
+impl Progress {
+    /// `interval` is how often plain output summarizes progress.
+    pub fn new(mode: ProgressMode, interval: Duration) -> Self {
+        match mode {
+            ProgressMode::Bar => {
+                let bar = ProgressBar::new_spinner();
+                bar.enable_steady_tick(120);
+                Self { bar, plain: None }
+            },
+            // Events on stdout take the bar's place
+            ProgressMode::Json => Self { bar: ProgressBar::hidden(), plain: None },
+            ProgressMode::Plain | ProgressMode::PlainVerbose => Self {
+                bar: ProgressBar::hidden(),
+                plain: Some(Mutex::new(PlainState {
+                    prefix: String::new(),
+                    message: String::new(),
+                    position: 0,
+                    length: None,
+                    started: Instant::now(),
+                    last_summary: Instant::now(),
+                    interval,
+                    verbose: mode == ProgressMode::PlainVerbose,
+                    failures: 0
+                }))
+            }
+        }
+    }
+
+    pub fn set_style(&self, style: ProgressStyle) {
+        self.bar.set_style(style);
+    }
+
+    pub fn println<I: Into<String>>(&self, msg: I) {
+        let msg = msg.into();
+        logging::record(Level::Notice, &msg);
+        if self.plain.is_some() {
+            log(&msg);
+        }
+        self.bar.println(msg);
+    }
+
+    /// Messages are logged unless they're just naming what a bar is working
+    /// on, which the periodic summaries take care of.
+    pub fn set_message(&self, msg: &str) {
+        if let Some(plain) = &self.plain {
+            let mut plain = plain.lock().unwrap();
+            if plain.length.is_none() && !msg.is_empty() && msg != plain.message {
+                log(msg);
+            }
+            plain.message = msg.to_string();
+        }
+        self.bar.set_message(msg);
+    }
+
+    pub fn set_prefix(&self, prefix: &str) {
+        if let Some(plain) = &self.plain {
+            let mut plain = plain.lock().unwrap();
+            if prefix != plain.prefix {
+                log(prefix);
+            }
+            plain.prefix = prefix.to_string();
+        }
+        self.bar.set_prefix(prefix);
+    }
+
+    pub fn set_length(&self, len: u64) {
+        if let Some(plain) = &self.plain {
+            // Spinners are given an "infinite" length
+            plain.lock().unwrap().length = Some(len).filter(|&len| len != !0);
+        }
+        self.bar.set_length(len);
+    }
+
+    pub fn inc(&self, delta: u64) {
+        if let Some(plain) = &self.plain {
+            let mut plain = plain.lock().unwrap();
+            plain.position += delta;
+
+            let done = plain.length == Some(plain.position);
+            if done || plain.last_summary.elapsed() >= plain.interval {
+                plain.summarize();
+            }
+        }
+        self.bar.inc(delta);
+    }
+
+    pub fn reset(&self) {
+        if let Some(plain) = &self.plain {
+            let mut plain = plain.lock().unwrap();
+            plain.position = 0;
+            plain.started = Instant::now();
+            plain.last_summary = Instant::now();
+        }
+        self.bar.reset();
+    }
+
+    /// Counts a failed item towards the failures that verbose summaries
+    /// mention.
+    pub fn failed(&self) {
+        if let Some(plain) = &self.plain {
+            plain.lock().unwrap().failures += 1;
+        }
+    }
+
+    pub fn finish_with_message(&self, msg: &str) {
+        if self.plain.is_some() {
+            log(msg);
+        }
+        self.bar.finish_with_message(msg);
+    }
+}
+
+/// Progress through work done in phases one after the other, each with its own
+/// total. The bar follows the current phase, with how far along the work is as
+/// a whole shown next to what's being worked on.
+pub struct Phases<'a> {
+    pb: &'a Progress,
+    label: &'static str,
+    phases: Vec<Phase>,
+    current: AtomicUsize,
+    /// What's being worked on right now
+    item: Mutex<String>,
+}
+
+struct Phase {
+    name: &'static str,
+    total: AtomicU64,
+    done: AtomicU64,
+}
+
+impl<'a> Phases<'a> {
+    pub fn new(pb: &'a Progress, label: &'static str, names: &[&'static str]) -> Self {
+        Self {
+            pb,
+            label,
+            phases: names
+                .iter()
+                .map(|&name| Phase { name, total: AtomicU64::new(0), done: AtomicU64::new(0) })
+                .collect(),
+            current: AtomicUsize::new(0),
+            item: Mutex::new(String::new())
+        }
+    }
+
+    /// Sets (or corrects) the number of items in the given phase; later
+    /// phases can be given an estimate until they start.
+    pub fn set_total(&self, phase: usize, total: u64) {
+        self.phases[phase].total.store(total, Ordering::SeqCst);
+        if phase == self.current.load(Ordering::SeqCst) {
+            self.pb.set_length(total);
+        }
+        self.refresh();
+    }
+
+    /// Moves the bar on to the given phase.
+    pub fn start(&self, phase: usize) {
+        self.current.store(phase, Ordering::SeqCst);
+        self.item.lock().unwrap().clear();
+
+        self.pb.reset();
+        self.pb.set_length(self.phases[phase].total.load(Ordering::SeqCst));
+        self.pb.set_prefix(&format!(
+            "{} - phase {}/{}: {}",
+            self.label,
+            phase + 1,
+            self.phases.len(),
+            self.phases[phase].name
+        ));
+        self.refresh();
+    }
+
+    pub fn working_on(&self, item: &str) {
+        *self.item.lock().unwrap() = item.to_string();
+        self.refresh();
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.phases[self.current.load(Ordering::SeqCst)].done.fetch_add(delta, Ordering::SeqCst);
+        self.pb.inc(delta);
+        self.refresh();
+    }
+
+    /// How many items of the given phase are done.
+    pub fn done(&self, phase: usize) -> u64 {
+        self.phases[phase].done.load(Ordering::SeqCst)
+    }
+
+    fn refresh(&self) {
+        let total: u64 = self.phases.iter().map(|p| p.total.load(Ordering::SeqCst)).sum();
+        let done: u64 = self.phases.iter().map(|p| p.done.load(Ordering::SeqCst)).sum();
+        let percent = (done * 100).checked_div(total).unwrap_or(0).min(100);
+
+        let item = self.item.lock().unwrap();
+        if item.is_empty() {
+            self.pb.set_message(&format!("{}% overall", percent));
+        } else {
+            self.pb.set_message(&format!("{}% overall - {}", percent, item));
+        }
+    }
+}
+
+/// How fast audio is coming in and how long the rest of a phase should take,
+/// shown next to the track being downloaded.
+///
+/// Downloads don't say how big they are up front, so track sizes are
+/// estimated from their durations (see [`plan::estimated_bytes`]).
+pub struct Transfer {
+    started: Mutex<Instant>,
+    last_shown: Mutex<Instant>,
+    downloaded: AtomicU64,
+    /// Estimated bytes of the tracks not finished yet
+    remaining: AtomicU64,
+    /// Bytes read so far of the tracks being downloaded
+    in_flight: AtomicU64,
+    /// What tracks of unknown duration are taken to be
+    unknown_estimate: AtomicU64,
+}
+
+impl Default for Transfer {
+    fn default() -> Self {
+        Self {
+            started: Mutex::new(Instant::now()),
+            last_shown: Mutex::new(Instant::now()),
+            downloaded: AtomicU64::new(0),
+            remaining: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            unknown_estimate: AtomicU64::new(0)
+        }
+    }
+}
+
+impl Transfer {
+    /// Starts over for a phase downloading the given tracks.
+    pub fn start<'t>(&self, tracks: impl IntoIterator<Item = &'t TrackInfo>) {
+        let (mut known, mut num_known, mut num_unknown) = (0, 0, 0);
+        for track in tracks {
+            match plan::estimated_bytes(track) {
+                Some(bytes) => {
+                    known += bytes;
+                    num_known += 1;
+                },
+                None => num_unknown += 1
+            }
+        }
+        // Tracks of unknown duration are taken to be about as big as the rest
+        let unknown_estimate = known.checked_div(num_known).unwrap_or(0);
+
+        *self.started.lock().unwrap() = Instant::now();
+        self.downloaded.store(0, Ordering::SeqCst);
+        self.in_flight.store(0, Ordering::SeqCst);
+        self.unknown_estimate.store(unknown_estimate, Ordering::SeqCst);
+        self.remaining.store(known + unknown_estimate * num_unknown, Ordering::SeqCst);
+    }
+
+    /// Wraps a track's download so that it's counted as it's read, showing
+    /// how far along it is on `pb`.
+    pub fn track<'a, R>(&'a self, track: &TrackInfo, pb: &'a Progress, inner: R) -> Counted<'a, R> {
+        Counted {
+            inner,
+            transfer: self,
+            pb,
+            title: track.title.clone().unwrap_or_else(|| "untitled".into()),
+            estimate: self.estimate(track),
+            read: 0
+        }
+    }
+
+    /// Takes a track that won't be downloaded after all out of the estimate.
+    pub fn skip(&self, track: &TrackInfo) {
+        self.finish(self.estimate(track), 0);
+    }
+
+    fn estimate(&self, track: &TrackInfo) -> u64 {
+        plan::estimated_bytes(track).unwrap_or_else(|| self.unknown_estimate.load(Ordering::SeqCst))
+    }
+
+    fn finish(&self, estimate: u64, read: u64) {
+        let less = |n: u64| move |left: u64| Some(left.saturating_sub(n));
+        let _ = self.remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, less(estimate));
+        let _ = self.in_flight.fetch_update(Ordering::SeqCst, Ordering::SeqCst, less(read));
+    }
+
+    // Whether it's time to show progress again
+    fn due(&self) -> bool {
+        let mut last_shown = self.last_shown.lock().unwrap();
+        if last_shown.elapsed() < TRANSFER_INTERVAL {
+            return false;
+        }
+        *last_shown = Instant::now();
+        true
+    }
+
+    /// Like "2.4 MiB/s, about 12 minutes left".
+    pub fn status(&self) -> String {
+        let elapsed = self.started.lock().unwrap().elapsed().as_secs_f64();
+        let downloaded = self.downloaded.load(Ordering::SeqCst);
+        if elapsed < 1.0 || downloaded == 0 {
+            return "starting".into();
+        }
+
+        let speed = downloaded as f64 / elapsed;
+        let left = self.remaining.load(Ordering::SeqCst).saturating_sub(self.in_flight.load(Ordering::SeqCst));
+        format!(
+            "{}/s, about {} left",
+            locale::bytes(speed as u64),
+            locale::duration(Duration::from_secs_f64(left as f64 / speed))
+        )
+    }
+}
+
+/// A track's download, counted by a [`Transfer`] as it's read.
+pub struct Counted<'a, R> {
+    inner: R,
+    transfer: &'a Transfer,
+    pb: &'a Progress,
+    title: String,
+    estimate: u64,
+    read: u64,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        self.transfer.downloaded.fetch_add(read as u64, Ordering::SeqCst);
+        self.transfer.in_flight.fetch_add(read as u64, Ordering::SeqCst);
+
+        if self.transfer.due() {
+            // The estimate can be off, so only say it's done once it is
+            let done = match (self.read * 100).checked_div(self.estimate) {
+                Some(percent) => format!("{}%, {}", percent.min(99), locale::bytes(self.read)),
+                None => locale::bytes(self.read)
+            };
+            self.pb.set_message(&format!("{} ({}) - {}", self.title, done, self.transfer.status()));
+        }
+        Ok(read)
+    }
+}
+
+impl<R> Drop for Counted<'_, R> {
+    fn drop(&mut self) {
+        self.transfer.finish(self.estimate, self.read);
+    }
+}
+
+impl PlainState {
+    fn summarize(&mut self) {
+        if self.verbose {
+            log(&self.sentence());
+            self.last_summary = Instant::now();
+            return;
+        }
+
+        let of = match self.length {
+            Some(length) if length > 0 => format!(
+                "{}/{} ({}%)",
+                self.position,
+                length,
+                self.position * 100 / length
+            ),
+            _ => self.position.to_string()
+        };
+        let heading = if self.prefix.is_empty() { "Progress" } else { &self.prefix };
+
+        if self.message.is_empty() {
+            log(&format!("{}: {}", heading, of));
+        } else {
+            log(&format!("{}: {}, at {}", heading, of, self.message));
+        }
+        self.last_summary = Instant::now();
+    }
+
+    // Like "Zesting likes audio: done 240 of 1,900, 3 failures, about 2 hours
+    // remaining."
+    fn sentence(&self) -> String {
+        let heading = if self.prefix.is_empty() { "Progress" } else { &self.prefix };
+        let failures = match self.failures {
+            1 => "1 failure".to_string(),
+            n => format!("{} failures", locale::number(n))
+        };
+
+        match self.length {
+            Some(length) if length > 0 => {
+                let remaining = length.saturating_sub(self.position);
+                let estimate = if remaining == 0 {
+                    "finished".to_string()
+                } else if self.position == 0 {
+                    "time remaining not known yet".to_string()
+                } else {
+                    let left = self.started.elapsed().mul_f64(remaining as f64 / self.position as f64);
+                    format!("about {} remaining", locale::duration(left))
+                };
+                format!(
+                    "{}: done {} of {}, {}, {}.",
+                    heading,
+                    locale::number(self.position),
+                    locale::number(length),
+                    failures,
+                    estimate
+                )
+            },
+            _ => format!("{}: done {} so far, {}.", heading, locale::number(self.position), failures)
+        }
+    }
+}
+
+fn log(line: &str) {
+    println!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), line.trim_start());
+}
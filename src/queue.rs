@@ -0,0 +1,226 @@
+//! A persistent list of SoundCloud links to download later, so deciding what to
+//! save can happen separately from (and long before) downloading it.
+
+use crate::api_usage::ApiBudget;
+use crate::clipboard::soundcloud_urls;
+use crate::concurrency::Credentials;
+use crate::download::{download_loose_tracks, TrackSaver};
+use crate::events::EventFeed;
+use crate::interrupt;
+use crate::keychain::{KeyringOpts, ProfileOpts};
+use crate::logging::LogOpts;
+use crate::naming::{FolderLayout, Namer};
+use crate::net::{ApiOpts, TimeoutOpts};
+use crate::notify::NotifyOpts;
+use crate::progress::Progress;
+use crate::soundcloud::ApiClient;
+use crate::state::{load_state, save_state};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::Zester;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+const QUEUE_FILE: &str = "queue.json";
+
+// Only ever one of these around, parsed once at startup
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+pub enum QueueCommand {
+    /// Add track or playlist links to the queue
+    Add {
+        /// soundcloud.com track or playlist URLs
+        #[structopt(required = true, min_values = 1)]
+        urls: Vec<String>,
+    },
+    /// Take links back out of the queue
+    Remove {
+        #[structopt(required = true, min_values = 1)]
+        urls: Vec<String>,
+    },
+    /// Show what's waiting in the queue
+    List,
+    /// Download everything in the queue, taking each link out once it's saved
+    Run {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Folder to download queued tracks into
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+        /// Make at most n API calls during this run, leaving the rest queued
+        #[structopt(long, value_name = "n")]
+        max_api_calls: Option<u64>,
+    },
+}
+
+impl QueueCommand {
+    /// Whether this command only touches the queue file.
+    pub fn is_local(&self) -> bool {
+        !matches!(self, QueueCommand::Run { .. })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct Queue {
+    pub items: Vec<QueueItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct QueueItem {
+    pub url: String,
+    pub added_at: DateTime<Utc>,
+    /// What went wrong the last time this was run, if anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl Queue {
+    /// Loads the queue from the state directory, or an empty one if there
+    /// isn't one yet.
+    pub fn load() -> Result<Self, Error> {
+        load_state(QUEUE_FILE)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        save_state(QUEUE_FILE, self)
+    }
+}
+
+/// Runs the queue commands that don't need the API.
+pub fn edit(command: QueueCommand) -> Result<(), Error> {
+    let mut queue = Queue::load()?;
+
+    match command {
+        QueueCommand::Add { urls } => {
+            for arg in urls {
+                let found = soundcloud_urls(&arg);
+                if found.is_empty() {
+                    return Err(Error::InvalidUrl(format!("{} isn't a soundcloud.com link", arg)));
+                }
+
+                for url in found {
+                    if queue.items.iter().any(|item| item.url == url) {
+                        println!("Already queued: {}", url);
+                        continue;
+                    }
+
+                    println!("Queued {}", url);
+                    queue.items.push(QueueItem { url, added_at: Utc::now(), last_error: None });
+                }
+            }
+            queue.save()?;
+        },
+        QueueCommand::Remove { urls } => {
+            for arg in urls {
+                // Match however the link was normalized when it was added
+                let url = soundcloud_urls(&arg).pop().unwrap_or(arg);
+                let before = queue.items.len();
+                queue.items.retain(|item| item.url != url);
+
+                if queue.items.len() == before {
+                    println!("Not queued: {}", url);
+                } else {
+                    println!("Removed {}", url);
+                }
+            }
+            queue.save()?;
+        },
+        QueueCommand::List => {
+            if queue.items.is_empty() {
+                println!("The queue is empty");
+            }
+            for item in &queue.items {
+                print!("{}  {}", item.added_at.format("%Y-%m-%d %H:%M"), item.url);
+                match &item.last_error {
+                    Some(err) => println!("  (last attempt failed: {})", err),
+                    None => println!()
+                }
+            }
+        },
+        QueueCommand::Run { .. } => unreachable!("needs a zester")
+    }
+
+    Ok(())
+}
+
+/// Downloads everything in the queue into `output_folder`. Links whose tracks
+/// were all saved are taken out of the queue; the rest stay for next time.
+pub fn run(
+    output_folder: &Path,
+    max_api_calls: Option<u64>,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    let mut queue = Queue::load()?;
+    fs::create_dir_all(output_folder)?;
+
+    let budget = ApiBudget::new("queue", max_api_calls);
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::Flat, filename: None };
+    let saver = TrackSaver::new(output_folder, namer, api_client, &budget, &events, pb)?;
+
+    let total = queue.items.len();
+    let mut done = 0;
+    let mut index = 0;
+    while index < queue.items.len() {
+        if budget.exhausted() {
+            pb.println(format!("  [warning] API call budget used up, leaving {} links queued", queue.items.len()));
+            break;
+        }
+
+        let item = &mut queue.items[index];
+        pb.set_prefix(&format!("Zesting queue ({}/{}) - {}", done + 1, total, item.url));
+        let result = api_client.resolve_tracks(&item.url, || budget.record(1)).and_then(|tracks| {
+            pb.reset();
+            pb.set_length(tracks.len() as u64);
+            let result = download_loose_tracks(&saver, tracks.clone(), 1, zester, credentials);
+            saver.save_manifest()?;
+            result?;
+
+            match tracks.iter().filter(|t| !saver.was_saved(t)).count() {
+                0 => Ok(()),
+                failed => Err(Error::PartialFailure(format!("{} of {} tracks failed to download", failed, tracks.len())))
+            }
+        });
+
+        match result {
+            Ok(()) => {
+                queue.items.remove(index);
+            },
+            // Stays queued, with nothing to say about why
+            Err(Error::Interrupted) => break,
+            Err(e) => {
+                pb.println(format!("  [warning] {} stays queued: {:?}", item.url, e));
+                item.last_error = Some(format!("{:?}", e));
+                index += 1;
+            }
+        }
+        done += 1;
+        queue.save()?;
+    }
+
+    interrupt::check()?;
+    pb.println(format!("Zested {} queued links, {} left in the queue", total - queue.items.len(), queue.items.len()));
+    saver.finish()
+}
@@ -0,0 +1,150 @@
+//! Probing whether a track can be streamed from elsewhere in the world, by
+//! looking it up through a list of proxies.
+
+use crate::progress::Progress;
+use crate::restriction::Restriction;
+use crate::soundcloud::{api_base, ApiClient};
+use crate::Error;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct ResolvedTrack {
+    policy: Option<String>,
+    media: Option<Media>,
+}
+
+#[derive(Deserialize)]
+struct Media {
+    #[serde(default)]
+    transcodings: Vec<Transcoding>,
+}
+
+#[derive(Deserialize)]
+struct Transcoding {
+    url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamUrl {
+    url: Option<String>,
+}
+
+/// Somewhere to probe from: a proxy listed in the proxies file, or straight out.
+struct Region {
+    /// What the proxy was labelled with in the file, or its URL
+    name: String,
+    proxy: Option<String>,
+}
+
+enum Outcome {
+    Streamable,
+    PreviewOnly,
+    Blocked,
+    /// The track couldn't be found (the API returned this status)
+    NotFound(u16),
+    /// Listed, but asking for a stream returned this status (0 if the track
+    /// has no streams at all)
+    NoStream(u16),
+    /// The proxy itself didn't work
+    Unreachable(String),
+}
+
+impl Outcome {
+    fn describe(&self) -> String {
+        match self {
+            Outcome::Streamable => "streamable".into(),
+            Outcome::PreviewOnly => "only a preview can be streamed".into(),
+            Outcome::Blocked => "blocked".into(),
+            Outcome::NotFound(status) => format!("not found (returned {})", status),
+            Outcome::NoStream(0) => "listed, but has no streams".into(),
+            Outcome::NoStream(status) => format!("listed, but no stream (returned {})", status),
+            Outcome::Unreachable(e) => format!("proxy failed: {}", e)
+        }
+    }
+}
+
+/// Looks up the track at `track_url` directly and through every proxy listed
+/// in `proxies_file`, reporting where it can be streamed from.
+///
+/// Each line of the file holds a proxy URL (anything `curl --proxy` takes),
+/// optionally followed by a label such as the proxy's country. Blank lines and
+/// lines starting with `#` are ignored.
+pub fn check(track_url: &str, proxies_file: &Path, client: &ApiClient, pb: &Progress) -> Result<(), Error> {
+    let mut regions = vec![Region { name: "direct".into(), proxy: None }];
+    for line in fs::read_to_string(proxies_file)?.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (proxy, label) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        regions.push(Region {
+            name: if label.trim().is_empty() { proxy.to_string() } else { label.trim().to_string() },
+            proxy: Some(proxy.to_string())
+        });
+    }
+
+    pb.set_length(regions.len() as u64);
+    let mut results = Vec::new();
+    for region in &regions {
+        pb.set_message(&format!("Probing via {}", region.name));
+        let outcome = probe(track_url, region.proxy.as_deref(), client).unwrap_or_else(|e| match e {
+            Error::HttpError(e) => Outcome::Unreachable(e),
+            e => Outcome::Unreachable(format!("{:?}", e))
+        });
+        results.push((region, outcome));
+        pb.inc(1);
+    }
+
+    let width = regions.iter().map(|r| r.name.len()).max().unwrap_or(0);
+    for (region, outcome) in &results {
+        pb.println(format!("{:<width$}  {}", region.name, outcome.describe(), width = width));
+    }
+
+    let streamable: Vec<&str> = results
+        .iter()
+        .filter(|(_, outcome)| matches!(outcome, Outcome::Streamable))
+        .map(|(region, _)| region.name.as_str())
+        .collect();
+    if streamable.is_empty() {
+        pb.println(format!("{} can't be streamed in full from any of the {} places tried", track_url, regions.len()));
+    } else {
+        pb.println(format!(
+            "Streamable via {} of {}: {}",
+            streamable.len(),
+            regions.len(),
+            streamable.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+// Resolves the track through the given proxy, then asks for its first stream
+// to see whether it's actually handed out
+fn probe(track_url: &str, proxy: Option<&str>, client: &ApiClient) -> Result<Outcome, Error> {
+    let url = format!("{}/resolve?url={}", api_base(), track_url);
+    let track: ResolvedTrack = match client.try_get_via(&url, proxy)? {
+        Ok(track) => track,
+        Err(status) => return Ok(Outcome::NotFound(status))
+    };
+
+    match Restriction::from_policy(track.policy.as_deref()) {
+        Some(Restriction::Blocked) => return Ok(Outcome::Blocked),
+        Some(Restriction::PreviewOnly) => return Ok(Outcome::PreviewOnly),
+        None => {}
+    }
+
+    let transcoding = track.media.into_iter().flat_map(|m| m.transcodings).find_map(|t| t.url);
+    let transcoding = match transcoding {
+        Some(url) => url,
+        None => return Ok(Outcome::NoStream(0))
+    };
+
+    Ok(match client.try_get_via::<StreamUrl>(&transcoding, proxy)? {
+        Ok(StreamUrl { url: Some(_) }) => Outcome::Streamable,
+        Ok(StreamUrl { url: None }) => Outcome::NoStream(200),
+        Err(status) => Outcome::NoStream(status)
+    })
+}
@@ -0,0 +1,46 @@
+//! Recognizing tracks SoundCloud won't stream in full, so that they can be
+//! skipped with a reason instead of failing with a generic error.
+
+use orange_zest::api::TrackInfo;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Restriction {
+    /// Blocked where the request came from, by region or after a rights claim
+    /// (`policy` is `BLOCK`)
+    Blocked,
+    /// Only a 30 second preview can be streamed (`policy` is `SNIP`), as with
+    /// Go+ tracks
+    PreviewOnly,
+}
+
+impl Restriction {
+    pub fn of(track: &TrackInfo) -> Option<Self> {
+        Self::from_policy(track.policy.as_deref())
+    }
+
+    pub fn from_policy(policy: Option<&str>) -> Option<Self> {
+        match policy {
+            Some("BLOCK") => Some(Restriction::Blocked),
+            Some("SNIP") => Some(Restriction::PreviewOnly),
+            _ => None
+        }
+    }
+
+    /// The name used for the restriction in JSON and CSV output.
+    pub fn name(self) -> &'static str {
+        match self {
+            Restriction::Blocked => "blocked",
+            Restriction::PreviewOnly => "preview_only"
+        }
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Restriction::Blocked => "blocked in this region or by a rights holder",
+            Restriction::PreviewOnly => "only a preview can be streamed"
+        }
+    }
+}
@@ -0,0 +1,82 @@
+//! Downloading again just the tracks that failed during an earlier run.
+
+use crate::api_usage::ApiBudget;
+use crate::concurrency::Credentials;
+use crate::download::{download_loose_tracks, TrackSaver};
+use crate::events::EventFeed;
+use crate::naming::{FolderLayout, Namer};
+use crate::progress::Progress;
+use crate::soundcloud::ApiClient;
+use crate::summary::{Failure, FailureReport, FAILURES_FILE};
+use crate::user;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use orange_zest::Zester;
+use std::path::Path;
+
+/// How many tracks to look up per request
+const BATCH_SIZE: usize = 50;
+
+/// Retries the tracks listed in `failures.json` in `output_folder`, rewriting
+/// it after each batch so that it only lists tracks that still haven't been
+/// saved.
+pub fn run(
+    output_folder: &Path,
+    max_api_calls: Option<u64>,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    let path = output_folder.join(FAILURES_FILE);
+    if !path.exists() {
+        return Err(Error::JsonFileNotFound(path.to_string_lossy().into()));
+    }
+    let report: FailureReport = orange_zest::load_json(&path)?;
+
+    // Private tracks in the account's own archive are only visible to it
+    let whose = user::whose_archive(output_folder);
+    let budget = ApiBudget::new("retry-failed", max_api_calls);
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::Flat, filename: None };
+    let saver = TrackSaver::new(output_folder, namer, api_client, &budget, &events, pb)?;
+
+    // Without an id there's nothing to look the track up by
+    let (mut pending, unknown): (Vec<Failure>, Vec<Failure>) = report.failures.into_iter().partition(|f| f.id.is_some());
+    unknown.into_iter().for_each(|f| saver.summary.carry_over(f));
+
+    pb.set_length(pending.len() as u64);
+    while !pending.is_empty() {
+        if budget.exhausted() {
+            pb.println(format!("  [warning] API call budget used up, leaving {} tracks to retry later", pending.len()));
+            break;
+        }
+
+        let batch: Vec<Failure> = pending.drain(..pending.len().min(BATCH_SIZE)).collect();
+        saver.summary.set_pending(batch.iter().chain(&pending).cloned().collect());
+        let ids: Vec<u64> = batch.iter().filter_map(|f| f.id).collect();
+        budget.record(1);
+        let found: Vec<TrackInfo> = api_client.tracks(&ids, whose)?;
+
+        for failure in &batch {
+            if !found.iter().any(|t| t.id == failure.id) {
+                pb.println(format!(
+                    "  [warning] {} is no longer available",
+                    failure.title.as_deref().unwrap_or("untitled")
+                ));
+                saver.summary.carry_over(Failure { error: "no longer available".into(), ..failure.clone() });
+                pb.inc(1);
+            }
+        }
+
+        let result = download_loose_tracks(&saver, found, 1, zester, credentials);
+        saver.save_manifest()?;
+        saver.summary.set_pending(pending.clone());
+        saver.summary.write_report(output_folder)?;
+        result?;
+    }
+
+    saver.summary.set_pending(Vec::new());
+    pending.into_iter().for_each(|f| saver.summary.carry_over(f));
+    saver.finish()
+}
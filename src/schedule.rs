@@ -0,0 +1,322 @@
+//! What `daemon` and `watch` share: when the next cycle is due, and running
+//! zester commands as child processes of this executable.
+//!
+//! Schedules are either a time between cycles (`6h`) or a standard five-field
+//! cron expression (`0 */6 * * *`, in local time).
+
+use crate::filter::parse_duration;
+use crate::locale;
+use crate::state::STATE_DIR_VAR;
+use chrono::{DateTime, Datelike, Local, Timelike};
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+/// When cycles happen.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Every(Duration),
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// When the next cycle after `now` is due.
+    pub fn next_after(&self, now: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Schedule::Every(every) => now + chrono::Duration::from_std(*every).unwrap_or_else(|_| chrono::Duration::days(365)),
+            // Only impossible dates (like 31 2 *) match nothing within a few years
+            Schedule::Cron(cron) => cron.next_after(now).unwrap_or(now + chrono::Duration::days(365))
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(every) => write!(f, "every {}", locale::duration(*every)),
+            Schedule::Cron(cron) => write!(f, "on \"{}\"", cron.expression)
+        }
+    }
+}
+
+pub fn parse_schedule(arg: &str) -> Result<Schedule, String> {
+    if arg.trim().contains(char::is_whitespace) {
+        Cron::parse(arg).map(Schedule::Cron)
+    } else {
+        parse_duration(arg).map(|ms| Schedule::Every(Duration::from_millis(ms)))
+    }
+}
+
+/// A standard five-field cron expression: minute, hour, day of month, month
+/// and day of week.
+#[derive(Debug, Clone)]
+pub struct Cron {
+    expression: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// Whether the day of month and day of week fields were restricted; if
+    /// both were, a day matching either is enough, as in cron
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "\"{}\" isn't a cron expression (minute hour day-of-month month day-of-week)",
+                expression
+            ));
+        }
+        let field = |n: usize, min: u32, max: u32| {
+            parse_field(fields[n], min, max).map_err(|e| format!("\"{}\" in \"{}\": {}", fields[n], expression, e))
+        };
+
+        let mut weekdays = field(4, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes: field(0, 0, 59)?,
+            hours: field(1, 0, 23)?,
+            days: field(2, 1, 31)?,
+            months: field(3, 1, 12)?,
+            weekdays,
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*')
+        })
+    }
+
+    // The first minute after `now` that the expression matches, looking up to
+    // four years ahead
+    fn next_after(&self, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut time = now.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        for _ in 0..4 * 366 * 24 * 60 {
+            if self.matches(&time) {
+                return Some(time);
+            }
+            time += chrono::Duration::minutes(1);
+        }
+        None
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        let day_matches = match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday
+        };
+
+        self.minutes[time.minute() as usize]
+            && self.hours[time.hour() as usize]
+            && self.months[time.month() as usize]
+            && day_matches
+    }
+}
+
+// Parses a field like `*`, `*/15`, `1-5`, `0,30` or `10-40/10` into which of
+// the values up to `max` it includes (indexed by value)
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<bool>, String> {
+    let mut included = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|s| *s > 0).ok_or("bad step")?),
+            None => (part, 1)
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| "not a number")?,
+                    end.parse().map_err(|_| "not a number")?
+                ),
+                None => {
+                    let value = range.parse().map_err(|_| "not a number")?;
+                    // `5/15` means from 5 onwards
+                    (value, if step > 1 { max } else { value })
+                }
+            }
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("out of range ({}-{})", min, max));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            included[value as usize] = true;
+        }
+    }
+    Ok(included)
+}
+
+/// Runs zester command lines as child processes of this executable, with the
+/// credentials they'd otherwise each ask for on the terminal.
+pub struct Runner {
+    exe: PathBuf,
+    oauth_token: String,
+    client_id: String,
+    state_dir: Option<PathBuf>,
+    detached: bool,
+}
+
+impl Runner {
+    pub fn new(exe: PathBuf, oauth_token: String, client_id: String) -> Self {
+        Self {
+            exe,
+            oauth_token,
+            client_id,
+            state_dir: None,
+            detached: false
+        }
+    }
+
+    /// Where the children keep their state, instead of the default state
+    /// folder.
+    pub fn state_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.state_dir = dir;
+        self
+    }
+
+    /// Keeps the children out of the terminal's process group, so that a
+    /// Ctrl-C only reaches them by way of this process.
+    pub fn detached(mut self) -> Self {
+        self.detached = true;
+        self
+    }
+
+    /// Runs `zester <args>` to completion, telling `running` the child's pid
+    /// once it has started and `None` once it has exited.
+    pub fn run<S: AsRef<str>>(&self, args: &[S], running: impl Fn(Option<u32>)) -> Result<(), String> {
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        let mut command = Command::new(&self.exe);
+        command
+            .args(&args)
+            .env("OAUTH_TOKEN", &self.oauth_token)
+            .env("CLIENT_ID", &self.client_id);
+        if let Some(dir) = &self.state_dir {
+            command.env(STATE_DIR_VAR, dir);
+        }
+        #[cfg(unix)]
+        {
+            if self.detached {
+                use std::os::unix::process::CommandExt;
+                command.process_group(0);
+            }
+        }
+
+        let line = args.join(" ");
+        let mut child = command.spawn().map_err(|e| format!("failed to start `zester {}`: {}", line, e))?;
+        running(Some(child.id()));
+        let exit = child.wait();
+        running(None);
+
+        match exit {
+            Ok(exit) if exit.success() => Ok(()),
+            Ok(exit) => Err(format!("`zester {}` failed ({})", line, exit)),
+            Err(e) => Err(format!("`zester {}` failed: {}", line, e))
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn signal(pid: u32, signal: &str) {
+    let _ = Command::new("kill").arg(format!("-{}", signal)).arg(pid.to_string()).status();
+}
+
+#[cfg(not(unix))]
+pub(crate) fn signal(_: u32, _: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn values(field: &str, min: u32, max: u32) -> Vec<u32> {
+        let included = parse_field(field, min, max).unwrap();
+        (0..=max).filter(|v| included[*v as usize]).collect()
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn next(expression: &str, now: DateTime<Local>) -> DateTime<Local> {
+        Cron::parse(expression).unwrap().next_after(now).unwrap()
+    }
+
+    #[test]
+    fn parses_fields() {
+        let cases: &[(&str, u32, u32, &[u32])] = &[
+            ("*/15", 0, 59, &[0, 15, 30, 45]),
+            ("1-5", 0, 59, &[1, 2, 3, 4, 5]),
+            ("10-40/10", 0, 59, &[10, 20, 30, 40]),
+            ("0,30", 0, 59, &[0, 30]),
+            ("5/20", 0, 59, &[5, 25, 45]),
+            ("*/5", 1, 12, &[1, 6, 11]),
+            ("7", 0, 23, &[7])
+        ];
+        for (field, min, max, expected) in cases {
+            assert_eq!(values(field, *min, *max), *expected, "{}", field);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_fields() {
+        for expression in &[
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 0 *",
+            "* * * 13 *",
+            "* * * * 8",
+            "40-10 * * * *",
+            "*/0 * * * *",
+            "a * * * *",
+            "* * * *",
+            "* * * * * *"
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        // 2024-01-01 was a Monday
+        let monday = at(2024, 1, 1, 12, 0);
+        assert_eq!(next("0 0 * * 0", monday), at(2024, 1, 7, 0, 0));
+        assert_eq!(next("0 0 * * 7", monday), at(2024, 1, 7, 0, 0));
+        assert_eq!(next("0 0 * * 5-7", monday), at(2024, 1, 5, 0, 0));
+    }
+
+    #[test]
+    fn day_of_month_or_day_of_week() {
+        // Both restricted: the 1st of the month or any Monday
+        assert_eq!(next("0 9 1 * 1", at(2024, 1, 1, 9, 0)), at(2024, 1, 8, 9, 0));
+        assert_eq!(next("0 9 1 * 1", at(2024, 1, 29, 9, 0)), at(2024, 2, 1, 9, 0));
+        // Only one restricted: just that one
+        assert_eq!(next("0 9 * * 1", at(2024, 1, 29, 9, 0)), at(2024, 2, 5, 9, 0));
+        assert_eq!(next("0 9 1 * *", at(2024, 1, 1, 9, 0)), at(2024, 2, 1, 9, 0));
+    }
+
+    #[test]
+    fn next_is_strictly_after_now() {
+        assert_eq!(next("*/15 * * * *", at(2024, 1, 1, 12, 0)), at(2024, 1, 1, 12, 15));
+        assert_eq!(next("*/15 * * * *", at(2024, 1, 1, 12, 59)), at(2024, 1, 1, 13, 0));
+        assert_eq!(next("0 0 1 1 *", at(2024, 6, 1, 0, 0)), at(2025, 1, 1, 0, 0));
+    }
+
+    #[test]
+    fn impossible_dates_match_nothing() {
+        assert!(Cron::parse("0 0 31 2 *").unwrap().next_after(at(2024, 1, 1, 0, 0)).is_none());
+    }
+}
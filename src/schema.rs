@@ -0,0 +1,102 @@
+//! JSON Schema documents for the files zester writes, generated from the types
+//! that write them.
+
+use crate::api_usage::UsageLog;
+use crate::availability::RemovedTracksReport;
+use crate::events::Event;
+use crate::history::RunRecord;
+use crate::manifest::{Manifest, Replacement};
+use crate::queue::Queue;
+use crate::songlink::CrossPlatformLinks;
+use crate::subscribe::Subscriptions;
+use crate::summary::FailureReport;
+use crate::Error;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// The documented formats, by name
+const FORMATS: &[&str] = &[
+    "manifest",
+    "replacements",
+    "failures",
+    "removed-tracks",
+    "events",
+    "queue",
+    "subscriptions",
+    "api-usage",
+    "song-links",
+    "history",
+];
+
+#[derive(StructOpt, Debug)]
+pub struct SchemaOpts {
+    /// Only print the schema of this format
+    #[structopt(possible_values = FORMATS)]
+    format: Option<String>,
+    /// Write every schema into this folder as <format>.schema.json
+    #[structopt(short, long, parse(from_os_str), conflicts_with = "format", value_name = "path")]
+    output_folder: Option<PathBuf>,
+}
+
+fn schema(format: &str) -> RootSchema {
+    let mut schema = match format {
+        "manifest" => schema_for!(Manifest),
+        "replacements" => schema_for!(Vec<Replacement>),
+        "failures" => schema_for!(FailureReport),
+        "removed-tracks" => schema_for!(RemovedTracksReport),
+        // One of these per line
+        "events" => schema_for!(Event<'static>),
+        "queue" => schema_for!(Queue),
+        "subscriptions" => schema_for!(Subscriptions),
+        "api-usage" => schema_for!(UsageLog),
+        // Keyed by track id
+        "song-links" => schema_for!(BTreeMap<u64, CrossPlatformLinks>),
+        // One of these per line
+        "history" => schema_for!(RunRecord),
+        _ => unreachable!("formats are checked by clap")
+    };
+
+    schema.schema.metadata().title = Some(match format {
+        "manifest" => "manifest.json in audio output folders".into(),
+        "replacements" => "replacements.json in audio output folders".into(),
+        "failures" => "failures.json in audio output folders".into(),
+        "removed-tracks" => "removed-tracks.json written by check-availability".into(),
+        "events" => "NDJSON lines sent to --event-socket".into(),
+        "queue" => "the data in queue.json in the state folder".into(),
+        "subscriptions" => "the data in subscriptions.json in the state folder".into(),
+        "api-usage" => "the data in api-usage.json in the state folder".into(),
+        "song-links" => "the data in song-links.json in the state folder".into(),
+        "history" => "lines of history.jsonl in the state folder".into(),
+        _ => unreachable!("formats are checked by clap")
+    });
+    schema
+}
+
+pub fn run(opts: SchemaOpts) -> Result<(), Error> {
+    if let Some(folder) = opts.output_folder {
+        fs::create_dir_all(&folder)?;
+        for format in FORMATS {
+            let path = folder.join(format!("{}.schema.json", format));
+            fs::write(&path, serde_json::to_string_pretty(&schema(format)).unwrap())?;
+        }
+
+        println!("Wrote {} schemas to {}", FORMATS.len(), folder.display());
+        return Ok(());
+    }
+
+    match opts.format {
+        Some(format) => println!("{}", serde_json::to_string_pretty(&schema(&format)).unwrap()),
+        None => {
+            println!("Available schemas (`zester schema <format>` prints one):");
+            for format in FORMATS {
+                println!("  {}", format);
+            }
+        }
+    }
+
+    Ok(())
+}
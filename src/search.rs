@@ -0,0 +1,119 @@
+//! `search`: finding tracks in an archive by what they're called or tagged
+//! with, without going back to SoundCloud.
+
+use crate::archive::{self, artist};
+use crate::json_check::Strictness;
+use crate::manifest;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct SearchOpts {
+    /// Words to look for; a track matches if every one of them is somewhere in its title,
+    /// artist, genre, tags or description, regardless of case
+    #[structopt(value_name = "query")]
+    query: String,
+    /// Folder holding the JSON archive to search
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    input_folder: PathBuf,
+    /// Folder holding the audio archive (defaults to the input folder)
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    audio_folder: Option<PathBuf>,
+}
+
+/// A track found in the archive, and where it was found.
+struct Found<'a> {
+    track: &'a TrackInfo,
+    liked: bool,
+    playlists: Vec<&'a str>,
+}
+
+pub fn run(opts: SearchOpts) -> Result<(), Error> {
+    let words: Vec<String> = opts.query.split_whitespace().map(str::to_lowercase).collect();
+    let likes = archive::optional(archive::load_likes(&opts.input_folder, Strictness::Lenient))?;
+    let playlists = archive::optional(archive::load_playlists(&opts.input_folder, Strictness::Lenient))?;
+    if likes.is_none() && playlists.is_none() {
+        return Err(Error::JsonFileNotFound(opts.input_folder.join("likes.json").to_string_lossy().into()));
+    }
+
+    // Keyed by id so that a track liked and in playlists shows up once
+    let mut found: BTreeMap<u64, Found> = BTreeMap::new();
+    let hit = |track: &TrackInfo| track.id.is_some() && matches(track, &words);
+    for (_, track) in likes.iter().flat_map(archive::liked_tracks) {
+        if hit(track) {
+            found.entry(track.id.unwrap()).or_insert_with(|| Found::new(track)).liked = true;
+        }
+    }
+    for playlist in playlists.iter().flat_map(|p| &p.playlists) {
+        let title = playlist.title.as_deref().unwrap_or("untitled playlist");
+        for track in archive::playlist_tracks(playlist) {
+            if hit(track) {
+                let entry = found.entry(track.id.unwrap()).or_insert_with(|| Found::new(track));
+                if !entry.playlists.contains(&title) {
+                    entry.playlists.push(title);
+                }
+            }
+        }
+    }
+
+    if found.is_empty() {
+        println!("Nothing in {} matches \"{}\"", opts.input_folder.display(), opts.query);
+        return Ok(());
+    }
+
+    let audio_folder = opts.audio_folder.as_ref().unwrap_or(&opts.input_folder);
+    let audio = manifest::audio_locations(audio_folder)?;
+    for (id, result) in &found {
+        println!(
+            "{} - {} (id={})",
+            artist(result.track).unwrap_or("unknown artist"),
+            result.track.title.as_deref().unwrap_or("untitled"),
+            id
+        );
+
+        let mut sources = Vec::new();
+        if result.liked {
+            sources.push("liked".to_string());
+        }
+        sources.extend(result.playlists.iter().map(|title| format!("in \"{}\"", title)));
+        println!("  {}", sources.join(", "));
+
+        match audio.get(id) {
+            Some(paths) if !paths.is_empty() => {
+                for path in paths {
+                    println!("  {}", path.display());
+                }
+            },
+            _ => println!("  (no audio)")
+        }
+    }
+
+    println!("\n{} matching tracks", found.len());
+    Ok(())
+}
+
+impl<'a> Found<'a> {
+    fn new(track: &'a TrackInfo) -> Self {
+        Self { track, liked: false, playlists: Vec::new() }
+    }
+}
+
+fn matches(track: &TrackInfo, words: &[String]) -> bool {
+    let haystack = [
+        track.title.as_deref(),
+        artist(track),
+        track.genre.as_deref(),
+        track.tag_list.as_deref(),
+        track.description.as_deref(),
+    ]
+    .iter()
+    .flatten()
+    .map(|field| field.to_lowercase())
+    .collect::<Vec<_>>()
+    .join("\n");
+
+    words.iter().all(|word| haystack.contains(word.as_str()))
+}
@@ -0,0 +1,281 @@
+//! `serve`: a small web interface for browsing and listening to an archive,
+//! for anyone who'd rather not dig through folders of audio files.
+//!
+//! Everything is read once at startup; pages are rendered per request, and
+//! audio is streamed straight from disk with support for range requests so
+//! that browsers can seek.
+
+use crate::archive::{self, artist};
+use crate::html::{self, Collection, Row};
+use crate::json_check::Strictness;
+use crate::manifest;
+use crate::sidecar::artwork_paths;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct ServeOpts {
+    /// Folder holding the JSON archive to serve
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    input_folder: PathBuf,
+    /// Folder holding the audio archive (defaults to the input folder)
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    audio_folder: Option<PathBuf>,
+    /// Port to serve on
+    #[structopt(long, default_value = "8080", value_name = "port")]
+    port: u16,
+    /// Address to listen on; use 0.0.0.0 to let other devices on the network in
+    #[structopt(long, default_value = "127.0.0.1", value_name = "address")]
+    host: String,
+}
+
+/// Everything served, loaded once at startup.
+struct Library {
+    collections: Vec<Collection>,
+    audio: HashMap<u64, PathBuf>,
+    artwork: HashMap<u64, PathBuf>,
+}
+
+pub fn run(opts: ServeOpts) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(&opts.input_folder, Strictness::Lenient))?;
+    let playlists = archive::optional(archive::load_playlists(&opts.input_folder, Strictness::Lenient))?;
+    if likes.is_none() && playlists.is_none() {
+        return Err(Error::JsonFileNotFound(opts.input_folder.join("likes.json").to_string_lossy().into()));
+    }
+
+    let audio_folder = opts.audio_folder.as_ref().unwrap_or(&opts.input_folder);
+    let audio: HashMap<u64, PathBuf> = manifest::audio_locations(audio_folder)?
+        .into_iter()
+        .filter_map(|(id, paths)| paths.into_iter().find(|path| path.is_file()).map(|path| (id, path)))
+        .collect();
+    let artwork: HashMap<u64, PathBuf> = audio
+        .iter()
+        .filter_map(|(id, path)| artwork_paths(path).into_iter().next().map(|artwork| (*id, artwork)))
+        .collect();
+
+    let row = |track: &TrackInfo| Row {
+        title: track.title.clone().unwrap_or_else(|| "untitled".into()),
+        artist: artist(track).unwrap_or("unknown artist").into(),
+        artwork: match track.id {
+            Some(id) if artwork.contains_key(&id) => Some(format!("/artwork/{}", id)),
+            _ => track.artwork_url.clone()
+        },
+        duration: track.duration,
+        audio: track.id.filter(|id| audio.contains_key(id)).map(|id| format!("/audio/{}", id)),
+    };
+
+    let mut collections = Vec::new();
+    if let Some(likes) = &likes {
+        let tracks: Vec<Row> = archive::liked_tracks(likes).map(|(_, track)| row(track)).collect();
+        collections.push(Collection {
+            title: "Likes".into(),
+            href: "/likes".into(),
+            artwork: tracks.iter().find_map(|t| t.artwork.clone()),
+            tracks,
+        });
+    }
+    for (n, playlist) in playlists.iter().flat_map(|p| &p.playlists).enumerate() {
+        let tracks: Vec<Row> = archive::playlist_tracks(playlist).map(row).collect();
+        collections.push(Collection {
+            title: playlist.title.clone().unwrap_or_else(|| "untitled playlist".into()),
+            href: format!("/playlists/{}", n),
+            artwork: playlist.artwork_url.clone().or_else(|| tracks.iter().find_map(|t| t.artwork.clone())),
+            tracks,
+        });
+    }
+
+    let library = Arc::new(Library { collections, audio, artwork });
+    let listener = TcpListener::bind((opts.host.as_str(), opts.port))?;
+    println!(
+        "Serving {} collections ({} tracks with audio) at http://{}:{} (Ctrl-C to stop)",
+        library.collections.len(),
+        library.audio.len(),
+        opts.host,
+        opts.port
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue
+        };
+        let library = Arc::clone(&library);
+        thread::spawn(move || {
+            // Browsers drop connections all the time while seeking; nothing
+            // to do about it
+            let _ = handle(stream, &library);
+        });
+    }
+
+    unreachable!("incoming() never ends")
+}
+
+fn handle(mut stream: TcpStream, library: &Library) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut range = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("range") {
+                range = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    // GET /path?query HTTP/1.1
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let path = target.split('?').next().unwrap();
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain; charset=utf-8", b"Only GET is supported");
+    }
+
+    let id_after = |prefix: &str| path.strip_prefix(prefix).and_then(|id| id.parse::<u64>().ok());
+    if path == "/" {
+        respond(&mut stream, "200 OK", "text/html; charset=utf-8", html::index_page(&library.collections).as_bytes())
+    } else if let Some(collection) = library.collections.iter().find(|c| c.href == path) {
+        respond(&mut stream, "200 OK", "text/html; charset=utf-8", html::collection_page(collection, "/").as_bytes())
+    } else if let Some(file) = id_after("/audio/").and_then(|id| library.audio.get(&id)) {
+        send_file(&mut stream, file, range.as_deref(), method == "HEAD")
+    } else if let Some(file) = id_after("/artwork/").and_then(|id| library.artwork.get(&id)) {
+        send_file(&mut stream, file, None, method == "HEAD")
+    } else {
+        respond(&mut stream, "404 Not Found", "text/plain; charset=utf-8", b"Not found")
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+// Sends the whole file, or the part of it asked for with a `Range` header
+fn send_file(stream: &mut TcpStream, path: &Path, range: Option<&str>, head_only: bool) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    // Several ranges at once get the whole file, which servers are allowed to
+    // answer them with
+    let range = range.filter(|range| !range.contains(','));
+
+    let (status, start, end) = match range.and_then(|r| parse_range(r, size)) {
+        Some((start, end)) => ("206 Partial Content", start, end),
+        None if range.is_some() && size > 0 => {
+            write!(
+                stream,
+                "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                size
+            )?;
+            return Ok(());
+        },
+        None => ("200 OK", 0, size.saturating_sub(1))
+    };
+    let length = if size == 0 { 0 } else { end - start + 1 };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n",
+        status,
+        content_type(path),
+        length
+    )?;
+    if status.starts_with("206") {
+        write!(stream, "Content-Range: bytes {}-{}/{}\r\n", start, end, size)?;
+    }
+    write!(stream, "Connection: close\r\n\r\n")?;
+
+    if !head_only {
+        file.seek(SeekFrom::Start(start))?;
+        io::copy(&mut file.take(length), stream)?;
+    }
+    Ok(())
+}
+
+// Parses `bytes=<start>-<end>`, `bytes=<start>-` or `bytes=-<suffix length>`
+// into the inclusive range of bytes it asks for. Asking for several ranges at
+// once isn't supported
+fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = range.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.trim().split_once('-')?;
+    let last = size.checked_sub(1)?;
+
+    let (start, end) = match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        (Some(start), Some(end)) => (start, end.min(last)),
+        (Some(start), None) => (start, last),
+        (None, Some(suffix)) if suffix > 0 => (size.saturating_sub(suffix), last),
+        _ => return None
+    };
+    (start <= end).then_some((start, end))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+        Some("m4a") | Some("mp4") | Some("aac") => "audio/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") | Some("opus") => "audio/ogg",
+        Some("flac") => "audio/flac",
+        Some("wav") => "audio/wav",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=100-199", 1000), Some((100, 199)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Some((900, 999)));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-500", 1000), Some((500, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+        assert_eq!(parse_range("bytes=-0", 1000), None);
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), None);
+        assert_eq!(parse_range("bytes=2000-3000", 1000), None);
+        assert_eq!(parse_range("bytes=500-100", 1000), None);
+        assert_eq!(parse_range("bytes=0-", 0), None);
+    }
+
+    #[test]
+    fn rejects_malformed_and_multiple_ranges() {
+        assert_eq!(parse_range("bytes=0-99,200-299", 1000), None);
+        assert_eq!(parse_range("items=0-99", 1000), None);
+        assert_eq!(parse_range("bytes=abc", 1000), None);
+    }
+}
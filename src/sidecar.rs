@@ -0,0 +1,312 @@
+//! Per-track metadata files written next to downloaded audio.
+
+use crate::api_usage::ApiBudget;
+use crate::artwork::{self, ArtworkSource};
+use crate::atomic::write_json;
+use crate::logging;
+use crate::net;
+use crate::restriction::Restriction;
+use crate::songlink::{CrossPlatformLinks, SongLinks};
+use crate::soundcloud::{ApiClient, Comment, Visual};
+use crate::waveform::{self, WaveformFormat};
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Which extra information to gather for sidecars.
+#[derive(Debug, Default)]
+pub struct SidecarOptions {
+    /// Capture the comments a track's uploader left on it
+    pub uploader_comments: bool,
+    /// Look the track up on other platforms
+    pub song_links: Option<SongLinks>,
+    /// Download the visuals shown on the track's page
+    pub visuals: bool,
+    /// Download the track's artwork
+    pub artwork: bool,
+    /// Download the track's waveform, and / or draw it
+    pub waveform: Option<WaveformFormat>,
+    /// Write sidecars even with none of the above to put in them
+    pub always: bool,
+}
+
+impl SidecarOptions {
+    /// Whether sidecars should be written at all.
+    pub fn enabled(&self) -> bool {
+        self.always || self.uploader_comments || self.song_links.is_some() || self.visuals || self.artwork || self.waveform.is_some()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Sidecar<'a> {
+    track: &'a TrackInfo,
+    /// Why SoundCloud won't stream the track in full, if it won't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restriction: Option<Restriction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploader_comments: Option<Vec<UploaderComment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cross_platform: Option<CrossPlatformLinks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visuals: Option<Vec<SavedVisual>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    artwork: Option<SavedArtwork>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waveform: Option<SavedWaveform>,
+}
+
+/// A comment the uploader left on their own track; often where buy or free
+/// download links end up.
+#[derive(Serialize, Debug)]
+struct UploaderComment {
+    body: String,
+    created_at: Option<String>,
+    timestamp: Option<u64>,
+    links: Vec<String>,
+}
+
+/// A visual from the track's page, as downloaded into its visuals folder.
+#[derive(Serialize, Debug)]
+struct SavedVisual {
+    url: String,
+    /// Position in the track (in milliseconds) the visual is shown from
+    entry_time: Option<u64>,
+    /// Relative to the sidecar
+    path: String,
+}
+
+/// The track's artwork, as downloaded next to its audio.
+#[derive(Serialize, Debug)]
+struct SavedArtwork {
+    url: String,
+    /// Which size of the artwork it is, or whether it's the uploader's avatar
+    source: ArtworkSource,
+    /// Relative to the sidecar
+    path: String,
+}
+
+/// The track's waveform, as saved next to its audio.
+#[derive(Serialize, Debug)]
+struct SavedWaveform {
+    url: String,
+    /// The samples as downloaded, relative to the sidecar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json: Option<String>,
+    /// The waveform drawn from them, relative to the sidecar
+    #[serde(skip_serializing_if = "Option::is_none")]
+    png: Option<String>,
+}
+
+/// The sidecar path for the given audio file.
+pub fn sidecar_path(audio_path: &Path) -> PathBuf {
+    audio_path.with_extension("json")
+}
+
+/// Artwork saved for the given audio file, if there is any.
+pub fn artwork_paths(audio_path: &Path) -> Vec<PathBuf> {
+    ["jpg", "png", "gif", "webp", "bin"]
+        .iter()
+        .map(|ext| audio_path.with_extension(format!("artwork.{}", ext)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// The waveform data and image saved for the given audio file, if there are
+/// any.
+pub fn waveform_paths(audio_path: &Path) -> Vec<PathBuf> {
+    ["json", "png"]
+        .iter()
+        .map(|ext| audio_path.with_extension(format!("waveform.{}", ext)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// The folder the visuals for the given audio file go in.
+pub fn visuals_folder(audio_path: &Path) -> PathBuf {
+    audio_path.with_extension("visuals")
+}
+
+/// Gathers the requested information about a track and writes it into a
+/// sidecar next to its audio.
+pub fn write_sidecar(
+    audio_path: &Path,
+    track: &TrackInfo,
+    opts: &SidecarOptions,
+    client: &ApiClient,
+    budget: &ApiBudget
+) -> Result<(), Error> {
+    let uploader_comments = if opts.uploader_comments {
+        Some(uploader_comments(track, client, budget)?)
+    } else {
+        None
+    };
+
+    let cross_platform = opts.song_links.as_ref().map(|links| links.lookup(track)).transpose()?;
+    let visuals = if opts.visuals {
+        Some(save_visuals(audio_path, track, client, budget)?)
+    } else {
+        None
+    };
+
+    let artwork = if opts.artwork { save_artwork(audio_path, track)? } else { None };
+    let waveform = opts.waveform.map(|format| save_waveform(audio_path, track, format)).transpose()?.flatten();
+
+    write_json(
+        &Sidecar { track, restriction: Restriction::of(track), uploader_comments, cross_platform, visuals, artwork, waveform },
+        sidecar_path(audio_path),
+        true
+    )?;
+    Ok(())
+}
+
+// Downloads whatever visuals the track has, skipping (but noting) any that
+// can't be fetched
+fn save_visuals(audio_path: &Path, track: &TrackInfo, client: &ApiClient, budget: &ApiBudget) -> Result<Vec<SavedVisual>, Error> {
+    let title = track.title.as_deref().unwrap_or("untitled");
+    let track_id = match track.id {
+        Some(id) => id,
+        None => return Ok(Vec::new())
+    };
+
+    budget.record(1);
+    let visuals: Vec<Visual> = client.track_visuals(track_id)?;
+    if visuals.is_empty() {
+        logging::info(&format!("No visuals for {}", title));
+        return Ok(Vec::new());
+    }
+
+    let folder = visuals_folder(audio_path);
+    fs::create_dir_all(&folder)?;
+    let folder_name = folder.file_name().unwrap().to_string_lossy().into_owned();
+
+    let mut saved = Vec::new();
+    for (i, visual) in visuals.into_iter().enumerate() {
+        let url = match visual.visual_url {
+            Some(url) => url,
+            None => continue
+        };
+
+        let resp = net::get(&url).call();
+        if !resp.ok() {
+            logging::info(&format!("Skipping visual {} for {}: {} returned {}", i + 1, title, url, resp.status()));
+            continue;
+        }
+
+        let filename = format!("{:02}.{}", i + 1, extension(resp.content_type(), &url));
+        io::copy(&mut resp.into_reader(), &mut File::create(folder.join(&filename))?)?;
+        saved.push(SavedVisual {
+            url,
+            entry_time: visual.entry_time,
+            path: format!("{}/{}", folder_name, filename)
+        });
+    }
+
+    Ok(saved)
+}
+
+// Downloads the track's artwork at the best size there is
+fn save_artwork(audio_path: &Path, track: &TrackInfo) -> Result<Option<SavedArtwork>, Error> {
+    let title = track.title.as_deref().unwrap_or("untitled");
+    let avatar_url = track.user.as_ref().and_then(|user| user.avatar_url.as_deref());
+    let found = match artwork::fetch(track.artwork_url.as_deref(), avatar_url) {
+        Some(found) => found,
+        None => {
+            logging::info(&format!("No artwork could be downloaded for {}", title));
+            return Ok(None);
+        }
+    };
+    if found.source != ArtworkSource::Original {
+        logging::info(&format!("Using {:?} artwork for {}, as nothing better is available", found.source, title));
+    }
+
+    let path = audio_path.with_extension(format!("artwork.{}", extension(found.response.content_type(), &found.url)));
+    io::copy(&mut found.response.into_reader(), &mut File::create(&path)?)?;
+    Ok(Some(SavedArtwork {
+        url: found.url,
+        source: found.source,
+        path: path.file_name().unwrap().to_string_lossy().into_owned()
+    }))
+}
+
+// Downloads the track's waveform, drawing it as well if asked to
+fn save_waveform(audio_path: &Path, track: &TrackInfo, format: WaveformFormat) -> Result<Option<SavedWaveform>, Error> {
+    let title = track.title.as_deref().unwrap_or("untitled");
+    let url = match track.waveform_url.as_deref() {
+        Some(url) => url,
+        None => {
+            logging::info(&format!("No waveform for {}", title));
+            return Ok(None);
+        }
+    };
+
+    let fetched = waveform::fetch(url)?;
+    let file_name = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+    let mut saved = SavedWaveform { url: fetched.url, json: None, png: None };
+    if format.json() {
+        let path = audio_path.with_extension("waveform.json");
+        fs::write(&path, &fetched.raw)?;
+        saved.json = Some(file_name(&path));
+    }
+    if format.png() {
+        let path = audio_path.with_extension("waveform.png");
+        fs::write(&path, fetched.waveform.render_png())?;
+        saved.png = Some(file_name(&path));
+    }
+
+    Ok(Some(saved))
+}
+
+/// Picks a file extension for a downloaded image, going by what the server
+/// says it is and falling back on the URL.
+pub fn extension(content_type: &str, url: &str) -> String {
+    let known = match content_type {
+        "image/jpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "video/mp4" => Some("mp4"),
+        "video/webm" => Some("webm"),
+        _ => None
+    };
+
+    known.map(String::from).unwrap_or_else(|| {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        match path.rsplit('/').next().and_then(|name| name.rsplit_once('.')) {
+            Some((_, ext)) if !ext.is_empty() && ext.len() <= 4 => ext.to_lowercase(),
+            _ => "bin".into()
+        }
+    })
+}
+
+fn uploader_comments(track: &TrackInfo, client: &ApiClient, budget: &ApiBudget) -> Result<Vec<UploaderComment>, Error> {
+    let (track_id, uploader_id) = match (track.id, track.user.as_ref().and_then(|u| u.id)) {
+        (Some(track_id), Some(uploader_id)) => (track_id, uploader_id),
+        _ => return Ok(Vec::new())
+    };
+
+    let comments = client.track_comments(track_id, || budget.record(1))?;
+    Ok(comments
+        .into_iter()
+        .filter(|c| c.user_id == Some(uploader_id))
+        .filter_map(|Comment { body, created_at, timestamp, .. }| body.map(|body| UploaderComment {
+            links: extract_links(&body),
+            body,
+            created_at,
+            timestamp
+        }))
+        .collect())
+}
+
+/// Pulls anything that looks like a URL out of the given text.
+pub fn extract_links(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter_map(|word| {
+            let start = word.find("http://").or_else(|| word.find("https://"))?;
+            let link = word[start..].trim_end_matches(|c: char| ".,;:!?)]}\"'>".contains(c));
+            Some(link.to_string())
+        })
+        .collect()
+}
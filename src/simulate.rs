@@ -0,0 +1,298 @@
+//! `simulate`: a made-up archive to try things out on, without an account or
+//! network access.
+//!
+//! Writes the JSON `json` would (a profile, likes and playlists of invented
+//! tracks) and the audio `audio` would, named and recorded in the manifest the
+//! same way, so that layouts, templates, exports and whatever handles the
+//! archive afterwards can be tried before pointing zester at a real account.
+//! The audio is silence, made with `ffmpeg` if it's installed.
+
+use crate::archive;
+use crate::atomic::write_json;
+use crate::checksum::{sampled_sha256, sha256_file};
+use crate::filter;
+use crate::manifest::Manifest;
+use crate::naming::{FolderLayout, Namer, Template, TrackContext};
+use crate::Error;
+use chrono::{Duration, TimeZone, Utc};
+use orange_zest::api::{Likes, Me, Playlist, Playlists, TrackInfo};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use structopt::StructOpt;
+
+const ARTISTS: &[&str] = &[
+    "Ambient Fox", "Kilo Dreams", "Neon Harbor", "Sløwdive Club", "Tidal Echo", "Velvet Static",
+    "DJ Pastel", "Moss & Mirrors", "Nightbus", "Lumière", "Paper Comets", "サクラ Drive",
+];
+const WORDS: &[&str] = &[
+    "Midnight", "Glass", "Summer", "Echoes", "Drift", "Gold", "Signal", "Rain", "Neon", "Fading",
+    "Orbit", "Static", "Bloom", "Harbor", "Ghost", "Tide", "Velvet", "Afterglow", "Paper", "Satellite",
+];
+// Extras titles pick up now and then, so version grouping and encodings get
+// something to chew on
+const SUFFIXES: &[&str] = &[" (Remix)", " (VIP)", " (Extended Mix)", " [Live]", " (feat. Lumière)", " 🌙", " ✨🔥"];
+const GENRES: &[&str] = &["Electronic", "House", "Ambient", "Hip-hop & Rap", "Indie", "Drum & Bass", "Lo-fi"];
+
+/// Made-up tracks have ids from here up, well clear of where real ones are
+const FIRST_ID: u64 = 9_000_000_000;
+
+#[derive(StructOpt, Debug)]
+pub struct SimulateOpts {
+    /// Number of liked tracks to make up
+    #[structopt(long, default_value = "100", value_name = "n")]
+    likes: u64,
+    /// Number of playlists to make up
+    #[structopt(long, default_value = "5", value_name = "n")]
+    playlists: u64,
+    /// Number of tracks in each playlist; some are liked tracks as well
+    #[structopt(long, default_value = "20", value_name = "n")]
+    playlist_size: u64,
+    /// Length of the silence written for each track (e.g. 5s, the default)
+    #[structopt(long, parse(try_from_str = filter::parse_duration), default_value = "5s", value_name = "duration")]
+    audio_length: u64,
+    /// Only write the JSON, leaving out the audio
+    #[structopt(long)]
+    no_audio: bool,
+    /// Makes the same archive for the same seed
+    #[structopt(long, default_value = "1", value_name = "n")]
+    seed: u64,
+    /// Lua script whose `track_path(track)` function decides where each track is saved
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    naming_script: Option<PathBuf>,
+    /// Name files after this template, as for `audio`
+    #[structopt(long, conflicts_with = "naming_script", value_name = "template")]
+    filename_template: Option<String>,
+    /// Sort tracks into folders by source, artist, playlist or year, or not at all (flat),
+    /// as for `audio`
+    #[structopt(long, conflicts_with = "naming_script", value_name = "layout")]
+    organize_by: Option<String>,
+    /// Folder to write the JSON into
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    json_folder: PathBuf,
+    /// Folder to write the audio into
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    output_folder: PathBuf,
+}
+
+// A small xorshift generator; nothing here needs to be unpredictable, only
+// repeatable
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len() as u64) as usize]
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+}
+
+pub fn run(opts: SimulateOpts) -> Result<(), Error> {
+    let namer = match opts.naming_script {
+        Some(path) => Namer::from_script(path)?,
+        None => Namer::Standard {
+            folders: match opts.organize_by {
+                Some(layout) => FolderLayout::parse(&layout)?,
+                None => FolderLayout::BySource
+            },
+            filename: opts.filename_template.map(|t| Template::parse(&t, false)).transpose()?
+        }
+    };
+    let mut rng = Rng::new(opts.seed);
+    let mut next_id = FIRST_ID;
+    let mut track = |rng: &mut Rng| {
+        next_id += 1;
+        made_up_track(rng, next_id)
+    };
+
+    fs::create_dir_all(&opts.json_folder)?;
+    let me: Me = from_json(json!({
+        "id": FIRST_ID,
+        "username": "zester-simulation",
+        "permalink": "zester-simulation",
+        "permalink_url": "https://soundcloud.com/zester-simulation",
+        "likes_count": opts.likes,
+        "playlist_count": opts.playlists
+    }))?;
+    write_json(&me, opts.json_folder.join("me.json"), true)?;
+
+    let liked: Vec<(String, TrackInfo)> = (0..opts.likes)
+        .map(|n| Ok((timestamp(n * 3 + rng.below(3)), track(&mut rng)?)))
+        .collect::<Result<_, Error>>()?;
+    let likes: Likes = from_json(json!({
+        "collections": [{
+            "collection": liked.iter().map(|(liked_at, track)| json!({
+                "created_at": liked_at,
+                "kind": "like",
+                "track": track
+            })).collect::<Vec<_>>(),
+            "next_href": null
+        }]
+    }))?;
+    write_json(&likes, opts.json_folder.join("likes.json"), true)?;
+
+    let mut playlists = Vec::new();
+    for n in 0..opts.playlists {
+        let tracks = (0..opts.playlist_size)
+            .map(|_| {
+                if rng.chance(40) && !liked.is_empty() {
+                    Ok(liked[rng.below(liked.len() as u64) as usize].1.clone())
+                } else {
+                    track(&mut rng)
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let title = format!("{} {}", rng.pick(WORDS), rng.pick(WORDS));
+        let playlist: Playlist = from_json(json!({
+            "id": FIRST_ID / 2 + n,
+            "title": title,
+            "track_count": tracks.len(),
+            "tracks": tracks,
+            "created_at": timestamp(n * 40),
+            "permalink_url": format!("https://soundcloud.com/zester-simulation/sets/playlist-{}", n + 1)
+        }))?;
+        playlists.push(playlist);
+    }
+    let playlists = Playlists { playlists };
+    write_json(&playlists, opts.json_folder.join("playlists.json"), true)?;
+
+    println!(
+        "Made up {} likes and {} playlists in {}",
+        liked.len(),
+        playlists.playlists.len(),
+        opts.json_folder.display()
+    );
+    if opts.no_audio {
+        return Ok(());
+    }
+
+    fs::create_dir_all(&opts.output_folder)?;
+    let silence = Silence::make(&opts.output_folder, opts.audio_length)?;
+    let mut manifest = Manifest::open(&opts.output_folder)?;
+    let mut written = 0;
+
+    let tracks = archive::liked_tracks(&likes)
+        .map(|(_, track)| (track, None))
+        .chain(playlists.playlists.iter().flat_map(|p| archive::playlist_tracks(p).map(move |t| (t, Some(p)))));
+    for (track, playlist) in tracks {
+        let kind = if playlist.is_some() { "playlists" } else { "likes" };
+        let relative = namer.track_path(&TrackContext::new(kind, track, playlist))?;
+        let path = opts.output_folder.join(&relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        silence.write(&path)?;
+        manifest.record(track, &relative, fs::metadata(&path)?.len(), sha256_file(&path)?, Some(sampled_sha256(&path)?))?;
+        written += 1;
+    }
+    manifest.save(&opts.output_folder)?;
+    silence.clean_up();
+
+    println!("Wrote {} silent tracks into {}", written, opts.output_folder.display());
+    Ok(())
+}
+
+fn made_up_track(rng: &mut Rng, id: u64) -> Result<TrackInfo, Error> {
+    let artist = rng.pick(ARTISTS);
+    let mut title = format!("{} {}", rng.pick(WORDS), rng.pick(WORDS));
+    if rng.chance(25) {
+        title.push_str(rng.pick(SUFFIXES));
+    }
+    let slug = |text: &str| text.to_lowercase().replace(|c: char| !c.is_alphanumeric(), "-");
+
+    from_json(json!({
+        "id": id,
+        "title": title,
+        "user": {
+            "id": id % 1000,
+            "username": artist,
+            "permalink": slug(artist),
+            "permalink_url": format!("https://soundcloud.com/{}", slug(artist))
+        },
+        "user_id": id % 1000,
+        "genre": rng.pick(GENRES),
+        "tag_list": format!("{} \"{}\"", slug(rng.pick(WORDS)), rng.pick(WORDS)),
+        "duration": 90_000 + rng.below(330_000),
+        "permalink_url": format!("https://soundcloud.com/{}/{}-{}", slug(artist), slug(&title), id),
+        "created_at": timestamp(rng.below(3000) + 200),
+        "policy": "ALLOW",
+        "streamable": true,
+        "sharing": "public",
+        "playback_count": rng.below(1_000_000),
+        "likes_count": rng.below(50_000)
+    }))
+}
+
+// A timestamp `days_ago` days before the start of 2026, in the API's format
+fn timestamp(days_ago: u64) -> String {
+    let at = Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap() - Duration::days(days_ago as i64);
+    at.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn from_json<T: DeserializeOwned>(value: Value) -> Result<T, Error> {
+    serde_json::from_value(value).map_err(|e| Error::JsonFormatError(format!("made-up data didn't fit: {}", e)))
+}
+
+/// The audio written for every track.
+enum Silence {
+    /// Made once by `ffmpeg`, and copied for every track
+    Encoded(PathBuf),
+    /// Zeroes, the size the silence would be at 128 kbps
+    Placeholder(u64),
+}
+
+impl Silence {
+    fn make(folder: &Path, length_ms: u64) -> Result<Self, Error> {
+        let path = folder.join(".simulated-silence.m4a");
+        let encoded = Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-f", "lavfi", "-i", "anullsrc=r=44100:cl=stereo", "-t"])
+            .arg(format!("{:.3}", length_ms as f64 / 1000.0))
+            .args(["-c:a", "aac", "-b:a", "128k"])
+            .arg(&path)
+            .status()
+            .is_ok_and(|status| status.success());
+
+        if encoded {
+            Ok(Silence::Encoded(path))
+        } else {
+            println!("  [notice] couldn't make silence with ffmpeg (is it installed?); writing placeholder files instead");
+            Ok(Silence::Placeholder(length_ms * 16))
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<(), Error> {
+        match self {
+            Silence::Encoded(silence) => {
+                fs::copy(silence, path)?;
+            },
+            Silence::Placeholder(bytes) => fs::write(path, vec![0; *bytes as usize])?
+        }
+        Ok(())
+    }
+
+    fn clean_up(&self) {
+        if let Silence::Encoded(silence) = self {
+            let _ = fs::remove_file(silence);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Finding archived tracks on other platforms through Odesli (song.link), so
+//! they can be found again if their SoundCloud upload disappears.
+
+use crate::net;
+use crate::state::{load_state, save_state};
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const API_URL: &str = "https://api.song.link/v1-alpha.1/links";
+const CACHE_FILE: &str = "song-links.json";
+
+/// Odesli allows 10 requests a minute without an API key
+const MIN_INTERVAL: Duration = Duration::from_secs(6);
+
+/// Where a track can be found on one platform.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct PlatformLink {
+    pub url: String,
+    /// The platform's own id for the track
+    pub id: Option<String>,
+}
+
+/// Links to a track on other platforms, keyed by Odesli's platform names
+/// (`spotify`, `appleMusic`, `bandcamp`, ...).
+pub type CrossPlatformLinks = BTreeMap<String, PlatformLink>;
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LinksResponse {
+    links_by_platform: BTreeMap<String, ResponseLink>,
+    #[serde(default)]
+    entities_by_unique_id: BTreeMap<String, Entity>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResponseLink {
+    url: String,
+    entity_unique_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Entity {
+    id: Option<String>,
+}
+
+/// Cross-platform lookups, remembered in the state folder so tracks are only
+/// looked up once and exports can use what was found.
+#[derive(Debug)]
+pub struct SongLinks {
+    cache: Mutex<BTreeMap<u64, CrossPlatformLinks>>,
+    last_request: Mutex<Option<Instant>>,
+}
+
+impl SongLinks {
+    /// Loads the lookups made so far.
+    pub fn load() -> Result<Self, Error> {
+        let cache = load_state(CACHE_FILE)?;
+
+        Ok(Self { cache: Mutex::new(cache), last_request: Mutex::new(None) })
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        save_state(CACHE_FILE, &*self.cache.lock().unwrap())
+    }
+
+    /// What an earlier lookup found for the track with the given id.
+    pub fn cached(&self, track_id: u64) -> Option<CrossPlatformLinks> {
+        self.cache.lock().unwrap().get(&track_id).cloned()
+    }
+
+    /// Finds the given track on other platforms, asking Odesli unless it's been
+    /// looked up before. Waits as needed to stay within the rate limit.
+    pub fn lookup(&self, track: &TrackInfo) -> Result<CrossPlatformLinks, Error> {
+        let (id, url) = match (track.id, track.permalink_url.as_ref()) {
+            (Some(id), Some(url)) => (id, url),
+            _ => return Ok(CrossPlatformLinks::new())
+        };
+        if let Some(links) = self.cached(id) {
+            return Ok(links);
+        }
+
+        let links = self.request(url)?;
+        self.cache.lock().unwrap().insert(id, links.clone());
+        Ok(links)
+    }
+
+    fn request(&self, url: &str) -> Result<CrossPlatformLinks, Error> {
+        // Held for the whole request so concurrent downloads queue up here
+        let mut last_request = self.last_request.lock().unwrap();
+        for attempt in 0..2 {
+            if let Some(wait) = last_request.and_then(|last| MIN_INTERVAL.checked_sub(last.elapsed())) {
+                thread::sleep(wait);
+            }
+            *last_request = Some(Instant::now());
+
+            let resp = net::get(API_URL).query("url", url).call();
+            match resp.status() {
+                200 => {
+                    let body: LinksResponse = serde_json::from_value(resp.into_json()?)
+                        .map_err(|e| Error::HttpError(format!("unexpected response from song.link: {}", e)))?;
+                    return Ok(cross_platform_links(body));
+                },
+                // Odesli doesn't know the track anywhere else
+                400 | 404 => return Ok(CrossPlatformLinks::new()),
+                429 if attempt == 0 => thread::sleep(Duration::from_secs(60)),
+                status => return Err(Error::HttpError(format!("song.link lookup of {} returned {}", url, status)))
+            }
+        }
+
+        Err(Error::HttpError(format!("song.link lookup of {} was rate limited", url)))
+    }
+}
+
+fn cross_platform_links(resp: LinksResponse) -> CrossPlatformLinks {
+    let LinksResponse { links_by_platform, entities_by_unique_id } = resp;
+
+    links_by_platform
+        .into_iter()
+        .filter(|(platform, _)| platform != "soundcloud")
+        .map(|(platform, link)| {
+            let id = link.entity_unique_id
+                .and_then(|unique_id| entities_by_unique_id.get(&unique_id))
+                .and_then(|entity| entity.id.clone());
+
+            (platform, PlatformLink { url: link.url, id })
+        })
+        .collect()
+}
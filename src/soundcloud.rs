@@ -0,0 +1,591 @@
+//! A minimal client for the SoundCloud API endpoints that `orange-zest`
+//! doesn't cover.
+
+use crate::cache::{self, Cached};
+use crate::filter;
+use crate::logging;
+use crate::net;
+use crate::pace;
+use crate::pool::{Credential, CredentialPool, CredentialUsage, PoolEntry};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::api::{LikesCollection, Me, Playlist, TrackInfo, User};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Where API requests go unless `--api-base-url` says otherwise
+pub const API_BASE: &str = "https://api-v2.soundcloud.com";
+
+static API_BASE_OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Stop following `next_href` after this many pages, for collections (like
+/// comments and the home feed) that can go on far longer than is worth keeping
+const MAX_PAGES: usize = 50;
+
+pub struct ApiClient {
+    pool: CredentialPool,
+}
+
+/// Whose data a request is for, which decides the credentials it goes out
+/// with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Whose {
+    /// The account's own, private parts included, so always asked for with the
+    /// user's own credentials
+    Own,
+    /// Another user's, or anything public, so asked for with whichever
+    /// credential in the pool is current
+    Public
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    collection: Vec<T>,
+    next_href: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub id: Option<u64>,
+    pub body: Option<String>,
+    pub user_id: Option<u64>,
+    pub created_at: Option<String>,
+    /// Position in the track (in milliseconds) the comment was left at
+    pub timestamp: Option<u64>,
+}
+
+/// A play of a track, from the account's listening history.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayedTrack {
+    /// When the track was played, in milliseconds since the Unix epoch
+    pub played_at: Option<i64>,
+    pub track_id: Option<u64>,
+    /// Unset for tracks that have since gone away
+    pub track: Option<TrackInfo>,
+}
+
+impl PlayedTrack {
+    /// When the track was played, as an RFC 3339 timestamp.
+    pub fn played_at_rfc3339(&self) -> Option<String> {
+        self.played_at.and_then(DateTime::from_timestamp_millis).map(|at| at.to_rfc3339())
+    }
+}
+
+/// A playlist or album a user has liked.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PlaylistLike {
+    /// Unset for likes of system playlists (stations and the like)
+    pub playlist: Option<Playlist>,
+}
+
+/// Artwork shown on a track's page while it plays, on top of its cover.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Visual {
+    pub visual_url: Option<String>,
+    /// Position in the track (in milliseconds) the visual is shown from
+    pub entry_time: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct TrackVisuals {
+    visuals: Option<VisualsInfo>,
+}
+
+#[derive(Deserialize)]
+struct VisualsInfo {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default)]
+    visuals: Vec<Visual>,
+}
+
+/// The parts of a track's API representation that say whether it can be
+/// listened to.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TrackStatus {
+    pub id: u64,
+    /// `ALLOW`, `MONETIZE`, `SNIP` or `BLOCK`
+    pub policy: Option<String>,
+    /// `public` or `private`
+    pub sharing: Option<String>,
+    pub streamable: Option<bool>,
+}
+
+/// Sends API requests to the given base URL (like `https://api-v2.soundcloud.com`)
+/// for the rest of the run, rather than SoundCloud's.
+pub fn set_api_base(url: &str) {
+    let _ = API_BASE_OVERRIDE.set(url.trim_end_matches('/').to_string());
+}
+
+/// The base URL API requests go to.
+pub fn api_base() -> &'static str {
+    API_BASE_OVERRIDE.get().map_or(API_BASE, String::as_str)
+}
+
+// Points URLs the API hands back (like `next_href`) at `--api-base-url` too
+fn rebase(url: &str) -> Cow<'_, str> {
+    match (API_BASE_OVERRIDE.get(), url.strip_prefix(API_BASE)) {
+        (Some(base), Some(rest)) => Cow::Owned(format!("{}{}", base, rest)),
+        _ => Cow::Borrowed(url)
+    }
+}
+
+// A value for a curl config file, which takes backslash escapes inside quotes
+fn curl_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl ApiClient {
+    /// Makes a client using the user's credentials, and moving on to the
+    /// `extra` ones when rate limited.
+    pub fn new(oauth_token: String, client_id: String, extra: Vec<PoolEntry>) -> Self {
+        Self { pool: CredentialPool::new(oauth_token, client_id, extra) }
+    }
+
+    /// How many credentials requests are spread over.
+    pub fn pool_size(&self) -> usize {
+        self.pool.size()
+    }
+
+    /// How much each credential has been used so far.
+    pub fn pool_usage(&self) -> Vec<CredentialUsage> {
+        self.pool.usage()
+    }
+
+    /// Makes an authenticated GET request to the given API URL and deserializes
+    /// the response.
+    pub fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        self.try_get(url)?
+            .map_err(|status| Error::HttpError(format!("GET {} returned {}", url, status)))
+    }
+
+    /// Like `get`, but hands back the status code of unsuccessful responses
+    /// rather than failing.
+    pub fn try_get<T: DeserializeOwned>(&self, url: &str) -> Result<Result<T, u16>, Error> {
+        self.send(url, self.pool.current(), true, true)
+    }
+
+    // Makes the request with the given credential, moving on through the pool
+    // if it's rate limited and `rotate` is set. With `use_cache`, a response
+    // cached by an earlier run is used if it's still good
+    fn send<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        credential: Credential,
+        rotate: bool,
+        use_cache: bool
+    ) -> Result<Result<T, u16>, Error> {
+        let url = &*rebase(url);
+        let parse = |json: Value| {
+            serde_json::from_value(json)
+                .map(Ok)
+                .map_err(|e| Error::HttpError(format!("unexpected response from {}: {}", url, e)))
+        };
+        // Cached responses belong to the credential they were fetched with, as
+        // others might not see the same (private) things
+        let cached = if use_cache { cache::load(url, credential.oauth_token) } else { None };
+        let mut cached = match cached {
+            Some(cached) if cached.is_fresh() => {
+                logging::debug(&format!("GET {} (cached)", url));
+                return parse(cached.body);
+            },
+            cached => cached.filter(Cached::can_revalidate)
+        };
+
+        let mut credential = credential;
+        let mut attempts = 1;
+        let mut retries = 0;
+        let resp = loop {
+            pace::wait();
+            logging::debug(&format!("GET {}", url));
+            let mut request = net::get(url);
+            if let Some(token) = credential.oauth_token {
+                request.set("Authorization", &format!("OAuth {}", token));
+            }
+            if let Some(cached) = &cached {
+                cached.revalidate(&mut request);
+            }
+            let resp = request.query("client_id", credential.client_id).call();
+            self.pool.record(&credential, resp.status());
+
+            if resp.status() == 429 && rotate && attempts < self.pool.size() {
+                let next = self.pool.rotate(&credential);
+                logging::info(&format!(
+                    "GET {} was rate limited with client id {}, moving on to {}",
+                    url,
+                    credential.label(),
+                    next.label()
+                ));
+                credential = next;
+                attempts += 1;
+                // What was cached for the last credential doesn't go for this one
+                cached = None;
+                continue;
+            }
+            // Failed connections aren't the API asking for fewer requests
+            if resp.synthetic() {
+                break resp;
+            }
+            let retry_after = resp.header("Retry-After").and_then(|secs| secs.trim().parse().ok()).map(Duration::from_secs);
+            pace::record(resp.status(), retry_after);
+            if !pace::is_overloaded(resp.status()) || retries >= pace::MAX_RETRIES {
+                break resp;
+            }
+            retries += 1;
+            logging::info(&format!(
+                "GET {} returned {}, trying again ({}/{})",
+                url,
+                resp.status(),
+                retries,
+                pace::MAX_RETRIES
+            ));
+        };
+
+        if let (304, Some(mut cached)) = (resp.status(), cached) {
+            logging::debug(&format!("GET {} hasn't changed since it was cached", url));
+            cached.fetched_at = Utc::now();
+            cache::store(&cached, credential.oauth_token);
+            return parse(cached.body);
+        }
+        if !resp.ok() {
+            logging::info(&format!("GET {} returned {}", url, resp.status()));
+            return Ok(Err(resp.status()));
+        }
+
+        let (etag, last_modified) = (resp.header("ETag").map(String::from), resp.header("Last-Modified").map(String::from));
+        let json: Value = resp.into_json()?;
+        if use_cache {
+            let cached = Cached { url: url.to_string(), fetched_at: Utc::now(), etag, last_modified, body: json };
+            cache::store(&cached, credential.oauth_token);
+            return parse(cached.body);
+        }
+        parse(json)
+    }
+
+    /// Like `try_get`, but sends the request through the given proxy (or
+    /// straight out if there isn't one) with `curl`, as `ureq` can't use
+    /// proxies.
+    pub fn try_get_via<T: DeserializeOwned>(&self, url: &str, proxy: Option<&str>) -> Result<Result<T, u16>, Error> {
+        let url = &*rebase(url);
+        logging::debug(&format!("GET {} via {}", url, proxy.unwrap_or("no proxy")));
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let credential = self.pool.current();
+        let mut curl = Command::new("curl");
+        let timeouts = net::timeouts();
+        curl.args(["--silent", "--show-error", "--write-out", "\n%{http_code}", "--speed-limit", "1"])
+            .arg("--connect-timeout")
+            .arg(timeouts.connect.as_secs().max(1).to_string())
+            // Give up once nothing's come through for this long
+            .arg("--speed-time")
+            .arg(timeouts.read.as_secs().max(1).to_string())
+            // Anything secret goes in through stdin, where other users can't
+            // see it the way they can a process's arguments
+            .args(["--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let with_client_id = format!("{}{}client_id={}", url, separator, credential.client_id);
+        let mut config = format!("url = {}\n", curl_quote(&with_client_id));
+        if let Some(token) = &credential.oauth_token {
+            config += &format!("header = {}\n", curl_quote(&format!("Authorization: OAuth {}", token)));
+        }
+        if let Some(proxy) = proxy {
+            config += &format!("proxy = {}\n", curl_quote(proxy));
+        }
+        if let Some(user_agent) = net::user_agent() {
+            config += &format!("user-agent = {}\n", curl_quote(user_agent));
+        }
+
+        pace::wait();
+        let mut child = curl
+            .spawn()
+            .map_err(|e| Error::HttpError(format!("couldn't run curl (is it installed?): {}", e)))?;
+        // Taken so that curl sees the end of its config once it's written
+        child.stdin.take().unwrap().write_all(config.as_bytes())?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(Error::HttpError(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+        let status: u16 = status.trim().parse().unwrap_or(0);
+        self.pool.record(&credential, status);
+        pace::record(status, None);
+        if !(200..300).contains(&status) {
+            logging::info(&format!("GET {} via {} returned {}", url, proxy.unwrap_or("no proxy"), status));
+            return Ok(Err(status));
+        }
+
+        serde_json::from_str(body)
+            .map(Ok)
+            .map_err(|e| Error::HttpError(format!("unexpected response from {}: {}", url, e)))
+    }
+
+    // Follows a paginated collection to its end, or for at most `max_pages`
+    // pages, calling `on_page` after each request
+    fn get_all<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        whose: Whose,
+        max_pages: Option<usize>,
+        on_page: impl Fn()
+    ) -> Result<Vec<T>, Error> {
+        let mut items = Vec::new();
+        let mut next = Some(url.to_string());
+        let mut pages = 0;
+
+        while let Some(url) = next.take() {
+            if max_pages.is_some_and(|max| pages == max) {
+                break;
+            }
+
+            let page: Page<T> = self.get_for(&url, whose)?;
+            on_page();
+            pages += 1;
+            items.extend(page.collection);
+            next = page.next_href;
+        }
+
+        Ok(items)
+    }
+
+    /// Looks up the tracks with the given ids (at most 50 at a time), as
+    /// `TrackStatus` or full `TrackInfo`.
+    ///
+    /// Tracks that don't exist or aren't visible to whoever's asking are left
+    /// out.
+    pub fn tracks<T: DeserializeOwned>(&self, ids: &[u64], whose: Whose) -> Result<Vec<T>, Error> {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        self.get_for(&format!("{}/tracks?ids={}", api_base(), ids.join(",")), whose)
+    }
+
+    /// Looks up a single track, returning the status code if that fails.
+    pub fn track(&self, id: u64) -> Result<Result<TrackStatus, u16>, Error> {
+        self.try_get(&format!("{}/tracks/{}", api_base(), id))
+    }
+
+    // Like `get`, but always with the user's own credentials, for requests
+    // about the account itself
+    fn get_own<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        self.send(url, self.pool.primary(), false, true)?
+            .map_err(|status| Error::HttpError(format!("GET {} returned {}", url, status)))
+    }
+
+    // `get_own` for the account's own data, and `get` for anything else
+    fn get_for<T: DeserializeOwned>(&self, url: &str, whose: Whose) -> Result<T, Error> {
+        match whose {
+            Whose::Own => self.get_own(url),
+            Whose::Public => self.get(url)
+        }
+    }
+
+    /// Makes sure the credentials work, returning the user they belong to.
+    pub fn check_credentials(&self) -> Result<User, Error> {
+        // Always asked of the API, as the point is to find out if it still
+        // takes the credentials
+        match self.send(&format!("{}/me", api_base()), self.pool.primary(), false, false)? {
+            Ok(user) => Ok(user),
+            Err(401) | Err(403) => Err(Error::InvalidCredentials(
+                "SoundCloud rejected the OAuth token or client ID; the token has most likely expired. \
+                 Run `zester login` or pass a fresh --oauth-token".into()
+            )),
+            Err(status) => Err(Error::HttpError(format!("checking credentials returned {}", status)))
+        }
+    }
+
+    /// Gets the account's own profile, with how many likes, playlists and so on
+    /// it has.
+    pub fn me(&self) -> Result<Me, Error> {
+        self.get_own(&format!("{}/me", api_base()))
+    }
+
+    /// Looks up a user by their permalink (the `name` in
+    /// `soundcloud.com/name`) or profile URL.
+    pub fn resolve_user(&self, permalink_or_url: &str) -> Result<User, Error> {
+        if permalink_or_url.contains("://") {
+            self.resolve(permalink_or_url)
+        } else {
+            self.resolve(&format!("https://soundcloud.com/{}", permalink_or_url.trim_matches('/')))
+        }
+    }
+
+    /// Looks up a user by their id.
+    pub fn user<T: DeserializeOwned>(&self, user_id: u64) -> Result<T, Error> {
+        self.get(&format!("{}/users/{}", api_base(), user_id))
+    }
+
+    /// Looks up whatever a soundcloud.com URL points to.
+    pub fn resolve<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        self.get(&format!("{}/resolve?url={}", api_base(), url))
+    }
+
+    /// Gets the tracks a track or playlist URL points to, calling `on_request`
+    /// for each request made.
+    pub fn resolve_tracks(&self, url: &str, on_request: impl Fn()) -> Result<Vec<TrackInfo>, Error> {
+        let resolved: Value = self.resolve(url)?;
+        on_request();
+
+        let unexpected = |e: serde_json::Error| Error::HttpError(format!("unexpected response for {}: {}", url, e));
+        match resolved.get("kind").and_then(Value::as_str) {
+            Some("track") => Ok(vec![serde_json::from_value(resolved).map_err(unexpected)?]),
+            Some("playlist") => {
+                // Playlists only come with the first few tracks filled in
+                let playlist: Playlist = serde_json::from_value(resolved).map_err(unexpected)?;
+                let ids: Vec<u64> = playlist.tracks.iter().flatten().filter_map(|t| t.id).collect();
+
+                let mut tracks = Vec::new();
+                for batch in ids.chunks(50) {
+                    tracks.extend(self.tracks::<TrackInfo>(batch, Whose::Public)?);
+                    on_request();
+                }
+                Ok(tracks)
+            },
+            other => Err(Error::InvalidUrl(format!(
+                "{} is a {}, not a track or playlist",
+                url,
+                other.unwrap_or("something unknown")
+            )))
+        }
+    }
+
+    /// Gets every track the given user has uploaded.
+    pub fn user_tracks(&self, user_id: u64, whose: Whose, on_page: impl Fn()) -> Result<Vec<TrackInfo>, Error> {
+        self.get_all(&format!("{}/users/{}/tracks?limit=200", api_base(), user_id), whose, None, on_page)
+    }
+
+    /// Gets the most recent `recent` playlists the given user has made,
+    /// without asking for the pages after them.
+    pub fn user_playlists(&self, user_id: u64, whose: Whose, recent: u64, on_page: impl Fn()) -> Result<Vec<Playlist>, Error> {
+        let mut playlists = Vec::new();
+        let mut next = Some(format!("{}/users/{}/playlists?limit=200", api_base(), user_id));
+
+        while let Some(url) = next.take() {
+            if playlists.len() as u64 >= recent {
+                break;
+            }
+
+            let page: Page<Playlist> = self.get_for(&url, whose)?;
+            on_page();
+            playlists.extend(page.collection);
+            next = page.next_href;
+        }
+
+        playlists.truncate(recent as usize);
+        Ok(playlists)
+    }
+
+    /// Gets every user the given user follows.
+    pub fn user_followings(&self, user_id: u64, whose: Whose, on_page: impl Fn()) -> Result<Vec<User>, Error> {
+        self.get_all(&format!("{}/users/{}/followings?limit=200", api_base(), user_id), whose, None, on_page)
+    }
+
+    /// Gets every user following the given user.
+    pub fn user_followers(&self, user_id: u64, whose: Whose, on_page: impl Fn()) -> Result<Vec<User>, Error> {
+        self.get_all(&format!("{}/users/{}/followers?limit=200", api_base(), user_id), whose, None, on_page)
+    }
+
+    /// Gets every playlist and album the given user has liked.
+    pub fn user_playlist_likes(&self, user_id: u64, whose: Whose, on_page: impl Fn()) -> Result<Vec<PlaylistLike>, Error> {
+        self.get_all(&format!("{}/users/{}/playlist_likes?limit=200", api_base(), user_id), whose, None, on_page)
+    }
+
+    /// Gets every track and playlist the given user has reposted, as the API
+    /// returns them.
+    pub fn user_reposts(&self, user_id: u64, whose: Whose, on_page: impl Fn()) -> Result<Vec<Value>, Error> {
+        self.get_all(&format!("{}/stream/users/{}/reposts?limit=200", api_base(), user_id), whose, None, on_page)
+    }
+
+    /// Gets the account's home feed (uploads and reposts from the people it
+    /// follows), newest first, as the API returns it. Stops after `max_items`
+    /// or at the first item from before `since`, or after `MAX_PAGES` pages if
+    /// neither is given.
+    pub fn stream(&self, max_items: Option<u64>, since: Option<DateTime<Utc>>, on_page: impl Fn()) -> Result<Vec<Value>, Error> {
+        let unbounded = max_items.is_none() && since.is_none();
+        let mut items = Vec::new();
+        let mut next = Some(format!("{}/stream?limit=100&linked_partitioning=1", api_base()));
+        let mut pages = 0;
+
+        while let Some(url) = next.take() {
+            if unbounded && pages == MAX_PAGES {
+                break;
+            }
+
+            let page: Page<Value> = self.get_own(&url)?;
+            on_page();
+            pages += 1;
+            for item in page.collection {
+                let created_at = item.get("created_at").and_then(Value::as_str).and_then(filter::parse_timestamp);
+                if since.is_some_and(|since| created_at.is_some_and(|at| at < since)) {
+                    return Ok(items);
+                }
+                items.push(item);
+                if max_items.is_some_and(|max| items.len() as u64 >= max) {
+                    return Ok(items);
+                }
+            }
+            next = page.next_href;
+        }
+
+        Ok(items)
+    }
+
+    /// Gets the account's listening history, most recent plays first, stopping
+    /// after `max_items`. `on_page` is called with the number of plays on each
+    /// page.
+    pub fn play_history(&self, max_items: u64, on_page: impl Fn(usize)) -> Result<Vec<PlayedTrack>, Error> {
+        let mut plays = Vec::new();
+        let mut next = Some(format!("{}/me/play-history/tracks?limit=200&linked_partitioning=1", api_base()));
+
+        while let Some(url) = next.take() {
+            let page: Page<PlayedTrack> = self.get_own(&url)?;
+            on_page(page.collection.len());
+            plays.extend(page.collection);
+            if plays.len() as u64 >= max_items {
+                plays.truncate(max_items as usize);
+                break;
+            }
+            next = page.next_href;
+        }
+
+        Ok(plays)
+    }
+
+    /// The URL of the first page of the given user's liked tracks, for
+    /// `likes_page`.
+    pub fn likes_url(user_id: u64) -> String {
+        format!("{}/users/{}/track_likes?limit=200&linked_partitioning=1", api_base(), user_id)
+    }
+
+    /// Gets a single page of liked tracks; its `next_href` is the URL of the
+    /// page after it, if there is one.
+    pub fn likes_page(&self, url: &str, whose: Whose) -> Result<LikesCollection, Error> {
+        self.get_for(url, whose)
+    }
+
+    /// Gets the visuals shown on the given track's page, if it has any.
+    pub fn track_visuals(&self, track_id: u64) -> Result<Vec<Visual>, Error> {
+        let track: TrackVisuals = self.get(&format!("{}/tracks/{}", api_base(), track_id))?;
+        Ok(match track.visuals {
+            Some(info) if info.enabled => info.visuals,
+            _ => Vec::new()
+        })
+    }
+
+    /// Gets the comments left on the given track, up to `MAX_PAGES` pages of
+    /// them.
+    pub fn track_comments(&self, track_id: u64, on_page: impl Fn()) -> Result<Vec<Comment>, Error> {
+        self.get_all(
+            &format!("{}/tracks/{}/comments?threaded=0&filter_replies=1&limit=200", api_base(), track_id),
+            Whose::Public,
+            Some(MAX_PAGES),
+            on_page
+        )
+    }
+}
@@ -0,0 +1,137 @@
+//! Checking there's room on the disk before audio is downloaded, so that a run
+//! that won't fit stops up front instead of failing every track once the disk
+//! fills.
+//!
+//! How much room a run needs is estimated from track durations (see
+//! [`plan::estimated_bytes`]), and free space is asked of `df`, or PowerShell
+//! on Windows.
+
+use crate::locale;
+use crate::plan;
+use crate::progress::Progress;
+use crate::throttle;
+use crate::Error;
+use orange_zest::api::TrackInfo;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// How much room `--min-free-space` asks to be left by default
+pub const DEFAULT_MIN_FREE: u64 = 1024 * 1024 * 1024;
+
+/// Parses sizes like `2GiB`, `500MB` or `1.5G`. `K`, `M` and `G` on their own
+/// are binary units.
+pub fn parse_size(arg: &str) -> Result<u64, String> {
+    throttle::parse_bytes(arg).ok_or_else(|| format!("\"{}\" is not a size like 2GiB, 500MB or 1.5G", arg))
+}
+
+/// Checks the tracks about to be downloaded into a folder fit in the space
+/// left where it is.
+pub struct SpaceCheck<'a> {
+    folder: &'a Path,
+    min_free: u64,
+}
+
+impl<'a> SpaceCheck<'a> {
+    /// Checks against the free space where `folder` is, warning when less
+    /// than `min_free` would be left.
+    pub fn new(folder: &'a Path, min_free: u64) -> Self {
+        Self { folder, min_free }
+    }
+
+    /// Fails if the given tracks won't fit, and warns if they'd leave less
+    /// than the minimum free. If free space can't be found out, that's only
+    /// warned about.
+    pub fn ensure_room_for<'t>(&self, tracks: impl IntoIterator<Item = &'t TrackInfo>, pb: &Progress) -> Result<(), Error> {
+        let (mut needed, mut unknown) = (0, 0);
+        for track in tracks {
+            match plan::estimated_bytes(track) {
+                Some(bytes) => needed += bytes,
+                None => unknown += 1
+            }
+        }
+        if needed == 0 {
+            return Ok(());
+        }
+
+        let free = match free_space(self.folder) {
+            Ok(free) => free,
+            Err(e) => {
+                pb.println(format!("  [warning] couldn't check the free space in {}: {}", self.folder.display(), e));
+                return Ok(());
+            }
+        };
+        let unknown = match unknown {
+            0 => String::new(),
+            n => format!(", plus {} tracks of unknown size", locale::number(n))
+        };
+
+        if needed > free {
+            return Err(Error::NotEnoughSpace(format!(
+                "the audio to download needs roughly {}{}, but only {} is free in {}",
+                locale::bytes(needed),
+                unknown,
+                locale::bytes(free),
+                self.folder.display()
+            )));
+        }
+        if free - needed < self.min_free {
+            pb.println(format!(
+                "  [warning] the audio to download needs roughly {}{}, which would leave only {} free in {}",
+                locale::bytes(needed),
+                unknown,
+                locale::bytes(free - needed),
+                self.folder.display()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How many bytes are free for this user on the disk holding `path`, or the
+/// closest folder above it that exists.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let existing = path.ancestors().find(|p| p.exists()).unwrap_or(path);
+    let output = free_space_command(existing).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} ({})",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    parse_free_space(&stdout).ok_or_else(|| io::Error::other(format!("unexpected output: {}", stdout.trim())))
+}
+
+#[cfg(not(windows))]
+fn free_space_command(path: &Path) -> Command {
+    // POSIX output, in 1024-byte blocks
+    let mut df = Command::new("df");
+    df.arg("-Pk").arg(path);
+    df
+}
+
+#[cfg(not(windows))]
+fn parse_free_space(stdout: &str) -> Option<u64> {
+    // Filesystem, 1024-blocks, Used, Available, Capacity, Mounted on
+    let available = stdout.lines().nth(1)?.split_whitespace().nth(3)?;
+    available.parse::<u64>().ok().map(|blocks| blocks * 1024)
+}
+
+#[cfg(windows)]
+fn free_space_command(path: &Path) -> Command {
+    let script = format!(
+        "([System.IO.DriveInfo]::new((Resolve-Path -LiteralPath '{}').Path)).AvailableFreeSpace",
+        path.display().to_string().replace('\'', "''")
+    );
+    let mut powershell = Command::new("powershell");
+    powershell.args(["-NoProfile", "-Command", &script]);
+    powershell
+}
+
+#[cfg(windows)]
+fn parse_free_space(stdout: &str) -> Option<u64> {
+    stdout.trim().parse().ok()
+}
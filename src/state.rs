@@ -0,0 +1,126 @@
+//! Files orange-zester keeps around between runs that aren't part of any one
+//! archive: the saved login, the queue, subscriptions, API usage, song.link
+//! lookups, when each archive was last fully scrubbed, the response cache and
+//! the run history. Those read with `load_state` are versioned and checksummed,
+//! so that damaged ones are noticed rather than half-read.
+//!
+//! What a run keeps about the archive it works on stays in the output folder
+//! with the archive: the manifest and its journal, the checkpoint a run resumes
+//! from, and `failures.json` and `replacements.json`.
+
+use crate::atomic::{write_json, write_private_json};
+use crate::checksum::sha256_bytes;
+use crate::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Overrides where state is kept, so that several users' runs can be kept
+/// apart (the daemon sets this for each of its profiles)
+pub const STATE_DIR_VAR: &str = "ZESTER_STATE_DIR";
+
+/// The folder that state shared between runs (regardless of archive) lives in,
+/// created if necessary.
+pub fn state_dir() -> io::Result<PathBuf> {
+    let dir = match env::var_os(STATE_DIR_VAR) {
+        Some(dir) => PathBuf::from(dir),
+        None => dirs::data_local_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local data directory for this platform"))?
+            .join("orange-zester")
+    };
+    fs::create_dir_all(&dir)?;
+
+    Ok(dir)
+}
+
+/// Bumped whenever the format of files in the state folder changes
+pub const STATE_VERSION: u32 = 1;
+
+/// How files in the state folder are stored, so that ones written by a newer
+/// version or damaged since can be told apart from good ones.
+#[derive(Serialize, Deserialize)]
+struct Stored<T> {
+    version: u32,
+    /// Of `data`, as compact JSON
+    sha256: String,
+    data: T,
+}
+
+/// Where the state file with the given name (which may include subfolders)
+/// lives, creating its folder if necessary.
+pub fn state_path(name: &str) -> io::Result<PathBuf> {
+    let path = state_dir()?.join(name);
+    fs::create_dir_all(path.parent().unwrap())?;
+
+    Ok(path)
+}
+
+/// Loads the state file with the given name, or `T::default()` if there isn't
+/// one yet.
+///
+/// A file that's damaged is moved aside (to `<name>.corrupt`) and started over
+/// rather than failing the run. Only some of it comes back by itself (usage
+/// and lookups do, the queue and subscriptions don't), so the damaged file is
+/// kept for picking through by hand.
+pub fn load_state<T: DeserializeOwned + Default>(name: &str) -> Result<T, Error> {
+    let path = state_path(name)?;
+    let contents = match fs::read(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e.into())
+    };
+
+    let problem = match serde_json::from_slice::<Stored<Value>>(&contents) {
+        Ok(stored) if stored.version > STATE_VERSION => return Err(Error::ConfigError(format!(
+            "{} was written by a newer orange-zester (state version {}, this one understands {})",
+            path.display(),
+            stored.version,
+            STATE_VERSION
+        ))),
+        Ok(stored) if sha256_bytes(stored.data.to_string().as_bytes()) != stored.sha256 =>
+            "its checksum doesn't match".to_string(),
+        Ok(stored) => match serde_json::from_value(stored.data) {
+            Ok(data) => return Ok(data),
+            Err(e) => e.to_string()
+        },
+        // Written before state files were checksummed
+        Err(_) => match serde_json::from_slice(&contents) {
+            Ok(data) => return Ok(data),
+            Err(e) => e.to_string()
+        }
+    };
+
+    let aside = path.with_extension("json.corrupt");
+    fs::rename(&path, &aside)?;
+    eprintln!(
+        "[warning] {} is damaged ({}); moved it to {} and starting over",
+        path.display(),
+        problem,
+        aside.display()
+    );
+    Ok(T::default())
+}
+
+/// Saves the state file with the given name.
+pub fn save_state<T: Serialize>(name: &str, data: &T) -> Result<(), Error> {
+    write_json(&stored(data), state_path(name)?, false)
+}
+
+/// Saves the state file with the given name so that only the user can read
+/// it, for ones holding credentials.
+pub fn save_private_state<T: Serialize>(name: &str, data: &T) -> Result<(), Error> {
+    write_private_json(&stored(data), &state_path(name)?)
+}
+
+fn stored<T: Serialize>(data: &T) -> Stored<Value> {
+    let data = serde_json::to_value(data).unwrap();
+    Stored {
+        version: STATE_VERSION,
+        sha256: sha256_bytes(data.to_string().as_bytes()),
+        data
+    }
+}
@@ -0,0 +1,416 @@
+//! Information about past runs and existing archives.
+
+use crate::api_usage::UsageLog;
+use crate::archive::{self, artist};
+use crate::json_check::Strictness;
+use crate::locale::{self, ReportFormat};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::probe::{ffprobe_installed, probe};
+use crate::sidecar::sidecar_path;
+use crate::{Error, OutputFormat};
+use orange_zest::api::TrackInfo;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+pub struct StatsOpts {
+    /// Summarize the JSON archive in this folder: totals, top artists and genres, and likes
+    /// per month
+    #[structopt(parse(from_os_str), value_name = "archive folder")]
+    archive: Option<PathBuf>,
+    /// Show how many API calls recent runs have made
+    #[structopt(long)]
+    api_usage: bool,
+    /// Break down the disk space used by the audio archive in the given folder
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    disk: Option<PathBuf>,
+    /// Tabulate the codecs, bitrates and sample rates of the audio archive in the given
+    /// folder (with ffprobe), listing low-quality files
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    codecs: Option<PathBuf>,
+    /// Folder holding the JSON archive, used to attribute audio to playlists
+    /// and artists (defaults to the folder given to --disk)
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    input_folder: Option<PathBuf>,
+    /// How many playlists, artists, genres and low-quality files to list
+    #[structopt(long, default_value = "10", value_name = "n")]
+    top: usize,
+    /// How to print the archive summary
+    #[structopt(
+        long = "format",
+        possible_values = &OutputFormat::variants(),
+        case_insensitive = true,
+        default_value = "Text"
+    )]
+    output_format: OutputFormat,
+    #[structopt(flatten)]
+    format: ReportFormat,
+}
+
+/// What's in a JSON archive, for `stats <archive folder>`.
+#[derive(Serialize, Debug, Default)]
+struct ArchiveSummary {
+    likes: u64,
+    playlists: u64,
+    /// Tracks across all playlists, counting repeats
+    playlist_tracks: u64,
+    /// Different tracks among the likes and playlists
+    unique_tracks: u64,
+    /// Of the unique tracks
+    total_duration_ms: u64,
+    top_artists: Vec<Count>,
+    top_genres: Vec<Count>,
+    /// `YYYY-MM` to the number of likes made that month
+    likes_per_month: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Count {
+    name: String,
+    tracks: u64,
+}
+
+pub fn run(opts: StatsOpts) -> Result<(), Error> {
+    opts.format.apply();
+    if let Some(archive_folder) = &opts.archive {
+        print_summary(archive_folder, opts.top, opts.output_format)?;
+    }
+
+    if opts.api_usage {
+        print_api_usage()?;
+    }
+
+    if let Some(audio_folder) = &opts.disk {
+        let input_folder = opts.input_folder.as_deref().unwrap_or(audio_folder);
+        print_disk_usage(audio_folder, input_folder, opts.top)?;
+    }
+
+    if let Some(audio_folder) = &opts.codecs {
+        print_codecs(audio_folder, opts.top)?;
+    }
+
+    if opts.archive.is_none() && !opts.api_usage && opts.disk.is_none() && opts.codecs.is_none() {
+        println!("Nothing to show; try an archive folder, --api-usage, --disk <path> or --codecs <path>");
+    }
+
+    Ok(())
+}
+
+fn print_summary(folder: &Path, top: usize, format: OutputFormat) -> Result<(), Error> {
+    let likes = archive::optional(archive::load_likes(folder, Strictness::Lenient))?;
+    let playlists = archive::optional(archive::load_playlists(folder, Strictness::Lenient))?;
+    if likes.is_none() && playlists.is_none() {
+        return Err(Error::JsonFileNotFound(folder.join("likes.json").to_string_lossy().into()));
+    }
+
+    let mut summary = ArchiveSummary::default();
+    let mut tracks: HashMap<u64, &TrackInfo> = HashMap::new();
+    for (liked_at, track) in likes.iter().flat_map(archive::liked_tracks) {
+        summary.likes += 1;
+        if let Some(month) = liked_at.filter(|at| at.len() >= 7) {
+            *summary.likes_per_month.entry(month[..7].replace('/', "-")).or_default() += 1;
+        }
+        if let Some(id) = track.id {
+            tracks.insert(id, track);
+        }
+    }
+    for playlist in playlists.iter().flat_map(|p| p.playlists.iter()) {
+        summary.playlists += 1;
+        for track in archive::playlist_tracks(playlist) {
+            summary.playlist_tracks += 1;
+            if let Some(id) = track.id {
+                tracks.entry(id).or_insert(track);
+            }
+        }
+    }
+
+    let (mut artists, mut genres): (HashMap<String, u64>, HashMap<String, u64>) = Default::default();
+    for track in tracks.values() {
+        summary.total_duration_ms += track.duration.unwrap_or(0);
+        *artists.entry(artist(track).unwrap_or("unknown artist").to_string()).or_default() += 1;
+        if let Some(genre) = track.genre.as_deref().map(str::trim).filter(|g| !g.is_empty()) {
+            *genres.entry(genre.to_lowercase()).or_default() += 1;
+        }
+    }
+    summary.unique_tracks = tracks.len() as u64;
+    summary.top_artists = top_counts(artists, top);
+    summary.top_genres = top_counts(genres, top);
+
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+        return Ok(());
+    }
+
+    println!("Archive in {}:", folder.display());
+    println!("  {:>7} likes", locale::number(summary.likes));
+    println!("  {:>7} playlists ({} tracks)", locale::number(summary.playlists), locale::number(summary.playlist_tracks));
+    println!(
+        "  {:>7} different tracks, {} in all",
+        locale::number(summary.unique_tracks),
+        locale::duration(Duration::from_millis(summary.total_duration_ms))
+    );
+    for (heading, counts) in [("Top artists:", &summary.top_artists), ("Top genres:", &summary.top_genres)] {
+        if counts.is_empty() {
+            continue;
+        }
+        println!("{}", heading);
+        for count in counts {
+            println!("  {:>7}  {}", locale::number(count.tracks), count.name);
+        }
+    }
+
+    let most = summary.likes_per_month.values().copied().max().unwrap_or(0);
+    if most > 0 {
+        println!("Likes per month:");
+        for (month, likes) in &summary.likes_per_month {
+            // Scaled so the busiest month fills the width
+            let bar = "#".repeat(((likes * HISTOGRAM_WIDTH).div_ceil(most)) as usize);
+            println!("  {}  {:>6}  {}", month, locale::number(*likes), bar);
+        }
+    }
+
+    Ok(())
+}
+
+/// How many characters the busiest month's bar takes up
+const HISTOGRAM_WIDTH: u64 = 40;
+
+// The `top` biggest counts, biggest first
+fn top_counts(counts: HashMap<String, u64>, top: usize) -> Vec<Count> {
+    let mut counts: Vec<_> = counts.into_iter().map(|(name, tracks)| Count { name, tracks }).collect();
+    counts.sort_by(|a, b| b.tracks.cmp(&a.tracks).then_with(|| a.name.cmp(&b.name)));
+    counts.truncate(top);
+    counts
+}
+
+fn print_api_usage() -> Result<(), Error> {
+    let log = UsageLog::load()?;
+
+    println!("API calls in the last 24 hours: {}", locale::number(log.calls_last_24h()));
+    if log.runs.is_empty() {
+        return Ok(());
+    }
+
+    println!("Recent runs:");
+    for run in log.runs.iter().rev() {
+        println!(
+            "  {}  {:<6} {} calls",
+            locale::date_time(run.started_at),
+            run.command,
+            locale::number(run.calls)
+        );
+    }
+
+    Ok(())
+}
+
+fn print_disk_usage(audio_folder: &Path, input_folder: &Path, top: usize) -> Result<(), Error> {
+    let manifest_path = audio_folder.join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+        return Err(Error::JsonFileNotFound(manifest_path.to_string_lossy().into()));
+    }
+    let manifest = Manifest::load(audio_folder)?;
+    let total = folder_size(audio_folder)?;
+
+    // Bytes of current audio per track, which everything below is attributed from
+    let mut track_bytes: HashMap<u64, u64> = HashMap::new();
+    let (mut audio, mut previous, mut sidecars) = (0, 0, 0);
+    for (id, entry) in &manifest.tracks {
+        // Offloaded audio no longer takes up space here
+        for file in entry.files.iter().filter(|f| f.offloaded_to.is_none()) {
+            audio += file.bytes;
+            *track_bytes.entry(*id).or_default() += file.bytes;
+
+            if let Ok(meta) = fs::metadata(sidecar_path(&audio_folder.join(&file.path))) {
+                sidecars += meta.len();
+            }
+        }
+        previous += entry.previous_versions.iter().filter(|f| f.offloaded_to.is_none()).map(|f| f.bytes).sum::<u64>();
+    }
+
+    println!("Disk usage of {} ({}):", audio_folder.display(), locale::bytes(total));
+    println!("By kind:");
+    print_share("audio", audio, total);
+    print_share("previous versions", previous, total);
+    print_share("sidecars", sidecars, total);
+    print_share("other", total.saturating_sub(audio + previous + sidecars), total);
+
+    let likes = archive::optional(archive::load_likes(input_folder, Strictness::Lenient))?;
+    let playlists = archive::optional(archive::load_playlists(input_folder, Strictness::Lenient))?;
+    if likes.is_none() && playlists.is_none() {
+        println!("(no JSON archive in {}; pass --input-folder to break down by playlist and artist)", input_folder.display());
+        return Ok(());
+    }
+
+    // A track in several playlists counts towards each of them
+    let mut by_source: HashMap<String, u64> = HashMap::new();
+    let mut by_artist: HashMap<String, u64> = HashMap::new();
+    let mut seen_for_artist = HashSet::new();
+    let mut attribute = |source: &str, track: &TrackInfo| {
+        let bytes = match track.id.and_then(|id| track_bytes.get(&id)) {
+            Some(bytes) => *bytes,
+            None => return
+        };
+
+        *by_source.entry(source.to_string()).or_default() += bytes;
+        if seen_for_artist.insert(track.id) {
+            *by_artist.entry(artist(track).unwrap_or("unknown artist").to_string()).or_default() += bytes;
+        }
+    };
+
+    for (_, track) in likes.iter().flat_map(archive::liked_tracks) {
+        attribute("likes", track);
+    }
+    for playlist in playlists.iter().flat_map(|p| p.playlists.iter()) {
+        let title = format!("playlist: {}", playlist.title.as_deref().unwrap_or("untitled"));
+        for track in archive::playlist_tracks(playlist) {
+            attribute(&title, track);
+        }
+    }
+
+    println!("By source (tracks in several places count towards each):");
+    print_top(by_source, audio, top);
+    println!("By artist:");
+    print_top(by_artist, audio, top);
+
+    Ok(())
+}
+
+/// Below this many bits per second, audio is flagged as low quality
+const LOW_BIT_RATE: u64 = 128_000;
+/// Below this many Hz, audio is flagged as low quality
+const LOW_SAMPLE_RATE: u64 = 44_100;
+
+fn print_codecs(audio_folder: &Path, top: usize) -> Result<(), Error> {
+    let manifest_path = audio_folder.join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+        return Err(Error::JsonFileNotFound(manifest_path.to_string_lossy().into()));
+    }
+    let manifest = Manifest::load(audio_folder)?;
+    // Rather than a warning for every file
+    if !ffprobe_installed() {
+        return Err(Error::ProbeError("couldn't run ffprobe (is ffmpeg installed?)".into()));
+    }
+
+    let mut by_codec: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut by_bit_rate: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut by_sample_rate: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut low_quality = Vec::new();
+    let (mut probed, mut unreadable) = (0, 0);
+    for entry in manifest.tracks.values() {
+        // Offloaded audio isn't here to probe
+        for file in entry.files.iter().filter(|f| f.offloaded_to.is_none()) {
+            let info = match probe(&audio_folder.join(&file.path)) {
+                Ok(info) => info,
+                Err(e) => {
+                    println!("  [warning] {:?}", e);
+                    unreadable += 1;
+                    continue;
+                }
+            };
+            probed += 1;
+
+            let codec = by_codec.entry(info.codec.clone()).or_default();
+            codec.0 += 1;
+            codec.1 += file.bytes;
+            // Bucketed to the nearest 32 kbps, as encoders never hit it exactly
+            if let Some(bit_rate) = info.bit_rate {
+                *by_bit_rate.entry((bit_rate + 16_000) / 32_000 * 32).or_default() += 1;
+            }
+            if let Some(sample_rate) = info.sample_rate {
+                *by_sample_rate.entry(sample_rate).or_default() += 1;
+            }
+
+            let low = info.bit_rate.is_some_and(|rate| rate < LOW_BIT_RATE)
+                || info.sample_rate.is_some_and(|rate| rate < LOW_SAMPLE_RATE);
+            if low {
+                low_quality.push((entry.title.clone().unwrap_or_else(|| "untitled".into()), file.path.clone(), info));
+            }
+        }
+    }
+
+    println!("Audio formats in {} ({} files):", audio_folder.display(), locale::number(probed));
+    println!("By codec:");
+    for (codec, (files, bytes)) in &by_codec {
+        println!("  {:>7} files  {:>10}  {}", locale::number(*files), locale::bytes(*bytes), codec);
+    }
+    println!("By bitrate:");
+    for (kbps, files) in &by_bit_rate {
+        println!("  {:>7} files  ~{} kbps", locale::number(*files), kbps);
+    }
+    println!("By sample rate:");
+    for (hz, files) in &by_sample_rate {
+        println!("  {:>7} files  {} Hz", locale::number(*files), locale::number(*hz));
+    }
+    if unreadable > 0 {
+        println!("({} files couldn't be probed)", locale::number(unreadable));
+    }
+
+    if low_quality.is_empty() {
+        return Ok(());
+    }
+    // Worst first
+    low_quality.sort_by_key(|(_, _, info)| (info.bit_rate.unwrap_or(u64::MAX), info.sample_rate.unwrap_or(u64::MAX)));
+    println!(
+        "Low quality (under {} kbps or {} Hz; worth downloading again if a better version turns up):",
+        LOW_BIT_RATE / 1000,
+        locale::number(LOW_SAMPLE_RATE)
+    );
+    for (title, path, info) in low_quality.iter().take(top) {
+        println!(
+            "  {}, {} kbps, {} Hz  {} ({})",
+            info.codec,
+            info.bit_rate.map_or_else(|| "?".into(), |rate| (rate / 1000).to_string()),
+            info.sample_rate.map_or_else(|| "?".into(), locale::number),
+            title,
+            path
+        );
+    }
+    if low_quality.len() > top {
+        println!("  ... and {} more", low_quality.len() - top);
+    }
+
+    Ok(())
+}
+
+fn print_top(usage: HashMap<String, u64>, total: u64, top: usize) {
+    let mut usage: Vec<_> = usage.into_iter().collect();
+    usage.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    for (name, bytes) in usage.iter().take(top) {
+        print_share(name, *bytes, total);
+    }
+    if usage.len() > top {
+        println!("  ... and {} more", usage.len() - top);
+    }
+}
+
+fn print_share(name: &str, bytes: u64, total: u64) {
+    let percent = if total == 0 { 0.0 } else { bytes as f64 * 100.0 / total as f64 };
+    println!("  {:>10}  {:>5.1}%  {}", locale::bytes(bytes), percent, name);
+}
+
+fn folder_size(folder: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![folder.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += meta.len();
+            }
+        }
+    }
+
+    Ok(total)
+}
@@ -0,0 +1,106 @@
+//! Zesting likes a page at a time into `likes.ndjson`, for accounts with so
+//! many likes that building `likes.json` in memory is a problem.
+//!
+//! Each line of the file is one page of likes as the API returned it. The
+//! page to carry on from is kept next to it until the last page is written,
+//! so a run that fails part way picks up where it left off instead of
+//! starting over.
+
+use crate::filter::DateRange;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::Error;
+use orange_zest::api::{Likes, LikesCollection};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+pub const LIKES_NDJSON: &str = "likes.ndjson";
+/// Holds how many likes have been fetched and the URL of the next page while
+/// a streamed zest is unfinished
+const RESUME_FILE: &str = "likes.ndjson.next";
+
+/// Streams the likes of the account, or of the user with the given id (the
+/// most recent `recent` of them) into `likes.ndjson` in `output_folder`,
+/// calling `on_page` with the number of likes on each page as it's written.
+/// Returns how many likes were fetched.
+pub fn zest_likes(
+    client: &ApiClient,
+    user_id: Option<u64>,
+    output_folder: &Path,
+    recent: u64,
+    dates: &DateRange,
+    mut on_page: impl FnMut(usize)
+) -> Result<u64, Error> {
+    let whose = if user_id.is_some() { Whose::Public } else { Whose::Own };
+    let path = output_folder.join(LIKES_NDJSON);
+    let resume_path = output_folder.join(RESUME_FILE);
+
+    let resume = fs::read_to_string(&resume_path).ok().and_then(|resume| {
+        let (written, url) = resume.trim().split_once('\n')?;
+        Some((written.parse::<u64>().ok()?, url.to_string()))
+    });
+    let (mut next, mut written, mut file) = match resume {
+        Some((written, url)) if path.exists() => {
+            println!("Resuming the unfinished {} after {} likes", LIKES_NDJSON, written);
+            (Some(url), written, OpenOptions::new().append(true).open(&path)?)
+        },
+        _ => {
+            let user_id = match user_id {
+                Some(user_id) => user_id,
+                None => client
+                    .check_credentials()?
+                    .id
+                    .ok_or_else(|| Error::HttpError("the account has no id".into()))?
+            };
+            (Some(ApiClient::likes_url(user_id)), 0, File::create(&path)?)
+        }
+    };
+
+    while let Some(url) = next.take() {
+        if written >= recent {
+            break;
+        }
+
+        let mut page = client.likes_page(&url, whose)?;
+        page.collection.truncate((recent - written) as usize);
+        next = page.next_href.clone();
+
+        // Likes outside the dates count towards `recent` all the same, as
+        // they do for likes.json
+        let fetched = page.collection.len();
+        page.collection.retain(|like| dates.contains(like.created_at.as_deref()));
+        let mut line = serde_json::to_vec(&page).unwrap();
+        line.push(b'\n');
+        file.write_all(&line)?;
+        file.sync_data()?;
+        written += fetched as u64;
+
+        if let Some(url) = &next {
+            fs::write(&resume_path, format!("{}\n{}", written, url))?;
+        }
+        on_page(fetched);
+    }
+
+    match fs::remove_file(&resume_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    Ok(written)
+}
+
+/// Reads a `likes.ndjson` back in as if it were `likes.json`.
+pub fn load_likes(path: &Path) -> Result<Likes, Error> {
+    let mut likes = Likes::default();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let page: LikesCollection = serde_json::from_str(&line)
+            .map_err(|e| Error::JsonFormatError(format!("{} line {}: {}", path.display(), i + 1, e)))?;
+        likes.collections.push(page);
+    }
+
+    Ok(likes)
+}
@@ -0,0 +1,252 @@
+//! A persistent list of artists to archive the uploads of, so that new
+//! releases get saved without having to be liked first.
+
+use crate::api_usage::ApiBudget;
+use crate::concurrency::Credentials;
+use crate::download::{download_loose_tracks, TrackSaver};
+use crate::events::EventFeed;
+use crate::interrupt;
+use crate::keychain::{KeyringOpts, ProfileOpts};
+use crate::logging::LogOpts;
+use crate::naming::{FolderLayout, Namer};
+use crate::net::{ApiOpts, TimeoutOpts};
+use crate::notify::NotifyOpts;
+use crate::progress::Progress;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::state::{load_state, save_state};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::Zester;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+const SUBSCRIPTIONS_FILE: &str = "subscriptions.json";
+
+// Only ever one of these around, parsed once at startup
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt, Debug)]
+pub enum SubscribeCommand {
+    /// Subscribe to artists
+    Add {
+        /// soundcloud.com artist URLs, or just the artists' permalinks
+        #[structopt(required = true, min_values = 1)]
+        urls: Vec<String>,
+    },
+    /// Unsubscribe from artists
+    Remove {
+        #[structopt(required = true, min_values = 1)]
+        urls: Vec<String>,
+    },
+    /// Show the artists subscribed to
+    List,
+    /// Download whatever subscribed artists have uploaded that isn't archived
+    /// yet (everything, the first time); run it from the daemon to keep up
+    Run {
+        /// OAuth token
+        #[structopt(long)]
+        oauth_token: Option<String>,
+        /// Client ID
+        #[structopt(long)]
+        client_id: Option<String>,
+        #[structopt(flatten)]
+        profile: ProfileOpts,
+        #[structopt(flatten)]
+        keyring: KeyringOpts,
+        #[structopt(flatten)]
+        log: LogOpts,
+        #[structopt(flatten)]
+        notify: NotifyOpts,
+        #[structopt(flatten)]
+        timeouts: TimeoutOpts,
+        #[structopt(flatten)]
+        api: ApiOpts,
+        /// Folder to download uploads into, one folder per artist
+        #[structopt(short, long, parse(from_os_str), required = true, value_name = "path")]
+        output_folder: PathBuf,
+        /// Make at most n API calls during this run, leaving the rest for next time
+        #[structopt(long, value_name = "n")]
+        max_api_calls: Option<u64>,
+    },
+}
+
+impl SubscribeCommand {
+    /// Whether this command only touches the subscriptions file.
+    pub fn is_local(&self) -> bool {
+        !matches!(self, SubscribeCommand::Run { .. })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct Subscriptions {
+    pub artists: Vec<Subscription>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Subscription {
+    pub url: String,
+    pub added_at: DateTime<Utc>,
+    /// When the artist's uploads were last looked through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checked_at: Option<DateTime<Utc>>,
+    /// What went wrong the last time this was run, if anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl Subscriptions {
+    /// Loads the subscriptions from the state directory, or none if there
+    /// aren't any yet.
+    pub fn load() -> Result<Self, Error> {
+        load_state(SUBSCRIPTIONS_FILE)
+    }
+
+    fn save(&self) -> Result<(), Error> {
+        save_state(SUBSCRIPTIONS_FILE, self)
+    }
+}
+
+/// Turns an artist link or bare permalink into the artist's profile URL.
+fn artist_url(arg: &str) -> Option<String> {
+    let arg = arg.trim().trim_end_matches('/');
+    let path = arg
+        .strip_prefix("https://")
+        .or_else(|| arg.strip_prefix("http://"))
+        .unwrap_or(arg);
+    let path = path
+        .strip_prefix("www.")
+        .or_else(|| path.strip_prefix("m."))
+        .unwrap_or(path);
+    let permalink = path.strip_prefix("soundcloud.com/").unwrap_or(path);
+
+    // Anything deeper than the profile is a track, set or tab of it
+    let valid = !permalink.is_empty()
+        && permalink.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| format!("https://soundcloud.com/{}", permalink.to_ascii_lowercase()))
+}
+
+/// Runs the subscription commands that don't need the API.
+pub fn edit(command: SubscribeCommand) -> Result<(), Error> {
+    let mut subscriptions = Subscriptions::load()?;
+
+    match command {
+        SubscribeCommand::Add { urls } => {
+            for arg in urls {
+                let url = artist_url(&arg)
+                    .ok_or_else(|| Error::InvalidUrl(format!("{} isn't a soundcloud.com artist link", arg)))?;
+                if subscriptions.artists.iter().any(|sub| sub.url == url) {
+                    println!("Already subscribed: {}", url);
+                    continue;
+                }
+
+                println!("Subscribed to {}", url);
+                subscriptions.artists.push(Subscription { url, added_at: Utc::now(), checked_at: None, last_error: None });
+            }
+            subscriptions.save()?;
+        },
+        SubscribeCommand::Remove { urls } => {
+            for arg in urls {
+                let url = artist_url(&arg).unwrap_or(arg);
+                let before = subscriptions.artists.len();
+                subscriptions.artists.retain(|sub| sub.url != url);
+
+                if subscriptions.artists.len() == before {
+                    println!("Not subscribed: {}", url);
+                } else {
+                    println!("Unsubscribed from {}", url);
+                }
+            }
+            subscriptions.save()?;
+        },
+        SubscribeCommand::List => {
+            if subscriptions.artists.is_empty() {
+                println!("Not subscribed to anyone");
+            }
+            for sub in &subscriptions.artists {
+                print!(
+                    "{}  (last checked {})",
+                    sub.url,
+                    sub.checked_at.map_or_else(|| "never".into(), |at| at.format("%Y-%m-%d %H:%M").to_string())
+                );
+                match &sub.last_error {
+                    Some(err) => println!("  (last check failed: {})", err),
+                    None => println!()
+                }
+            }
+        },
+        SubscribeCommand::Run { .. } => unreachable!("needs a zester")
+    }
+
+    Ok(())
+}
+
+/// Downloads the uploads of every subscribed artist that aren't in the
+/// manifest of `output_folder` yet.
+pub fn run(
+    output_folder: &Path,
+    max_api_calls: Option<u64>,
+    zester: &Zester,
+    credentials: &Credentials,
+    api_client: &ApiClient,
+    pb: &Progress
+) -> Result<(), Error> {
+    let mut subscriptions = Subscriptions::load()?;
+    fs::create_dir_all(output_folder)?;
+
+    let budget = ApiBudget::new("subscribe", max_api_calls);
+    let events = EventFeed::default();
+    let namer = Namer::Standard { folders: FolderLayout::ByArtist, filename: None };
+    let saver = TrackSaver::new(output_folder, namer, api_client, &budget, &events, pb)?;
+
+    let total = subscriptions.artists.len();
+    let mut new_uploads = 0;
+    for (i, sub) in subscriptions.artists.iter_mut().enumerate() {
+        if budget.exhausted() {
+            pb.println(format!("  [warning] API call budget used up, leaving {} artists for next time", total - i));
+            break;
+        }
+
+        pb.set_prefix(&format!("Zesting subscriptions ({}/{}) - {}", i + 1, total, sub.url));
+        let result = api_client.resolve_user(&sub.url).and_then(|artist| {
+            budget.record(1);
+            let artist_id = artist.id.ok_or_else(|| Error::HttpError(format!("{} resolved to a user without an id", sub.url)))?;
+            let tracks: Vec<_> = api_client
+                .user_tracks(artist_id, Whose::Public, || budget.record(1))?
+                .into_iter()
+                .filter(|track| track.id.is_some_and(|id| !saver.manifest.lock().unwrap().tracks.contains_key(&id)))
+                .collect();
+
+            pb.reset();
+            pb.set_length(tracks.len() as u64);
+            let result = download_loose_tracks(&saver, tracks.clone(), 1, zester, credentials);
+            saver.save_manifest()?;
+            result?;
+
+            new_uploads += tracks.iter().filter(|t| saver.was_saved(t)).count();
+            match tracks.iter().filter(|t| !saver.was_saved(t)).count() {
+                0 => Ok(()),
+                failed => Err(Error::PartialFailure(format!("{} of {} new uploads failed to download", failed, tracks.len())))
+            }
+        });
+
+        // Not checked, so it's looked at again next time
+        if let Err(Error::Interrupted) = result {
+            break;
+        }
+        sub.checked_at = Some(Utc::now());
+        sub.last_error = match result {
+            Ok(()) => None,
+            Err(e) => {
+                pb.println(format!("  [warning] couldn't archive everything from {}: {:?}", sub.url, e));
+                Some(format!("{:?}", e))
+            }
+        };
+    }
+    subscriptions.save()?;
+    interrupt::check()?;
+
+    pb.println(format!("Zested {} new uploads from {} subscribed artists", new_uploads, total));
+    saver.finish()
+}
@@ -0,0 +1,170 @@
+//! Tallying up what an audio run did, so it can be reported at the end rather
+//! than scrolling past on the progress bar.
+
+use crate::atomic::write_json;
+use crate::history::{self, RunCounts};
+use crate::locale;
+use crate::progress::Progress;
+use crate::Error;
+use chrono::{DateTime, Utc};
+use orange_zest::api::{Playlist, TrackInfo};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const FAILURES_FILE: &str = "failures.json";
+
+/// A track that couldn't be saved.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct Failure {
+    pub id: Option<u64>,
+    pub title: Option<String>,
+    pub permalink_url: Option<String>,
+    /// The playlist the track was being saved from, if any
+    pub playlist: Option<String>,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct FailureReport {
+    pub finished_at: DateTime<Utc>,
+    pub failures: Vec<Failure>,
+}
+
+pub struct RunSummary {
+    started: Instant,
+    downloaded: AtomicU64,
+    bytes: AtomicU64,
+    linked: AtomicU64,
+    skipped: AtomicU64,
+    restricted: AtomicU64,
+    failures: Mutex<Vec<Failure>>,
+    /// Failures from an earlier run that are still being dealt with
+    pending: Mutex<Vec<Failure>>,
+}
+
+impl Default for RunSummary {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            downloaded: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            linked: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            restricted: AtomicU64::new(0),
+            failures: Mutex::new(Vec::new()),
+            pending: Mutex::new(Vec::new())
+        }
+    }
+}
+
+impl RunSummary {
+    pub fn downloaded(&self, bytes: u64) {
+        self.downloaded.fetch_add(1, Ordering::SeqCst);
+        self.bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn linked(&self) {
+        self.linked.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Counts tracks that were left out (by filters, limits or the budget).
+    pub fn skipped(&self, count: u64) {
+        self.skipped.fetch_add(count, Ordering::SeqCst);
+    }
+
+    /// Counts skipped tracks that were skipped for being restricted (these
+    /// are counted by `skipped` too).
+    pub fn restricted(&self, count: u64) {
+        self.restricted.fetch_add(count, Ordering::SeqCst);
+    }
+
+    pub fn failed(&self, track: &TrackInfo, playlist: Option<&Playlist>, error: String) {
+        self.failures.lock().unwrap().push(Failure {
+            id: track.id,
+            title: track.title.clone(),
+            permalink_url: track.permalink_url.clone(),
+            playlist: playlist.and_then(|p| p.title.clone()),
+            error
+        });
+    }
+
+    /// Records a failure from an earlier run that still stands.
+    pub fn carry_over(&self, failure: Failure) {
+        self.failures.lock().unwrap().push(failure);
+    }
+
+    /// Sets the failures from an earlier run that haven't been dealt with yet,
+    /// so that they're kept in the report if the run stops early.
+    pub fn set_pending(&self, pending: Vec<Failure>) {
+        *self.pending.lock().unwrap() = pending;
+    }
+
+    /// Writes `failures.json` into the output folder as things stand, along
+    /// with pending failures that haven't been dealt with yet. An old one is
+    /// cleared out if there's nothing to report.
+    pub fn write_report(&self, output_folder: &Path) -> Result<(), Error> {
+        let mut failures = self.failures.lock().unwrap().clone();
+        for failure in self.pending.lock().unwrap().iter() {
+            // A retry that failed again is already listed
+            if failure.id.is_none() || !failures.iter().any(|f| f.id == failure.id) {
+                failures.push(failure.clone());
+            }
+        }
+
+        let path = output_folder.join(FAILURES_FILE);
+        if failures.is_empty() {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(())
+            }
+        } else {
+            write_json(&FailureReport { finished_at: Utc::now(), failures }, &path, true)
+        }
+    }
+
+    /// What the run has done so far.
+    pub fn counts(&self) -> RunCounts {
+        RunCounts {
+            downloaded: self.downloaded.load(Ordering::SeqCst),
+            bytes: self.bytes.load(Ordering::SeqCst),
+            linked: self.linked.load(Ordering::SeqCst),
+            skipped: self.skipped.load(Ordering::SeqCst),
+            failed: self.failures.lock().unwrap().len() as u64
+        }
+    }
+
+    /// Prints the summary and writes `failures.json` into the output folder,
+    /// clearing out one left by an earlier run if nothing failed this time.
+    pub fn finish(self, output_folder: &Path, pb: &Progress) -> Result<(), Error> {
+        self.write_report(output_folder)?;
+
+        history::record_counts(self.counts(), output_folder);
+        let failures = self.failures.into_inner().unwrap();
+        let mut summary = format!(
+            "Downloaded {} tracks ({}), linked {}, skipped {}{}, failed {} in {}",
+            locale::number(self.downloaded.load(Ordering::SeqCst)),
+            locale::bytes(self.bytes.load(Ordering::SeqCst)),
+            locale::number(self.linked.load(Ordering::SeqCst)),
+            locale::number(self.skipped.load(Ordering::SeqCst)),
+            match self.restricted.load(Ordering::SeqCst) {
+                0 => String::new(),
+                restricted => format!(" ({} restricted)", locale::number(restricted))
+            },
+            locale::number(failures.len() as u64),
+            locale::duration(self.started.elapsed())
+        );
+
+        if !failures.is_empty() {
+            summary.push_str(&format!(" (see {})", output_folder.join(FAILURES_FILE).display()));
+        }
+
+        pb.println(summary);
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Capping how fast audio is downloaded, across every download at once.
+
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Downloads are read in pieces at most this big so that the pauses between
+/// them stay short
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Parses rates like `2MiB/s`, `500KB/s`, `1.5M` or `800k` into bytes per
+/// second. `K`, `M` and `G` on their own are binary units, as with curl.
+pub fn parse_rate(arg: &str) -> Result<u64, String> {
+    match parse_bytes(arg.trim().trim_end_matches("/s")) {
+        Some(0) => Err(format!("\"{}\" would stop downloads entirely", arg)),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("\"{}\" is not a rate like 2MiB/s, 500KB/s or 800k", arg))
+    }
+}
+
+// Parses an amount of bytes like `2MiB`, `500KB` or `1.5G`
+pub(crate) fn parse_bytes(arg: &str) -> Option<u64> {
+    let arg = arg.trim();
+    let split = arg.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(arg.len());
+    let (num, unit) = arg.split_at(split);
+
+    let num: f64 = num.parse().ok()?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kib" => 1024.0,
+        "kb" => 1000.0,
+        "m" | "mib" => 1024.0 * 1024.0,
+        "mb" => 1000.0 * 1000.0,
+        "g" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        "gb" => 1000.0 * 1000.0 * 1000.0,
+        _ => return None
+    };
+    Some((num * multiplier) as u64)
+}
+
+/// A token bucket shared by every download, allowing a second's worth of bytes
+/// to be read in a burst.
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Bytes that can be read right now; negative once reads have been let
+    /// through that still have to be waited out
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket { available: bytes_per_sec as f64, refilled_at: Instant::now() })
+        }
+    }
+
+    // Takes `bytes` out of the bucket, sleeping until they're paid for if it
+    // runs dry
+    fn take(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * self.bytes_per_sec;
+            bucket.available = (bucket.available + refill).min(self.bytes_per_sec);
+            bucket.refilled_at = now;
+            bucket.available -= bytes as f64;
+
+            if bucket.available < 0.0 {
+                Duration::from_secs_f64(-bucket.available / self.bytes_per_sec)
+            } else {
+                Duration::from_secs(0)
+            }
+        };
+
+        thread::sleep(wait);
+    }
+}
+
+/// Wraps a download so that reading from it is held to the limiter's rate, if
+/// there is one.
+pub struct Throttled<'a, R> {
+    inner: R,
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl<'a, R> Throttled<'a, R> {
+    pub fn new(inner: R, limiter: Option<&'a RateLimiter>) -> Self {
+        Self { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limiter = match self.limiter {
+            Some(limiter) => limiter,
+            None => return self.inner.read(buf)
+        };
+
+        let len = buf.len().min(CHUNK_SIZE);
+        let read = self.inner.read(&mut buf[..len])?;
+        limiter.take(read);
+        Ok(read)
+    }
+}
@@ -0,0 +1,258 @@
+//! Somewhere for commands that tidy up an archive to put what they'd otherwise
+//! delete or overwrite, and the `trash` command for getting it back (or
+//! getting rid of it for good).
+//!
+//! Each run that discards anything gets its own batch folder in the trash,
+//! named after the run's id, holding the files at their paths within the
+//! archive alongside a `trashed.json` saying where each came from.
+
+use crate::atomic::write_json;
+use crate::history;
+use crate::lock::{Lock, LOCK_FILE};
+use crate::offload::{move_file, parse_age};
+use crate::Error;
+use chrono::{DateTime, Utc};
+use indicatif::HumanBytes;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// Where the trash goes when no `--trash-dir` is given, inside the archive
+pub const TRASH_DIR: &str = ".zester-trash";
+const INDEX_FILE: &str = "trashed.json";
+
+#[derive(StructOpt, Debug)]
+pub enum TrashCommand {
+    /// List what's in the trash, one batch per run that put things there
+    List(TrashLocation),
+    /// Put the files from a batch back where they came from
+    Restore {
+        #[structopt(flatten)]
+        location: TrashLocation,
+        /// The batch to restore, as shown by `trash list`
+        batch: String,
+    },
+    /// Delete batches from the trash for good
+    Empty {
+        #[structopt(flatten)]
+        location: TrashLocation,
+        /// Only delete batches trashed longer ago than this (e.g. 30d, 12w, 6m, 2y)
+        #[structopt(long, parse(try_from_str = parse_age), value_name = "age")]
+        older_than: Option<chrono::Duration>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub struct TrashLocation {
+    /// Archive folder the trash belongs to
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+    /// Trash folder, if it isn't the one inside the archive
+    #[structopt(long, parse(from_os_str), value_name = "path")]
+    trash_dir: Option<PathBuf>,
+}
+
+/// What a batch holds, kept in its `trashed.json`.
+#[derive(Serialize, Deserialize, Debug)]
+struct Batch {
+    /// The command that discarded the files, like `compact`
+    command: String,
+    archive: PathBuf,
+    trashed_at: DateTime<Utc>,
+    items: Vec<TrashedItem>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashedItem {
+    /// Where the file was
+    original: PathBuf,
+    /// Where it is now, relative to the batch folder
+    trashed: PathBuf,
+    bytes: u64,
+}
+
+/// The batch the current run is discarding files into.
+pub struct Trash {
+    archive: PathBuf,
+    folder: PathBuf,
+    /// Created along with the batch folder, when the first file is discarded
+    batch: Option<Batch>,
+    command: &'static str,
+}
+
+impl Trash {
+    /// `archive` is the folder files are discarded from; the trash goes in
+    /// `trash_dir`, or inside the archive if not given.
+    pub fn new(archive: &Path, trash_dir: Option<&Path>, command: &'static str) -> Self {
+        let trash_dir = trash_dir.map_or_else(|| archive.join(TRASH_DIR), Path::to_path_buf);
+
+        Self {
+            archive: archive.to_path_buf(),
+            folder: trash_dir.join(history::run_id()),
+            batch: None,
+            command
+        }
+    }
+
+    /// Moves `path` into the trash, keeping track of where it came from.
+    pub fn discard(&mut self, path: &Path) -> Result<(), Error> {
+        let relative = path
+            .strip_prefix(&self.archive)
+            .map_or_else(|_| PathBuf::from(path.file_name().unwrap_or_default()), Path::to_path_buf);
+        let bytes = fs::metadata(path)?.len();
+
+        let mut trashed = relative.clone();
+        let mut n = 1;
+        while self.folder.join(&trashed).exists() {
+            n += 1;
+            trashed = relative.with_file_name(format!("{}.{}", relative.file_name().unwrap().to_string_lossy(), n));
+        }
+
+        let dest = self.folder.join(&trashed);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(path, &dest)?;
+
+        let (command, archive) = (self.command, &self.archive);
+        let batch = self.batch.get_or_insert_with(|| Batch {
+            command: command.to_string(),
+            archive: fs::canonicalize(archive).unwrap_or_else(|_| archive.clone()),
+            trashed_at: Utc::now(),
+            items: Vec::new()
+        });
+        batch.items.push(TrashedItem {
+            original: fs::canonicalize(path.parent().unwrap_or(Path::new(".")))
+                .map_or_else(|_| path.to_path_buf(), |parent| parent.join(path.file_name().unwrap())),
+            trashed,
+            bytes
+        });
+        // Saved as it goes so that a run stopped part way still leaves a way
+        // back for what it's trashed
+        write_json(batch, self.folder.join(INDEX_FILE), true)
+    }
+
+    /// Says what was put in the trash, if anything.
+    pub fn report(&self) {
+        if let Some(batch) = &self.batch {
+            println!(
+                "Moved {} files ({}) to {}; `zester trash restore` puts them back, `zester trash empty` deletes them",
+                batch.items.len(),
+                HumanBytes(batch.items.iter().map(|item| item.bytes).sum()),
+                self.folder.display()
+            );
+        }
+    }
+}
+
+pub fn run(command: TrashCommand) -> Result<(), Error> {
+    match command {
+        TrashCommand::List(location) => {
+            let batches = load_batches(&location.trash_dir())?;
+            if batches.is_empty() {
+                println!("The trash is empty");
+            }
+
+            for (id, batch) in &batches {
+                println!(
+                    "{}  {:<10} {:>5} files {:>10}",
+                    id,
+                    batch.command,
+                    batch.items.len(),
+                    HumanBytes(batch.items.iter().map(|item| item.bytes).sum()).to_string()
+                );
+            }
+        },
+        TrashCommand::Restore { location, batch: id } => {
+            let _lock = Lock::acquire(location.folder.join(LOCK_FILE))?;
+            let trash_dir = location.trash_dir();
+            let folder = trash_dir.join(&id);
+            let mut batch = load_batch(&folder)?
+                .ok_or_else(|| Error::NoSuchRun(format!("no batch {} in {}", id, trash_dir.display())))?;
+
+            let (mut restored, mut left) = (0, Vec::new());
+            for item in batch.items.drain(..) {
+                if item.original.exists() {
+                    println!(
+                        "  [skipped] {} is in the way; left {} in the trash",
+                        item.original.display(),
+                        item.trashed.display()
+                    );
+                    left.push(item);
+                    continue;
+                }
+
+                if let Some(parent) = item.original.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                move_file(&folder.join(&item.trashed), &item.original)?;
+                println!("  [restored] {}", item.original.display());
+                restored += 1;
+            }
+
+            batch.items = left;
+            if batch.items.is_empty() {
+                fs::remove_dir_all(&folder)?;
+            } else {
+                write_json(&batch, folder.join(INDEX_FILE), true)?;
+            }
+            println!("Restored {} files, {} left in the trash", restored, batch.items.len());
+        },
+        TrashCommand::Empty { location, older_than } => {
+            let _lock = Lock::acquire(location.folder.join(LOCK_FILE))?;
+            let trash_dir = location.trash_dir();
+            let (mut emptied, mut bytes) = (0, 0);
+
+            for (id, batch) in load_batches(&trash_dir)? {
+                if older_than.is_some_and(|age| Utc::now() - batch.trashed_at < age) {
+                    continue;
+                }
+
+                fs::remove_dir_all(trash_dir.join(&id))?;
+                emptied += 1;
+                bytes += batch.items.iter().map(|item| item.bytes).sum::<u64>();
+            }
+
+            println!("Deleted {} batches from the trash, freeing {}", emptied, HumanBytes(bytes));
+        }
+    }
+
+    Ok(())
+}
+
+impl TrashLocation {
+    fn trash_dir(&self) -> PathBuf {
+        self.trash_dir.clone().unwrap_or_else(|| self.folder.join(TRASH_DIR))
+    }
+}
+
+fn load_batch(folder: &Path) -> Result<Option<Batch>, Error> {
+    let index = folder.join(INDEX_FILE);
+    if !index.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(orange_zest::load_json(&index)?))
+}
+
+// Batches in the trash, oldest first
+fn load_batches(trash_dir: &Path) -> Result<Vec<(String, Batch)>, Error> {
+    let entries = match fs::read_dir(trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into())
+    };
+
+    let mut batches = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if let Some(batch) = load_batch(&path)? {
+            batches.push((path.file_name().unwrap().to_string_lossy().into_owned(), batch));
+        }
+    }
+
+    batches.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(batches)
+}
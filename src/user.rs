@@ -0,0 +1,129 @@
+//! Zesting through the API client rather than the library: likes and
+//! playlists (the account's own as well as those of users given with
+//! `--user`), users' uploads and reposts, and the playlists anyone has liked.
+//!
+//! Going through the client means these are paced and cached like any other
+//! request zester makes. Other users' are rotated across the credential pool;
+//! the account's own are always asked for with its own credentials, so that
+//! nothing private is left out.
+
+use crate::sanitize;
+use crate::sink::SinkUrl;
+use crate::soundcloud::{ApiClient, Whose};
+use crate::Error;
+use orange_zest::api::{Likes, Me, Playlist, Playlists, TrackInfo};
+use std::path::{Path, PathBuf};
+
+/// Where each user's archive goes, inside the output (and input) folder
+pub const USERS_DIR: &str = "users";
+
+/// A user given with `--user`.
+pub struct OtherUser {
+    pub id: u64,
+    pub permalink: String,
+    /// Their public profile, saved in place of `me.json`
+    pub profile: Me,
+}
+
+impl OtherUser {
+    /// Looks up a user by their numeric id, permalink or profile URL.
+    pub fn resolve(client: &ApiClient, arg: &str) -> Result<Self, Error> {
+        let profile: Me = match arg.parse::<u64>() {
+            Ok(id) => client.user(id)?,
+            Err(_) => client.resolve_user(arg).and_then(|user| {
+                client.user(user.id.ok_or_else(|| Error::HttpError(format!("{} resolved to a user without an id", arg)))?)
+            })?
+        };
+
+        let id = profile.id.ok_or_else(|| Error::HttpError(format!("{} resolved to a user without an id", arg)))?;
+        let permalink = profile.permalink.clone().unwrap_or_else(|| id.to_string());
+        Ok(Self { id, permalink, profile })
+    }
+
+    /// The folder this user's archive goes in, inside `folder`.
+    pub fn folder(&self, folder: &Path) -> PathBuf {
+        folder.join(USERS_DIR).join(sanitize(&self.permalink))
+    }
+
+    /// Where this user's archive goes, inside the given `--output`.
+    pub fn within(&self, output: &SinkUrl) -> SinkUrl {
+        output.join(&format!("{}/{}", USERS_DIR, sanitize(&self.permalink)))
+    }
+}
+
+/// Whose data the archive in `folder` is: another user's if it's one of the
+/// folders `--user` archives into, and the account's own otherwise.
+pub fn whose_archive(folder: &Path) -> Whose {
+    match folder.parent().and_then(Path::file_name) {
+        Some(parent) if parent == USERS_DIR => Whose::Public,
+        _ => Whose::Own
+    }
+}
+
+/// Gets the most recent `recent` of the given user's likes, calling `on_page`
+/// with the number of likes on each page.
+pub fn likes(
+    client: &ApiClient,
+    user_id: u64,
+    whose: Whose,
+    recent: u64,
+    mut on_page: impl FnMut(usize)
+) -> Result<Likes, Error> {
+    let mut likes = Likes::default();
+    let (mut next, mut fetched) = (Some(ApiClient::likes_url(user_id)), 0);
+
+    while let Some(url) = next.take() {
+        if fetched >= recent {
+            break;
+        }
+
+        let mut page = client.likes_page(&url, whose)?;
+        page.collection.truncate((recent - fetched) as usize);
+        next = page.next_href.take();
+        fetched += page.collection.len() as u64;
+        on_page(page.collection.len());
+        likes.collections.push(page);
+    }
+
+    Ok(likes)
+}
+
+/// Gets the most recent `recent` of the playlists and albums the given user
+/// has liked, with all of their tracks, calling `on_request` for each request
+/// made.
+pub fn liked_playlists(
+    client: &ApiClient,
+    user_id: u64,
+    whose: Whose,
+    recent: u64,
+    on_request: impl Fn()
+) -> Result<Playlists, Error> {
+    let mut playlists: Vec<Playlist> = client
+        .user_playlist_likes(user_id, whose, &on_request)?
+        .into_iter()
+        .filter_map(|like| like.playlist)
+        .collect();
+    playlists.truncate(recent as usize);
+
+    for playlist in &mut playlists {
+        fill_tracks(client, playlist, whose, &on_request)?;
+    }
+    Ok(Playlists { playlists })
+}
+
+/// Fills in all of a playlist's tracks, as playlists come from the API with
+/// only the first few of them filled in.
+pub fn fill_tracks(client: &ApiClient, playlist: &mut Playlist, whose: Whose, on_request: impl Fn()) -> Result<(), Error> {
+    let ids: Vec<u64> = playlist.tracks.iter().flatten().filter_map(|t| t.id).collect();
+
+    let mut tracks: Vec<TrackInfo> = Vec::new();
+    for batch in ids.chunks(50) {
+        tracks.extend(client.tracks::<TrackInfo>(batch, whose)?);
+        on_request();
+    }
+    // Keep the playlist's order, which the lookup doesn't
+    tracks.sort_by_key(|track| ids.iter().position(|&id| Some(id) == track.id));
+
+    playlist.tracks = Some(tracks);
+    Ok(())
+}
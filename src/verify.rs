@@ -0,0 +1,217 @@
+//! Checking archived audio against the checksums in the manifest.
+
+use crate::checksum::{sampled_sha256, sha256_bytes, sha256_file};
+use crate::json_check::Strictness;
+use crate::lock::{Lock, LOCK_FILE};
+use crate::manifest::{manifest_path, FileEntry, Manifest, MANIFEST_FILE};
+use crate::mirror::REMOVED_DIR;
+use crate::state::{load_state, save_state};
+use crate::trash::TRASH_DIR;
+use crate::Error;
+use chrono::{DateTime, Duration, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use structopt::clap::arg_enum;
+use structopt::StructOpt;
+
+/// Where scrub state used to be kept, inside the archive itself
+const LEGACY_SCRUB_STATE_FILE: &str = ".zester-scrub.json";
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ScrubMode {
+        Full,
+        Sampled
+    }
+}
+
+#[derive(StructOpt, Debug)]
+pub struct VerifyOpts {
+    /// Archive folder containing a manifest.json
+    #[structopt(parse(from_os_str))]
+    folder: PathBuf,
+    /// How thoroughly to check each file
+    #[structopt(
+        long,
+        possible_values = &ScrubMode::variants(),
+        case_insensitive = true,
+        default_value = "Full"
+    )]
+    scrub_mode: ScrubMode,
+    /// In sampled mode, do a full pass instead if the last one was more than n days ago
+    #[structopt(long, value_name = "days")]
+    full_every: Option<i64>,
+    /// Refuse a manifest with fields that are unknown or missing instead of warning
+    #[structopt(long)]
+    strict_json: bool,
+}
+
+/// What scrubbing remembers between passes over an archive.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ScrubState {
+    last_full_pass: Option<DateTime<Utc>>,
+}
+
+impl ScrubState {
+    // Each archive gets its own file in the state folder, named after where
+    // the archive is
+    fn name(folder: &Path) -> Result<String, Error> {
+        let folder = fs::canonicalize(folder)?;
+        Ok(format!("scrub/{}.json", sha256_bytes(folder.to_string_lossy().as_bytes())))
+    }
+
+    fn load(folder: &Path) -> Result<Self, Error> {
+        let legacy = folder.join(LEGACY_SCRUB_STATE_FILE);
+        if legacy.exists() {
+            let state: Self = orange_zest::load_json(&legacy)?;
+            state.save(folder)?;
+            fs::remove_file(&legacy)?;
+            return Ok(state);
+        }
+
+        load_state(&Self::name(folder)?)
+    }
+
+    fn save(&self, folder: &Path) -> Result<(), Error> {
+        save_state(&Self::name(folder)?, self)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FileStatus {
+    Ok,
+    Missing,
+    Corrupted,
+}
+
+/// Checks a single file from the manifest stored under `folder`.
+///
+/// Sampled checks fall back to a full hash for entries recorded before sampled
+/// hashes were kept.
+pub fn check_file(folder: &Path, entry: &FileEntry, mode: ScrubMode) -> io::Result<FileStatus> {
+    let path = entry.location(folder);
+    if !path.exists() {
+        return Ok(FileStatus::Missing);
+    }
+
+    let matches = match (mode, entry.sampled_sha256.as_ref()) {
+        (ScrubMode::Sampled, Some(expected)) => &sampled_sha256(&path)? == expected,
+        _ => sha256_file(&path)? == entry.sha256
+    };
+
+    Ok(if matches { FileStatus::Ok } else { FileStatus::Corrupted })
+}
+
+pub fn run(opts: VerifyOpts) -> Result<(), Error> {
+    let folder = opts.folder;
+    if !folder.join(MANIFEST_FILE).exists() {
+        return Err(Error::JsonFileNotFound(folder.join(MANIFEST_FILE).to_string_lossy().into()));
+    }
+
+    let _lock = Lock::acquire(folder.join(LOCK_FILE))?;
+    let mut manifest = Manifest::load_checked(&folder, Strictness::from_flag(opts.strict_json))?;
+    let mut state = ScrubState::load(&folder)?;
+
+    let full_pass_due = match (opts.full_every, state.last_full_pass) {
+        (Some(days), Some(last)) => Utc::now() - last > Duration::days(days),
+        (Some(_), None) => true,
+        (None, _) => false
+    };
+    let mode = if full_pass_due { ScrubMode::Full } else { opts.scrub_mode };
+
+    let num_files = manifest.tracks.values().map(|t| t.files.len() as u64).sum();
+    let pb = ProgressBar::new(num_files);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .progress_chars("#>-")
+            .template("{msg:<34!} [{bar:30.cyan/blue}] ({pos}/{len}) ({eta})")
+    );
+    pb.set_message(&format!("Verifying ({} pass)", mode.to_string().to_lowercase()));
+
+    let (mut missing, mut corrupted) = (0, 0);
+    let mut known = HashSet::new();
+    for entry in manifest.tracks.values_mut().flat_map(|t| t.files.iter_mut()) {
+        known.insert(entry.path.clone());
+
+        match check_file(&folder, entry, mode)? {
+            FileStatus::Ok => {
+                // Full passes are a good time to fill in sampled hashes for
+                // entries that predate them
+                if mode == ScrubMode::Full && entry.sampled_sha256.is_none() {
+                    entry.sampled_sha256 = Some(sampled_sha256(entry.location(&folder))?);
+                }
+            },
+            FileStatus::Missing => {
+                pb.println(format!("  [missing] {}", entry.path));
+                missing += 1;
+            },
+            FileStatus::Corrupted => {
+                pb.println(format!("  [corrupted] {}", entry.path));
+                corrupted += 1;
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+
+    let extra = untracked_audio(&folder, &known)?;
+    for path in &extra {
+        println!("  [extra] {}", path);
+    }
+
+    if mode == ScrubMode::Full {
+        manifest.save(&folder)?;
+        state.last_full_pass = Some(Utc::now());
+        state.save(&folder)?;
+    }
+
+    println!(
+        "Verified {} files ({} pass): {} missing, {} corrupted, {} extra",
+        num_files,
+        mode.to_string().to_lowercase(),
+        missing,
+        corrupted,
+        extra.len()
+    );
+
+    if missing + corrupted + extra.len() > 0 {
+        return Err(Error::IntegrityCheckFailed(format!(
+            "{} missing, {} corrupted, {} extra",
+            missing,
+            corrupted,
+            extra.len()
+        )));
+    }
+    Ok(())
+}
+
+// Finds audio files under `folder` that the manifest doesn't know about
+fn untracked_audio(folder: &Path, known: &HashSet<String>) -> Result<Vec<String>, Error> {
+    let mut extra = Vec::new();
+    let mut pending = vec![folder.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                // Trashed and removed audio is meant to be out of the way
+                if !path.ends_with(TRASH_DIR) && !path.ends_with(REMOVED_DIR) {
+                    pending.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "m4a") {
+                let relative = manifest_path(path.strip_prefix(folder).unwrap());
+                if !known.contains(&relative) {
+                    extra.push(relative);
+                }
+            }
+        }
+    }
+
+    extra.sort();
+    Ok(extra)
+}
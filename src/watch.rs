@@ -0,0 +1,196 @@
+//! `watch`: staying resident and keeping an archive up to date, for running
+//! as a service rather than from cron.
+//!
+//! Each sync zests the most recent likes and playlists into the archive and
+//! then downloads their audio, running `json` and `audio` as child processes
+//! of this executable, then optionally scrubs the archive with `verify`. Syncs
+//! follow an interval (`6h`) or a cron expression
+//! (`0 */6 * * *`, in local time); a lock file in the archive keeps a second
+//! `watch` out of it. SIGTERM or Ctrl-C lets the sync in progress stop cleanly
+//! before exiting; a second one stops it right away.
+
+use crate::daemon::daemon_credentials;
+use crate::lock::Lock;
+use crate::schedule::{parse_schedule, signal, Runner, Schedule};
+use crate::verify::ScrubMode;
+use crate::{Error, OutputFormat};
+use chrono::{DateTime, Local, Utc};
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use structopt::StructOpt;
+
+const LOCK_FILE: &str = ".zester-watch.lock";
+
+#[derive(StructOpt, Debug)]
+pub struct WatchOpts {
+    /// Folder holding the archive, JSON and audio alike
+    #[structopt(short, long, parse(from_os_str), value_name = "path")]
+    output_folder: PathBuf,
+    /// When to sync: a time between syncs (e.g. 30m, 6h) or a cron expression
+    /// (e.g. "0 */6 * * *", in local time)
+    #[structopt(long, parse(try_from_str = parse_schedule), default_value = "6h", value_name = "schedule")]
+    interval: Schedule,
+    /// How many of the most recent likes and playlists each sync looks at
+    #[structopt(long, default_value = "200", value_name = "n")]
+    recent: u64,
+    /// Only keep the JSON up to date, without downloading audio
+    #[structopt(long)]
+    no_audio: bool,
+    /// Check the archived audio against the manifest after each sync, hashing
+    /// each file in full or only sampled blocks of it
+    #[structopt(
+        long,
+        possible_values = &ScrubMode::variants(),
+        case_insensitive = true,
+        conflicts_with = "no-audio",
+        value_name = "mode"
+    )]
+    scrub_mode: Option<ScrubMode>,
+    /// With sampled scrubs, do a full one instead if the last was more than n days ago
+    #[structopt(long, requires = "scrub-mode", value_name = "days")]
+    full_every: Option<i64>,
+    /// Log as timestamped lines, or as one JSON object per line (syncs then report
+    /// their progress as JSON too)
+    #[structopt(
+        long,
+        possible_values = &OutputFormat::variants(),
+        case_insensitive = true,
+        default_value = "Text"
+    )]
+    log_format: OutputFormat,
+}
+
+/// Whether the watch has been asked to stop, and the sync that has to finish
+/// first.
+#[derive(Default)]
+struct Stop {
+    requested: Mutex<bool>,
+    changed: Condvar,
+    running_pid: Mutex<Option<u32>>,
+}
+
+pub fn run(opts: WatchOpts) -> Result<(), Error> {
+    let log = Log(opts.log_format);
+    fs::create_dir_all(&opts.output_folder)?;
+    let _lock = Lock::acquire(opts.output_folder.join(LOCK_FILE))?;
+    let (oauth_token, client_id) = daemon_credentials()?;
+    let runner = Runner::new(env::current_exe()?, oauth_token, client_id).detached();
+
+    let stop = Arc::new(Stop::default());
+    {
+        let stop = Arc::clone(&stop);
+        ctrlc::set_handler(move || {
+            *stop.requested.lock().unwrap() = true;
+            // The sync runs apart from the terminal's signals; pass this on. A
+            // second one has it quit right away, and the watch stops with it
+            if let Some(pid) = *stop.running_pid.lock().unwrap() {
+                signal(pid, "TERM");
+            }
+            stop.changed.notify_all();
+        })
+        .map_err(|e| Error::IoError(io::Error::other(e)))?;
+    }
+
+    log.event(
+        "watch_started",
+        &format!("Watching {}, syncing {}", opts.output_folder.display(), opts.interval),
+        json!({ "output_folder": opts.output_folder })
+    );
+    let folder = opts.output_folder.to_string_lossy();
+    let recent = opts.recent.to_string();
+    let progress = match opts.log_format {
+        OutputFormat::Text => "plain",
+        OutputFormat::Json => "json"
+    };
+    let mut runs = vec![vec!["json", "--recent", &recent, "--progress", progress, "-o", &folder, "likes", "playlists"]];
+    if !opts.no_audio {
+        runs.push(vec!["audio", "--recent", &recent, "--progress", progress, "-i", &folder, "-o", &folder, "likes", "playlists"]);
+    }
+    // `verify` keeps track of when the last full pass was in the state
+    // folder, so `--full-every` carries over from one sync to the next
+    let scrub_mode = opts.scrub_mode.map(|mode| mode.to_string());
+    let full_every = opts.full_every.map(|days| days.to_string());
+    if let Some(mode) = &scrub_mode {
+        let mut scrub = vec!["verify", &folder, "--scrub-mode", mode];
+        if let Some(days) = &full_every {
+            scrub.extend(vec!["--full-every", days]);
+        }
+        runs.push(scrub);
+    }
+
+    let mut cycle = 0;
+    loop {
+        cycle += 1;
+        log.event("sync_started", &format!("Sync {} started", cycle), json!({ "sync": cycle }));
+
+        let mut result = Ok(());
+        for args in &runs {
+            if *stop.requested.lock().unwrap() {
+                result = Err("stopped".to_string());
+                break;
+            }
+            log.event("run_started", &format!("Running: zester {}", args.join(" ")), json!({ "args": args }));
+            result = runner.run(args, |pid| *stop.running_pid.lock().unwrap() = pid);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let next = opts.interval.next_after(Local::now());
+        match &result {
+            Ok(()) => log.event(
+                "sync_finished",
+                &format!("Sync {} finished; the next is at {}", cycle, next.format("%Y-%m-%d %H:%M")),
+                json!({ "sync": cycle, "ok": true, "next_at": next })
+            ),
+            Err(e) => log.event(
+                "sync_failed",
+                &format!("[warning] sync {} failed: {}; the next is at {}", cycle, e, next.format("%Y-%m-%d %H:%M")),
+                json!({ "sync": cycle, "ok": false, "error": e, "next_at": next })
+            )
+        }
+
+        if !wait_until(&stop, next) {
+            break;
+        }
+    }
+
+    log.event("watch_stopped", "Stopped", json!({}));
+    Ok(())
+}
+
+// Sleeps until `next`, returning false instead if asked to stop first
+fn wait_until(stop: &Stop, next: DateTime<Local>) -> bool {
+    let mut requested = stop.requested.lock().unwrap();
+    loop {
+        if *requested {
+            return false;
+        }
+        let left = match (next - Local::now()).to_std() {
+            Ok(left) if !left.is_zero() => left,
+            _ => return true
+        };
+        requested = stop.changed.wait_timeout(requested, left).unwrap().0;
+    }
+}
+
+struct Log(OutputFormat);
+
+impl Log {
+    fn event(&self, event: &str, message: &str, fields: Value) {
+        match self.0 {
+            OutputFormat::Text => println!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message),
+            OutputFormat::Json => {
+                let mut line = json!({ "time": Utc::now(), "event": event, "message": message });
+                if let (Some(line), Value::Object(fields)) = (line.as_object_mut(), fields) {
+                    line.extend(fields);
+                }
+                println!("{}", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! A track's waveform: the loudness samples SoundCloud draws under its player,
+//! which only the API has.
+//!
+//! `waveform_url` points at the samples as JSON, or at a PNG of them for
+//! tracks archived long enough ago; the JSON is at the same URL either way.
+
+use crate::net;
+use crate::Error;
+use serde::Deserialize;
+use structopt::clap::arg_enum;
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum WaveformFormat {
+        Json,
+        Png,
+        Both
+    }
+}
+
+impl WaveformFormat {
+    pub fn json(self) -> bool {
+        self != WaveformFormat::Png
+    }
+
+    pub fn png(self) -> bool {
+        self != WaveformFormat::Json
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Waveform {
+    pub height: u32,
+    /// One per column, each between 0 and `height`
+    pub samples: Vec<u32>,
+}
+
+/// The waveform as the API returns it, along with where it came from.
+pub struct Fetched {
+    pub url: String,
+    /// The JSON exactly as it was downloaded
+    pub raw: String,
+    pub waveform: Waveform,
+}
+
+/// Downloads the waveform at `waveform_url`.
+pub fn fetch(waveform_url: &str) -> Result<Fetched, Error> {
+    let url = match waveform_url.strip_suffix(".png") {
+        Some(stem) => format!("{}.json", stem),
+        None => waveform_url.to_string()
+    };
+
+    let resp = net::get(&url).call();
+    if !resp.ok() {
+        return Err(Error::HttpError(format!("GET {} returned {}", url, resp.status())));
+    }
+    let raw = resp.into_string()?;
+    let waveform = serde_json::from_str(&raw)
+        .map_err(|e| Error::HttpError(format!("unexpected waveform from {}: {}", url, e)))?;
+
+    Ok(Fetched { url, raw, waveform })
+}
+
+impl Waveform {
+    /// Draws the waveform as a black-on-white PNG, a column per sample,
+    /// centered on the middle of the image the way SoundCloud draws it.
+    pub fn render_png(&self) -> Vec<u8> {
+        let width = (self.samples.len() as u32).max(1);
+        let height = self.height.max(1);
+        let row_bytes = width.div_ceil(8) as usize;
+
+        // Filter type (none) then one bit per pixel, set for white
+        let mut pixels = Vec::with_capacity((row_bytes + 1) * height as usize);
+        for y in 0..height {
+            pixels.push(0);
+            let mut row = vec![0xff; row_bytes];
+            for (x, &sample) in self.samples.iter().enumerate() {
+                let bar = sample.min(height);
+                let top = (height - bar) / 2;
+                if y >= top && y < top + bar {
+                    row[x / 8] &= !(0x80 >> (x % 8));
+                }
+            }
+            pixels.extend(row);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend(width.to_be_bytes());
+        header.extend(height.to_be_bytes());
+        // 1-bit greyscale, no interlacing
+        header.extend([1, 0, 0, 0, 0]);
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib_stored(&pixels));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend((data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend(kind);
+    png.extend(data);
+    let crc = crc32(&png[start..]);
+    png.extend(crc.to_be_bytes());
+}
+
+// Wraps data in a zlib stream without compressing it; waveforms are small
+// enough that it doesn't matter
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(if blocks.peek().is_none() { 1 } else { 0 });
+        let len = block.len() as u16;
+        out.extend(len.to_le_bytes());
+        out.extend((!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}